@@ -0,0 +1,75 @@
+//! Async wrappers around the blocking library API, for embedding in async
+//! applications. Each call runs the blocking work on tokio's blocking thread
+//! pool, so the async executor is never stalled by disk I/O.
+
+use crate::{ComparisonResult, DiffOptions, ScanOptions, ScanProgress, Snapshot};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio::task;
+
+/// Like `scan_drive_with_options`, sending each `ScanProgress` to `progress`.
+/// Updates are dropped rather than awaited when the channel is full, so a slow
+/// consumer never holds up the scan.
+pub async fn scan_drive_async(drive_path: String, options: ScanOptions, progress: Sender<ScanProgress>) -> Result<Snapshot, String> {
+    task::spawn_blocking(move || {
+        crate::scan_drive_with_options(drive_path, &options, |update| {
+            let _ = progress.try_send(update.clone());
+        })
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))?
+}
+
+pub async fn load_snapshot_async(data_dir: PathBuf, snapshot_id: String, password: Option<String>) -> Result<Snapshot, String> {
+    task::spawn_blocking(move || crate::load_snapshot(&data_dir, &snapshot_id, password.as_deref()))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+pub async fn save_snapshot_async(data_dir: PathBuf, snapshot: Arc<Snapshot>, encrypt: bool, password: Option<String>) -> Result<(), String> {
+    task::spawn_blocking(move || crate::save_snapshot(&data_dir, &snapshot, encrypt, password.as_deref()))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+/// Like `compare_snapshots_with_options`. The snapshots are shared rather than
+/// moved so the caller can keep using them.
+pub async fn compare_snapshots_async(snapshot1: Arc<Snapshot>, snapshot2: Arc<Snapshot>, options: DiffOptions) -> Result<ComparisonResult, String> {
+    task::spawn_blocking(move || crate::compare_snapshots_with_options(&snapshot1, &snapshot2, &options))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{scan_dir, write_tree, TestDataDir};
+    use crate::{compare_snapshots_with_options, load_snapshot};
+
+    #[tokio::test]
+    async fn async_calls_match_their_blocking_counterparts() {
+        let data_dir = TestDataDir::new();
+        let dir = write_tree(&["d0/a.txt", "d0/b.txt", "d1/a.txt", "d1/b.txt"]);
+        let drive_path = dir.path().to_string_lossy().to_string();
+        let paths = |snapshot: &Snapshot| snapshot.files.iter().map(|f| (f.path.clone(), f.size)).collect::<Vec<_>>();
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1024);
+        let scanned = Arc::new(scan_drive_async(drive_path, ScanOptions::default(), sender).await.unwrap());
+        let blocking = scan_dir(dir.path(), &ScanOptions::default());
+        assert_eq!(paths(&scanned), paths(&blocking));
+        assert!(receiver.recv().await.is_some());
+
+        save_snapshot_async(data_dir.path().to_path_buf(), scanned.clone(), true, Some("secret".to_string())).await.unwrap();
+        let loaded = load_snapshot_async(data_dir.path().to_path_buf(), scanned.id.clone(), Some("secret".to_string())).await.unwrap();
+        assert_eq!(paths(&loaded), paths(&load_snapshot(data_dir.path(), &scanned.id, Some("secret")).unwrap()));
+
+        let options = DiffOptions { include_unchanged: true, ..DiffOptions::default() };
+        let mut compared = compare_snapshots_async(Arc::new(blocking), scanned, options.clone()).await.unwrap();
+        let mut expected = compare_snapshots_with_options(&scan_dir(dir.path(), &ScanOptions::default()), &loaded, &options);
+        // Diffs of the same status come in hash order
+        compared.sort_by_status();
+        expected.sort_by_status();
+        assert_eq!(serde_json::to_value(&compared.diffs).unwrap(), serde_json::to_value(&expected.diffs).unwrap());
+    }
+}
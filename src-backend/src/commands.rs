@@ -1,45 +1,88 @@
-use drive_pulse_lib::{FileEntry, Snapshot, SnapshotSummary, FileDiff, DiffStatus, ComparisonResult};
-use std::collections::HashMap;
+use drive_pulse_lib::{Snapshot, SnapshotSummary, ComparisonResult, ComparisonSummary, DiffOptions, ProgressThrottle, ScanOptions, SizeBucket, VerifyReport};
 use std::fs;
-use std::io::{Read, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tauri::{Window};
-use walkdir::WalkDir;
-use aes_gcm::{
-    aead::{Aead, KeyInit},
-    Aes256Gcm, Nonce,
-};
-use sha2::{Sha256, Digest};
 
 #[derive(Clone, serde::Serialize)]
 pub struct DriveInfo {
     pub path: String,
     pub label: String,
+    /// Why the drive can't be scanned right now (e.g. "not ready" for an empty
+    /// card reader), or `None` when its contents can be listed.
+    pub status: Option<String>,
+}
+
+/// `None` if `path` can be listed, otherwise a short note on why not.
+fn drive_status(path: &Path) -> Option<String> {
+    match fs::read_dir(path) {
+        Ok(_) => None,
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Some("access denied".to_string()),
+        // ERROR_NOT_READY: removable media missing or the device is offline
+        #[cfg(target_os = "windows")]
+        Err(e) if e.raw_os_error() == Some(21) => Some("not ready".to_string()),
+        Err(e) => Some(e.to_string()),
+    }
 }
 
 #[derive(Clone, serde::Serialize)]
 struct ScanProgress {
-    files_scanned: usize,
-    current_path: String,
+    /// The same running totals the library scan reports.
+    #[serde(flatten)]
+    progress: drive_pulse_lib::ScanProgress,
+    /// Seconds left, estimated from the previous scan of the same drive.
+    eta_seconds: Option<u64>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct SaveProgress {
+    bytes_written: u64,
+    total_bytes: u64,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct VerifyProgress {
+    checked: usize,
+    total: usize,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct ScanComplete {
+    snapshot_id: String,
+    total_files: usize,
     total_size: u64,
+    scan_errors_count: usize,
+    scan_errors: Vec<String>,
+    file_count: usize,
+    dir_count: usize,
+    files_per_second: f64,
+    size_histogram: Vec<SizeBucket>,
 }
 
+// How many error messages are sent back with the scan summary
+const SCAN_ERROR_SAMPLE_SIZE: usize = 10;
+
 #[tauri::command]
 pub fn get_available_drives() -> Result<Vec<DriveInfo>, String> {
     let mut drives = Vec::new();
     
     #[cfg(target_os = "windows")]
     {
-        // On Windows, check drives A-Z
+        // On Windows, check drives A-Z. A letter in use but not readable (an
+        // empty card reader, say) is still listed, with its status.
         for letter in b'A'..=b'Z' {
             let drive_path = format!("{}:\\", letter as char);
-            if Path::new(&drive_path).exists() {
-                let label = format!("{}: Drive", letter as char);
-                drives.push(DriveInfo {
-                    path: drive_path,
-                    label,
-                });
+            match fs::metadata(&drive_path) {
+                // ERROR_INVALID_DRIVE: the letter isn't assigned
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound || e.raw_os_error() == Some(15) => continue,
+                _ => {}
             }
+            let label = format!("{}: Drive", letter as char);
+            drives.push(DriveInfo {
+                status: drive_status(Path::new(&drive_path)),
+                path: drive_path,
+                label,
+            });
         }
     }
     
@@ -53,6 +96,7 @@ pub fn get_available_drives() -> Result<Vec<DriveInfo>, String> {
                     if let Ok(name) = entry.file_name().into_string() {
                         let full_path = format!("/Volumes/{}", name);
                         drives.push(DriveInfo {
+                            status: drive_status(Path::new(&full_path)),
                             path: full_path.clone(),
                             label: name,
                         });
@@ -68,6 +112,7 @@ pub fn get_available_drives() -> Result<Vec<DriveInfo>, String> {
         drives.push(DriveInfo {
             path: "/".to_string(),
             label: "Root (/)".to_string(),
+            status: drive_status(Path::new("/")),
         });
         
         let media_path = Path::new("/media");
@@ -77,6 +122,7 @@ pub fn get_available_drives() -> Result<Vec<DriveInfo>, String> {
                     if let Ok(name) = entry.file_name().into_string() {
                         let full_path = format!("/media/{}", name);
                         drives.push(DriveInfo {
+                            status: drive_status(Path::new(&full_path)),
                             path: full_path.clone(),
                             label: format!("Media: {}", name),
                         });
@@ -92,6 +138,7 @@ pub fn get_available_drives() -> Result<Vec<DriveInfo>, String> {
                     if let Ok(name) = entry.file_name().into_string() {
                         let full_path = format!("/mnt/{}", name);
                         drives.push(DriveInfo {
+                            status: drive_status(Path::new(&full_path)),
                             path: full_path.clone(),
                             label: format!("Mount: {}", name),
                         });
@@ -104,12 +151,28 @@ pub fn get_available_drives() -> Result<Vec<DriveInfo>, String> {
     Ok(drives)
 }
 
+/// The frontend's scan `options`, or the defaults when omitted, checked for
+/// use by `scan_drive`. `ScanOptions::max_entries_in_memory` is refused
+/// there: the histogram sent with `scan-complete` needs every entry.
+fn frontend_scan_options(options: Option<ScanOptions>) -> Result<ScanOptions, String> {
+    let options = options.unwrap_or_default();
+    options.validate()?;
+    if options.max_entries_in_memory.is_some() {
+        return Err("max_entries_in_memory is not supported when scanning from the app".to_string());
+    }
+    Ok(options)
+}
+
+/// Scans `drive_path` with the frontend's `options` (the defaults when
+/// omitted), emitting `scan-progress` events, then saves the snapshot.
 #[tauri::command]
-pub async fn scan_drive(drive_path: String, encrypt: bool, password: Option<String>, window: Window) -> Result<Snapshot, String> {
+pub async fn scan_drive(drive_path: String, encrypt: bool, password: Option<String>, options: Option<ScanOptions>, window: Window) -> Result<Snapshot, String> {
     // Validate encryption parameters
     if encrypt && password.is_none() {
         return Err("Password required for encryption".to_string());
     }
+    let options = frontend_scan_options(options)?;
+    let data_dir = drive_pulse_lib::get_data_dir()?;
     
     // Run the blocking scan operation in a separate thread
     let drive_path_clone = drive_path.clone();
@@ -118,89 +181,57 @@ pub async fn scan_drive(drive_path: String, encrypt: bool, password: Option<Stri
     tokio::task::spawn_blocking(move || {
         println!("[RUST] Starting scan of: {}", drive_path_clone);
         let scan_start = std::time::Instant::now();
-        let mut files = Vec::new();
-        let mut total_size: u64 = 0;
-        let mut progress_counter = 0;
-
-        // Walk through the directory
-        for entry in WalkDir::new(&drive_path_clone)
-            .follow_links(false)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            let path = entry.path();
-            let metadata = match entry.metadata() {
-                Ok(m) => m,
-                Err(_) => continue,
-            };
-
-            let size = metadata.len();
-            let modified = metadata
-                .modified()
-                .ok()
-                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                .map(|d| d.as_secs() as i64)
-                .unwrap_or(0);
-
-            let file_entry = FileEntry {
-                path: path.to_string_lossy().to_string(),
-                size,
-                modified,
-                is_dir: metadata.is_dir(),
-            };
-
-            if !metadata.is_dir() {
-                total_size += size;
-            }
+        let expected_files = drive_pulse_lib::latest_snapshot_for_drive(&data_dir, &drive_path_clone)
+            .ok()
+            .flatten()
+            .map(|previous| previous.total_files);
+        let mut eta = drive_pulse_lib::EtaEstimator::default();
 
-            files.push(file_entry);
-            
+        let snapshot = drive_pulse_lib::scan_drive_with_options(drive_path_clone.clone(), &options, |progress| {
             // Emit progress every 100 files to avoid overwhelming the frontend
-            progress_counter += 1;
-            if progress_counter % 100 == 0 {
+            if progress.files_scanned % 100 == 0 {
+                let remaining = eta.update(progress.files_scanned, expected_files, scan_start.elapsed());
                 let _ = window_clone.emit("scan-progress", ScanProgress {
-                    files_scanned: files.len(),
-                    current_path: path.to_string_lossy().to_string(),
-                    total_size,
+                    progress: progress.clone(),
+                    eta_seconds: remaining.map(|d| d.as_secs()),
                 });
             }
-        }
+        })?;
         
-        println!("[RUST] Scan completed! Files: {}, Size: {}", files.len(), total_size);
-        let scan_duration = scan_start.elapsed().as_secs();
-
-        let timestamp = chrono::Utc::now().timestamp();
-        let id = format!("{}_{}", timestamp, drive_path_clone.replace([':', '\\', '/'], "_"));
-
-        let snapshot = Snapshot {
-            id: id.clone(),
-            drive_path: drive_path_clone.clone(),
-            timestamp,
-            total_files: files.len(),
-            total_size,
-            scan_duration,
-            files,
-        };
+        println!("[RUST] Scan completed! Files: {}, Size: {}, Errors: {}", snapshot.total_files, snapshot.total_size, snapshot.scan_errors_count);
 
         println!("[RUST] Saving snapshot to disk...");
         // Save snapshot to disk with optional encryption
-        drive_pulse_lib::save_snapshot(&snapshot, encrypt, password.as_deref())?;
+        drive_pulse_lib::save_snapshot_with_progress(&data_dir, &snapshot, encrypt, password.as_deref(), |bytes_written, total_bytes| {
+            let _ = window_clone.emit("save-progress", SaveProgress {
+                bytes_written,
+                total_bytes,
+            });
+        })?;
         
         // Save metadata separately for fast history loading
-        drive_pulse_lib::save_snapshot_metadata(&snapshot)?;
+        drive_pulse_lib::save_snapshot_metadata(&data_dir, &snapshot)?;
         
         println!("[RUST] Snapshot saved successfully!");
 
+        // Precompute what the summary card charts, the entries themselves stay here
+        let dir_count = snapshot.files.iter().filter(|f| f.is_dir).count();
+        let size_histogram = drive_pulse_lib::size_histogram(&snapshot.files);
+
         // Return a lightweight summary instead of full snapshot to avoid IPC overflow
-        let summary = Snapshot {
-            id: snapshot.id,
-            drive_path: snapshot.drive_path,
-            timestamp: snapshot.timestamp,
-            total_files: snapshot.total_files,
-            total_size: snapshot.total_size,
-            scan_duration: snapshot.scan_duration,
-            files: Vec::new(), // Don't send millions of file entries over IPC
-        };
+        let summary = ipc_summary(snapshot);
+
+        let _ = window_clone.emit("scan-complete", ScanComplete {
+            snapshot_id: summary.id.clone(),
+            total_files: summary.total_files,
+            total_size: summary.total_size,
+            scan_errors_count: summary.scan_errors_count,
+            scan_errors: summary.scan_errors.clone(),
+            file_count: summary.total_files - dir_count,
+            dir_count,
+            files_per_second: summary.files_per_sec.unwrap_or_default(),
+            size_histogram,
+        });
         
         println!("[RUST] Returning summary to frontend");
         Ok(summary)
@@ -209,16 +240,117 @@ pub async fn scan_drive(drive_path: String, encrypt: bool, password: Option<Stri
     .map_err(|e| format!("Task join error: {}", e))?
 }
 
+/// `snapshot` without its entries and with only a sample of its scan errors,
+/// small enough to send back over IPC.
+fn ipc_summary(snapshot: Snapshot) -> Snapshot {
+    Snapshot {
+        format_version: snapshot.format_version,
+        id: snapshot.id,
+        drive_path: snapshot.drive_path,
+        timestamp: snapshot.timestamp,
+        total_files: snapshot.total_files,
+        total_size: snapshot.total_size,
+        scan_duration: snapshot.scan_duration,
+        files: Vec::new(), // Don't send millions of file entries over IPC
+        scan_errors_count: snapshot.scan_errors_count,
+        // Only a sample, the full list is available through get_scan_errors
+        scan_errors: snapshot.scan_errors.into_iter().take(SCAN_ERROR_SAMPLE_SIZE).collect(),
+        hard_link_count: snapshot.hard_link_count,
+        hard_link_bytes_saved: snapshot.hard_link_bytes_saved,
+        parent_id: snapshot.parent_id,
+        is_consistent: snapshot.is_consistent,
+        excluded_by_extension_count: snapshot.excluded_by_extension_count,
+        excluded_by_extension_bytes: snapshot.excluded_by_extension_bytes,
+        // Sized by the number of files, so it stays with the full snapshot
+        content_filter: None,
+        extracted_from: snapshot.extracted_from,
+        files_per_sec: snapshot.files_per_sec,
+        peak_memory_bytes: snapshot.peak_memory_bytes,
+        threads: snapshot.threads,
+    }
+}
+
 #[tauri::command]
-pub fn get_scan_history() -> Result<Vec<SnapshotSummary>, String> {
-    drive_pulse_lib::get_scan_history()
+pub fn get_scan_errors(snapshot_id: String, password: Option<String>) -> Result<Vec<String>, String> {
+    let data_dir = drive_pulse_lib::get_data_dir()?;
+    let snapshot = drive_pulse_lib::load_snapshot(&data_dir, &snapshot_id, password.as_deref())?;
+    Ok(snapshot.scan_errors)
 }
 
 #[tauri::command]
-pub fn compare_snapshots(snapshot1_id: String, snapshot2_id: String, password: Option<String>) -> Result<ComparisonResult, String> {
-    let snapshot1 = drive_pulse_lib::load_snapshot(&snapshot1_id, password.as_deref())?;
-    let snapshot2 = drive_pulse_lib::load_snapshot(&snapshot2_id, password.as_deref())?;
-    Ok(drive_pulse_lib::compare_snapshots(&snapshot1, &snapshot2))
+pub fn get_scan_history(check_paths: Option<bool>) -> Result<Vec<SnapshotSummary>, String> {
+    let data_dir = drive_pulse_lib::get_data_dir()?;
+    let mut history = drive_pulse_lib::get_scan_history(&data_dir)?;
+    if check_paths.unwrap_or(false) {
+        drive_pulse_lib::check_drive_paths(&mut history);
+    }
+    Ok(history)
+}
+
+/// Set by `cancel_compare` to stop the comparison in progress.
+static COMPARE_CANCEL: AtomicBool = AtomicBool::new(false);
+
+/// Each snapshot is loaded with its own password; only encrypted ones need one.
+#[tauri::command]
+pub async fn compare_snapshots(snapshot1_id: String, snapshot2_id: String, password1: Option<String>, password2: Option<String>) -> Result<ComparisonResult, String> {
+    COMPARE_CANCEL.store(false, Ordering::Relaxed);
+    let data_dir = drive_pulse_lib::get_data_dir()?;
+    // Off the main thread, so cancel_compare can be handled meanwhile
+    tokio::task::spawn_blocking(move || {
+        let options = DiffOptions::default();
+        // Reopening the same comparison is served from the cache
+        drive_pulse_lib::cached_comparison(&data_dir, &snapshot1_id, &snapshot2_id, &options, || {
+            let snapshot1 = drive_pulse_lib::load_snapshot(&data_dir, &snapshot1_id, password1.as_deref())?;
+            let snapshot2 = drive_pulse_lib::load_snapshot(&data_dir, &snapshot2_id, password2.as_deref())?;
+            drive_pulse_lib::compare_snapshots_cancellable(&snapshot1, &snapshot2, &options, &COMPARE_CANCEL)
+        })
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))?
+}
+
+/// Counts and byte totals only, for views that don't list the diffs.
+#[tauri::command]
+pub async fn compare_summary(snapshot1_id: String, snapshot2_id: String, password1: Option<String>, password2: Option<String>) -> Result<ComparisonSummary, String> {
+    let data_dir = drive_pulse_lib::get_data_dir()?;
+    tokio::task::spawn_blocking(move || {
+        let snapshot1 = drive_pulse_lib::load_snapshot(&data_dir, &snapshot1_id, password1.as_deref())?;
+        let snapshot2 = drive_pulse_lib::load_snapshot(&data_dir, &snapshot2_id, password2.as_deref())?;
+        Ok(drive_pulse_lib::compare_summary(&snapshot1, &snapshot2, &DiffOptions::default()))
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+pub fn cancel_compare() {
+    COMPARE_CANCEL.store(true, Ordering::Relaxed);
+}
+
+/// Set by `cancel_verify` to stop the verification in progress.
+static VERIFY_CANCEL: AtomicBool = AtomicBool::new(false);
+
+/// Re-hashes the scanned files, emitting `verify-progress` events.
+#[tauri::command]
+pub async fn verify_snapshot(snapshot_id: String, password: Option<String>, resume: Option<bool>, window: Window) -> Result<VerifyReport, String> {
+    VERIFY_CANCEL.store(false, Ordering::Relaxed);
+    let data_dir = drive_pulse_lib::get_data_dir()?;
+    tokio::task::spawn_blocking(move || {
+        let snapshot = drive_pulse_lib::load_snapshot(&data_dir, &snapshot_id, password.as_deref())?;
+        let mut throttle = ProgressThrottle::default();
+        drive_pulse_lib::verify_snapshot_hashes(&data_dir, &snapshot, resume.unwrap_or(false), &VERIFY_CANCEL, |checked, total| {
+            if throttle.ready(checked, total) {
+                let _ = window.emit("verify-progress", VerifyProgress { checked, total });
+            }
+        })
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+pub fn cancel_verify() {
+    VERIFY_CANCEL.store(true, Ordering::Relaxed);
 }
 
 #[tauri::command]
@@ -265,29 +397,146 @@ pub fn open_data_directory() -> Result<(), String> {
 }
 
 #[tauri::command]
-pub fn delete_snapshot(snapshot_id: String) -> Result<(), String> {
+pub fn open_snapshot_file(snapshot_id: String) -> Result<(), String> {
     let data_dir = drive_pulse_lib::get_data_dir()?;
-    let snapshots_dir = data_dir.join("snapshots");
-    let metadata_dir = data_dir.join("metadata");
+    let snapshot_path = drive_pulse_lib::snapshot_file_path(&data_dir, &snapshot_id)?;
     
-    // Try both .json and .bin extensions for snapshot
-    let json_path = snapshots_dir.join(format!("{}.json", snapshot_id));
-    let bin_path = snapshots_dir.join(format!("{}.bin", snapshot_id));
+    // Reveal the file itself where the file manager supports selecting it
+    #[cfg(target_os = "windows")]
+    {
+        std::process::Command::new("explorer")
+            .arg(format!("/select,{}", snapshot_path.display()))
+            .spawn()
+            .map_err(|e| format!("Failed to open explorer: {}", e))?;
+    }
     
-    // Delete metadata file
-    let metadata_path = metadata_dir.join(format!("{}.json", snapshot_id));
-
-    if json_path.exists() {
-        fs::remove_file(json_path).map_err(|e| e.to_string())?;
-    } else if bin_path.exists() {
-        fs::remove_file(bin_path).map_err(|e| e.to_string())?;
+    #[cfg(target_os = "macos")]
+    {
+        std::process::Command::new("open")
+            .arg("-R")
+            .arg(&snapshot_path)
+            .spawn()
+            .map_err(|e| format!("Failed to open finder: {}", e))?;
     }
     
-    // Also remove metadata file if it exists
-    if metadata_path.exists() {
-        fs::remove_file(metadata_path).map_err(|e| e.to_string())?;
+    #[cfg(target_os = "linux")]
+    {
+        // xdg-open has no way to select a file, so open its folder instead
+        let folder = snapshot_path.parent().unwrap_or(&snapshot_path);
+        std::process::Command::new("xdg-open")
+            .arg(folder)
+            .spawn()
+            .map_err(|e| format!("Failed to open file manager: {}", e))?;
     }
-
+    
     Ok(())
 }
 
+#[tauri::command]
+pub fn delete_snapshot(snapshot_id: String, force: Option<bool>) -> Result<(), String> {
+    let data_dir = drive_pulse_lib::get_data_dir()?;
+    drive_pulse_lib::delete_snapshot(&data_dir, &snapshot_id, force.unwrap_or(false))
+}
+
+/// What deleting the given scans would remove and free, without deleting.
+#[tauri::command]
+pub fn preview_delete_snapshots(snapshot_ids: Vec<String>, force: Option<bool>) -> Result<drive_pulse_lib::DeletePreview, String> {
+    let data_dir = drive_pulse_lib::get_data_dir()?;
+    drive_pulse_lib::preview_delete_snapshots_where(&data_dir, |scan| snapshot_ids.contains(&scan.id), force.unwrap_or(false))
+}
+
+#[tauri::command]
+pub fn set_snapshot_locked(snapshot_id: String, locked: bool) -> Result<(), String> {
+    let data_dir = drive_pulse_lib::get_data_dir()?;
+    drive_pulse_lib::set_snapshot_locked(&data_dir, &snapshot_id, locked)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_summary_keeps_the_error_count_and_a_sample() {
+        let errors: Vec<String> = (0..12).map(|i| format!("/data/locked{}: Permission denied", i)).collect();
+        let snapshot: Snapshot = serde_json::from_value(serde_json::json!({
+            "id": "scan",
+            "drive_path": "/data",
+            "timestamp": 1_704_067_200,
+            "total_files": 1,
+            "total_size": 10,
+            "scan_duration": 0,
+            "files": [{ "path": "/data/a.txt", "size": 10, "modified": 1_704_067_200, "is_dir": false }],
+            "scan_errors_count": errors.len(),
+            "scan_errors": errors,
+        }))
+        .unwrap();
+
+        let summary = ipc_summary(snapshot);
+        assert_eq!(summary.scan_errors_count, 12);
+        assert_eq!(summary.scan_errors.len(), SCAN_ERROR_SAMPLE_SIZE);
+        assert_eq!(summary.scan_errors[0], "/data/locked0: Permission denied");
+        assert!(summary.files.is_empty());
+    }
+
+    #[test]
+    fn scan_options_from_the_frontend_reach_the_scanner() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(dir.path().join("keep.txt"), b"kept").unwrap();
+        fs::write(dir.path().join("debug.log"), b"noise").unwrap();
+        // As sent by App.tsx: snake_case fields, the rest left out
+        let payload = serde_json::json!({ "hash_files": true, "exclude_extensions": ["log"], "max_size": null });
+        let options: Option<ScanOptions> = serde_json::from_value(payload).unwrap();
+        let options = frontend_scan_options(options).unwrap();
+
+        let drive_path = dir.path().to_string_lossy().to_string();
+        let snapshot = drive_pulse_lib::scan_drive_with_options(drive_path, &options, |_| {}).unwrap();
+        let files: Vec<_> = snapshot.files.iter().filter(|f| !f.is_dir).collect();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].path.ends_with("keep.txt"));
+        assert!(files[0].hash.is_some());
+        assert_eq!(snapshot.excluded_by_extension_count, 1);
+
+        // Omitting the options entirely scans with the defaults
+        let omitted: Option<ScanOptions> = serde_json::from_value(serde_json::Value::Null).unwrap();
+        assert!(omitted.is_none());
+        assert!(frontend_scan_options(omitted).unwrap().exclude_extensions.is_empty());
+
+        let globbed: ScanOptions = serde_json::from_value(serde_json::json!({ "exclude_extensions": ["*.log"] })).unwrap();
+        let error = globbed.validate().unwrap_err();
+        assert!(error.contains("*.log"), "{}", error);
+
+        // The histogram needs every entry, so spilling is refused rather than ignored
+        let spilled: Option<ScanOptions> = serde_json::from_value(serde_json::json!({ "max_entries_in_memory": 1000 })).unwrap();
+        let error = frontend_scan_options(spilled).unwrap_err();
+        assert!(error.contains("max_entries_in_memory"), "{}", error);
+    }
+
+    #[test]
+    fn unreadable_drives_are_listed_with_a_status() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert_eq!(drive_status(dir.path()), None);
+        assert!(drive_status(&dir.path().join("unplugged")).is_some());
+        fs::write(dir.path().join("not-a-dir"), b"").unwrap();
+        assert!(drive_status(&dir.path().join("not-a-dir")).is_some());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let locked = dir.path().join("locked");
+            fs::create_dir(&locked).unwrap();
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+            // Root can list it anyway
+            if fs::read_dir(&locked).is_err() {
+                assert_eq!(drive_status(&locked).as_deref(), Some("access denied"));
+            }
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        // Listing never fails, whatever state each drive is in
+        let drives = get_available_drives().unwrap();
+        #[cfg(target_os = "linux")]
+        assert!(drives.iter().any(|drive| drive.path == "/"));
+        assert!(drives.iter().all(|drive| drive.status.as_deref() != Some("")));
+    }
+}
@@ -0,0 +1,1520 @@
+//! Diffing two snapshots, and the on-disk cache of comparison results.
+
+use crate::snapshot::{FileEntry, Snapshot, SnapshotSummary};
+use crate::storage::{is_snapshot_encrypted, load_snapshot, snapshot_file_path};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use sha2::{Sha256, Digest};
+use std::time;
+use std::sync::atomic::{AtomicBool, Ordering};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileDiff {
+    pub path: String,
+    pub status: DiffStatus,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+    pub old_modified: Option<i64>,
+    pub new_modified: Option<i64>,
+    /// `FileEntry::raw_path` of the entry, when its name isn't valid Unicode.
+    #[serde(default)]
+    pub raw_path: Option<String>,
+    /// What differs about a `Modified` path. All false for other statuses and
+    /// for directories reported by `DiffOptions::propagate_dir_changes`.
+    #[serde(default)]
+    pub size_changed: bool,
+    #[serde(default)]
+    pub mtime_changed: bool,
+    /// The hashes differ, or the sizes do. Without hashes on both sides an
+    /// edit that keeps the size isn't detected as such.
+    #[serde(default)]
+    pub content_changed: bool,
+    /// The permission bits differ; only known when both scans recorded them.
+    #[serde(default)]
+    pub perm_changed: bool,
+    /// The extended attributes differ, with `DiffOptions::compare_xattrs` and
+    /// both scans having recorded them.
+    #[serde(default)]
+    pub xattrs_changed: bool,
+}
+
+impl FileDiff {
+    /// Names of what differs (`content`, `size`, `mtime`, `perm`, `xattrs`),
+    /// for labels.
+    pub fn change_reasons(&self) -> Vec<&'static str> {
+        [
+            (self.content_changed, "content"),
+            (self.size_changed, "size"),
+            (self.mtime_changed, "mtime"),
+            (self.perm_changed, "perm"),
+            (self.xattrs_changed, "xattrs"),
+        ]
+        .into_iter()
+        .filter(|(changed, _)| *changed)
+        .map(|(_, name)| name)
+        .collect()
+    }
+}
+
+/// What differs between two entries matched across snapshots.
+#[derive(Default)]
+struct ChangeReasons {
+    size: bool,
+    mtime: bool,
+    content: bool,
+    perm: bool,
+    xattrs: bool,
+}
+
+impl ChangeReasons {
+    fn between(file1: &FileEntry, file2: &FileEntry, compare_xattrs: bool) -> ChangeReasons {
+        let size = file1.size != file2.size;
+        let hashes_differ = match (&file1.hash, &file2.hash) {
+            (Some(hash1), Some(hash2)) => !hash1.eq_ignore_ascii_case(hash2),
+            _ => false,
+        };
+        ChangeReasons {
+            size,
+            mtime: file1.modified != file2.modified,
+            // Directories have no content of their own, their size is what's in them
+            content: !file1.is_dir && (size || hashes_differ),
+            perm: matches!((file1.mode, file2.mode), (Some(mode1), Some(mode2)) if mode1 != mode2),
+            xattrs: compare_xattrs
+                && matches!((&file1.xattrs, &file2.xattrs), (Some(xattrs1), Some(xattrs2)) if xattrs1 != xattrs2),
+        }
+    }
+
+    fn any(&self) -> bool {
+        self.size || self.mtime || self.content || self.perm || self.xattrs
+    }
+}
+
+/// Ordered added, deleted, modified, unchanged, which is also the grouping
+/// order of `compare_snapshots` results.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffStatus {
+    Added,
+    Deleted,
+    Modified,
+    /// The path changed kind, e.g. a file became a directory or a symlink.
+    #[serde(rename = "type_changed")]
+    TypeChanged,
+    Unchanged,
+}
+
+impl DiffStatus {
+    /// Canonical lowercase name, matching the serialized form.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiffStatus::Added => "added",
+            DiffStatus::Deleted => "deleted",
+            DiffStatus::Modified => "modified",
+            DiffStatus::TypeChanged => "type_changed",
+            DiffStatus::Unchanged => "unchanged",
+        }
+    }
+
+    /// The status named `name` as `as_str` writes it, in any case.
+    pub fn parse(name: &str) -> Option<DiffStatus> {
+        [DiffStatus::Added, DiffStatus::Deleted, DiffStatus::Modified, DiffStatus::TypeChanged, DiffStatus::Unchanged]
+            .into_iter()
+            .find(|status| status.as_str().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ComparisonResult {
+    pub snapshot1: SnapshotSummary,
+    pub snapshot2: SnapshotSummary,
+    pub diffs: Vec<FileDiff>,
+    pub added_count: usize,
+    pub deleted_count: usize,
+    pub modified_count: usize,
+    /// Paths present in both snapshots that were not modified. Always counted,
+    /// even when the unchanged diffs themselves aren't included.
+    #[serde(default)]
+    pub unchanged_count: usize,
+    #[serde(default)]
+    pub type_changed_count: usize,
+    /// Directories reported as `Modified` only because something below them
+    /// changed, see `DiffOptions::propagate_dir_changes`.
+    #[serde(default)]
+    pub propagated_dir_count: usize,
+}
+
+impl ComparisonResult {
+    pub fn added(&self) -> impl Iterator<Item = &FileDiff> {
+        self.with_status(DiffStatus::Added)
+    }
+
+    pub fn deleted(&self) -> impl Iterator<Item = &FileDiff> {
+        self.with_status(DiffStatus::Deleted)
+    }
+
+    pub fn modified(&self) -> impl Iterator<Item = &FileDiff> {
+        self.with_status(DiffStatus::Modified)
+    }
+
+    /// How many paths have `status`, from the counts rather than `diffs`, so it
+    /// holds even when the diffs were filtered or unchanged ones left out.
+    pub fn count(&self, status: DiffStatus) -> usize {
+        match status {
+            DiffStatus::Added => self.added_count,
+            DiffStatus::Deleted => self.deleted_count,
+            DiffStatus::Modified => self.modified_count,
+            DiffStatus::TypeChanged => self.type_changed_count,
+            DiffStatus::Unchanged => self.unchanged_count,
+        }
+    }
+
+    pub fn with_status(&self, status: DiffStatus) -> impl Iterator<Item = &FileDiff> {
+        self.diffs.iter().filter(move |diff| diff.status == status)
+    }
+
+    /// Orders the diffs by status, then by path, so exports are deterministic.
+    pub fn sort_by_status(&mut self) {
+        self.diffs.sort_by(|a, b| a.status.cmp(&b.status).then_with(|| a.path.cmp(&b.path)));
+    }
+}
+
+/// Renders the changes as `diff`-style lines sorted by path: `+` added, `-`
+/// deleted, `~` modified and `!` type changed, each followed by the size in
+/// bytes (`old -> new` for modified paths). Unchanged paths are left out.
+pub fn to_unified_text(comparison: &ComparisonResult) -> String {
+    let mut diffs: Vec<&FileDiff> = comparison.diffs.iter()
+        .filter(|diff| diff.status != DiffStatus::Unchanged)
+        .collect();
+    diffs.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let size = |size: Option<u64>| size.map_or_else(|| "?".to_string(), |size| size.to_string());
+    let mut text = String::new();
+    for diff in diffs {
+        let line = match diff.status {
+            DiffStatus::Added => format!("+ {} ({})", diff.path, size(diff.new_size)),
+            DiffStatus::Deleted => format!("- {} ({})", diff.path, size(diff.old_size)),
+            DiffStatus::Modified => format!("~ {} ({} -> {})", diff.path, size(diff.old_size), size(diff.new_size)),
+            DiffStatus::TypeChanged => format!("! {} ({} -> {})", diff.path, size(diff.old_size), size(diff.new_size)),
+            DiffStatus::Unchanged => continue,
+        };
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DiffOptions {
+    /// Only report paths that appeared or vanished; size and mtime changes are ignored.
+    #[serde(default)]
+    pub presence_only: bool,
+    /// When set, only paths with one of these extensions (case-insensitive, with
+    /// or without the leading dot) take part in the comparison.
+    #[serde(default)]
+    pub extensions: Option<Vec<String>>,
+    /// Also emit an `Unchanged` diff for every path present and identical in both
+    /// snapshots, for complete file-by-file audit exports.
+    #[serde(default)]
+    pub include_unchanged: bool,
+    /// Also report every directory (present in both snapshots) above a changed
+    /// path as `Modified`, for a folder-level changelog. These are counted in
+    /// `ComparisonResult::propagated_dir_count`, not `modified_count`.
+    #[serde(default)]
+    pub propagate_dir_changes: bool,
+    /// Match paths by their NFC form, so a name written precomposed on one
+    /// system and decomposed on another (as macOS does) is the same path
+    /// rather than an added/deleted pair. Case is still significant.
+    #[serde(default)]
+    pub unicode_normalize: bool,
+    /// Match files by content hash instead of path, so a moved or copied file
+    /// is `Unchanged` and only content missing from the other snapshot is
+    /// `Added` or `Deleted`. Entries without a hash (directories, files scanned
+    /// without `hash_files`) are left out of the comparison.
+    #[serde(default)]
+    pub match_by_content: bool,
+    /// Also report a path as `Modified` when its extended attributes differ.
+    /// Only paths with xattrs recorded in both snapshots (`ScanOptions::record_xattrs`)
+    /// can differ this way.
+    #[serde(default)]
+    pub compare_xattrs: bool,
+}
+
+impl DiffOptions {
+    /// What `file` is matched by across the two snapshots.
+    fn key<'a>(&self, file: &'a FileEntry) -> Cow<'a, str> {
+        if self.match_by_content {
+            if let Some(hash) = &file.hash {
+                return Cow::Borrowed(hash);
+            }
+        }
+        // Raw paths are hex of bytes that aren't UTF-8, so there is nothing to normalize
+        if self.unicode_normalize && file.raw_path.is_none() && !is_nfc(&file.path) {
+            Cow::Owned(file.path.nfc().collect())
+        } else {
+            Cow::Borrowed(file.path_key())
+        }
+    }
+
+    /// Whether `file` takes part in the comparison at all.
+    fn compares(&self, file: &FileEntry) -> bool {
+        self.includes(&file.path) && (!self.match_by_content || (!file.is_dir && file.hash.is_some()))
+    }
+
+    /// What makes two matched entries `Modified`. Files matched by content are
+    /// the same file as far as the comparison goes.
+    fn changes(&self, file1: &FileEntry, file2: &FileEntry) -> ChangeReasons {
+        if self.presence_only || self.match_by_content {
+            ChangeReasons::default()
+        } else {
+            ChangeReasons::between(file1, file2, self.compare_xattrs)
+        }
+    }
+
+    fn includes(&self, path: &str) -> bool {
+        match &self.extensions {
+            None => true,
+            Some(extensions) => Path::new(path)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .is_some_and(|ext| {
+                    extensions.iter().any(|wanted| wanted.trim_start_matches('.').to_lowercase() == ext)
+                }),
+        }
+    }
+}
+
+/// Computes the same diffs as `compare_snapshots` but hands each one to `sink`
+/// as soon as it is known instead of collecting them, so memory stays bounded
+/// by the two path maps rather than the size of the diff.
+pub fn compare_snapshots_streaming<F>(snapshot1: &Snapshot, snapshot2: &Snapshot, sink: F)
+where
+    F: FnMut(FileDiff),
+{
+    compare_snapshots_streaming_with_options(snapshot1, snapshot2, &DiffOptions::default(), sink)
+}
+
+pub fn compare_snapshots_streaming_with_options<F>(snapshot1: &Snapshot, snapshot2: &Snapshot, options: &DiffOptions, sink: F)
+where
+    F: FnMut(FileDiff),
+{
+    stream_diffs(snapshot1, snapshot2, options, None, sink);
+}
+
+/// Error returned by `compare_snapshots_cancellable` when it was cancelled.
+pub const CANCELLED: &str = "Cancelled";
+
+/// How many entries are processed between checks of the cancel flag.
+const CANCEL_CHECK_INTERVAL: usize = 4096;
+
+fn is_cancelled(cancel: Option<&AtomicBool>, processed: usize) -> bool {
+    processed.is_multiple_of(CANCEL_CHECK_INTERVAL) && cancel.is_some_and(|flag| flag.load(Ordering::Relaxed))
+}
+
+/// Feeds every difference to `sink`, stopping early and returning `false` if
+/// `cancel` gets set.
+fn stream_diffs<F>(snapshot1: &Snapshot, snapshot2: &Snapshot, options: &DiffOptions, cancel: Option<&AtomicBool>, mut sink: F) -> bool
+where
+    F: FnMut(FileDiff),
+{
+    let mut processed = 0;
+    let mut map1: HashMap<Cow<str>, &FileEntry> = HashMap::new();
+    for file in snapshot1.files.iter().filter(|f| options.compares(f)) {
+        if is_cancelled(cancel, processed) {
+            return false;
+        }
+        processed += 1;
+        map1.insert(options.key(file), file);
+    }
+    // Several files can share a key when matching by content, so both sides
+    // are walked file by file rather than key by key
+    let mut keys2: HashSet<Cow<str>> = HashSet::new();
+    for file2 in snapshot2.files.iter().filter(|f| options.compares(f)) {
+        if is_cancelled(cancel, processed) {
+            return false;
+        }
+        processed += 1;
+        let key = options.key(file2);
+        if let Some(file1) = map1.get(&key) {
+            let type_changed = kind_changed(file1, file2);
+            let changes = options.changes(file1, file2);
+            let changed = !type_changed && changes.any();
+            if type_changed || changed || options.include_unchanged {
+                let status = if type_changed {
+                    DiffStatus::TypeChanged
+                } else if changed {
+                    DiffStatus::Modified
+                } else {
+                    DiffStatus::Unchanged
+                };
+                let reasons = if status == DiffStatus::Modified { changes } else { ChangeReasons::default() };
+                sink(FileDiff {
+                    path: file2.path.clone(),
+                    status,
+                    old_size: Some(file1.size),
+                    new_size: Some(file2.size),
+                    old_modified: Some(file1.modified),
+                    new_modified: Some(file2.modified),
+                    raw_path: file2.raw_path.clone(),
+                    size_changed: reasons.size,
+                    mtime_changed: reasons.mtime,
+                    content_changed: reasons.content,
+                    perm_changed: reasons.perm,
+                    xattrs_changed: reasons.xattrs,
+                });
+            }
+        } else {
+            sink(FileDiff {
+                path: file2.path.clone(),
+                status: DiffStatus::Added,
+                old_size: None,
+                new_size: Some(file2.size),
+                old_modified: None,
+                new_modified: Some(file2.modified),
+                raw_path: file2.raw_path.clone(),
+                size_changed: false,
+                mtime_changed: false,
+                content_changed: false,
+                perm_changed: false,
+                xattrs_changed: false,
+            });
+        }
+        keys2.insert(key);
+    }
+    for file1 in snapshot1.files.iter().filter(|f| options.compares(f)) {
+        if is_cancelled(cancel, processed) {
+            return false;
+        }
+        processed += 1;
+        if !keys2.contains(&options.key(file1)) {
+            sink(FileDiff {
+                path: file1.path.clone(),
+                status: DiffStatus::Deleted,
+                old_size: Some(file1.size),
+                new_size: None,
+                old_modified: Some(file1.modified),
+                new_modified: None,
+                raw_path: file1.raw_path.clone(),
+                size_changed: false,
+                mtime_changed: false,
+                content_changed: false,
+                perm_changed: false,
+                xattrs_changed: false,
+            });
+        }
+    }
+    true
+}
+
+pub fn compare_snapshots(snapshot1: &Snapshot, snapshot2: &Snapshot) -> ComparisonResult {
+    compare_snapshots_with_options(snapshot1, snapshot2, &DiffOptions::default())
+}
+
+pub fn compare_snapshots_with_options(snapshot1: &Snapshot, snapshot2: &Snapshot, options: &DiffOptions) -> ComparisonResult {
+    build_comparison(snapshot1, snapshot2, options, None).expect("comparison without a cancel flag always completes")
+}
+
+/// Like `compare_snapshots_with_options`, giving up with `CANCELLED` as soon
+/// as `cancel` is set. Nothing of a cancelled comparison is returned.
+pub fn compare_snapshots_cancellable(snapshot1: &Snapshot, snapshot2: &Snapshot, options: &DiffOptions, cancel: &AtomicBool) -> Result<ComparisonResult, String> {
+    build_comparison(snapshot1, snapshot2, options, Some(cancel)).ok_or_else(|| CANCELLED.to_string())
+}
+
+// How the snapshots were stored isn't known here, so `encrypted` stays false
+fn comparison_side(snapshot: &Snapshot) -> SnapshotSummary {
+    SnapshotSummary {
+        id: snapshot.id.clone(),
+        drive_path: snapshot.drive_path.clone(),
+        timestamp: snapshot.timestamp,
+        total_files: snapshot.total_files,
+        total_size: snapshot.total_size,
+        scan_duration: snapshot.scan_duration,
+        encrypted: false,
+        parent_id: snapshot.parent_id.clone(),
+        compressed: false,
+        format_version: Some(snapshot.format_version),
+        drive_present: None,
+        locked: false,
+        files_per_sec: snapshot.files_per_sec,
+        peak_memory_bytes: snapshot.peak_memory_bytes,
+        threads: snapshot.threads,
+    }
+}
+
+/// Whether a path changed kind between two scans, e.g. a file became a
+/// directory or a symlink. Kinds only recorded on one side aren't compared
+/// beyond `is_dir`, so an older scan doesn't flag every symlink.
+fn kind_changed(file1: &FileEntry, file2: &FileEntry) -> bool {
+    match (file1.file_kind, file2.file_kind) {
+        (Some(kind1), Some(kind2)) => kind1 != kind2,
+        _ => file1.is_dir != file2.is_dir,
+    }
+}
+
+fn build_comparison(snapshot1: &Snapshot, snapshot2: &Snapshot, options: &DiffOptions, cancel: Option<&AtomicBool>) -> Option<ComparisonResult> {
+    let mut added = Vec::new();
+    let mut deleted = Vec::new();
+    let mut modified = Vec::new();
+    let mut type_changed = Vec::new();
+    let mut unchanged = Vec::new();
+    let completed = stream_diffs(snapshot1, snapshot2, options, cancel, |diff| match diff.status {
+        DiffStatus::Added => added.push(diff),
+        DiffStatus::Deleted => deleted.push(diff),
+        DiffStatus::Modified => modified.push(diff),
+        DiffStatus::TypeChanged => type_changed.push(diff),
+        DiffStatus::Unchanged => unchanged.push(diff),
+    });
+    if !completed {
+        return None;
+    }
+    let added_count = added.len();
+    let deleted_count = deleted.len();
+    let modified_count = modified.len();
+    let type_changed_count = type_changed.len();
+    let propagated = if options.propagate_dir_changes {
+        let changed: Vec<&FileDiff> = added.iter().chain(&deleted).chain(&modified).chain(&type_changed).collect();
+        propagated_dir_diffs(snapshot1, snapshot2, &changed)
+    } else {
+        Vec::new()
+    };
+    let propagated_dir_count = propagated.len();
+    // Every path of the second snapshot is either added, modified, type changed or unchanged
+    let compared_files = snapshot2.files.iter().filter(|f| options.compares(f)).count();
+    // Folders are never compared when matching by content, so none was counted above
+    let propagated_compared = if options.match_by_content {
+        0
+    } else {
+        propagated.iter().filter(|diff| options.includes(&diff.path)).count()
+    };
+    let unchanged_count = compared_files.saturating_sub(added_count + modified_count + type_changed_count + propagated_compared);
+    if !propagated.is_empty() {
+        let propagated_paths: HashSet<&str> = propagated.iter().map(|diff| diff.path.as_str()).collect();
+        unchanged.retain(|diff| !propagated_paths.contains(diff.path.as_str()));
+        modified.extend(propagated);
+    }
+
+    Some(ComparisonResult {
+        snapshot1: comparison_side(snapshot1),
+        snapshot2: comparison_side(snapshot2),
+        diffs: added.into_iter().chain(deleted).chain(modified).chain(type_changed).chain(unchanged).collect(),
+        added_count,
+        deleted_count,
+        modified_count,
+        unchanged_count,
+        type_changed_count,
+        propagated_dir_count,
+    })
+}
+
+/// `Modified` diffs for the directories above `changed` paths that exist in
+/// both snapshots and aren't already reported themselves.
+fn propagated_dir_diffs(snapshot1: &Snapshot, snapshot2: &Snapshot, changed: &[&FileDiff]) -> Vec<FileDiff> {
+    let changed: Vec<&str> = changed.iter().map(|diff| diff.path.as_str()).collect();
+    propagated_dirs(snapshot1, snapshot2, &changed)
+        .into_iter()
+        .map(|(old, new)| FileDiff {
+            path: new.path.clone(),
+            status: DiffStatus::Modified,
+            old_size: Some(old.size),
+            new_size: Some(new.size),
+            old_modified: Some(old.modified),
+            new_modified: Some(new.modified),
+            raw_path: None,
+            size_changed: false,
+            mtime_changed: false,
+            content_changed: false,
+            perm_changed: false,
+            xattrs_changed: false,
+        })
+        .collect()
+}
+
+/// The directories above `changed` paths that exist in both snapshots and
+/// aren't changed paths themselves, as their entries before and after.
+fn propagated_dirs<'a>(snapshot1: &'a Snapshot, snapshot2: &'a Snapshot, changed: &[&str]) -> Vec<(&'a FileEntry, &'a FileEntry)> {
+    let dirs = |snapshot: &'a Snapshot| -> HashMap<&'a str, &'a FileEntry> {
+        snapshot.files.iter().filter(|f| f.is_dir).map(|f| (f.path.as_str(), f)).collect()
+    };
+    let dirs1 = dirs(snapshot1);
+    let dirs2 = dirs(snapshot2);
+    let reported: HashSet<&str> = changed.iter().copied().collect();
+    let mut marked: HashSet<String> = HashSet::new();
+    let mut propagated = Vec::new();
+    for path in changed {
+        for ancestor in Path::new(path).ancestors().skip(1) {
+            let ancestor = ancestor.to_string_lossy().to_string();
+            // Everything further up was handled along with this one
+            if marked.contains(&ancestor) {
+                break;
+            }
+            let (old, new) = match (dirs1.get(ancestor.as_str()), dirs2.get(ancestor.as_str())) {
+                (Some(old), Some(new)) => (*old, *new),
+                _ => continue,
+            };
+            if !reported.contains(ancestor.as_str()) {
+                propagated.push((old, new));
+            }
+            marked.insert(ancestor);
+        }
+    }
+    propagated
+}
+
+/// Counts and byte totals of a comparison, without the per-file diffs.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ComparisonSummary {
+    pub snapshot1: SnapshotSummary,
+    pub snapshot2: SnapshotSummary,
+    pub added_count: usize,
+    pub deleted_count: usize,
+    pub modified_count: usize,
+    pub type_changed_count: usize,
+    pub unchanged_count: usize,
+    /// See `ComparisonResult::propagated_dir_count`.
+    pub propagated_dir_count: usize,
+    pub added_bytes: u64,
+    pub deleted_bytes: u64,
+    /// Size of the modified files before and after, folders reported by
+    /// `DiffOptions::propagate_dir_changes` left out.
+    pub modified_old_bytes: u64,
+    pub modified_new_bytes: u64,
+}
+
+/// Same counts as `compare_snapshots_with_options`, plus byte totals, for
+/// callers that don't need the diffs themselves. No `FileDiff` is built, which
+/// keeps it cheap on huge snapshots and small over IPC. Propagated folders
+/// are counted as they are there; `DiffOptions::include_unchanged` only adds
+/// diffs, so it changes nothing here.
+pub fn compare_summary(snapshot1: &Snapshot, snapshot2: &Snapshot, options: &DiffOptions) -> ComparisonSummary {
+    let mut summary = ComparisonSummary {
+        snapshot1: comparison_side(snapshot1),
+        snapshot2: comparison_side(snapshot2),
+        added_count: 0,
+        deleted_count: 0,
+        modified_count: 0,
+        type_changed_count: 0,
+        unchanged_count: 0,
+        propagated_dir_count: 0,
+        added_bytes: 0,
+        deleted_bytes: 0,
+        modified_old_bytes: 0,
+        modified_new_bytes: 0,
+    };
+    // Paths of the changes, only gathered when the folders above them count
+    let mut changed: Vec<&str> = Vec::new();
+    let map1: HashMap<Cow<str>, &FileEntry> = snapshot1
+        .files
+        .iter()
+        .filter(|f| options.compares(f))
+        .map(|f| (options.key(f), f))
+        .collect();
+    let mut seen = 0;
+    for file2 in snapshot2.files.iter().filter(|f| options.compares(f)) {
+        match map1.get(&options.key(file2)) {
+            Some(file1) => {
+                seen += 1;
+                if kind_changed(file1, file2) {
+                    summary.type_changed_count += 1;
+                } else if options.changes(file1, file2).any() {
+                    summary.modified_count += 1;
+                    summary.modified_old_bytes += file1.size;
+                    summary.modified_new_bytes += file2.size;
+                } else {
+                    summary.unchanged_count += 1;
+                    continue;
+                }
+            }
+            None => {
+                summary.added_count += 1;
+                summary.added_bytes += file2.size;
+            }
+        }
+        if options.propagate_dir_changes {
+            changed.push(&file2.path);
+        }
+    }
+    // Whatever of the first snapshot wasn't matched above is deleted. Files
+    // sharing content make `seen` no measure of how much of it was matched.
+    if seen < map1.len() || options.match_by_content {
+        let keys2: HashSet<Cow<str>> = snapshot2.files.iter().filter(|f| options.compares(f)).map(|f| options.key(f)).collect();
+        for file1 in snapshot1.files.iter().filter(|f| options.compares(f)) {
+            if !keys2.contains(&options.key(file1)) {
+                summary.deleted_count += 1;
+                summary.deleted_bytes += file1.size;
+                if options.propagate_dir_changes {
+                    changed.push(&file1.path);
+                }
+            }
+        }
+    }
+    if options.propagate_dir_changes {
+        let propagated = propagated_dirs(snapshot1, snapshot2, &changed);
+        summary.propagated_dir_count = propagated.len();
+        // As in `build_comparison`, a propagated folder isn't unchanged
+        let propagated_compared = propagated.iter().filter(|(_, dir)| options.compares(dir)).count();
+        summary.unchanged_count = summary.unchanged_count.saturating_sub(propagated_compared);
+    }
+    summary
+}
+
+/// Bump when `ComparisonResult` changes shape, so older cache entries are
+/// recomputed instead of misread.
+const COMPARISON_CACHE_VERSION: u32 = 4;
+
+#[derive(Serialize, Deserialize)]
+struct CachedComparison {
+    version: u32,
+    /// Size and modification time of both snapshot files when cached.
+    fingerprint: String,
+    result: ComparisonResult,
+}
+
+fn comparison_cache_dir(data_dir: &Path) -> Result<PathBuf, String> {
+    Ok(data_dir.join("cache"))
+}
+
+fn comparison_cache_path(data_dir: &Path, snapshot1_id: &str, snapshot2_id: &str, options: &DiffOptions) -> Result<PathBuf, String> {
+    let options = serde_json::to_string(options).map_err(|e| format!("Failed to serialize diff options: {}", e))?;
+    let options_hash = format!("{:x}", Sha256::digest(options.as_bytes()));
+    Ok(comparison_cache_dir(data_dir)?.join(format!("{}-{}-{}.bin", snapshot1_id, snapshot2_id, &options_hash[..16])))
+}
+
+fn snapshot_fingerprint(data_dir: &Path, snapshot_id: &str) -> Result<String, String> {
+    let path = snapshot_file_path(data_dir, snapshot_id)?;
+    let metadata = fs::metadata(&path).map_err(|e| format!("Failed to read snapshot file: {}", e))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(time::SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_nanos());
+    Ok(format!("{}:{}:{}", snapshot_id, metadata.len(), modified))
+}
+
+/// `compare_snapshots_with_options` on two stored snapshots, reusing the
+/// result of an earlier identical comparison from the data directory's cache.
+/// Each snapshot gets its own password, which is ignored when it isn't
+/// encrypted, so a plaintext and an encrypted snapshot can be compared.
+pub fn compare_snapshots_cached(
+    data_dir: &Path,
+    snapshot1_id: &str,
+    snapshot2_id: &str,
+    options: &DiffOptions,
+    password1: Option<&str>,
+    password2: Option<&str>,
+) -> Result<ComparisonResult, String> {
+    cached_comparison(data_dir, snapshot1_id, snapshot2_id, options, || {
+        let snapshot1 = load_snapshot(data_dir, snapshot1_id, password1)?;
+        let snapshot2 = load_snapshot(data_dir, snapshot2_id, password2)?;
+        Ok(compare_snapshots_with_options(&snapshot1, &snapshot2, options))
+    })
+}
+
+/// Returns the cached comparison of two stored snapshots under `options`, or
+/// runs `compare` and caches what it returns. An entry is only used while both
+/// snapshot files keep the size and modification time they had when it was
+/// written. Comparisons involving an encrypted snapshot are never cached, as
+/// the cache would store their paths in the clear.
+pub fn cached_comparison<F>(data_dir: &Path, snapshot1_id: &str, snapshot2_id: &str, options: &DiffOptions, compare: F) -> Result<ComparisonResult, String>
+where
+    F: FnOnce() -> Result<ComparisonResult, String>,
+{
+    if is_snapshot_encrypted(data_dir, snapshot1_id)? || is_snapshot_encrypted(data_dir, snapshot2_id)? {
+        return compare();
+    }
+    let cache_path = comparison_cache_path(data_dir, snapshot1_id, snapshot2_id, options)?;
+    let fingerprint = format!("{}|{}", snapshot_fingerprint(data_dir, snapshot1_id)?, snapshot_fingerprint(data_dir, snapshot2_id)?);
+    // Anything unreadable is just a miss
+    if let Ok(data) = fs::read(&cache_path) {
+        if let Ok(cached) = bincode::deserialize::<CachedComparison>(&data) {
+            if cached.version == COMPARISON_CACHE_VERSION && cached.fingerprint == fingerprint {
+                return Ok(cached.result);
+            }
+        }
+    }
+    let result = compare()?;
+    let cached = CachedComparison { version: COMPARISON_CACHE_VERSION, fingerprint, result };
+    fs::create_dir_all(comparison_cache_dir(data_dir)?).map_err(|e| format!("Failed to create cache directory: {}", e))?;
+    let data = bincode::serialize(&cached).map_err(|e| format!("Failed to serialize comparison: {}", e))?;
+    fs::write(&cache_path, data).map_err(|e| format!("Failed to write comparison cache: {}", e))?;
+    Ok(cached.result)
+}
+
+/// Removes cached comparisons, all of them or only those involving `snapshot_id`.
+pub fn clear_comparison_cache(data_dir: &Path, snapshot_id: Option<&str>) -> Result<usize, String> {
+    let cache_dir = comparison_cache_dir(data_dir)?;
+    if !cache_dir.exists() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for entry in fs::read_dir(&cache_dir).map_err(|e| format!("Failed to read cache directory: {}", e))? {
+        let path = entry.map_err(|e| format!("Failed to read entry: {}", e))?.path();
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let matches = match snapshot_id {
+            Some(id) => name.split('-').take(2).any(|part| part == id),
+            None => true,
+        };
+        if matches {
+            fs::remove_file(&path).map_err(|e| format!("Failed to remove cache entry: {}", e))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Byte totals from `shared_data_estimate`. Each distinct content hash is
+/// counted once, so duplicates within one snapshot don't inflate the numbers.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SharedDataEstimate {
+    pub shared_bytes: u64,
+    pub unique_to_first_bytes: u64,
+    pub unique_to_second_bytes: u64,
+    pub shared_contents: usize,
+    /// Regular files skipped because they have no hash (scanned without
+    /// `hash_files`, or unreadable at the time).
+    pub unhashed_files: usize,
+}
+
+/// Estimates how much data two scans have in common by content rather than by
+/// path, for dedup planning: a file moved or copied between scans still counts
+/// as shared. Only regular files with a hash are considered.
+pub fn shared_data_estimate(snapshot1: &Snapshot, snapshot2: &Snapshot) -> SharedDataEstimate {
+    let mut estimate = SharedDataEstimate::default();
+    let mut contents = |snapshot: &Snapshot| -> HashMap<String, u64> {
+        let mut by_hash = HashMap::new();
+        for file in snapshot.files.iter().filter(|f| !f.is_dir) {
+            match &file.hash {
+                Some(hash) => {
+                    by_hash.insert(hash.clone(), file.size);
+                }
+                None => estimate.unhashed_files += 1,
+            }
+        }
+        by_hash
+    };
+    let first = contents(snapshot1);
+    let second = contents(snapshot2);
+    for (hash, size) in &first {
+        if second.contains_key(hash) {
+            estimate.shared_bytes += size;
+            estimate.shared_contents += 1;
+        } else {
+            estimate.unique_to_first_bytes += size;
+        }
+    }
+    estimate.unique_to_second_bytes = second
+        .iter()
+        .filter(|(hash, _)| !first.contains_key(*hash))
+        .map(|(_, size)| size)
+        .sum();
+    estimate
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DirectoryCountDiff {
+    pub path: String,
+    pub old_count: usize,
+    pub new_count: usize,
+}
+
+/// Directories present in both snapshots whose immediate child count changed.
+/// Only directories that have a recorded `child_count` on both sides are considered.
+pub fn compare_directory_counts(snapshot1: &Snapshot, snapshot2: &Snapshot) -> Vec<DirectoryCountDiff> {
+    let old_counts: HashMap<&str, usize> = snapshot1
+        .files
+        .iter()
+        .filter_map(|f| f.child_count.map(|count| (f.path.as_str(), count)))
+        .collect();
+    let mut diffs: Vec<DirectoryCountDiff> = snapshot2
+        .files
+        .iter()
+        .filter_map(|f| {
+            let new_count = f.child_count?;
+            let old_count = *old_counts.get(f.path.as_str())?;
+            (old_count != new_count).then(|| DirectoryCountDiff {
+                path: f.path.clone(),
+                old_count,
+                new_count,
+            })
+        })
+        .collect();
+    diffs.sort_by(|a, b| a.path.cmp(&b.path));
+    diffs
+}
+
+/// The changes below one directory of a comparison, see `aggregate_diffs_by_dir`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DirectoryChurn {
+    pub directory: String,
+    pub added_count: usize,
+    pub deleted_count: usize,
+    pub modified_count: usize,
+    pub type_changed_count: usize,
+    pub added_bytes: u64,
+    pub deleted_bytes: u64,
+    /// Net change in size of the modified and type-changed paths.
+    pub modified_bytes_delta: i64,
+}
+
+impl DirectoryChurn {
+    /// Net change in bytes below the directory.
+    pub fn size_delta(&self) -> i64 {
+        self.added_bytes as i64 - self.deleted_bytes as i64 + self.modified_bytes_delta
+    }
+}
+
+/// Totals the changed diffs of `comparison` by the directory `depth` levels
+/// below the scanned drive that contains them, sorted by directory. Paths
+/// less deep than that count towards their parent, so the drive itself has
+/// a row for the changes at its top. Every changed diff is counted exactly
+/// once, which includes directories reported through
+/// `DiffOptions::propagate_dir_changes`; unchanged diffs are left out.
+pub fn aggregate_diffs_by_dir(comparison: &ComparisonResult, depth: usize) -> Vec<DirectoryChurn> {
+    let roots = [Path::new(&comparison.snapshot2.drive_path), Path::new(&comparison.snapshot1.drive_path)];
+    let mut by_dir: HashMap<PathBuf, DirectoryChurn> = HashMap::new();
+    for diff in comparison.diffs.iter().filter(|diff| diff.status != DiffStatus::Unchanged) {
+        let path = Path::new(&diff.path);
+        let directory = match roots.iter().find_map(|root| path.strip_prefix(root).ok().map(|relative| (root, relative))) {
+            Some((root, relative)) => {
+                let levels = relative.components().count().saturating_sub(1).min(depth);
+                relative.components().take(levels).fold(root.to_path_buf(), |dir, part| dir.join(part))
+            }
+            None => path.parent().unwrap_or(path).to_path_buf(),
+        };
+        let churn = by_dir.entry(directory).or_default();
+        let old_size = diff.old_size.unwrap_or(0);
+        let new_size = diff.new_size.unwrap_or(0);
+        match diff.status {
+            DiffStatus::Added => {
+                churn.added_count += 1;
+                churn.added_bytes += new_size;
+            }
+            DiffStatus::Deleted => {
+                churn.deleted_count += 1;
+                churn.deleted_bytes += old_size;
+            }
+            DiffStatus::Modified => {
+                churn.modified_count += 1;
+                churn.modified_bytes_delta += new_size as i64 - old_size as i64;
+            }
+            DiffStatus::TypeChanged => {
+                churn.type_changed_count += 1;
+                churn.modified_bytes_delta += new_size as i64 - old_size as i64;
+            }
+            DiffStatus::Unchanged => {}
+        }
+    }
+    let mut churn: Vec<DirectoryChurn> = by_dir
+        .into_iter()
+        .map(|(directory, churn)| DirectoryChurn { directory: directory.to_string_lossy().to_string(), ..churn })
+        .collect();
+    churn.sort_by(|a, b| a.directory.cmp(&b.directory));
+    churn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{file_entry, fixture_dir, scan_dir, snapshot, FixtureSpec, TestDataDir};
+    use crate::scan::ScanOptions;
+    use crate::snapshot::SnapshotBuilder;
+    use crate::storage::save_snapshot;
+    use tempfile::TempDir;
+
+    /// A small tree scanned before and after deleting `d0/a.txt`, growing
+    /// `d1/b.txt` and adding `d1/new.txt`.
+    fn changed_tree() -> (TempDir, Snapshot, Snapshot) {
+        changed_tree_scanned_with(&ScanOptions::default())
+    }
+
+    fn changed_tree_scanned_with(options: &ScanOptions) -> (TempDir, Snapshot, Snapshot) {
+        let (dir, _) = fixture_dir(&FixtureSpec::files(&[("d0/a.txt", 8), ("d0/b.txt", 8), ("d1/a.txt", 8), ("d1/b.txt", 8)]));
+        let before = scan_dir(dir.path(), options);
+        fs::remove_file(dir.path().join("d0/a.txt")).unwrap();
+        fs::write(dir.path().join("d1/b.txt"), vec![0u8; 1000]).unwrap();
+        fs::write(dir.path().join("d1/new.txt"), b"new").unwrap();
+        let after = scan_dir(dir.path(), options);
+        (dir, before, after)
+    }
+
+    fn sorted_json(diffs: &[FileDiff]) -> Vec<String> {
+        let mut json: Vec<String> = diffs.iter().map(|diff| serde_json::to_string(diff).unwrap()).collect();
+        json.sort();
+        json
+    }
+
+    #[test]
+    fn streaming_yields_the_same_diffs_as_collecting() {
+        let _data_dir = TestDataDir::new();
+        let (_dir, before, after) = changed_tree();
+
+        let mut streamed = Vec::new();
+        compare_snapshots_streaming(&before, &after, |diff| streamed.push(diff));
+        let collected = compare_snapshots(&before, &after);
+
+        assert_eq!(streamed.len(), collected.diffs.len());
+        assert_eq!(sorted_json(&streamed), sorted_json(&collected.diffs));
+        assert_eq!(streamed.iter().filter(|diff| matches!(diff.status, DiffStatus::Added)).count(), collected.added_count);
+        assert_eq!(streamed.iter().filter(|diff| matches!(diff.status, DiffStatus::Deleted)).count(), collected.deleted_count);
+        assert_eq!(collected.added_count, 1);
+        assert_eq!(collected.deleted_count, 1);
+        assert!(collected.diffs.iter().any(|diff| diff.path.ends_with("b.txt") && matches!(diff.status, DiffStatus::Modified) && diff.new_size == Some(1000)));
+    }
+
+    #[test]
+    fn presence_only_ignores_size_changes_but_reports_deletions() {
+        let _data_dir = TestDataDir::new();
+        let (_dir, before, after) = changed_tree();
+        let options = DiffOptions { presence_only: true, ..DiffOptions::default() };
+        let result = compare_snapshots_with_options(&before, &after, &options);
+
+        assert_eq!(result.modified_count, 0);
+        assert!(!result.diffs.iter().any(|diff| matches!(diff.status, DiffStatus::Modified)));
+        assert!(result.diffs.iter().any(|diff| diff.path.ends_with("d0/a.txt") && matches!(diff.status, DiffStatus::Deleted)));
+        assert!(result.diffs.iter().any(|diff| diff.path.ends_with("new.txt") && matches!(diff.status, DiffStatus::Added)));
+        assert_eq!(result.diffs.len(), 2);
+
+        // The same change is reported without the option
+        assert!(compare_snapshots(&before, &after).modified_count > 0);
+    }
+
+    #[test]
+    fn grouped_iterators_partition_the_diffs() {
+        let _data_dir = TestDataDir::new();
+        let (_dir, before, after) = changed_tree();
+        let result = compare_snapshots(&before, &after);
+
+        let groups: Vec<Vec<&FileDiff>> = vec![
+            result.added().collect(),
+            result.deleted().collect(),
+            result.modified().collect(),
+            result.with_status(DiffStatus::Unchanged).collect(),
+        ];
+        assert_eq!(groups.iter().map(Vec::len).sum::<usize>(), result.diffs.len());
+        let mut grouped: Vec<&str> = groups.iter().flatten().map(|diff| diff.path.as_str()).collect();
+        let mut flat: Vec<&str> = result.diffs.iter().map(|diff| diff.path.as_str()).collect();
+        grouped.sort();
+        flat.sort();
+        assert_eq!(grouped, flat);
+
+        assert!(result.added().all(|diff| diff.status == DiffStatus::Added));
+        assert!(result.deleted().all(|diff| diff.status == DiffStatus::Deleted));
+        assert!(result.modified().all(|diff| diff.status == DiffStatus::Modified));
+        assert_eq!(result.added().count(), result.added_count);
+        assert_eq!(result.deleted().count(), result.deleted_count);
+        assert_eq!(result.modified().count(), result.modified_count);
+    }
+
+    #[test]
+    fn extension_filter_limits_diffs_and_counts() {
+        let _data_dir = TestDataDir::new();
+        let (dir, _) = fixture_dir(&FixtureSpec::files(&[("d0/a.dat", 8), ("d0/b.dat", 8), ("d0/notes.txt", 12), ("d1/c.dat", 8)]));
+        let before = scan_dir(dir.path(), &ScanOptions::default());
+        fs::remove_file(dir.path().join("d0/a.dat")).unwrap();
+        fs::write(dir.path().join("d1/c.dat"), vec![0u8; 1000]).unwrap();
+        fs::write(dir.path().join("d1/new.txt"), b"new").unwrap();
+        let after = scan_dir(dir.path(), &ScanOptions::default());
+
+        let options = DiffOptions { extensions: Some(vec![".DAT".to_string()]), ..DiffOptions::default() };
+        let result = compare_snapshots_with_options(&before, &after, &options);
+        assert!(result.diffs.iter().all(|diff| diff.path.ends_with(".dat")), "{:?}", result.diffs);
+        assert_eq!((result.added_count, result.deleted_count, result.modified_count), (0, 1, 1));
+        assert_eq!(result.deleted().count(), result.deleted_count);
+        assert_eq!(result.modified().count(), result.modified_count);
+
+        let txt_only = DiffOptions { extensions: Some(vec!["txt".to_string()]), ..DiffOptions::default() };
+        let result = compare_snapshots_with_options(&before, &after, &txt_only);
+        assert_eq!((result.added_count, result.deleted_count, result.modified_count), (1, 0, 0));
+    }
+
+    #[test]
+    fn child_counts_follow_added_and_removed_files() {
+        let _data_dir = TestDataDir::new();
+        let options = ScanOptions { record_child_counts: true, ..ScanOptions::default() };
+        let (dir, before, after) = changed_tree_scanned_with(&options);
+        let count_of = |snapshot: &Snapshot, relative: &str| {
+            let path = dir.path().join(relative).to_string_lossy().to_string();
+            snapshot.files.iter().find(|f| f.path == path).and_then(|f| f.child_count)
+        };
+        assert_eq!(count_of(&before, "d0"), Some(2));
+        assert_eq!(count_of(&after, "d0"), Some(1));
+        assert_eq!(count_of(&after, "d1"), Some(3));
+        assert_eq!(count_of(&after, "d1/new.txt"), None);
+
+        let changes: Vec<(String, usize, usize)> = compare_directory_counts(&before, &after)
+            .into_iter()
+            .map(|diff| (diff.path, diff.old_count, diff.new_count))
+            .collect();
+        let path = |relative: &str| dir.path().join(relative).to_string_lossy().to_string();
+        assert_eq!(changes, vec![(path("d0"), 2, 1), (path("d1"), 2, 3)]);
+
+        // Without recorded counts there is nothing to compare
+        let (_dir, before, after) = changed_tree();
+        assert!(compare_directory_counts(&before, &after).is_empty());
+    }
+
+    #[test]
+    fn counts_add_up_to_the_union_of_both_path_sets() {
+        let _data_dir = TestDataDir::new();
+        let (_dir, before, after) = changed_tree();
+        let union: HashSet<&str> = before.files.iter().chain(&after.files).map(|f| f.path.as_str()).collect();
+
+        for options in [DiffOptions::default(), DiffOptions { include_unchanged: true, ..DiffOptions::default() }] {
+            let result = compare_snapshots_with_options(&before, &after, &options);
+            let total = result.added_count + result.deleted_count + result.modified_count + result.type_changed_count + result.unchanged_count;
+            assert_eq!(total, union.len());
+            assert!(result.unchanged_count > 0);
+        }
+    }
+
+    #[test]
+    fn shared_data_counts_identical_contents_once_wherever_they_are() {
+        let sized = |path: &str, size: u64, hash: char| FileEntry { hash: Some(hash.to_string().repeat(64)), ..file_entry(path, size) };
+        let first = snapshot(
+            "first",
+            1_704_067_200,
+            vec![
+                sized("/data/a.bin", 100, 'a'),
+                sized("/data/copy-of-a.bin", 100, 'a'),
+                sized("/data/b.bin", 20, 'b'),
+                sized("/data/only-first.bin", 7, 'c'),
+                file_entry("/data/unhashed.bin", 1000),
+            ],
+        );
+        let second = snapshot(
+            "second",
+            1_704_070_800,
+            vec![
+                sized("/data/moved/a.bin", 100, 'a'),
+                sized("/data/b.bin", 20, 'b'),
+                sized("/data/only-second.bin", 3000, 'd'),
+            ],
+        );
+
+        let estimate = shared_data_estimate(&first, &second);
+        assert_eq!(estimate.shared_bytes, 120);
+        assert_eq!(estimate.shared_contents, 2);
+        assert_eq!(estimate.unique_to_first_bytes, 7);
+        assert_eq!(estimate.unique_to_second_bytes, 3000);
+        assert_eq!(estimate.unhashed_files, 1);
+    }
+
+    #[test]
+    fn cancelling_mid_comparison_stops_within_one_check_interval() {
+        let files = (0..CANCEL_CHECK_INTERVAL * 5).map(|i| file_entry(&format!("/data/f{:06}.dat", i), 1)).collect();
+        let empty = snapshot("empty", 1_704_067_200, Vec::new());
+        let full = snapshot("full", 1_704_070_800, files);
+
+        let cancel = AtomicBool::new(false);
+        let mut streamed = 0;
+        let completed = stream_diffs(&empty, &full, &DiffOptions::default(), Some(&cancel), |_| {
+            streamed += 1;
+            cancel.store(true, Ordering::Relaxed);
+        });
+        assert!(!completed);
+        assert!(streamed > 0 && streamed <= CANCEL_CHECK_INTERVAL, "{}", streamed);
+
+        // A set flag discards the whole result; an unset one changes nothing
+        assert_eq!(compare_snapshots_cancellable(&empty, &full, &DiffOptions::default(), &cancel).unwrap_err(), CANCELLED);
+        let result = compare_snapshots_cancellable(&empty, &full, &DiffOptions::default(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(result.added_count, full.files.len());
+    }
+
+    #[test]
+    fn summary_counts_match_the_full_comparison() {
+        let (_dir, before, after) = changed_tree();
+        for options in [
+            DiffOptions::default(),
+            DiffOptions { presence_only: true, ..DiffOptions::default() },
+            DiffOptions { extensions: Some(vec!["dat".to_string()]), ..DiffOptions::default() },
+            DiffOptions { include_unchanged: true, ..DiffOptions::default() },
+            DiffOptions { propagate_dir_changes: true, ..DiffOptions::default() },
+            DiffOptions { propagate_dir_changes: true, extensions: Some(vec!["txt".to_string()]), ..DiffOptions::default() },
+        ] {
+            let full = compare_snapshots_with_options(&before, &after, &options);
+            let summary = compare_summary(&before, &after, &options);
+            assert_eq!(
+                (summary.added_count, summary.deleted_count, summary.modified_count, summary.type_changed_count, summary.unchanged_count),
+                (full.added_count, full.deleted_count, full.modified_count, full.type_changed_count, full.unchanged_count),
+            );
+            assert_eq!(summary.propagated_dir_count, full.propagated_dir_count);
+            // The byte totals leave propagated folders out
+            let files_only = compare_snapshots_with_options(&before, &after, &DiffOptions { propagate_dir_changes: false, ..options });
+            let bytes = |status: DiffStatus, size: fn(&FileDiff) -> Option<u64>| files_only.with_status(status).filter_map(size).sum::<u64>();
+            assert_eq!(summary.added_bytes, bytes(DiffStatus::Added, |diff| diff.new_size));
+            assert_eq!(summary.deleted_bytes, bytes(DiffStatus::Deleted, |diff| diff.old_size));
+            assert_eq!(summary.modified_old_bytes, bytes(DiffStatus::Modified, |diff| diff.old_size));
+            assert_eq!(summary.modified_new_bytes, bytes(DiffStatus::Modified, |diff| diff.new_size));
+        }
+    }
+
+    #[test]
+    fn a_path_flipping_between_file_and_directory_is_type_changed() {
+        let dir = |path: &str| FileEntry { is_dir: true, ..file_entry(path, 0) };
+        let before = snapshot(
+            "before",
+            1_704_067_200,
+            vec![file_entry("/data/becomes-dir", 10), dir("/data/becomes-file"), file_entry("/data/same.txt", 1)],
+        );
+        let after = snapshot(
+            "after",
+            1_704_070_800,
+            vec![dir("/data/becomes-dir"), file_entry("/data/becomes-file", 10), file_entry("/data/same.txt", 1)],
+        );
+
+        let result = compare_snapshots(&before, &after);
+        assert_eq!(result.type_changed_count, 2);
+        assert_eq!((result.added_count, result.deleted_count, result.modified_count), (0, 0, 0));
+        let mut flipped: Vec<&str> = result.with_status(DiffStatus::TypeChanged).map(|diff| diff.path.as_str()).collect();
+        flipped.sort();
+        assert_eq!(flipped, vec!["/data/becomes-dir", "/data/becomes-file"]);
+        assert_eq!(compare_summary(&before, &after, &DiffOptions::default()).type_changed_count, 2);
+    }
+
+    #[test]
+    fn unified_text_prefixes_each_change_and_sorts_by_path() {
+        let dir = |path: &str| FileEntry { is_dir: true, ..file_entry(path, 0) };
+        let before = SnapshotBuilder::new("/data")
+            .files(vec![file_entry("/data/z-same.txt", 7), file_entry("/data/d", 4), file_entry("/data/c.txt", 1), file_entry("/data/a.txt", 10)])
+            .build();
+        let after = SnapshotBuilder::new("/data")
+            .files(vec![file_entry("/data/c.txt", 3), file_entry("/data/b.txt", 5), dir("/data/d"), file_entry("/data/z-same.txt", 7)])
+            .build();
+        let options = DiffOptions { include_unchanged: true, ..DiffOptions::default() };
+        let comparison = compare_snapshots_with_options(&before, &after, &options);
+
+        assert_eq!(
+            to_unified_text(&comparison),
+            "- /data/a.txt (10)\n+ /data/b.txt (5)\n~ /data/c.txt (1 -> 3)\n! /data/d (4 -> 0)\n"
+        );
+        let unchanged = SnapshotBuilder::new("/data").file(file_entry("/data/z-same.txt", 7)).build();
+        assert_eq!(to_unified_text(&compare_snapshots(&unchanged, &unchanged)), "");
+    }
+
+    #[test]
+    fn nfc_and_nfd_names_match_only_when_normalizing() {
+        let composed = "/data/caf\u{e9}.txt";
+        let decomposed = "/data/cafe\u{301}.txt";
+        assert_ne!(composed, decomposed);
+        let before = crate::snapshot::SnapshotBuilder::new("/data")
+            .files(vec![file_entry(composed, 10), file_entry("/data/Caf\u{e9}.txt", 5)])
+            .build();
+        let after = crate::snapshot::SnapshotBuilder::new("/data")
+            .files(vec![file_entry(decomposed, 10), file_entry("/data/Caf\u{e9}.txt", 5)])
+            .build();
+
+        let plain = compare_snapshots(&before, &after);
+        assert_eq!((plain.added_count, plain.deleted_count), (1, 1));
+        assert_eq!(plain.with_status(DiffStatus::Added).next().unwrap().path, decomposed);
+        assert_eq!(plain.with_status(DiffStatus::Deleted).next().unwrap().path, composed);
+
+        let options = DiffOptions { unicode_normalize: true, include_unchanged: true, ..DiffOptions::default() };
+        let normalized = compare_snapshots_with_options(&before, &after, &options);
+        assert_eq!((normalized.added_count, normalized.deleted_count, normalized.modified_count), (0, 0, 0));
+        // Case still tells names apart, so both paths are there, unchanged
+        assert_eq!(normalized.with_status(DiffStatus::Unchanged).count(), 2);
+        let summary = compare_summary(&before, &after, &options);
+        assert_eq!((summary.added_count, summary.deleted_count), (0, 0));
+    }
+
+    #[test]
+    fn matching_by_content_leaves_moved_files_unchanged() {
+        let with_hash = |path: &str, size: u64, hash: char| FileEntry { hash: Some(hash.to_string().repeat(64)), ..file_entry(path, size) };
+        let dir = |path: &str| FileEntry { is_dir: true, ..file_entry(path, 0) };
+        let before = crate::snapshot::SnapshotBuilder::new("/data")
+            .files(vec![
+                dir("/data/old"),
+                with_hash("/data/old/report.pdf", 100, 'a'),
+                with_hash("/data/notes.txt", 20, 'b'),
+                with_hash("/data/removed.bin", 7, 'c'),
+            ])
+            .build();
+        let after = crate::snapshot::SnapshotBuilder::new("/data")
+            .files(vec![
+                dir("/data/new"),
+                with_hash("/data/new/report.pdf", 100, 'a'),
+                with_hash("/data/renamed.txt", 20, 'b'),
+                with_hash("/data/copy-of-notes.txt", 20, 'b'),
+                with_hash("/data/fresh.bin", 3000, 'd'),
+            ])
+            .build();
+
+        let by_path = compare_snapshots(&before, &after);
+        assert_eq!((by_path.added_count, by_path.deleted_count), (5, 4));
+
+        let options = DiffOptions { match_by_content: true, include_unchanged: true, ..DiffOptions::default() };
+        let by_content = compare_snapshots_with_options(&before, &after, &options);
+        let paths = |status| {
+            let mut paths: Vec<&str> = by_content.with_status(status).map(|diff| diff.path.as_str()).collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(paths(DiffStatus::Added), vec!["/data/fresh.bin"]);
+        assert_eq!(paths(DiffStatus::Deleted), vec!["/data/removed.bin"]);
+        assert_eq!(paths(DiffStatus::Unchanged), vec!["/data/copy-of-notes.txt", "/data/new/report.pdf", "/data/renamed.txt"]);
+        assert_eq!((by_content.added_count, by_content.deleted_count, by_content.modified_count), (1, 1, 0));
+
+        let summary = compare_summary(&before, &after, &options);
+        assert_eq!((summary.added_count, summary.deleted_count, summary.modified_count), (1, 1, 0));
+    }
+
+    #[test]
+    fn propagating_folders_while_matching_by_content_keeps_every_file_counted() {
+        let with_hash = |path: &str, hash: char| FileEntry { hash: Some(hash.to_string().repeat(64)), ..file_entry(path, 10) };
+        let dir = |path: &str| FileEntry { is_dir: true, ..file_entry(path, 0) };
+        let before = crate::snapshot::SnapshotBuilder::new("/data")
+            .files(vec![dir("/data/docs"), with_hash("/data/docs/kept.txt", 'a'), with_hash("/data/docs/gone.txt", 'b')])
+            .build();
+        let after = crate::snapshot::SnapshotBuilder::new("/data")
+            .files(vec![dir("/data/docs"), with_hash("/data/docs/kept.txt", 'a'), with_hash("/data/docs/fresh.txt", 'c')])
+            .build();
+
+        let options = DiffOptions { match_by_content: true, propagate_dir_changes: true, ..DiffOptions::default() };
+        let result = compare_snapshots_with_options(&before, &after, &options);
+        assert_eq!(result.propagated_dir_count, 1);
+        // Both files of the second scan are either added or unchanged; the folder is neither
+        assert_eq!((result.added_count, result.deleted_count, result.unchanged_count), (1, 1, 1));
+        let summary = compare_summary(&before, &after, &options);
+        assert_eq!(
+            (summary.added_count, summary.deleted_count, summary.unchanged_count, summary.propagated_dir_count),
+            (1, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn modified_diffs_say_whether_permissions_or_content_changed() {
+        let entry = |path: &str, size: u64, hash: char, mode: Option<u32>| FileEntry {
+            hash: Some(hash.to_string().repeat(64)),
+            mode,
+            ..file_entry(path, size)
+        };
+        let before = crate::snapshot::SnapshotBuilder::new("/data")
+            .files(vec![
+                entry("/data/chmodded.sh", 10, 'a', Some(0o644)),
+                entry("/data/rewritten.txt", 10, 'b', Some(0o644)),
+                entry("/data/grown.log", 10, 'c', Some(0o644)),
+                entry("/data/untouched.txt", 10, 'd', Some(0o644)),
+                entry("/data/older-scan.txt", 10, 'e', None),
+            ])
+            .build();
+        let after = crate::snapshot::SnapshotBuilder::new("/data")
+            .files(vec![
+                entry("/data/chmodded.sh", 10, 'a', Some(0o755)),
+                entry("/data/rewritten.txt", 10, 'f', Some(0o644)),
+                FileEntry { modified: 1_800_000_000, ..entry("/data/grown.log", 20, '0', Some(0o644)) },
+                entry("/data/untouched.txt", 10, 'd', Some(0o644)),
+                entry("/data/older-scan.txt", 10, 'e', Some(0o600)),
+            ])
+            .build();
+
+        let comparison = compare_snapshots(&before, &after);
+        let reasons = |path: &str| comparison.diffs.iter().find(|diff| diff.path == path).map(FileDiff::change_reasons);
+        assert_eq!(reasons("/data/chmodded.sh"), Some(vec!["perm"]));
+        assert_eq!(reasons("/data/rewritten.txt"), Some(vec!["content"]));
+        assert_eq!(reasons("/data/grown.log"), Some(vec!["content", "size", "mtime"]));
+        // Unchanged, and a scan without permissions doesn't make every file perm-changed
+        assert_eq!(reasons("/data/untouched.txt"), None);
+        assert_eq!(reasons("/data/older-scan.txt"), None);
+        assert_eq!(comparison.modified_count, 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn changed_xattrs_are_reported_only_when_compared() {
+        let _data_dir = TestDataDir::new();
+        let dir = TempDir::new().unwrap();
+        let tagged = dir.path().join("tagged.txt");
+        fs::write(&tagged, b"same content").unwrap();
+        fs::write(dir.path().join("plain.txt"), b"untouched").unwrap();
+        // Not every filesystem a test runs on takes user attributes
+        if xattr::set(&tagged, "user.owner", b"alice").is_err() {
+            return;
+        }
+        xattr::set(&tagged, "user.acl", &[0x02, 0x00, 0xff]).unwrap();
+        let options = ScanOptions { record_xattrs: true, ..ScanOptions::default() };
+        let before = scan_dir(dir.path(), &options);
+        let recorded = before.files.iter().find(|f| f.path.ends_with("tagged.txt")).unwrap().xattrs.clone().unwrap();
+        assert_eq!(recorded["user.owner"], "alice");
+        assert_eq!(recorded["user.acl"], "hex:0200ff");
+
+        xattr::set(&tagged, "user.owner", b"bob").unwrap();
+        let after = scan_dir(dir.path(), &options);
+
+        let plain = compare_snapshots(&before, &after);
+        assert_eq!(plain.modified_count, 0);
+        let with_xattrs = DiffOptions { compare_xattrs: true, ..DiffOptions::default() };
+        let compared = compare_snapshots_with_options(&before, &after, &with_xattrs);
+        let modified: Vec<&FileDiff> = compared.with_status(DiffStatus::Modified).collect();
+        assert_eq!(modified.len(), 1);
+        assert!(modified[0].path.ends_with("tagged.txt"));
+        assert_eq!(modified[0].change_reasons(), vec!["xattrs"]);
+
+        // Scans without recorded attributes never differ in them
+        let unrecorded = scan_dir(dir.path(), &ScanOptions::default());
+        assert!(unrecorded.files.iter().all(|f| f.xattrs.is_none()));
+        let against_unrecorded = compare_snapshots_with_options(&before, &unrecorded, &with_xattrs);
+        assert!(against_unrecorded.diffs.iter().all(|diff| !diff.xattrs_changed));
+    }
+
+    #[test]
+    fn directory_cross_tab_totals_reconcile_with_the_flat_counts() {
+        let dir = |path: &str| FileEntry { is_dir: true, ..file_entry(path, 0) };
+        let before = crate::snapshot::SnapshotBuilder::new("/data")
+            .files(vec![
+                dir("/data/a"),
+                dir("/data/a/deep"),
+                file_entry("/data/a/deep/gone.txt", 100),
+                file_entry("/data/a/grows.txt", 10),
+                dir("/data/b"),
+                file_entry("/data/b/same.txt", 5),
+                file_entry("/data/top.txt", 1),
+                file_entry("/data/flips", 7),
+            ])
+            .build();
+        let after = crate::snapshot::SnapshotBuilder::new("/data")
+            .files(vec![
+                dir("/data/a"),
+                dir("/data/a/deep"),
+                file_entry("/data/a/deep/new.txt", 30),
+                file_entry("/data/a/grows.txt", 50),
+                dir("/data/b"),
+                file_entry("/data/b/same.txt", 5),
+                file_entry("/data/b/new.txt", 2),
+                dir("/data/flips"),
+            ])
+            .build();
+        let options = DiffOptions { include_unchanged: true, propagate_dir_changes: true, ..DiffOptions::default() };
+        let comparison = compare_snapshots_with_options(&before, &after, &options);
+        let changed: Vec<&FileDiff> = comparison.diffs.iter().filter(|diff| diff.status != DiffStatus::Unchanged).collect();
+        let flat_delta: i64 = changed.iter().map(|d| d.new_size.unwrap_or(0) as i64 - d.old_size.unwrap_or(0) as i64).sum();
+
+        for depth in [0, 1, 2, 5] {
+            let rows = aggregate_diffs_by_dir(&comparison, depth);
+            let total = |count: fn(&DirectoryChurn) -> usize| rows.iter().map(count).sum::<usize>();
+            assert_eq!(total(|row| row.added_count), changed.iter().filter(|d| d.status == DiffStatus::Added).count(), "depth {}", depth);
+            assert_eq!(total(|row| row.deleted_count), changed.iter().filter(|d| d.status == DiffStatus::Deleted).count());
+            assert_eq!(total(|row| row.modified_count), changed.iter().filter(|d| d.status == DiffStatus::Modified).count());
+            assert_eq!(total(|row| row.type_changed_count), comparison.type_changed_count);
+            assert_eq!(rows.iter().map(|row| row.size_delta()).sum::<i64>(), flat_delta, "depth {}", depth);
+            assert!(rows.windows(2).all(|pair| pair[0].directory < pair[1].directory));
+        }
+
+        let by_top: Vec<(String, usize, usize)> = aggregate_diffs_by_dir(&comparison, 1)
+            .into_iter()
+            .map(|row| (row.directory, row.added_count, row.deleted_count))
+            .collect();
+        assert_eq!(
+            by_top,
+            vec![("/data".to_string(), 0, 1), ("/data/a".to_string(), 1, 1), ("/data/b".to_string(), 1, 0)]
+        );
+    }
+
+    #[test]
+    fn cached_comparisons_are_reused_until_a_snapshot_changes() {
+        let data_dir = TestDataDir::new();
+        let save = |id: &str, files: Vec<FileEntry>| {
+            let snapshot = SnapshotBuilder::new("/data").id(id).timestamp(1_704_067_200).files(files).build();
+            save_snapshot(data_dir.path(), &snapshot, false, None).unwrap();
+        };
+        save("before", vec![file_entry("/data/a.txt", 1), file_entry("/data/gone.txt", 2)]);
+        save("after", vec![file_entry("/data/a.txt", 5), file_entry("/data/new.txt", 3)]);
+
+        let computed = std::cell::Cell::new(0);
+        let compare = || -> Result<ComparisonResult, String> {
+            computed.set(computed.get() + 1);
+            Ok(compare_snapshots(&load_snapshot(data_dir.path(), "before", None)?, &load_snapshot(data_dir.path(), "after", None)?))
+        };
+        let first = cached_comparison(data_dir.path(), "before", "after", &DiffOptions::default(), compare).unwrap();
+        let second = cached_comparison(data_dir.path(), "before", "after", &DiffOptions::default(), compare).unwrap();
+        assert_eq!(computed.get(), 1, "the second call is a cache hit");
+        assert_eq!(serde_json::to_value(&first).unwrap(), serde_json::to_value(&second).unwrap());
+        assert_eq!((second.added_count, second.deleted_count, second.modified_count), (1, 1, 1));
+
+        // Other options are cached separately
+        let presence_only = DiffOptions { presence_only: true, ..DiffOptions::default() };
+        cached_comparison(data_dir.path(), "before", "after", &presence_only, compare).unwrap();
+        assert_eq!(computed.get(), 2);
+
+        save("after", vec![file_entry("/data/a.txt", 1), file_entry("/data/gone.txt", 2), file_entry("/data/extra-file.txt", 9)]);
+        let refreshed = cached_comparison(data_dir.path(), "before", "after", &DiffOptions::default(), compare).unwrap();
+        assert_eq!(computed.get(), 3, "re-saving a snapshot invalidates its entries");
+        assert_eq!((refreshed.added_count, refreshed.deleted_count, refreshed.modified_count), (1, 0, 0));
+    }
+
+    #[test]
+    fn a_plaintext_scan_compares_with_an_encrypted_one_given_only_its_password() {
+        let data_dir = TestDataDir::new();
+        let plain = SnapshotBuilder::new("/data").id("plain").file(file_entry("/data/a.txt", 1)).build();
+        let secret = SnapshotBuilder::new("/data")
+            .id("secret")
+            .files(vec![file_entry("/data/a.txt", 1), file_entry("/data/new.txt", 2)])
+            .build();
+        save_snapshot(data_dir.path(), &plain, false, None).unwrap();
+        save_snapshot(data_dir.path(), &secret, true, Some("pw")).unwrap();
+        let options = DiffOptions::default();
+
+        let forward = compare_snapshots_cached(data_dir.path(), "plain", "secret", &options, None, Some("pw")).unwrap();
+        assert_eq!((forward.added_count, forward.deleted_count, forward.modified_count), (1, 0, 0));
+        let backward = compare_snapshots_cached(data_dir.path(), "secret", "plain", &options, Some("pw"), None).unwrap();
+        assert_eq!((backward.added_count, backward.deleted_count), (0, 1));
+        // A password for the plaintext side is simply unused
+        assert!(compare_snapshots_cached(data_dir.path(), "plain", "secret", &options, Some("pw"), Some("pw")).is_ok());
+
+        let wrong_side = compare_snapshots_cached(data_dir.path(), "plain", "secret", &options, Some("pw"), None).unwrap_err();
+        assert_eq!(wrong_side, crate::storage::PASSWORD_REQUIRED);
+    }
+
+    #[test]
+    fn one_changed_file_marks_every_ancestor_directory_modified() {
+        let dir = |path: &str| FileEntry { is_dir: true, ..file_entry(path, 0) };
+        let tree = |deep_size: u64| {
+            SnapshotBuilder::new("/data")
+                .files(vec![
+                    dir("/data"),
+                    dir("/data/a"),
+                    dir("/data/a/b"),
+                    file_entry("/data/a/b/deep.txt", deep_size),
+                    dir("/data/other"),
+                    file_entry("/data/other/same.txt", 1),
+                ])
+                .build()
+        };
+        let (before, after) = (tree(10), tree(20));
+
+        let options = DiffOptions { propagate_dir_changes: true, ..DiffOptions::default() };
+        let result = compare_snapshots_with_options(&before, &after, &options);
+        let mut modified: Vec<&str> = result.modified().map(|diff| diff.path.as_str()).collect();
+        modified.sort();
+        assert_eq!(modified, vec!["/data", "/data/a", "/data/a/b", "/data/a/b/deep.txt"]);
+        assert_eq!(result.modified_count, 1, "folders don't count as file changes");
+        assert_eq!(result.propagated_dir_count, 3);
+        assert_eq!(result.unchanged_count, 2);
+        let summary = compare_summary(&before, &after, &options);
+        assert_eq!((summary.modified_count, summary.propagated_dir_count, summary.unchanged_count), (1, 3, 2));
+        assert_eq!((summary.modified_old_bytes, summary.modified_new_bytes), (10, 20));
+
+        let plain = compare_snapshots(&before, &after);
+        assert_eq!(plain.modified().count(), 1);
+        assert_eq!(plain.unchanged_count, 5);
+    }
+
+    #[test]
+    fn including_unchanged_covers_the_union_of_both_snapshots() {
+        let old = snapshot("old", 1, vec![file_entry("/data/gone.txt", 1), file_entry("/data/same.txt", 2), file_entry("/data/grown.txt", 3)]);
+        let new = snapshot("new", 2, vec![file_entry("/data/same.txt", 2), file_entry("/data/grown.txt", 30), file_entry("/data/fresh.txt", 4)]);
+
+        let options = DiffOptions { include_unchanged: true, ..DiffOptions::default() };
+        let result = compare_snapshots_with_options(&old, &new, &options);
+        let mut paths: Vec<&str> = result.diffs.iter().map(|diff| diff.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, ["/data/fresh.txt", "/data/gone.txt", "/data/grown.txt", "/data/same.txt"]);
+        let same = result.diffs.iter().find(|diff| diff.path == "/data/same.txt").unwrap();
+        assert_eq!(same.status, DiffStatus::Unchanged);
+        assert_eq!((result.added_count, result.deleted_count, result.modified_count), (1, 1, 1));
+
+        // Without the flag only the changes are listed
+        assert_eq!(compare_snapshots(&old, &new).diffs.len(), 3);
+    }
+}
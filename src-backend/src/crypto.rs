@@ -0,0 +1,331 @@
+//! Password-based encryption of snapshot payloads with AES-256-GCM.
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use sha2::{Digest, Sha256};
+use std::io::{self, BufRead, Read, Write};
+
+/// Header of whole-blob encrypted snapshots: magic, per-file salt, then the
+/// AES-GCM nonce. Still read, no longer written.
+pub(crate) const ENCRYPTED_MAGIC: &[u8; 4] = b"DPE1";
+/// Header of chunk-framed encrypted snapshots: magic, per-file salt, then the
+/// nonce prefix shared by every chunk.
+pub(crate) const CHUNKED_MAGIC: &[u8; 4] = b"DPE2";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// Chunk nonces are this prefix, a big-endian chunk counter, and a final byte
+/// flagging the last chunk, so chunks can't be reordered, dropped or appended.
+const NONCE_PREFIX_LEN: usize = 7;
+/// Plaintext bytes sealed per chunk. Only one chunk is held in memory at a time
+/// while reading.
+const CHUNK_LEN: usize = 1024 * 1024;
+const TAG_LEN: usize = 16;
+
+pub(crate) fn derive_key(password: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(password.as_bytes());
+    let result = hasher.finalize();
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&result);
+    key
+}
+
+/// Derives a per-file subkey from the password and a random salt, so a nonce
+/// collision between two files encrypted with the same password is harmless.
+pub(crate) fn derive_file_key(password: &str, salt: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(derive_key(password));
+    hasher.update(salt);
+    let result = hasher.finalize();
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&result);
+    key
+}
+
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u32, last: bool) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    nonce
+}
+
+/// The salt and nonce (or nonce prefix) following the magic of an encrypted
+/// file, `None` for the legacy headerless layout, which has no salt.
+pub(crate) fn key_material(data: &[u8]) -> Option<&[u8]> {
+    let len = if data.starts_with(CHUNKED_MAGIC) {
+        SALT_LEN + NONCE_PREFIX_LEN
+    } else if data.starts_with(ENCRYPTED_MAGIC) {
+        SALT_LEN + NONCE_LEN
+    } else {
+        return None;
+    };
+    data.get(CHUNKED_MAGIC.len()..CHUNKED_MAGIC.len() + len)
+}
+
+/// Refuses a salt and nonce prefix identical to those of the file being
+/// replaced, see `key_material`. Astronomically unlikely with random values,
+/// but a broken RNG would otherwise reuse a nonce under the same key.
+fn ensure_fresh(salt: &[u8], nonce_prefix: &[u8], replaced: Option<&[u8]>) -> Result<(), String> {
+    let reused = replaced.is_some_and(|stored| stored.len() == salt.len() + nonce_prefix.len() && stored.starts_with(salt) && stored.ends_with(nonce_prefix));
+    if reused {
+        return Err("Refusing to encrypt with the salt and nonce of the snapshot being replaced".to_string());
+    }
+    Ok(())
+}
+
+/// Size of the chunk-framed file `ChunkedEncryptWriter` writes for
+/// `plaintext_len` bytes of plaintext.
+pub(crate) fn chunked_len(plaintext_len: u64) -> u64 {
+    let chunk_count = plaintext_len.div_ceil(CHUNK_LEN as u64).max(1);
+    (CHUNKED_MAGIC.len() + SALT_LEN + NONCE_PREFIX_LEN) as u64 + plaintext_len + chunk_count * TAG_LEN as u64
+}
+
+/// Encrypts `plaintext` in the chunk-framed layout, see `ChunkedEncryptWriter`.
+#[cfg(test)]
+pub(crate) fn encrypt_payload(plaintext: &[u8], password: &str, replaced: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    let capacity = chunked_len(plaintext.len() as u64) as usize;
+    let mut writer = ChunkedEncryptWriter::new(Vec::with_capacity(capacity), password, replaced)?;
+    writer.write_all(plaintext).map_err(|e| format!("Encryption failed: {}", e))?;
+    writer.finish().map_err(|e| format!("Encryption failed: {}", e))
+}
+
+/// Writes the chunk-framed layout to `inner`: the header, then each `CHUNK_LEN`
+/// piece of what is written sealed on its own, so a snapshot can be encrypted
+/// as it is serialized. Every file gets a fresh salt and so a fresh key, which
+/// keeps nonces from repeating across files. A full chunk is only sealed once
+/// more data follows, since the last chunk's nonce is flagged; `finish` seals
+/// what is left and must be called.
+pub(crate) struct ChunkedEncryptWriter<W> {
+    inner: W,
+    cipher: Aes256Gcm,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    counter: u32,
+    plaintext: Vec<u8>,
+}
+
+impl<W: Write> ChunkedEncryptWriter<W> {
+    /// Writes the header to `inner`. `replaced` is the `key_material` of the
+    /// file this one overwrites, if any, which must not be used again.
+    pub(crate) fn new(mut inner: W, password: &str, replaced: Option<&[u8]>) -> Result<Self, String> {
+        let salt: [u8; SALT_LEN] = rand::random();
+        let nonce_prefix: [u8; NONCE_PREFIX_LEN] = rand::random();
+        ensure_fresh(&salt, &nonce_prefix, replaced)?;
+        let key = derive_file_key(password, &salt);
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|e| format!("Failed to create cipher: {}", e))?;
+        let write_error = |e: io::Error| format!("Failed to write file: {}", e);
+        inner.write_all(CHUNKED_MAGIC).map_err(write_error)?;
+        inner.write_all(&salt).map_err(write_error)?;
+        inner.write_all(&nonce_prefix).map_err(write_error)?;
+        Ok(ChunkedEncryptWriter { inner, cipher, nonce_prefix, counter: 0, plaintext: Vec::with_capacity(CHUNK_LEN) })
+    }
+
+    fn seal_chunk(&mut self, last: bool) -> io::Result<()> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let nonce = chunk_nonce(&self.nonce_prefix, self.counter, last);
+        let sealed = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), self.plaintext.as_ref())
+            .map_err(|e| invalid(format!("Encryption failed: {}", e)))?;
+        self.inner.write_all(&sealed)?;
+        self.plaintext.clear();
+        self.counter = self.counter.checked_add(1).ok_or_else(|| invalid("Snapshot too large to encrypt".to_string()))?;
+        Ok(())
+    }
+
+    /// Seals the last chunk (empty if nothing was written) and returns `inner`.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.seal_chunk(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedEncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() && self.plaintext.len() == CHUNK_LEN {
+            self.seal_chunk(false)?;
+        }
+        let count = buf.len().min(CHUNK_LEN - self.plaintext.len());
+        self.plaintext.extend_from_slice(&buf[..count]);
+        Ok(count)
+    }
+
+    /// Only flushes `inner`; the pending chunk waits for more data or `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts the whole-blob `DPE1` layout or the legacy headerless one (nonce
+/// followed by ciphertext, keyed directly by the password).
+pub(crate) fn decrypt_payload(data: &[u8], password: &str) -> Result<Vec<u8>, String> {
+    let (key, nonce_bytes, ciphertext) = if data.starts_with(ENCRYPTED_MAGIC) {
+        let header_len = ENCRYPTED_MAGIC.len() + SALT_LEN + NONCE_LEN;
+        if data.len() < header_len {
+            return Err("Invalid encrypted file".to_string());
+        }
+        let salt = &data[ENCRYPTED_MAGIC.len()..ENCRYPTED_MAGIC.len() + SALT_LEN];
+        (derive_file_key(password, salt), &data[header_len - NONCE_LEN..header_len], &data[header_len..])
+    } else {
+        if data.len() < NONCE_LEN {
+            return Err("Invalid encrypted file".to_string());
+        }
+        (derive_key(password), &data[..NONCE_LEN], &data[NONCE_LEN..])
+    };
+    let cipher = Aes256Gcm::new_from_slice(&key).map_err(|e| format!("Failed to create cipher: {}", e))?;
+    let nonce = Nonce::from_slice(nonce_bytes);
+    cipher.decrypt(nonce, ciphertext).map_err(|e| format!("Decryption failed: {}", e))
+}
+
+/// Streams the plaintext of a chunk-framed file. Each chunk is authenticated
+/// before any of it is returned; a failure surfaces as an `InvalidData` error.
+pub(crate) struct ChunkedDecryptReader<R> {
+    inner: R,
+    cipher: Aes256Gcm,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    counter: u32,
+    plaintext: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: BufRead> ChunkedDecryptReader<R> {
+    /// Reads the header from `inner`, which must start at the magic.
+    pub(crate) fn new(mut inner: R, password: &str) -> Result<Self, String> {
+        let mut header = [0u8; CHUNKED_MAGIC.len() + SALT_LEN + NONCE_PREFIX_LEN];
+        inner.read_exact(&mut header).map_err(|_| "Invalid encrypted file".to_string())?;
+        if !header.starts_with(CHUNKED_MAGIC) {
+            return Err("Invalid encrypted file".to_string());
+        }
+        let salt = &header[CHUNKED_MAGIC.len()..CHUNKED_MAGIC.len() + SALT_LEN];
+        let key = derive_file_key(password, salt);
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|e| format!("Failed to create cipher: {}", e))?;
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        nonce_prefix.copy_from_slice(&header[CHUNKED_MAGIC.len() + SALT_LEN..]);
+        Ok(ChunkedDecryptReader {
+            inner,
+            cipher,
+            nonce_prefix,
+            counter: 0,
+            plaintext: Vec::new(),
+            position: 0,
+            finished: false,
+        })
+    }
+
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut sealed = Vec::with_capacity(CHUNK_LEN + TAG_LEN);
+        (&mut self.inner).take((CHUNK_LEN + TAG_LEN) as u64).read_to_end(&mut sealed)?;
+        if sealed.len() < TAG_LEN {
+            return Err(invalid("Invalid encrypted file".to_string()));
+        }
+        let last = self.inner.fill_buf()?.is_empty();
+        let nonce = chunk_nonce(&self.nonce_prefix, self.counter, last);
+        self.plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), sealed.as_ref())
+            .map_err(|e| invalid(format!("Decryption failed: {}", e)))?;
+        self.counter = self.counter.checked_add(1).ok_or_else(|| invalid("Invalid encrypted file".to_string()))?;
+        self.position = 0;
+        self.finished = last;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedDecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+        let count = buf.len().min(self.plaintext.len() - self.position);
+        buf[..count].copy_from_slice(&self.plaintext[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_material_is_read_from_either_header() {
+        let encrypted = encrypt_payload(b"payload", "secret", None).unwrap();
+        let material = key_material(&encrypted).unwrap();
+        assert_eq!(material.len(), SALT_LEN + NONCE_PREFIX_LEN);
+        assert_eq!(material, &encrypted[CHUNKED_MAGIC.len()..CHUNKED_MAGIC.len() + SALT_LEN + NONCE_PREFIX_LEN]);
+
+        let mut whole_blob = ENCRYPTED_MAGIC.to_vec();
+        whole_blob.extend_from_slice(&[7u8; SALT_LEN + NONCE_LEN + TAG_LEN]);
+        assert_eq!(key_material(&whole_blob), Some(&[7u8; SALT_LEN + NONCE_LEN][..]));
+
+        assert_eq!(key_material(&[1u8; 64]), None);
+        assert_eq!(key_material(CHUNKED_MAGIC), None);
+    }
+
+    #[test]
+    fn reusing_the_replaced_salt_and_nonce_is_refused() {
+        let salt = [1u8; SALT_LEN];
+        let prefix = [2u8; NONCE_PREFIX_LEN];
+        let stored = [salt.as_slice(), prefix.as_slice()].concat();
+        assert!(ensure_fresh(&salt, &prefix, Some(&stored)).is_err());
+        assert!(ensure_fresh(&salt, &[3u8; NONCE_PREFIX_LEN], Some(&stored)).is_ok());
+        assert!(ensure_fresh(&[4u8; SALT_LEN], &prefix, Some(&stored)).is_ok());
+        assert!(ensure_fresh(&salt, &prefix, None).is_ok());
+    }
+
+    fn decrypt_chunked(data: &[u8], password: &str) -> Result<Vec<u8>, String> {
+        let mut reader = ChunkedDecryptReader::new(data, password)?;
+        let mut plaintext = Vec::new();
+        reader.read_to_end(&mut plaintext).map_err(|e| e.to_string())?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn chunk_framed_payloads_round_trip_and_detect_tampering() {
+        let plaintext: Vec<u8> = (0..CHUNK_LEN * 5 / 2).map(|i| (i % 251) as u8).collect();
+        let encrypted = encrypt_payload(&plaintext, "secret", None).unwrap();
+        assert!(encrypted.starts_with(CHUNKED_MAGIC));
+        assert_eq!(decrypt_chunked(&encrypted, "secret").unwrap(), plaintext);
+        assert_eq!(decrypt_chunked(&encrypt_payload(b"", "secret", None).unwrap(), "secret").unwrap(), b"");
+        assert!(decrypt_chunked(&encrypted, "wrong").unwrap_err().contains("Decryption failed"));
+
+        let header_len = CHUNKED_MAGIC.len() + SALT_LEN + NONCE_PREFIX_LEN;
+        let sealed_len = CHUNK_LEN + TAG_LEN;
+        let chunk = |i: usize| &encrypted[header_len + i * sealed_len..(header_len + (i + 1) * sealed_len).min(encrypted.len())];
+        let mut flipped = encrypted.clone();
+        flipped[header_len + sealed_len + 10] ^= 1;
+        let truncated = encrypted[..header_len + 2 * sealed_len].to_vec();
+        let mut appended = encrypted.clone();
+        appended.push(0);
+        let swapped = [&encrypted[..header_len], chunk(1), chunk(0), chunk(2)].concat();
+        let dropped = [&encrypted[..header_len], chunk(0), chunk(2)].concat();
+        for (name, tampered) in [("flipped", flipped), ("truncated", truncated), ("appended", appended), ("swapped", swapped), ("dropped", dropped)] {
+            let error = decrypt_chunked(&tampered, "secret").unwrap_err();
+            assert!(error.contains("Decryption failed") || error.contains("Invalid encrypted file"), "{}: {}", name, error);
+        }
+    }
+
+    #[test]
+    fn whole_blob_payloads_still_decrypt() {
+        let salt = [5u8; SALT_LEN];
+        let nonce = [6u8; NONCE_LEN];
+        let cipher = Aes256Gcm::new_from_slice(&derive_file_key("secret", &salt)).unwrap();
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), b"old payload".as_ref()).unwrap();
+        let whole_blob = [ENCRYPTED_MAGIC.as_slice(), salt.as_slice(), nonce.as_slice(), ciphertext.as_slice()].concat();
+        assert_eq!(decrypt_payload(&whole_blob, "secret").unwrap(), b"old payload");
+
+        let legacy_cipher = Aes256Gcm::new_from_slice(&derive_key("secret")).unwrap();
+        let legacy_ciphertext = legacy_cipher.encrypt(Nonce::from_slice(&nonce), b"older".as_ref()).unwrap();
+        let legacy = [nonce.as_slice(), legacy_ciphertext.as_slice()].concat();
+        assert_eq!(decrypt_payload(&legacy, "secret").unwrap(), b"older");
+        assert!(decrypt_payload(&whole_blob, "wrong").is_err());
+    }
+}
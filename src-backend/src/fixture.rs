@@ -0,0 +1,342 @@
+//! Test support: small and generated directory trees for exercising scans
+//! and comparisons, and a private data directory for tests that touch it.
+//! A generated tree is deterministic: the same spec always lays out the same
+//! paths, sizes, contents and modification times, whatever the platform.
+
+use crate::{scan_drive_with_options, FileEntry, ScanOptions, Snapshot, DATA_DIR_ENV, SNAPSHOT_FORMAT_VERSION};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time;
+use tempfile::TempDir;
+
+/// Lays out `files` (relative paths, `/`-separated) in a fresh temporary
+/// directory. Each file holds its own path, so sizes differ by name.
+pub fn write_tree(files: &[&str]) -> TempDir {
+    let dir = TempDir::new().expect("create temp dir");
+    for file in files {
+        let path = dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, file.as_bytes()).unwrap();
+    }
+    dir
+}
+
+/// What `build_fixture` lays out below its root.
+#[derive(Debug, Clone)]
+pub struct FixtureSpec {
+    /// Seed for file sizes and contents.
+    pub seed: u64,
+    /// Levels of subdirectories below the root; 0 puts every file in the root.
+    pub depth: usize,
+    /// Subdirectories in every directory above the last level.
+    pub dirs_per_dir: usize,
+    /// Files in every directory, the root included.
+    pub files_per_dir: usize,
+    /// Smallest and largest generated file, in bytes (inclusive).
+    pub min_file_size: u64,
+    pub max_file_size: u64,
+    /// Files at given paths relative to the root, with their sizes, for names
+    /// a test cares about. Missing parent directories are created.
+    pub extra_files: Vec<(String, u64)>,
+    /// Modification time of every file and directory, in unix seconds.
+    pub modified: i64,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        FixtureSpec {
+            seed: 0,
+            depth: 2,
+            dirs_per_dir: 3,
+            files_per_dir: 4,
+            min_file_size: 0,
+            max_file_size: 4096,
+            extra_files: Vec::new(),
+            // 2024-01-01T00:00:00Z
+            modified: 1_704_067_200,
+        }
+    }
+}
+
+impl FixtureSpec {
+    /// Only the given files (relative paths and sizes), nothing generated.
+    pub fn files(files: &[(&str, u64)]) -> Self {
+        FixtureSpec {
+            depth: 0,
+            files_per_dir: 0,
+            extra_files: files.iter().map(|(path, size)| (path.to_string(), *size)).collect(),
+            ..FixtureSpec::default()
+        }
+    }
+
+    /// Number of directories below the root that `build_fixture` creates for
+    /// the generated files.
+    pub fn dir_count(&self) -> usize {
+        (1..=self.depth).map(|level| self.dirs_per_dir.pow(level as u32)).sum()
+    }
+
+    /// Number of files `build_fixture` writes, extra files included.
+    pub fn file_count(&self) -> usize {
+        (self.dir_count() + 1) * self.files_per_dir + self.extra_files.len()
+    }
+}
+
+/// A file written by `build_fixture`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixtureFile {
+    /// Path relative to the fixture root.
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Lays out `spec` below `root`, which is created if needed and should be
+/// empty. Directories are named `d0`, `d1`, ... and generated files `f0.dat`,
+/// `f1.dat`, ... Returns every file written, sorted by path.
+pub fn build_fixture(root: &Path, spec: &FixtureSpec) -> Result<Vec<FixtureFile>, String> {
+    if spec.min_file_size > spec.max_file_size {
+        return Err("min_file_size must not exceed max_file_size".to_string());
+    }
+    let mut rng = SplitMix64(spec.seed);
+    let mut files = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    let mut level = vec![PathBuf::new()];
+    for _ in 0..spec.depth {
+        level = level
+            .iter()
+            .flat_map(|parent| (0..spec.dirs_per_dir).map(move |i| parent.join(format!("d{}", i))))
+            .collect();
+        dirs.extend(level.iter().cloned());
+    }
+    for dir in &dirs {
+        fs::create_dir_all(root.join(dir)).map_err(|e| format!("Failed to create {}: {}", root.join(dir).display(), e))?;
+        for i in 0..spec.files_per_dir {
+            let span = spec.max_file_size - spec.min_file_size;
+            let size = spec.min_file_size + if span == u64::MAX { rng.next() } else { rng.next() % (span + 1) };
+            let path = dir.join(format!("f{}.dat", i));
+            write_file(&root.join(&path), size, &mut rng, spec.modified)?;
+            files.push(FixtureFile { path, size });
+        }
+    }
+    for (path, size) in &spec.extra_files {
+        let path = PathBuf::from(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(root.join(parent)).map_err(|e| format!("Failed to create {}: {}", root.join(parent).display(), e))?;
+            for ancestor in parent.ancestors() {
+                if !dirs.iter().any(|d| d == ancestor) {
+                    dirs.push(ancestor.to_path_buf());
+                }
+            }
+        }
+        write_file(&root.join(&path), *size, &mut rng, spec.modified)?;
+        files.push(FixtureFile { path, size: *size });
+    }
+    // Writing the files touched their directories
+    for dir in &dirs {
+        set_modified(&root.join(dir), spec.modified);
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+fn write_file(path: &Path, size: u64, rng: &mut SplitMix64, modified: i64) -> Result<(), String> {
+    let write_error = |e: std::io::Error| format!("Failed to write {}: {}", path.display(), e);
+    let file = fs::File::create(path).map_err(write_error)?;
+    let mut writer = BufWriter::new(file);
+    let mut remaining = size;
+    while remaining > 0 {
+        let bytes = rng.next().to_le_bytes();
+        let count = remaining.min(bytes.len() as u64) as usize;
+        writer.write_all(&bytes[..count]).map_err(write_error)?;
+        remaining -= count as u64;
+    }
+    let file = writer.into_inner().map_err(|e| write_error(e.into_error()))?;
+    file.set_modified(unix_time(modified)).map_err(write_error)?;
+    Ok(())
+}
+
+/// A fresh temporary directory with `spec` laid out inside it, removed when
+/// the returned handle is dropped.
+pub fn fixture_dir(spec: &FixtureSpec) -> (TempDir, Vec<FixtureFile>) {
+    let dir = TempDir::new().expect("create temp dir");
+    let files = build_fixture(dir.path(), spec).expect("build fixture");
+    (dir, files)
+}
+
+/// Scans `root` with `options`, for tests that expect the scan to succeed.
+pub fn scan_dir(root: &Path, options: &ScanOptions) -> Snapshot {
+    scan_drive_with_options(root.to_string_lossy().to_string(), options, |_| {}).expect("scan")
+}
+
+/// Paths recorded in `snapshot`, relative to `root` and with `/` separators,
+/// the root itself left out.
+pub fn relative_paths(snapshot: &Snapshot, root: &Path) -> Vec<String> {
+    snapshot
+        .files
+        .iter()
+        .filter_map(|f| Path::new(&f.path).strip_prefix(root).ok())
+        .filter(|p| !p.as_os_str().is_empty())
+        .map(|p| p.to_string_lossy().replace('\\', "/"))
+        .collect()
+}
+
+/// A file entry with the given path and size, modified at 2024-01-01.
+pub fn file_entry(path: &str, size: u64) -> FileEntry {
+    serde_json::from_value(serde_json::json!({
+        "path": path,
+        "size": size,
+        "modified": 1_704_067_200,
+        "is_dir": false,
+    }))
+    .unwrap()
+}
+
+/// A snapshot of `/data` holding `files`, with totals to match.
+pub fn snapshot(id: &str, timestamp: i64, files: Vec<FileEntry>) -> Snapshot {
+    serde_json::from_value(serde_json::json!({
+        "format_version": SNAPSHOT_FORMAT_VERSION,
+        "id": id,
+        "drive_path": "/data",
+        "timestamp": timestamp,
+        "total_files": files.len(),
+        "total_size": files.iter().map(|f| f.size).sum::<u64>(),
+        "scan_duration": 0,
+        "files": files,
+    }))
+    .unwrap()
+}
+
+/// Serializes tests that use the data directory: it comes from an
+/// environment variable, which is process-wide.
+static DATA_DIR_LOCK: Mutex<()> = Mutex::new(());
+
+/// A private, empty data directory for the duration of a test.
+pub struct TestDataDir {
+    dir: TempDir,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl TestDataDir {
+    pub fn new() -> Self {
+        // A test that panicked while holding the lock left nothing to clean up
+        let guard = DATA_DIR_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = TempDir::new().expect("create temp dir");
+        std::env::set_var(DATA_DIR_ENV, dir.path());
+        TestDataDir { dir, _guard: guard }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Points the data directory somewhere else for the rest of the test.
+    pub fn relocate(&self, path: &Path) {
+        std::env::set_var(DATA_DIR_ENV, path);
+    }
+}
+
+impl Drop for TestDataDir {
+    fn drop(&mut self) {
+        std::env::remove_var(DATA_DIR_ENV);
+    }
+}
+
+/// Best effort: directories can't be opened for this on every platform.
+fn set_modified(path: &Path, modified: i64) {
+    if let Ok(dir) = fs::File::open(path) {
+        let _ = dir.set_modified(unix_time(modified));
+    }
+}
+
+fn unix_time(seconds: i64) -> time::SystemTime {
+    time::SystemTime::UNIX_EPOCH + time::Duration::from_secs(seconds.max(0) as u64)
+}
+
+/// SplitMix64, spelled out here rather than taken from `rand` so fixtures
+/// stay the same across `rand` versions.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_exactly_the_specified_files_and_sizes() {
+        let spec = FixtureSpec {
+            seed: 7,
+            depth: 2,
+            dirs_per_dir: 2,
+            files_per_dir: 3,
+            min_file_size: 10,
+            max_file_size: 20,
+            extra_files: vec![("named/deep/report.txt".to_string(), 1234)],
+            ..FixtureSpec::default()
+        };
+        let (dir, files) = fixture_dir(&spec);
+        assert_eq!(spec.dir_count(), 6);
+        assert_eq!(files.len(), spec.file_count());
+        assert_eq!(files.len(), 7 * 3 + 1);
+
+        let mut on_disk = Vec::new();
+        for entry in walkdir::WalkDir::new(dir.path()).min_depth(1) {
+            let entry = entry.unwrap();
+            if entry.file_type().is_file() {
+                let path = entry.path().strip_prefix(dir.path()).unwrap().to_path_buf();
+                on_disk.push(FixtureFile { path, size: entry.metadata().unwrap().len() });
+            }
+        }
+        on_disk.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(on_disk, files);
+        assert!(files.iter().any(|f| f.path == Path::new("named/deep/report.txt") && f.size == 1234));
+        assert!(files
+            .iter()
+            .filter(|f| f.path.extension().is_some_and(|ext| ext == "dat"))
+            .all(|f| (10..=20).contains(&f.size)));
+        assert!(dir.path().join("d1/d0/f2.dat").is_file());
+    }
+
+    #[test]
+    fn same_spec_gives_same_contents_and_times() {
+        let spec = FixtureSpec { files_per_dir: 2, depth: 1, dirs_per_dir: 2, ..FixtureSpec::default() };
+        let (first, files) = fixture_dir(&spec);
+        let (second, _) = fixture_dir(&spec);
+        for file in &files {
+            let a = fs::read(first.path().join(&file.path)).unwrap();
+            let b = fs::read(second.path().join(&file.path)).unwrap();
+            assert_eq!(a, b, "{}", file.path.display());
+            let modified = fs::metadata(first.path().join(&file.path)).unwrap().modified().unwrap();
+            assert_eq!(modified, unix_time(spec.modified));
+        }
+        let other = FixtureSpec { seed: 1, ..spec };
+        let (third, _) = fixture_dir(&other);
+        let differs = files.iter().any(|file| {
+            file.size > 0 && fs::read(first.path().join(&file.path)).unwrap() != fs::read(third.path().join(&file.path)).unwrap()
+        });
+        assert!(differs);
+    }
+
+    #[test]
+    fn test_data_dir_is_private_and_empty() {
+        let data_dir = TestDataDir::new();
+        assert_eq!(crate::storage::get_data_dir().unwrap(), data_dir.path());
+        assert!(crate::storage::get_scan_history(data_dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_inverted_size_range() {
+        let spec = FixtureSpec { min_file_size: 5, max_file_size: 4, ..FixtureSpec::default() };
+        let dir = TempDir::new().unwrap();
+        assert!(build_fixture(dir.path(), &spec).is_err());
+    }
+}
@@ -3,17 +3,25 @@
 
 mod commands;
 
-use commands::{scan_drive, get_scan_history, compare_snapshots, delete_snapshot, get_data_directory, open_data_directory, get_available_drives};
+use commands::{scan_drive, get_scan_history, get_scan_errors, compare_snapshots, compare_summary, cancel_compare, verify_snapshot, cancel_verify, delete_snapshot, preview_delete_snapshots, set_snapshot_locked, get_data_directory, open_data_directory, open_snapshot_file, get_available_drives};
 
 fn main() {
     tauri::Builder::default()
         .invoke_handler(tauri::generate_handler![
             scan_drive,
             get_scan_history,
+            get_scan_errors,
             compare_snapshots,
+            compare_summary,
+            cancel_compare,
+            verify_snapshot,
+            cancel_verify,
             delete_snapshot,
+            preview_delete_snapshots,
+            set_snapshot_locked,
             get_data_directory,
             open_data_directory,
+            open_snapshot_file,
             get_available_drives
         ])
         .run(tauri::generate_context!())
@@ -0,0 +1,1492 @@
+//! Walking a drive into a `Snapshot`.
+
+use crate::snapshot::{
+    encode_raw_path, new_snapshot_id, ContentFilter, FileEntry, FileKind, Snapshot, CONTENT_FILTER_FALSE_POSITIVE_RATE, SNAPSHOT_FORMAT_VERSION,
+};
+use crate::spill::EntrySpill;
+use crate::storage::{
+    get_data_dir, latest_snapshot_for_drive, save_scan, save_snapshot_metadata, save_snapshot_streamed,
+    SnapshotFormat,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use sha2::{Sha256, Digest};
+use std::io::Read;
+use std::time;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ScanOptions {
+    /// Scan Drive Pulse's own data directory instead of skipping it.
+    #[serde(default)]
+    pub include_data_dir: bool,
+    /// Additional paths (e.g. an export target) that should never be recorded.
+    #[serde(default)]
+    pub exclude_paths: Vec<String>,
+    /// Canonicalize the scan root so every stored path is absolute and free of
+    /// `.`/`..`, making snapshots of the same tree comparable however the root was
+    /// typed. This resolves symlinks in the root itself (entries below it are still
+    /// recorded as links, not followed). On Windows paths gain the `\\?\` prefix.
+    #[serde(default)]
+    pub canonicalize_paths: bool,
+    /// Also record the exact bytes of paths that aren't valid Unicode
+    /// (`FileEntry::raw_path`), which `path` can only hold lossily. Without it,
+    /// such files can't be opened again from the snapshot and two names that
+    /// differ only in invalid bytes compare as one.
+    #[serde(default)]
+    pub lossless_paths: bool,
+    /// Stay on the scan root's filesystem (like `find -xdev`): entries on another
+    /// device than the root, mount points included, are left out, so a NAS
+    /// under `/mnt` isn't scanned.
+    #[serde(default)]
+    pub one_filesystem: bool,
+    /// Record inode numbers and count each hard-linked file's size only once in
+    /// `total_size`. Only has an effect on Unix.
+    #[serde(default)]
+    pub dedupe_hard_links: bool,
+    /// Compute a SHA-256 of every regular file. Reads all file contents, so it is
+    /// much slower than a metadata-only scan.
+    #[serde(default)]
+    pub hash_files: bool,
+    /// With `hash_files`, also store a Bloom filter of the content hashes on
+    /// the snapshot (`Snapshot::content_filter`) for quick membership checks.
+    /// Ignored without hashing.
+    #[serde(default)]
+    pub content_filter: bool,
+    /// Store each directory's immediate child count, enabling the cheap
+    /// structural comparison in `compare_directory_counts`.
+    #[serde(default)]
+    pub record_child_counts: bool,
+    /// What to do with Windows reparse points (junctions, mount points) below
+    /// the scan root.
+    #[serde(default)]
+    pub reparse_points: ReparsePointPolicy,
+    /// Leave out files larger than this many bytes (e.g. VM images), so they
+    /// neither appear in the snapshot nor count towards `total_size`.
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Only record entries at least this many levels below the scan root (the
+    /// root itself is level 0), e.g. 2 to leave out the top-level folders and
+    /// record only their contents. Shallower levels are walked, not recorded.
+    #[serde(default)]
+    pub min_depth: Option<usize>,
+    /// Leave out directories with no file recorded anywhere below them. This
+    /// is decided after every other filter, so a folder whose files were all
+    /// excluded (by size, extension or depth) counts as empty.
+    #[serde(default)]
+    pub exclude_empty_dirs: bool,
+    /// Leave out files with these extensions (without the dot, any case), e.g.
+    /// `tmp` or `log`. How many were left out is recorded on the snapshot.
+    #[serde(default)]
+    pub exclude_extensions: Vec<String>,
+    /// A caller's own rule, asked about every entry that passes the built-in
+    /// filters. Entries it rejects are left out, and a rejected directory is
+    /// not descended into. Code only, so never serialized.
+    #[serde(skip)]
+    pub filter: Option<EntryFilter>,
+    /// After the scan, re-stat this many randomly chosen files and flag the
+    /// snapshot as inconsistent if any changed meanwhile (e.g. a backup was
+    /// writing to the drive). 0 skips the check.
+    #[serde(default)]
+    pub consistency_sample: usize,
+    /// Threads used to hash file contents. `None` (or 0) picks one per
+    /// available CPU; 1 hashes each file inline as the walk reaches it.
+    #[serde(default)]
+    pub threads: Option<usize>,
+    /// Bytes read at a time when hashing a file. Larger reads can help on fast
+    /// NVMe drives, smaller ones on a slow NAS. 0 means `DEFAULT_HASH_BUFFER_SIZE`.
+    #[serde(default)]
+    pub hash_buffer_size: usize,
+    /// Soft limit on entries held in memory while scanning. Past it, entries
+    /// are flushed in sorted batches to a temporary file in the data directory
+    /// and merged into the snapshot file as it is saved, so memory stays
+    /// bounded on drives with tens of millions of files. Only
+    /// `scan_drive_to_storage` honours it; scans returned whole ignore it.
+    #[serde(default)]
+    pub max_entries_in_memory: Option<usize>,
+    /// Record extended attributes (`FileEntry::xattrs`) on Unix, for
+    /// comparisons with `DiffOptions::compare_xattrs`. Costs a few system
+    /// calls per entry. POSIX ACLs on Linux are the `system.posix_acl_access`
+    /// and `system.posix_acl_default` attributes.
+    #[serde(default)]
+    pub record_xattrs: bool,
+    /// With `record_xattrs`, only record these attributes (e.g. `user.owner`);
+    /// empty records all of them.
+    #[serde(default)]
+    pub xattr_names: Vec<String>,
+}
+
+/// Read size used to hash files unless `ScanOptions::hash_buffer_size` is set.
+pub const DEFAULT_HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+impl ScanOptions {
+    /// The thread count a scan with these options actually uses.
+    pub fn effective_threads(&self) -> usize {
+        match self.threads {
+            Some(threads) if threads > 0 => threads,
+            _ => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        }
+    }
+
+    /// The hashing read size a scan with these options actually uses.
+    pub fn effective_hash_buffer_size(&self) -> usize {
+        if self.hash_buffer_size > 0 { self.hash_buffer_size } else { DEFAULT_HASH_BUFFER_SIZE }
+    }
+
+    /// Checks the options for values a scan can't act on, such as glob
+    /// patterns where a plain extension or path is expected, so a caller
+    /// passing options through (e.g. from the GUI) gets a clear error up front
+    /// instead of a scan that silently excludes nothing.
+    pub fn validate(&self) -> Result<(), String> {
+        for ext in &self.exclude_extensions {
+            if ext.contains(['*', '?', '[', '/', '\\']) {
+                return Err(format!(
+                    "Invalid excluded extension \"{}\": give the extension alone, e.g. \"log\" rather than \"*.log\"",
+                    ext
+                ));
+            }
+        }
+        for path in &self.exclude_paths {
+            if path.trim().is_empty() {
+                return Err("Excluded paths must not be empty".to_string());
+            }
+            // Exact paths only; a literal `*` in an existing name is still fine
+            if path.contains(['*', '?']) && !Path::new(path).exists() {
+                return Err(format!("Invalid excluded path \"{}\": patterns aren't supported, give an exact path", path));
+            }
+        }
+        Ok(())
+    }
+
+    /// `exclude_extensions` lowercased and without leading dots.
+    fn normalized_exclude_extensions(&self) -> HashSet<String> {
+        self.exclude_extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect()
+    }
+}
+
+/// A predicate deciding whether a scan records an entry, for rules beyond the
+/// built-in filters (e.g. size and extension combined), see `ScanOptions::filter`.
+#[derive(Clone)]
+pub struct EntryFilter(Arc<EntryPredicate>);
+
+type EntryPredicate = dyn Fn(&Path, &fs::Metadata) -> bool + Send + Sync;
+
+impl EntryFilter {
+    /// Records an entry only when `predicate` returns true for it.
+    pub fn new<P>(predicate: P) -> Self
+    where
+        P: Fn(&Path, &fs::Metadata) -> bool + Send + Sync + 'static,
+    {
+        EntryFilter(Arc::new(predicate))
+    }
+
+    pub fn accepts(&self, path: &Path, metadata: &fs::Metadata) -> bool {
+        (self.0)(path, metadata)
+    }
+}
+
+impl std::fmt::Debug for EntryFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EntryFilter(..)")
+    }
+}
+
+fn has_excluded_extension(path: &Path, extensions: &HashSet<String>) -> bool {
+    !extensions.is_empty()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| extensions.contains(&ext.to_lowercase()))
+}
+
+/// Handling of Windows reparse points during a scan. Junctions can point back
+/// up the tree or at other volumes, so descending into them by default risks
+/// duplicated or endless scans.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReparsePointPolicy {
+    /// Leave reparse points and everything behind them out of the snapshot.
+    Skip,
+    /// Record the reparse point itself without descending into it, the same
+    /// way symlinks are recorded on Unix.
+    #[default]
+    Record,
+    /// Descend into reparse points, relying on the walker's loop detection to
+    /// stop cycles. This follows symlinks on every platform.
+    Follow,
+}
+
+/// Resolves the paths that a scan of `drive_path` must skip, expressed in the
+/// same form `WalkDir` will yield them so they can be compared directly.
+pub fn scan_exclusions(drive_path: &str, options: &ScanOptions) -> Vec<PathBuf> {
+    let mut candidates: Vec<PathBuf> = options.exclude_paths.iter().map(PathBuf::from).collect();
+    if !options.include_data_dir {
+        // Every profile's data, not just the one being saved to
+        if let Ok(data_dir) = get_data_dir() {
+            candidates.push(data_dir);
+        }
+    }
+    let root = Path::new(drive_path);
+    let canonical_root = match fs::canonicalize(root) {
+        Ok(p) => p,
+        Err(_) => return Vec::new(),
+    };
+    candidates
+        .into_iter()
+        .filter_map(|candidate| fs::canonicalize(&candidate).ok())
+        .filter_map(|candidate| {
+            candidate
+                .strip_prefix(&canonical_root)
+                .ok()
+                .filter(|relative| !relative.as_os_str().is_empty())
+                .map(|relative| root.join(relative))
+        })
+        .collect()
+}
+
+/// Streams a file through SHA-256 and returns the lowercase hex digest.
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    hash_file_with_buffer(path, DEFAULT_HASH_BUFFER_SIZE)
+}
+
+/// Like `hash_file`, reading `buffer_size` bytes at a time. The digest doesn't
+/// depend on the buffer size.
+pub fn hash_file_with_buffer(path: &Path, buffer_size: usize) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; buffer_size.max(1)];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hashes `files[i]` for every `i` in `indices` on up to `threads` threads,
+/// returning the results in the order of `indices`. A file whose hashing
+/// panicked gets an error rather than going missing.
+fn hash_in_parallel(files: &[FileEntry], indices: &[usize], threads: usize, buffer_size: usize) -> Vec<(usize, std::io::Result<String>)> {
+    if indices.is_empty() {
+        return Vec::new();
+    }
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    // Filled in as each file is done, so a panicking worker loses only the file it was on
+    let done: std::sync::Mutex<Vec<Option<std::io::Result<String>>>> = std::sync::Mutex::new((0..indices.len()).map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..threads.min(indices.len()) {
+            scope.spawn(|| loop {
+                let position = next.fetch_add(1, Ordering::Relaxed);
+                let index = match indices.get(position) {
+                    Some(index) => *index,
+                    None => break,
+                };
+                let result = hash_file_with_buffer(&files[index].os_path(), buffer_size);
+                done.lock().unwrap_or_else(|poisoned| poisoned.into_inner())[position] = Some(result);
+            });
+        }
+    });
+    let done = done.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+    indices
+        .iter()
+        .zip(done)
+        .map(|(index, result)| (*index, result.unwrap_or_else(|| Err(std::io::Error::other("hashing thread panicked")))))
+        .collect()
+}
+
+/// Adds every ancestor directory of `path` to `dirs`.
+pub(crate) fn mark_ancestors(path: &str, dirs: &mut HashSet<PathBuf>) {
+    for ancestor in Path::new(path).ancestors().skip(1) {
+        // Its own ancestors are already in from an earlier path
+        if !dirs.insert(ancestor.to_path_buf()) {
+            break;
+        }
+    }
+}
+
+/// Drops directory entries with no non-directory entry anywhere below them,
+/// see `ScanOptions::exclude_empty_dirs`. Returns the bytes the dropped
+/// entries added to `total_size`.
+fn prune_empty_dirs(files: &mut Vec<FileEntry>) -> u64 {
+    let mut non_empty = HashSet::new();
+    for file in files.iter().filter(|f| !f.is_dir) {
+        mark_ancestors(&file.path, &mut non_empty);
+    }
+    let mut pruned_bytes = 0;
+    files.retain(|file| {
+        let keep = !file.is_dir || non_empty.contains(Path::new(&file.path));
+        if !keep {
+            pruned_bytes += file.size;
+        }
+        keep
+    });
+    pruned_bytes
+}
+
+fn fill_child_counts(files: &mut [FileEntry]) {
+    let mut counts: HashMap<PathBuf, usize> = HashMap::new();
+    for file in files.iter() {
+        if let Some(parent) = Path::new(&file.path).parent() {
+            *counts.entry(parent.to_path_buf()).or_insert(0) += 1;
+        }
+    }
+    for file in files.iter_mut().filter(|f| f.is_dir) {
+        file.child_count = Some(counts.get(Path::new(&file.path)).copied().unwrap_or(0));
+    }
+}
+
+#[cfg(unix)]
+fn inode_of(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn inode_of(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// `(device, inode)` of a regular file that has more than one link.
+#[cfg(unix)]
+fn hard_link_identity(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    if metadata.is_file() && metadata.nlink() > 1 {
+        Some((metadata.dev(), metadata.ino()))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn hard_link_identity(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Device id of the filesystem holding an entry, `None` where not known.
+#[cfg(unix)]
+fn device_of(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_of(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Whether an entry lies on another filesystem than the scan root, for
+/// `ScanOptions::one_filesystem`. Unknown devices count as the same.
+fn on_other_device(root_device: Option<u64>, entry_device: Option<u64>) -> bool {
+    matches!((root_device, entry_device), (Some(root), Some(entry)) if root != entry)
+}
+
+#[cfg(windows)]
+fn is_reparse_point(metadata: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+    metadata.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0
+}
+
+#[cfg(not(windows))]
+fn is_reparse_point(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Peak resident memory of this process so far in bytes, as stored in
+/// `Snapshot::peak_memory_bytes`. Only Linux reports it (`VmHWM`).
+#[cfg(target_os = "linux")]
+pub fn peak_memory_bytes() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line.trim_start_matches("VmHWM:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn peak_memory_bytes() -> Option<u64> {
+    None
+}
+
+fn modified_secs(metadata: &fs::Metadata) -> i64 {
+    metadata.modified().unwrap_or(time::SystemTime::UNIX_EPOCH).duration_since(time::SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+}
+
+/// Re-stats up to `sample_size` random regular files and checks that their size
+/// and modification time still match what was recorded. A file that vanished
+/// counts as changed.
+fn sample_is_consistent(files: &[FileEntry], sample_size: usize, follow_links: bool) -> bool {
+    let candidates: Vec<&FileEntry> = files.iter().filter(|f| !f.is_dir).collect();
+    let mut rng = rand::thread_rng();
+    rand::seq::index::sample(&mut rng, candidates.len(), sample_size.min(candidates.len()))
+        .into_iter()
+        .map(|i| candidates[i])
+        .all(|file| {
+            let path = file.os_path();
+            let metadata = if follow_links { fs::metadata(&path) } else { fs::symlink_metadata(&path) };
+            metadata.is_ok_and(|metadata| metadata.len() == file.size && modified_secs(&metadata) == file.modified)
+        })
+}
+
+/// Hashes the files at `to_hash` (indices into `files`) in parallel, recording
+/// failures in `scan_errors`, and empties `to_hash`.
+fn hash_pending(files: &mut [FileEntry], to_hash: &mut Vec<usize>, threads: usize, buffer_size: usize, scan_errors: &mut Vec<String>) {
+    for (index, result) in hash_in_parallel(files, to_hash, threads, buffer_size) {
+        match result {
+            Ok(hash) => files[index].hash = Some(hash),
+            Err(e) => scan_errors.push(format!("{}: {}", files[index].path, e)),
+        }
+    }
+    to_hash.clear();
+}
+
+/// A uniformly random sample of up to `size` regular files, taken from
+/// batches offered one at a time (reservoir sampling). Lets a spilled scan
+/// pick files for the consistency check without keeping every entry.
+struct FileSample {
+    size: usize,
+    seen: usize,
+    files: Vec<FileEntry>,
+}
+
+impl FileSample {
+    fn new(size: usize) -> Self {
+        FileSample { size, seen: 0, files: Vec::new() }
+    }
+
+    fn offer(&mut self, files: &[FileEntry]) {
+        if self.size == 0 {
+            return;
+        }
+        let mut rng = rand::thread_rng();
+        for file in files.iter().filter(|f| !f.is_dir) {
+            self.seen += 1;
+            if self.files.len() < self.size {
+                self.files.push(file.clone());
+            } else {
+                let slot = rng.gen_range(0..self.seen);
+                if slot < self.size {
+                    self.files[slot] = file.clone();
+                }
+            }
+        }
+    }
+}
+
+/// `ContentFilter::from_files` for spilled entries, which can't be deduplicated
+/// up front. A hash the filter already reports is not inserted again, which
+/// keeps `len` close to the number of distinct hashes.
+fn spilled_content_filter(spill: &EntrySpill) -> Result<ContentFilter, String> {
+    let mut filter = ContentFilter::new(spill.hashed_len(), CONTENT_FILTER_FALSE_POSITIVE_RATE);
+    for file in spill.entries() {
+        let file = file?;
+        if let (false, Some(hash)) = (file.is_dir, file.hash.as_deref()) {
+            if !filter.contains(hash) {
+                filter.insert(hash);
+            }
+        }
+    }
+    Ok(filter)
+}
+
+/// Running totals of a scan, passed to the progress callback after every
+/// entry and sent to the GUI as the `scan-progress` event.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ScanProgress {
+    /// Entries recorded so far, directories included.
+    pub files_scanned: usize,
+    /// The entry just recorded.
+    pub current_path: String,
+    /// Bytes counted so far, as `Snapshot::total_size` counts them.
+    pub total_size: u64,
+    /// Directories among `files_scanned`.
+    pub total_dirs: usize,
+}
+
+pub fn scan_drive<F>(drive_path: String, progress_callback: F) -> Result<Snapshot, String>
+where
+    F: FnMut(&ScanProgress),
+{
+    scan_drive_with_options(drive_path, &ScanOptions::default(), progress_callback)
+}
+
+pub fn scan_drive_with_options<F>(drive_path: String, options: &ScanOptions, progress_callback: F) -> Result<Snapshot, String>
+where
+    F: FnMut(&ScanProgress),
+{
+    scan_drive_with_clock(drive_path, options, &SystemClock, progress_callback)
+}
+
+/// Throughput of a scan that recorded `total_files` entries in `elapsed`, as
+/// stored in `Snapshot::files_per_sec`. Scans too quick to time are counted as
+/// taking a millisecond.
+pub fn files_per_sec(total_files: usize, elapsed: time::Duration) -> f64 {
+    total_files as f64 / elapsed.as_secs_f64().max(0.001)
+}
+
+/// Estimates the time left in a scan from its progress so far, smoothing the
+/// throughput with an exponential moving average so the estimate doesn't jump
+/// around between fast and slow directories. Scans don't know their size up
+/// front; the previous scan of the same drive is a good `total` estimate.
+#[derive(Debug, Clone)]
+pub struct EtaEstimator {
+    smoothing: f64,
+    rate: Option<f64>,
+    last_sample: Option<(usize, time::Duration)>,
+}
+
+impl Default for EtaEstimator {
+    fn default() -> Self {
+        EtaEstimator::new(0.3)
+    }
+}
+
+impl EtaEstimator {
+    /// Throughput is only re-measured after this much time, so per-file
+    /// updates don't turn timer noise into wild rates.
+    const MIN_SAMPLE_INTERVAL: time::Duration = time::Duration::from_millis(500);
+
+    /// `smoothing` is the weight of the newest throughput sample, between 0
+    /// (ignore new samples) and 1 (no smoothing).
+    pub fn new(smoothing: f64) -> Self {
+        EtaEstimator { smoothing: smoothing.clamp(0.0, 1.0), rate: None, last_sample: None }
+    }
+
+    /// Records that `done` entries were processed after `elapsed`, and returns
+    /// the estimated time left to reach `total`. `None` when there is no total,
+    /// no throughput measured yet, or `done` already exceeds the total (the
+    /// estimate was wrong, so no guess is better than zero).
+    pub fn update(&mut self, done: usize, total: Option<usize>, elapsed: time::Duration) -> Option<time::Duration> {
+        let (last_done, last_elapsed) = self.last_sample.unwrap_or((0, time::Duration::ZERO));
+        let interval = elapsed.saturating_sub(last_elapsed);
+        if interval >= Self::MIN_SAMPLE_INTERVAL && done >= last_done {
+            let sample = (done - last_done) as f64 / interval.as_secs_f64();
+            self.rate = Some(match self.rate {
+                Some(rate) => self.smoothing * sample + (1.0 - self.smoothing) * rate,
+                None => sample,
+            });
+            self.last_sample = Some((done, elapsed));
+        }
+        let remaining = total?.checked_sub(done)?;
+        match self.rate {
+            Some(rate) if rate > 0.0 => Some(time::Duration::from_secs_f64(remaining as f64 / rate)),
+            _ => None,
+        }
+    }
+}
+
+/// Minimum time between two progress reports shown to the user, shared by the
+/// CLI and the GUI events of every long-running operation.
+pub const PROGRESS_INTERVAL: time::Duration = time::Duration::from_millis(250);
+
+/// Decides which of many per-item progress callbacks are worth reporting: the
+/// first, the last, and then at most one every `PROGRESS_INTERVAL`.
+#[derive(Debug, Clone, Default)]
+pub struct ProgressThrottle {
+    last_report: Option<time::Instant>,
+}
+
+impl ProgressThrottle {
+    /// Whether to report that `done` of `total` items are processed.
+    pub fn ready(&mut self, done: usize, total: usize) -> bool {
+        let due = done >= total || self.last_report.is_none_or(|last| last.elapsed() >= PROGRESS_INTERVAL);
+        if due {
+            self.last_report = Some(time::Instant::now());
+        }
+        due
+    }
+}
+
+/// Source of the wall-clock time stamped on snapshots, injectable for tests.
+pub trait Clock {
+    fn now(&self) -> time::SystemTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> time::SystemTime {
+        time::SystemTime::now()
+    }
+}
+
+/// A clock stuck at a given unix timestamp (in seconds).
+pub struct FixedClock(pub i64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> time::SystemTime {
+        time::SystemTime::UNIX_EPOCH + time::Duration::from_secs(self.0.max(0) as u64)
+    }
+}
+
+/// Scan using `clock` for the snapshot timestamp. The id is derived from the
+/// drive path and that same instant, so a fixed clock gives a reproducible id.
+/// The scan's parent is the latest scan of the drive in the default data
+/// directory; `scan_drive_to_storage` looks in the directory it saves to.
+pub fn scan_drive_with_clock<F>(drive_path: String, options: &ScanOptions, clock: &dyn Clock, progress_callback: F) -> Result<Snapshot, String>
+where
+    F: FnMut(&ScanProgress),
+{
+    let mut snapshot = scan_entries(drive_path, options, clock, None, progress_callback)?;
+    snapshot.parent_id = get_data_dir().ok().and_then(|data_dir| parent_scan_id(&data_dir, &snapshot.drive_path));
+    Ok(snapshot)
+}
+
+/// Id of the most recent scan of `drive_path` in `data_dir`, which a new scan
+/// of the drive records as its parent.
+fn parent_scan_id(data_dir: &Path, drive_path: &str) -> Option<String> {
+    latest_snapshot_for_drive(data_dir, drive_path).ok().flatten().map(|parent| parent.id)
+}
+
+/// Scans a drive and saves it like `save_scan`, returning the snapshot. With
+/// `ScanOptions::max_entries_in_memory` set, entries go to a spill file as the
+/// limit is reached and are streamed into the snapshot file from there, so the
+/// returned snapshot has every total but an empty `files`; load it by id to
+/// read them.
+pub fn scan_drive_to_storage<F>(
+    data_dir: &Path,
+    drive_path: String,
+    options: &ScanOptions,
+    format: SnapshotFormat,
+    password: Option<&str>,
+    write_metadata: bool,
+    progress_callback: F,
+) -> Result<Snapshot, String>
+where
+    F: FnMut(&ScanProgress),
+{
+    // Better to fail now than after hours of scanning
+    if format == SnapshotFormat::Encrypted && password.is_none() {
+        return Err("Password required for encryption".to_string());
+    }
+    options.validate()?;
+    let limit = match options.max_entries_in_memory {
+        Some(limit) => limit.max(1),
+        None => {
+            let mut snapshot = scan_entries(drive_path, options, &SystemClock, None, progress_callback)?;
+            snapshot.parent_id = parent_scan_id(data_dir, &snapshot.drive_path);
+            save_scan(data_dir, &snapshot, format, password, write_metadata, |_, _| {})?;
+            return Ok(snapshot);
+        }
+    };
+    let mut spill = EntrySpill::create(
+        &data_dir.join("tmp"),
+        options.record_child_counts,
+        options.exclude_empty_dirs,
+    )?;
+    let mut snapshot = scan_entries(drive_path, options, &SystemClock, Some((&mut spill, limit)), progress_callback)?;
+    snapshot.parent_id = parent_scan_id(data_dir, &snapshot.drive_path);
+    save_snapshot_streamed(data_dir, &snapshot, spill.merged()?, format, password)?;
+    if write_metadata {
+        save_snapshot_metadata(data_dir, &snapshot)?;
+    }
+    Ok(snapshot)
+}
+
+/// The scan itself. With a `spill`, every `limit` entries are hashed and
+/// written out as a sorted run, and the snapshot comes back with an empty
+/// `files`, its totals and content filter taken from the spill.
+fn scan_entries<F>(
+    drive_path: String,
+    options: &ScanOptions,
+    clock: &dyn Clock,
+    mut spill: Option<(&mut EntrySpill, usize)>,
+    mut progress_callback: F,
+) -> Result<Snapshot, String>
+where
+    F: FnMut(&ScanProgress),
+{
+    options.validate()?;
+    let scan_start = time::Instant::now();
+    let drive_path = if options.canonicalize_paths {
+        fs::canonicalize(&drive_path)
+            .map_err(|e| format!("Failed to canonicalize {}: {}", drive_path, e))?
+            .to_string_lossy()
+            .to_string()
+    } else {
+        drive_path
+    };
+    let mut files = Vec::new();
+    let mut total_size: u64 = 0;
+    let exclusions = scan_exclusions(&drive_path, options);
+    let root_device = if options.one_filesystem {
+        fs::metadata(&drive_path).ok().and_then(|metadata| device_of(&metadata))
+    } else {
+        None
+    };
+    let mut walker = WalkDir::new(&drive_path)
+        .min_depth(options.min_depth.unwrap_or(0))
+        .same_file_system(options.one_filesystem)
+        .follow_links(options.reparse_points == ReparsePointPolicy::Follow)
+        .into_iter()
+        .filter_entry(|e| !exclusions.iter().any(|excluded| e.path() == excluded));
+    let mut scan_errors = Vec::new();
+    let mut seen_hard_links = HashSet::new();
+    let threads = options.effective_threads();
+    let hash_buffer_size = options.effective_hash_buffer_size();
+    let mut to_hash = Vec::new();
+    let mut hard_link_count = 0;
+    let mut hard_link_bytes_saved: u64 = 0;
+    let excluded_extensions = options.normalized_exclude_extensions();
+    let mut excluded_by_extension_count = 0;
+    let mut excluded_by_extension_bytes: u64 = 0;
+    let mut sample = FileSample::new(if spill.is_some() { options.consistency_sample } else { 0 });
+    let mut progress = ScanProgress::default();
+    while let Some(entry) = walker.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                scan_errors.push(e.to_string());
+                continue;
+            }
+        };
+        let path = entry.path();
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                scan_errors.push(e.to_string());
+                continue;
+            }
+        };
+        if on_other_device(root_device, device_of(&metadata)) {
+            if metadata.is_dir() {
+                walker.skip_current_dir();
+            }
+            continue;
+        }
+        let stop_at_reparse_point = entry.depth() > 0
+            && options.reparse_points != ReparsePointPolicy::Follow
+            && is_reparse_point(&metadata);
+        if stop_at_reparse_point && metadata.is_dir() {
+            walker.skip_current_dir();
+        }
+        if stop_at_reparse_point && options.reparse_points == ReparsePointPolicy::Skip {
+            continue;
+        }
+        if !metadata.is_dir() && options.max_size.is_some_and(|max_size| metadata.len() > max_size) {
+            continue;
+        }
+        if !metadata.is_dir() && has_excluded_extension(path, &excluded_extensions) {
+            excluded_by_extension_count += 1;
+            excluded_by_extension_bytes += metadata.len();
+            continue;
+        }
+        if options.filter.as_ref().is_some_and(|filter| !filter.accepts(path, &metadata)) {
+            // A reparse point's contents were skipped already, and skipping
+            // again would skip the rest of its parent
+            if metadata.is_dir() && !stop_at_reparse_point {
+                walker.skip_current_dir();
+            }
+            continue;
+        }
+        let file_size = metadata.len();
+        let inode = if options.dedupe_hard_links { inode_of(&metadata) } else { None };
+        let already_counted = options.dedupe_hard_links
+            && hard_link_identity(&metadata).is_some_and(|identity| !seen_hard_links.insert(identity));
+        if already_counted {
+            hard_link_count += 1;
+            hard_link_bytes_saved += file_size;
+        } else {
+            total_size += file_size;
+        }
+        let modified = modified_secs(&metadata);
+        let hash = if options.hash_files && metadata.is_file() && threads > 1 {
+            // Hashed in parallel once the walk is done
+            to_hash.push(files.len());
+            None
+        } else if options.hash_files && metadata.is_file() {
+            match hash_file_with_buffer(path, hash_buffer_size) {
+                Ok(hash) => Some(hash),
+                Err(e) => {
+                    scan_errors.push(format!("{}: {}", path.display(), e));
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let xattrs = if options.record_xattrs {
+            let follow_links = options.reparse_points == ReparsePointPolicy::Follow;
+            match FileEntry::xattrs_of(path, &options.xattr_names, follow_links) {
+                Ok(xattrs) => xattrs,
+                Err(e) => {
+                    scan_errors.push(format!("{}: {}", path.display(), e));
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        files.push(FileEntry {
+            path: path.to_string_lossy().to_string(),
+            size: file_size,
+            modified,
+            is_dir: metadata.is_dir(),
+            inode,
+            hash,
+            child_count: None,
+            file_kind: Some(FileKind::from_file_type(&metadata.file_type())),
+            raw_path: if options.lossless_paths { encode_raw_path(path) } else { None },
+            mode: FileEntry::mode_of(&metadata),
+            created: FileEntry::created_of(&metadata),
+            xattrs,
+        });
+        let spilled = spill.as_ref().map_or(0, |(spill, _)| spill.len());
+        progress.files_scanned = spilled + files.len();
+        progress.current_path = path.to_string_lossy().to_string();
+        progress.total_size = total_size;
+        if metadata.is_dir() {
+            progress.total_dirs += 1;
+        }
+        progress_callback(&progress);
+        if let Some((spill, limit)) = spill.as_mut() {
+            if files.len() >= *limit {
+                hash_pending(&mut files, &mut to_hash, threads, hash_buffer_size, &mut scan_errors);
+                sample.offer(&files);
+                spill.write_run(&mut files)?;
+            }
+        }
+    }
+    hash_pending(&mut files, &mut to_hash, threads, hash_buffer_size, &mut scan_errors);
+    let follow_links = options.reparse_points == ReparsePointPolicy::Follow;
+    let wants_content_filter = options.hash_files && options.content_filter;
+    let (total_files, is_consistent, content_filter) = match spill.as_mut() {
+        Some((spill, _)) => {
+            sample.offer(&files);
+            spill.write_run(&mut files)?;
+            if options.exclude_empty_dirs {
+                total_size -= spill.prune_empty_dirs()?;
+            }
+            let is_consistent = (options.consistency_sample > 0)
+                .then(|| sample_is_consistent(&sample.files, options.consistency_sample, follow_links));
+            let content_filter = if wants_content_filter { Some(spilled_content_filter(spill)?) } else { None };
+            (spill.len(), is_consistent, content_filter)
+        }
+        None => {
+            let is_consistent = (options.consistency_sample > 0)
+                .then(|| sample_is_consistent(&files, options.consistency_sample, follow_links));
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            if options.exclude_empty_dirs {
+                total_size -= prune_empty_dirs(&mut files);
+            }
+            if options.record_child_counts {
+                fill_child_counts(&mut files);
+            }
+            let content_filter = wants_content_filter
+                .then(|| ContentFilter::from_files(&files, CONTENT_FILTER_FALSE_POSITIVE_RATE));
+            (files.len(), is_consistent, content_filter)
+        }
+    };
+    let scan_elapsed = scan_start.elapsed();
+    let scan_duration = scan_elapsed.as_secs();
+    let finished_at = clock.now().duration_since(time::SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let snapshot_id = new_snapshot_id(&drive_path, finished_at);
+    let snapshot = Snapshot {
+        format_version: SNAPSHOT_FORMAT_VERSION,
+        id: snapshot_id,
+        drive_path,
+        timestamp: finished_at.as_secs() as i64,
+        total_files,
+        total_size,
+        scan_duration,
+        files,
+        scan_errors_count: scan_errors.len(),
+        scan_errors,
+        hard_link_count,
+        hard_link_bytes_saved,
+        parent_id: None,
+        is_consistent,
+        excluded_by_extension_count,
+        excluded_by_extension_bytes,
+        content_filter,
+        extracted_from: None,
+        files_per_sec: Some(files_per_sec(total_files, scan_elapsed)),
+        peak_memory_bytes: peak_memory_bytes(),
+        threads: Some(threads),
+    };
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare::compare_snapshots;
+    use crate::fixture::{fixture_dir, relative_paths, scan_dir, write_tree, FixtureSpec, TestDataDir};
+    use crate::snapshot::count_file_kinds;
+    use crate::storage::{load_snapshot, save_snapshot, save_snapshot_metadata, snapshot_lineage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn data_dir_inside_the_scanned_tree_is_skipped_unless_included() {
+        let data_dir = TestDataDir::new();
+        let root = write_tree(&["a.txt", "docs/b.txt", "app-data/snapshots/old.json"]);
+        fs::write(root.path().join("app-data/snapshots/old.json"), "{}").unwrap();
+        data_dir.relocate(&root.path().join("app-data"));
+
+        let skipped = relative_paths(&scan_dir(root.path(), &ScanOptions::default()), root.path());
+        assert!(!skipped.iter().any(|p| p.starts_with("app-data")), "{:?}", skipped);
+        assert!(skipped.contains(&"docs/b.txt".to_string()));
+
+        let options = ScanOptions { include_data_dir: true, ..ScanOptions::default() };
+        let included = relative_paths(&scan_dir(root.path(), &options), root.path());
+        assert!(included.contains(&"app-data/snapshots/old.json".to_string()), "{:?}", included);
+        assert_eq!(included.len(), skipped.len() + 3);
+    }
+
+    /// `path` spelled relative to the working directory, starting with `./`,
+    /// without changing the working directory of the whole test process.
+    fn relative_to_cwd(path: &Path) -> String {
+        let cwd = std::env::current_dir().unwrap();
+        let up = "../".repeat(cwd.components().count() - 1);
+        let below_root: PathBuf = path.components().skip(1).collect();
+        format!("./{}{}", up, below_root.to_string_lossy())
+    }
+
+    #[test]
+    fn canonicalized_scans_match_however_the_root_is_written() {
+        let _data_dir = TestDataDir::new();
+        let root = write_tree(&["d0/a.txt", "d0/b.txt", "d0/sub/c.txt", "d1/a.txt"]);
+        let absolute = fs::canonicalize(root.path().join("d0")).unwrap();
+        let spellings = [
+            relative_to_cwd(&absolute),
+            format!("{}/../d0", absolute.display()),
+            format!("{}/./", absolute.display()),
+        ];
+        let options = ScanOptions { canonicalize_paths: true, ..ScanOptions::default() };
+        let expected = scan_dir(&absolute, &options);
+        assert!(!expected.files.is_empty());
+
+        for spelling in &spellings {
+            let snapshot = scan_drive_with_options(spelling.clone(), &options, |_| {}).unwrap();
+            assert_eq!(snapshot.drive_path, absolute.to_string_lossy(), "{}", spelling);
+            let paths: Vec<&str> = snapshot.files.iter().map(|f| f.path.as_str()).collect();
+            let expected_paths: Vec<&str> = expected.files.iter().map(|f| f.path.as_str()).collect();
+            assert_eq!(paths, expected_paths, "{}", spelling);
+            let comparison = compare_snapshots(&expected, &snapshot);
+            assert_eq!(comparison.added_count + comparison.deleted_count, 0, "{}", spelling);
+        }
+
+        // Without the flag the root is stored as written
+        let relative = scan_drive_with_options(spellings[0].clone(), &ScanOptions::default(), |_| {}).unwrap();
+        assert!(relative.files.iter().all(|f| f.path.starts_with("./")));
+    }
+
+    #[test]
+    fn only_entries_on_another_known_device_are_pruned() {
+        assert!(!on_other_device(Some(2049), Some(2049)));
+        assert!(on_other_device(Some(2049), Some(2050)));
+        assert!(!on_other_device(None, Some(2050)));
+        assert!(!on_other_device(Some(2049), None));
+        assert!(!on_other_device(None, None));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn one_filesystem_leaves_out_entries_on_other_devices() {
+        let _data_dir = TestDataDir::new();
+        let root = write_tree(&["d0/a.txt", "d1/b.txt"]);
+        let options = ScanOptions { one_filesystem: true, ..ScanOptions::default() };
+        let same_device = relative_paths(&scan_dir(root.path(), &options), root.path());
+        assert_eq!(same_device, relative_paths(&scan_dir(root.path(), &ScanOptions::default()), root.path()));
+
+        // Needs /dev mounted apart from / and /dev/shm apart from /dev, as on
+        // most Linux hosts; nothing to check where either boundary is missing
+        let device = |path: &str| fs::metadata(path).ok().and_then(|m| device_of(&m));
+        let dev_device = device("/dev");
+        if !on_other_device(device("/"), dev_device) || !on_other_device(dev_device, device("/dev/shm")) {
+            return;
+        }
+        let snapshot = scan_drive_with_options("/dev".to_string(), &options, |_| {}).unwrap();
+        assert!(!snapshot.files.iter().any(|f| Path::new(&f.path).starts_with("/dev/shm")));
+        assert!(snapshot.files.iter().any(|f| f.path == "/dev"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_links_are_counted_once() {
+        let _data_dir = TestDataDir::new();
+        let root = TempDir::new().unwrap();
+        fs::write(root.path().join("original.dat"), vec![0u8; 1000]).unwrap();
+        fs::hard_link(root.path().join("original.dat"), root.path().join("link.dat")).unwrap();
+
+        let counted_twice = scan_dir(root.path(), &ScanOptions::default());
+        let options = ScanOptions { dedupe_hard_links: true, ..ScanOptions::default() };
+        let deduped = scan_dir(root.path(), &options);
+
+        let root_size = deduped.files.iter().filter(|f| f.is_dir).map(|f| f.size).sum::<u64>();
+        assert_eq!(counted_twice.total_size, root_size + 2000);
+        assert_eq!(deduped.total_size, root_size + 1000);
+        assert_eq!(deduped.hard_link_count, 1);
+        assert_eq!(deduped.hard_link_bytes_saved, 1000);
+        assert_eq!(counted_twice.hard_link_count, 0);
+        let inodes: Vec<Option<u64>> = deduped.files.iter().filter(|f| !f.is_dir).map(|f| f.inode).collect();
+        assert_eq!(inodes.len(), 2);
+        assert!(inodes[0].is_some() && inodes[0] == inodes[1]);
+    }
+
+    #[test]
+    fn a_fixed_clock_gives_a_predictable_timestamp_and_id() {
+        let _data_dir = TestDataDir::new();
+        let root = write_tree(&["d0/a.txt", "d1/b.txt"]);
+        let drive_path = root.path().to_string_lossy().to_string();
+        let scan = |clock: &dyn Clock| scan_drive_with_clock(drive_path.clone(), &ScanOptions::default(), clock, |_| {}).unwrap();
+
+        let first = scan(&FixedClock(1_704_067_200));
+        let second = scan(&FixedClock(1_704_067_200));
+        assert_eq!(first.timestamp, 1_704_067_200);
+        assert_eq!(first.id, second.id);
+        assert_eq!(first.id.len(), 16);
+
+        let later = scan(&FixedClock(1_704_067_201));
+        assert_eq!(later.timestamp, 1_704_067_201);
+        assert_ne!(later.id, first.id);
+    }
+
+    #[test]
+    fn scans_of_a_static_tree_list_files_in_the_same_order() {
+        let _data_dir = TestDataDir::new();
+        let dir = write_tree(&["B.txt", "a.txt", "d0/Z.txt", "d0/a.txt", "d1/b.txt"]);
+        let first = scan_dir(dir.path(), &ScanOptions::default());
+        let second = scan_dir(dir.path(), &ScanOptions::default());
+
+        let paths = |snapshot: &Snapshot| snapshot.files.iter().map(|f| f.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths(&first), paths(&second));
+        let mut sorted = paths(&first);
+        sorted.sort();
+        assert_eq!(paths(&first), sorted, "byte order, so upper case sorts first");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn junctions_are_skipped_or_recorded_without_descending() {
+        let data_dir = TestDataDir::new();
+        let dir = write_tree(&["target/inner.txt"]);
+        let status = std::process::Command::new("cmd")
+            .args(["/C", "mklink", "/J"])
+            .arg(dir.path().join("link"))
+            .arg(dir.path().join("target"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let scan_with = |policy: ReparsePointPolicy| {
+            let options = ScanOptions { reparse_points: policy, ..ScanOptions::default() };
+            relative_paths(&scan_dir(dir.path(), &options), dir.path())
+        };
+        let skipped = scan_with(ReparsePointPolicy::Skip);
+        assert_eq!(skipped, vec!["target", "target/inner.txt"]);
+        let recorded = scan_with(ReparsePointPolicy::Record);
+        assert_eq!(recorded, vec!["link", "target", "target/inner.txt"]);
+    }
+
+    #[test]
+    fn a_scan_links_to_the_latest_earlier_scan_of_its_drive() {
+        let data_dir = TestDataDir::new();
+        let root = write_tree(&["d0/a.txt", "d1/b.txt"]);
+        let other = write_tree(&["d0/a.txt"]);
+        let scan_and_save = |root: &Path, at: i64| {
+            let drive_path = root.to_string_lossy().to_string();
+            let snapshot = scan_drive_with_clock(drive_path, &ScanOptions::default(), &FixedClock(at), |_| {}).unwrap();
+            save_snapshot(data_dir.path(), &snapshot, false, None).unwrap();
+            save_snapshot_metadata(data_dir.path(), &snapshot).unwrap();
+            snapshot
+        };
+
+        let first = scan_and_save(root.path(), 1_000);
+        assert_eq!(first.parent_id, None);
+        let second = scan_and_save(root.path(), 2_000);
+        let other_drive = scan_and_save(other.path(), 3_000);
+        assert_eq!(second.parent_id.as_deref(), Some(first.id.as_str()));
+        assert_eq!(other_drive.parent_id, None);
+
+        fs::write(root.path().join("d0/new.txt"), b"new").unwrap();
+        let third = scan_and_save(root.path(), 4_000);
+        assert_eq!(third.parent_id.as_deref(), Some(second.id.as_str()));
+        let lineage: Vec<String> = snapshot_lineage(data_dir.path(), &third.id).unwrap().into_iter().map(|scan| scan.id).collect();
+        assert_eq!(lineage, vec![third.id.clone(), second.id.clone(), first.id.clone()]);
+
+        // Comparing with the parent shows only what changed since it
+        let parent = load_snapshot(data_dir.path(), third.parent_id.as_deref().unwrap(), None).unwrap();
+        let comparison = compare_snapshots(&parent, &third);
+        let changed: Vec<&str> = comparison.diffs.iter().map(|diff| diff.path.as_str()).collect();
+        assert!(changed.iter().any(|path| path.ends_with("new.txt")), "{:?}", changed);
+        assert_eq!(comparison.added_count, 1);
+        assert_eq!(comparison.deleted_count, 0);
+    }
+
+    #[test]
+    fn files_over_the_size_cap_are_left_out_of_the_totals() {
+        let _data_dir = TestDataDir::new();
+        let (dir, _) = fixture_dir(&FixtureSpec::files(&[("small.dat", 100), ("at-cap.dat", 1000), ("vm/disk.img", 5000)]));
+        let uncapped = scan_dir(dir.path(), &ScanOptions::default());
+        let capped = scan_dir(dir.path(), &ScanOptions { max_size: Some(1000), ..ScanOptions::default() });
+
+        let paths = relative_paths(&capped, dir.path());
+        assert_eq!(paths, vec!["at-cap.dat", "small.dat", "vm"]);
+        assert_eq!(capped.total_files, uncapped.total_files - 1);
+        assert_eq!(capped.total_size, uncapped.total_size - 5000);
+    }
+
+    #[test]
+    fn excluded_extensions_are_left_out_and_tallied() {
+        let _data_dir = TestDataDir::new();
+        let (dir, _) = fixture_dir(&FixtureSpec::files(&[("keep.txt", 10), ("a.tmp", 1000), ("sub/b.LOG", 500), ("sub/tmp", 20)]));
+        let options = ScanOptions { exclude_extensions: vec![".tmp".to_string(), "log".to_string()], ..ScanOptions::default() };
+        let snapshot = scan_dir(dir.path(), &options);
+
+        assert_eq!(relative_paths(&snapshot, dir.path()), vec!["keep.txt", "sub", "sub/tmp"]);
+        assert_eq!(snapshot.excluded_by_extension_count, 2);
+        assert_eq!(snapshot.excluded_by_extension_bytes, 1500);
+
+        let unfiltered = scan_dir(dir.path(), &ScanOptions::default());
+        assert_eq!(snapshot.total_size, unfiltered.total_size - 1500);
+        assert_eq!(unfiltered.excluded_by_extension_count, 0);
+        assert_eq!(unfiltered.excluded_by_extension_bytes, 0);
+    }
+
+    #[test]
+    fn min_depth_leaves_out_the_shallow_levels_and_their_sizes() {
+        let _data_dir = TestDataDir::new();
+        let (root, _) = fixture_dir(&FixtureSpec { depth: 3, dirs_per_dir: 2, files_per_dir: 1, ..FixtureSpec::default() });
+        let depth_of = |path: &String| path.split('/').count();
+        let everything = relative_paths(&scan_dir(root.path(), &ScanOptions::default()), root.path());
+        let deep = scan_dir(root.path(), &ScanOptions { min_depth: Some(2), ..ScanOptions::default() });
+
+        let recorded = relative_paths(&deep, root.path());
+        assert!(recorded.iter().all(|path| depth_of(path) >= 2), "{:?}", recorded);
+        let expected: Vec<String> = everything.into_iter().filter(|path| depth_of(path) >= 2).collect();
+        assert_eq!(recorded, expected);
+        assert!(!recorded.contains(&"f0.dat".to_string()) && !recorded.contains(&"d0".to_string()));
+        assert_eq!(deep.total_files, deep.files.len());
+        assert_eq!(deep.total_size, deep.files.iter().map(|f| f.size).sum::<u64>());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_round_trip_with_lossless_paths() {
+        use std::os::unix::ffi::OsStrExt;
+        let data_dir = TestDataDir::new();
+        let root = tempfile::TempDir::new().unwrap();
+        let names = [&b"a\xff.txt"[..], &b"a\xfe.txt"[..]];
+        for name in names {
+            // Some filesystems (e.g. APFS) refuse names that aren't UTF-8
+            if fs::write(root.path().join(std::ffi::OsStr::from_bytes(name)), name).is_err() {
+                return;
+            }
+        }
+        let options = ScanOptions { lossless_paths: true, hash_files: true, ..ScanOptions::default() };
+        let snapshot = scan_dir(root.path(), &options);
+
+        let files: Vec<&FileEntry> = snapshot.files.iter().filter(|f| !f.is_dir).collect();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, files[1].path, "the lossy names collide");
+        let mut raw_names: Vec<Vec<u8>> = files
+            .iter()
+            .map(|f| f.os_path().file_name().unwrap().as_bytes().to_vec())
+            .collect();
+        raw_names.sort();
+        assert_eq!(raw_names, vec![b"a\xfe.txt".to_vec(), b"a\xff.txt".to_vec()]);
+        assert!(files.iter().all(|f| f.hash.is_some()), "{:?}", snapshot.scan_errors);
+        assert!(snapshot.files.iter().filter(|f| f.is_dir).all(|f| f.raw_path.is_none()));
+
+        crate::storage::save_snapshot_as(data_dir.path(), &snapshot, crate::storage::SnapshotFormat::Binary, None, |_, _| {}).unwrap();
+        let loaded = crate::storage::load_snapshot(data_dir.path(), &snapshot.id, None).unwrap();
+        let raw_paths = |snapshot: &Snapshot| snapshot.files.iter().map(|f| f.raw_path.clone()).collect::<Vec<_>>();
+        assert_eq!(raw_paths(&loaded), raw_paths(&snapshot));
+
+        let lossy = ScanOptions { lossless_paths: false, ..options.clone() };
+        let lossy_before = scan_dir(root.path(), &lossy);
+        fs::remove_file(root.path().join(std::ffi::OsStr::from_bytes(names[0]))).unwrap();
+        let comparison = crate::compare::compare_snapshots(&loaded, &scan_dir(root.path(), &options));
+        assert_eq!((comparison.deleted_count, comparison.added_count), (1, 0));
+        // Without raw paths the two names are one key, and the deletion is missed
+        let lossy_comparison = crate::compare::compare_snapshots(&lossy_before, &scan_dir(root.path(), &lossy));
+        assert_eq!(lossy_comparison.deleted_count, 0);
+    }
+
+    #[test]
+    fn spilled_scans_hold_a_bounded_batch_and_save_every_entry() {
+        let data_dir = TestDataDir::new();
+        let (root, files) = fixture_dir(&FixtureSpec { depth: 2, dirs_per_dir: 2, files_per_dir: 3, ..FixtureSpec::default() });
+        let drive_path = root.path().to_string_lossy().to_string();
+        let options = ScanOptions { hash_files: true, max_entries_in_memory: Some(5), ..ScanOptions::default() };
+
+        let mut spill = EntrySpill::create(&data_dir.path().join("tmp"), false, false).unwrap();
+        let spilled = scan_entries(drive_path.clone(), &options, &SystemClock, Some((&mut spill, 5)), |_| {}).unwrap();
+        assert!(spilled.files.is_empty());
+        assert!(spill.len() > files.len());
+        assert!(spill.largest_run() <= 5, "{}", spill.largest_run());
+        drop(spill);
+
+        let in_memory = scan_dir(root.path(), &ScanOptions { hash_files: true, ..ScanOptions::default() });
+        let stored = scan_drive_to_storage(data_dir.path(), drive_path, &options, SnapshotFormat::Binary, None, true, |_| {}).unwrap();
+        assert!(stored.files.is_empty());
+        assert_eq!((stored.total_files, stored.total_size), (in_memory.total_files, in_memory.total_size));
+        let loaded = load_snapshot(data_dir.path(), &stored.id, None).unwrap();
+        let entries = |snapshot: &Snapshot| {
+            snapshot.files.iter().map(|f| (f.path.clone(), f.size, f.is_dir, f.hash.clone())).collect::<Vec<_>>()
+        };
+        assert_eq!(entries(&loaded), entries(&in_memory));
+        // The spill files are gone once the scan is saved
+        assert_eq!(fs::read_dir(data_dir.path().join("tmp")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn directories_emptied_by_filters_follow_the_empty_dirs_flag() {
+        let data_dir = TestDataDir::new();
+        let root = write_tree(&["keep/a.txt", "logs/only.log", "nested/inner/x.tmp"]);
+        fs::create_dir(root.path().join("empty")).unwrap();
+        let filtered = ScanOptions { exclude_extensions: vec!["log".to_string(), "tmp".to_string()], ..ScanOptions::default() };
+
+        let kept = scan_dir(root.path(), &filtered);
+        assert_eq!(relative_paths(&kept, root.path()), vec!["empty", "keep", "keep/a.txt", "logs", "nested", "nested/inner"]);
+
+        let pruned_options = ScanOptions { exclude_empty_dirs: true, ..filtered };
+        let pruned = scan_dir(root.path(), &pruned_options);
+        assert_eq!(relative_paths(&pruned, root.path()), vec!["keep", "keep/a.txt"]);
+        assert_eq!(pruned.total_files, pruned.files.len());
+        let dropped: u64 = kept.files.iter().filter(|f| !pruned.files.iter().any(|p| p.path == f.path)).map(|f| f.size).sum();
+        assert_eq!(pruned.total_size, kept.total_size - dropped);
+
+        // A spilled scan decides emptiness the same way
+        let spilled_options = ScanOptions { max_entries_in_memory: Some(2), ..pruned_options };
+        let drive_path = root.path().to_string_lossy().to_string();
+        let spilled = scan_drive_to_storage(data_dir.path(), drive_path, &spilled_options, SnapshotFormat::Json, None, false, |_| {}).unwrap();
+        assert_eq!((spilled.total_files, spilled.total_size), (pruned.total_files, pruned.total_size));
+        let loaded = load_snapshot(data_dir.path(), &spilled.id, None).unwrap();
+        assert_eq!(relative_paths(&loaded, root.path()), vec!["keep", "keep/a.txt"]);
+    }
+
+    #[test]
+    fn a_custom_filter_skips_entries_by_a_combined_rule() {
+        let _data_dir = TestDataDir::new();
+        let (root, _) = fixture_dir(&FixtureSpec::files(&[
+            ("a/small.log", 50),
+            ("a/big.log", 500),
+            ("b/big.dat", 500),
+            ("node_modules/pkg/index.log", 1),
+            ("notes.txt", 1000),
+        ]));
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen_by_filter = Arc::clone(&seen);
+        // Large logs go, and so does everything in node_modules
+        let filter = EntryFilter::new(move |path, metadata| {
+            seen_by_filter.lock().unwrap().push(path.to_path_buf());
+            let large_log = path.extension().is_some_and(|ext| ext == "log") && metadata.len() > 100;
+            !large_log && path.file_name().is_none_or(|name| name != "node_modules")
+        });
+        let options = ScanOptions { filter: Some(filter), exclude_extensions: vec!["txt".to_string()], ..ScanOptions::default() };
+        let snapshot = scan_dir(root.path(), &options);
+
+        assert_eq!(relative_paths(&snapshot, root.path()), vec!["a", "a/small.log", "b", "b/big.dat"]);
+        assert_eq!(snapshot.excluded_by_extension_count, 1);
+        // Built-in filters run first, and a rejected directory isn't descended into
+        let seen = seen.lock().unwrap();
+        assert!(!seen.iter().any(|path| path.ends_with("notes.txt")));
+        assert!(!seen.iter().any(|path| path.ends_with("pkg") || path.ends_with("index.log")));
+        assert!(seen.iter().any(|path| path.ends_with("node_modules")));
+    }
+
+    #[test]
+    fn running_totals_in_progress_never_decrease() {
+        let data_dir = TestDataDir::new();
+        let root = write_tree(&["a/1.txt", "a/2.txt", "a/x/3.txt", "a/x/4.txt", "b/5.txt", "b/y/6.txt", "b/y/7.txt", "8.txt"]);
+        let drive_path = root.path().to_string_lossy().to_string();
+        for max_entries_in_memory in [None, Some(4)] {
+            let options = ScanOptions { max_entries_in_memory, ..ScanOptions::default() };
+            let mut events: Vec<ScanProgress> = Vec::new();
+            let snapshot = scan_drive_to_storage(data_dir.path(), drive_path.clone(), &options, SnapshotFormat::Binary, None, false, |progress| {
+                events.push(progress.clone())
+            })
+            .unwrap();
+
+            assert!(events.windows(2).all(|pair| {
+                pair[1].files_scanned == pair[0].files_scanned + 1
+                    && pair[1].total_size >= pair[0].total_size
+                    && pair[1].total_dirs >= pair[0].total_dirs
+            }));
+            let last = events.last().unwrap();
+            assert_eq!((last.files_scanned, last.total_size), (snapshot.total_files, snapshot.total_size));
+            let dirs = crate::storage::load_snapshot(data_dir.path(), &snapshot.id, None).unwrap().files.iter().filter(|f| f.is_dir).count();
+            assert_eq!(last.total_dirs, dirs);
+        }
+    }
+
+    #[test]
+    fn throughput_is_the_file_count_over_the_elapsed_time() {
+        assert_eq!(files_per_sec(1000, time::Duration::from_secs(2)), 500.0);
+        assert_eq!(files_per_sec(3, time::Duration::from_millis(1500)), 2.0);
+        assert_eq!(files_per_sec(0, time::Duration::from_secs(5)), 0.0);
+        // Too quick to time counts as a millisecond rather than dividing by zero
+        assert_eq!(files_per_sec(7, time::Duration::ZERO), 7000.0);
+
+        let data_dir = TestDataDir::new();
+        let root = write_tree(&["a/1.txt", "a/2.txt", "b/3.txt"]);
+        let drive_path = root.path().to_string_lossy().to_string();
+        let snapshot =
+            scan_drive_to_storage(data_dir.path(), drive_path, &ScanOptions::default(), SnapshotFormat::Json, None, true, |_| {}).unwrap();
+        assert!(snapshot.files_per_sec.is_some_and(|rate| rate > 0.0));
+        let summary = crate::storage::get_scan_history(data_dir.path()).unwrap().into_iter().find(|summary| summary.id == snapshot.id).unwrap();
+        assert_eq!(summary.files_per_sec, snapshot.files_per_sec);
+        assert_eq!(summary.peak_memory_bytes, snapshot.peak_memory_bytes);
+    }
+
+    #[test]
+    fn a_file_changed_after_it_was_recorded_fails_the_consistency_check() {
+        let _data_dir = TestDataDir::new();
+        let files = ["d0/a.txt", "d0/b.txt", "d1/a.txt"];
+        let dir = write_tree(&files);
+        let options = ScanOptions { consistency_sample: files.len(), ..ScanOptions::default() };
+        let snapshot = scan_dir(dir.path(), &options);
+        assert_eq!(snapshot.is_consistent, Some(true));
+        assert_eq!(scan_dir(dir.path(), &ScanOptions::default()).is_consistent, None);
+
+        // As if a backup rewrote the file while the scan was still running
+        fs::write(dir.path().join(files[0]), vec![1u8; files[0].len() + 10]).unwrap();
+        assert!(!sample_is_consistent(&snapshot.files, files.len(), false));
+        fs::remove_file(dir.path().join(files[0])).unwrap();
+        assert!(!sample_is_consistent(&snapshot.files, files.len(), false));
+        assert!(sample_is_consistent(&snapshot.files, 0, false));
+    }
+
+    #[test]
+    fn hashes_do_not_depend_on_the_buffer_size() {
+        let _data_dir = TestDataDir::new();
+        let root = TempDir::new().unwrap();
+        fs::write(root.path().join("empty.dat"), b"").unwrap();
+        let big: Vec<u8> = (0..3 * DEFAULT_HASH_BUFFER_SIZE + 17).map(|i| (i % 251) as u8).collect();
+        fs::write(root.path().join("big.dat"), &big).unwrap();
+        for name in ["empty.dat", "big.dat"] {
+            let path = root.path().join(name);
+            let expected = format!("{:x}", Sha256::digest(fs::read(&path).unwrap()));
+            for buffer_size in [0, 1, 7, 4096, DEFAULT_HASH_BUFFER_SIZE, 1 << 20] {
+                assert_eq!(hash_file_with_buffer(&path, buffer_size).unwrap(), expected, "{} with {}", name, buffer_size);
+            }
+        }
+
+        let hashes = |hash_buffer_size: usize| {
+            let options = ScanOptions { hash_files: true, hash_buffer_size, ..ScanOptions::default() };
+            scan_dir(root.path(), &options).files.into_iter().map(|f| f.hash).collect::<Vec<_>>()
+        };
+        assert_eq!(hashes(512), hashes(0));
+        assert_eq!(ScanOptions::default().effective_hash_buffer_size(), DEFAULT_HASH_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn throttled_progress_reports_the_first_and_last_items_and_waits_in_between() {
+        let mut throttle = ProgressThrottle::default();
+        let reported: Vec<usize> = (0..=1000).filter(|&done| throttle.ready(done, 1000)).collect();
+        // A thousand quick items fit in one interval
+        assert_eq!(reported, vec![0, 1000]);
+
+        let mut throttle = ProgressThrottle::default();
+        assert!(throttle.ready(0, 3));
+        assert!(!throttle.ready(1, 3));
+        std::thread::sleep(PROGRESS_INTERVAL);
+        assert!(throttle.ready(2, 3));
+        assert!(throttle.ready(3, 3));
+    }
+
+    #[test]
+    fn auto_threads_resolve_to_a_positive_count_and_explicit_ones_are_kept() {
+        let auto = ScanOptions::default().effective_threads();
+        assert!(auto > 0);
+        assert_eq!(ScanOptions { threads: Some(0), ..ScanOptions::default() }.effective_threads(), auto);
+        assert_eq!(ScanOptions { threads: Some(3), ..ScanOptions::default() }.effective_threads(), 3);
+        assert_eq!(ScanOptions { threads: Some(1), ..ScanOptions::default() }.effective_threads(), 1);
+
+        // Hashing on several threads records what a serial scan does
+        let data_dir = TestDataDir::new();
+        let root = write_tree(&["a.txt", "b.txt", "d0/a.txt", "d0/b.txt", "d1/a.txt"]);
+        let hashes = |threads: usize| {
+            let options = ScanOptions { hash_files: true, threads: Some(threads), ..ScanOptions::default() };
+            scan_dir(root.path(), &options).files.into_iter().map(|f| (f.path, f.hash)).collect::<Vec<_>>()
+        };
+        let serial = hashes(1);
+        assert!(serial.iter().filter(|(_, hash)| hash.is_some()).count() > 1);
+        assert_eq!(hashes(4), serial);
+
+        // The count actually used is recorded, and reaches the history
+        let drive_path = root.path().to_string_lossy().to_string();
+        let snapshot =
+            scan_drive_to_storage(data_dir.path(), drive_path, &ScanOptions::default(), SnapshotFormat::Json, None, true, |_| {}).unwrap();
+        assert_eq!(snapshot.threads, Some(auto));
+        let summary = crate::storage::get_scan_history(data_dir.path()).unwrap().into_iter().find(|summary| summary.id == snapshot.id).unwrap();
+        assert_eq!(summary.threads, Some(auto));
+        let options = ScanOptions { threads: Some(3), ..ScanOptions::default() };
+        assert_eq!(scan_dir(root.path(), &options).threads, Some(3));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_files_are_recorded_with_their_kind() {
+        let _data_dir = TestDataDir::new();
+        let root = write_tree(&["sub/plain.txt"]);
+        std::os::unix::fs::symlink("sub/plain.txt", root.path().join("link")).unwrap();
+        let status = std::process::Command::new("mkfifo").arg(root.path().join("pipe")).status().unwrap();
+        assert!(status.success());
+        let _listener = std::os::unix::net::UnixListener::bind(root.path().join("socket")).unwrap();
+
+        let snapshot = scan_dir(root.path(), &ScanOptions::default());
+        let kind_of = |relative: &str| {
+            let path = root.path().join(relative).to_string_lossy().to_string();
+            snapshot.files.iter().find(|f| f.path == path).and_then(|f| f.file_kind)
+        };
+        assert_eq!(kind_of("link"), Some(FileKind::Symlink));
+        assert_eq!(kind_of("pipe"), Some(FileKind::Fifo));
+        assert_eq!(kind_of("socket"), Some(FileKind::Socket));
+        assert_eq!(kind_of("sub"), Some(FileKind::Dir));
+        assert_eq!(kind_of("sub/plain.txt"), Some(FileKind::File));
+        assert_eq!(
+            count_file_kinds(&snapshot.files),
+            vec![(FileKind::File, 1), (FileKind::Dir, 2), (FileKind::Symlink, 1), (FileKind::Fifo, 1), (FileKind::Socket, 1)]
+        );
+    }
+
+    #[test]
+    fn eta_follows_a_steady_rate_and_smooths_a_slowdown() {
+        let secs = time::Duration::from_secs;
+        let mut eta = EtaEstimator::default();
+        assert_eq!(eta.update(10, Some(2000), time::Duration::from_millis(100)), None, "nothing measured yet");
+
+        // 100 entries a second
+        let mut last = None;
+        for t in 1..=10 {
+            last = eta.update(100 * t as usize, Some(2000), secs(t));
+        }
+        assert!((last.unwrap().as_secs_f64() - 10.0).abs() < 0.01, "{:?}", last);
+
+        // Down to 10 a second: the estimate grows, but not straight to the new rate's 99s
+        let mut estimates = Vec::new();
+        for t in 11..=15 {
+            estimates.push(eta.update(1000 + 10 * (t as usize - 10), Some(2000), secs(t)).unwrap().as_secs_f64());
+        }
+        assert!(estimates.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", estimates);
+        assert!(estimates[0] > 10.0 && estimates[4] < 99.0, "{:?}", estimates);
+
+        assert_eq!(eta.update(1100, None, secs(16)), None, "no total");
+        assert_eq!(eta.update(2500, Some(2000), secs(17)), None, "past the total");
+        assert_eq!(eta.update(2000, Some(2000), secs(18)), Some(time::Duration::ZERO));
+    }
+
+    #[test]
+    fn excluded_paths_are_skipped_with_everything_below_them() {
+        let _data_dir = TestDataDir::new();
+        let root = write_tree(&["a.txt", "export/out.csv", "export/old/out.csv"]);
+        let options = ScanOptions {
+            exclude_paths: vec![root.path().join("export").to_string_lossy().to_string()],
+            ..ScanOptions::default()
+        };
+        assert_eq!(relative_paths(&scan_dir(root.path(), &options), root.path()), vec!["a.txt".to_string()]);
+    }
+}
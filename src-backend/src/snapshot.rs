@@ -0,0 +1,1107 @@
+//! Snapshot data model, its on-disk layout versions, and `SnapshotBuilder`.
+
+use bincode::Options;
+use serde::de::{self, DeserializeSeed, Deserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use sha2::{Sha256, Digest};
+use std::time;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileEntry {
+    pub path: String,
+    pub size: u64,
+    pub modified: i64,
+    pub is_dir: bool,
+    /// Inode number, recorded on Unix when `ScanOptions::dedupe_hard_links` is set.
+    #[serde(default)]
+    pub inode: Option<u64>,
+    /// Hex SHA-256 of the file contents, recorded when `ScanOptions::hash_files` is set.
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// Number of immediate children recorded under a directory, when
+    /// `ScanOptions::record_child_counts` is set.
+    #[serde(default)]
+    pub child_count: Option<usize>,
+    /// What kind of entry this is. `None` in scans from before kinds were
+    /// recorded, where only `is_dir` is known; see `FileEntry::kind`.
+    #[serde(default)]
+    pub file_kind: Option<FileKind>,
+    /// The exact path, see `encode_raw_path`, when it isn't valid Unicode and
+    /// `ScanOptions::lossless_paths` is set. `path` then holds a lossy form
+    /// for display only.
+    #[serde(default)]
+    pub raw_path: Option<String>,
+    /// Permission bits (`st_mode & 0o7777`), recorded on Unix. `None` on other
+    /// platforms and in older scans.
+    #[serde(default)]
+    pub mode: Option<u32>,
+    /// Creation (birth) time in unix seconds, where the platform and
+    /// filesystem report it. `None` otherwise and in older scans.
+    #[serde(default)]
+    pub created: Option<i64>,
+    /// Extended attributes by name, recorded on Unix when
+    /// `ScanOptions::record_xattrs` is set; see `encode_xattr_value` for the
+    /// values. `None` when not recorded or the filesystem has no xattrs.
+    #[serde(default)]
+    pub xattrs: Option<BTreeMap<String, String>>,
+}
+
+impl FileEntry {
+    /// The extended attributes of `path` as recorded in `xattrs`: only those
+    /// in `names`, or all of them if it is empty. Symlinks are read themselves
+    /// unless `follow_links` is set. `Ok(None)` where xattrs aren't supported.
+    pub fn xattrs_of(path: &Path, names: &[String], follow_links: bool) -> std::io::Result<Option<BTreeMap<String, String>>> {
+        #[cfg(unix)]
+        {
+            let get = |name: &std::ffi::OsStr| if follow_links { xattr::get_deref(path, name) } else { xattr::get(path, name) };
+            let listed = if follow_links { xattr::list_deref(path) } else { xattr::list(path) };
+            let listed: Vec<std::ffi::OsString> = match listed {
+                Ok(listed) => listed.collect(),
+                Err(e) if e.kind() == std::io::ErrorKind::Unsupported => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let mut xattrs = BTreeMap::new();
+            for name in listed {
+                let wanted = names.is_empty() || names.iter().any(|wanted| name == wanted.as_str());
+                // Removed since it was listed if there is no value
+                if let (true, Some(value)) = (wanted, get(&name)?) {
+                    xattrs.insert(name.to_string_lossy().into_owned(), encode_xattr_value(&value));
+                }
+            }
+            Ok(Some(xattrs))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (path, names, follow_links);
+            Ok(None)
+        }
+    }
+
+    /// The creation time of `metadata` as recorded in `created`.
+    pub fn created_of(metadata: &fs::Metadata) -> Option<i64> {
+        let created = metadata.created().ok()?;
+        Some(created.duration_since(time::SystemTime::UNIX_EPOCH).ok()?.as_secs() as i64)
+    }
+
+    /// The permission bits of `metadata` as recorded in `mode`.
+    pub fn mode_of(metadata: &fs::Metadata) -> Option<u32> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            Some(metadata.permissions().mode() & 0o7777)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = metadata;
+            None
+        }
+    }
+
+    /// The recorded kind, or the best guess from `is_dir` for older scans.
+    pub fn kind(&self) -> FileKind {
+        self.file_kind.unwrap_or(if self.is_dir { FileKind::Dir } else { FileKind::File })
+    }
+
+    /// What identifies the entry when matching snapshots: the raw path if one
+    /// was recorded, so two names that only differ in invalid bytes stay apart.
+    /// Raw paths are hex and never contain a separator, so they can't collide
+    /// with a recorded `path`.
+    pub fn path_key(&self) -> &str {
+        self.raw_path.as_deref().unwrap_or(&self.path)
+    }
+
+    /// The path to open the entry on disk, exact when a raw path was recorded.
+    pub fn os_path(&self) -> PathBuf {
+        self.raw_path.as_deref().and_then(decode_raw_path).unwrap_or_else(|| PathBuf::from(&self.path))
+    }
+}
+
+/// Hex encoding of `path`'s exact bytes (UTF-16 code units, little-endian, on
+/// Windows), or `None` when it is valid Unicode and `to_string_lossy` loses
+/// nothing.
+pub fn encode_raw_path(path: &Path) -> Option<String> {
+    if path.to_str().is_some() {
+        return None;
+    }
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec();
+    #[cfg(windows)]
+    let bytes: Vec<u8> = std::os::windows::ffi::OsStrExt::encode_wide(path.as_os_str()).flat_map(u16::to_le_bytes).collect();
+    #[cfg(not(any(unix, windows)))]
+    let bytes = path.to_string_lossy().into_owned().into_bytes();
+    Some(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// An extended attribute value as stored in `FileEntry::xattrs`: printable
+/// UTF-8 as it is, anything else (e.g. binary ACLs) as hex behind `hex:`.
+pub fn encode_xattr_value(value: &[u8]) -> String {
+    match std::str::from_utf8(value) {
+        Ok(text) if !text.starts_with("hex:") && !text.chars().any(char::is_control) => text.to_string(),
+        _ => format!("hex:{}", value.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+    }
+}
+
+/// Reverses `encode_raw_path`, or `None` if `raw` isn't a valid encoding.
+pub fn decode_raw_path(raw: &str) -> Option<PathBuf> {
+    if !raw.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..raw.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(raw.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    #[cfg(unix)]
+    return Some(PathBuf::from(<std::ffi::OsString as std::os::unix::ffi::OsStringExt>::from_vec(bytes)));
+    #[cfg(windows)]
+    {
+        if !bytes.len().is_multiple_of(2) {
+            return None;
+        }
+        let wide: Vec<u16> = bytes.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        return Some(PathBuf::from(<std::ffi::OsString as std::os::windows::ffi::OsStringExt>::from_wide(&wide)));
+    }
+    #[cfg(not(any(unix, windows)))]
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    Socket,
+    /// Block or character device.
+    Device,
+}
+
+impl FileKind {
+    pub fn from_file_type(file_type: &fs::FileType) -> FileKind {
+        if file_type.is_dir() {
+            return FileKind::Dir;
+        }
+        if file_type.is_symlink() {
+            return FileKind::Symlink;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if file_type.is_fifo() {
+                return FileKind::Fifo;
+            }
+            if file_type.is_socket() {
+                return FileKind::Socket;
+            }
+            if file_type.is_block_device() || file_type.is_char_device() {
+                return FileKind::Device;
+            }
+        }
+        FileKind::File
+    }
+
+    /// Lowercase name, matching the serialized form.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileKind::File => "file",
+            FileKind::Dir => "dir",
+            FileKind::Symlink => "symlink",
+            FileKind::Fifo => "fifo",
+            FileKind::Socket => "socket",
+            FileKind::Device => "device",
+        }
+    }
+}
+
+/// Number of entries of each kind, in `FileKind` order, leaving out kinds with
+/// no entries.
+pub fn count_file_kinds(files: &[FileEntry]) -> Vec<(FileKind, usize)> {
+    let mut counts: std::collections::BTreeMap<FileKind, usize> = std::collections::BTreeMap::new();
+    for file in files {
+        *counts.entry(file.kind()).or_default() += 1;
+    }
+    counts.into_iter().collect()
+}
+
+/// Hex SHA-256 of what a scan found: the drive path and every entry's path,
+/// kind, size, modification time and content hash. The id, timestamp and scan
+/// duration are left out, so two scans of an unchanged tree share a fingerprint.
+pub fn content_fingerprint(snapshot: &Snapshot) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(snapshot.drive_path.as_bytes());
+    for file in &snapshot.files {
+        hasher.update([0]);
+        hasher.update(file.path.as_bytes());
+        hasher.update([0]);
+        hasher.update(file.kind().as_str().as_bytes());
+        hasher.update(file.size.to_le_bytes());
+        hasher.update(file.modified.to_le_bytes());
+        hasher.update(file.hash.as_deref().unwrap_or("").as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Target false-positive rate of the content filters built by scans.
+pub const CONTENT_FILTER_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Bloom filter over content hashes, answering "might this snapshot hold a file
+/// with this content?" in about 10 bits per distinct hash at a 1% rate. A
+/// `false` answer is certain; a `true` one is wrong at about
+/// `false_positive_rate()`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ContentFilter {
+    bits: Vec<u64>,
+    hash_count: u32,
+    items: usize,
+}
+
+impl ContentFilter {
+    /// An empty filter sized for `expected_items` hashes at `false_positive_rate`.
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let items = expected_items.max(1) as f64;
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = (-items * rate.ln() / (ln2 * ln2)).ceil().max(64.0);
+        let hash_count = (bit_count / items * ln2).round().clamp(1.0, 32.0) as u32;
+        ContentFilter { bits: vec![0; (bit_count as usize).div_ceil(64)], hash_count, items: 0 }
+    }
+
+    /// A filter of the distinct content hashes among `files`.
+    pub fn from_files(files: &[FileEntry], false_positive_rate: f64) -> Self {
+        let hashes: HashSet<&str> = files.iter().filter(|f| !f.is_dir).filter_map(|f| f.hash.as_deref()).collect();
+        let mut filter = ContentFilter::new(hashes.len(), false_positive_rate);
+        for hash in hashes {
+            filter.insert(hash);
+        }
+        filter
+    }
+
+    pub fn insert(&mut self, hash: &str) {
+        for bit in self.bit_indexes(hash) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.items += 1;
+    }
+
+    /// Whether `hash` may have been inserted. Hex case doesn't matter.
+    pub fn contains(&self, hash: &str) -> bool {
+        !self.bits.is_empty() && self.bit_indexes(hash).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Number of hashes inserted.
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// Expected chance that `contains` is true for a hash never inserted,
+    /// given how full the filter is.
+    pub fn false_positive_rate(&self) -> f64 {
+        let bit_count = (self.bits.len() * 64) as f64;
+        let hash_count = self.hash_count as f64;
+        (1.0 - (-hash_count * self.items as f64 / bit_count).exp()).powf(hash_count)
+    }
+
+    /// Bit positions for `hash` by double hashing two halves of its SHA-256,
+    /// so any string spreads evenly, not only hex digests.
+    fn bit_indexes(&self, hash: &str) -> impl Iterator<Item = usize> {
+        let digest = Sha256::digest(hash.to_ascii_lowercase().as_bytes());
+        let first = u64::from_le_bytes(digest[..8].try_into().unwrap());
+        let step = u64::from_le_bytes(digest[8..16].try_into().unwrap()) | 1;
+        let bit_count = (self.bits.len() * 64).max(1) as u64;
+        (0..self.hash_count as u64).map(move |i| (first.wrapping_add(i.wrapping_mul(step)) % bit_count) as usize)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    /// Layout version the snapshot was written with, see `SNAPSHOT_FORMAT_VERSION`.
+    /// Kept as the first field so binary payloads start with it.
+    #[serde(default)]
+    pub format_version: u32,
+    pub id: String,
+    pub drive_path: String,
+    pub timestamp: i64,
+    pub total_files: usize,
+    pub total_size: u64,
+    pub scan_duration: u64,
+    /// Entries sorted by `path` in byte order, so scans of an unchanged tree are
+    /// identical regardless of directory traversal order.
+    pub files: Vec<FileEntry>,
+    /// Number of entries that could not be read during the scan.
+    #[serde(default)]
+    pub scan_errors_count: usize,
+    /// Messages for the unreadable entries (may be a sample, see `scan_errors_count`).
+    #[serde(default)]
+    pub scan_errors: Vec<String>,
+    /// Extra links to files that were already counted, when hard links are deduplicated.
+    #[serde(default)]
+    pub hard_link_count: usize,
+    /// Bytes left out of `total_size` because they belonged to an already counted inode.
+    #[serde(default)]
+    pub hard_link_bytes_saved: u64,
+    /// The previous snapshot of the same drive, if there was one at scan time.
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// Result of the post-scan consistency check (`ScanOptions::consistency_sample`):
+    /// false if sampled files changed while the scan ran, `None` if not checked.
+    #[serde(default)]
+    pub is_consistent: Option<bool>,
+    /// Files left out because of `ScanOptions::exclude_extensions`.
+    #[serde(default)]
+    pub excluded_by_extension_count: usize,
+    /// Bytes in those files, not part of `total_size`.
+    #[serde(default)]
+    pub excluded_by_extension_bytes: u64,
+    /// Bloom filter of the files' content hashes, see
+    /// `ScanOptions::content_filter`. `None` unless requested at scan time.
+    #[serde(default)]
+    pub content_filter: Option<ContentFilter>,
+    /// Id of the snapshot this one was cut out of by `subset_snapshot`.
+    /// `None` for scans.
+    #[serde(default)]
+    pub extracted_from: Option<String>,
+    /// Entries scanned per second, from the scan's exact elapsed time rather
+    /// than the whole seconds of `scan_duration`. `None` for snapshots that
+    /// weren't scanned directly, e.g. extracts, and for older scans.
+    #[serde(default)]
+    pub files_per_sec: Option<f64>,
+    /// Peak resident memory of the scanning process in bytes, where the
+    /// platform reports it (Linux). A process-wide high-water mark, so it
+    /// also covers whatever the process did before the scan.
+    #[serde(default)]
+    pub peak_memory_bytes: Option<u64>,
+    /// Threads the scan hashed files on, see `ScanOptions::effective_threads`.
+    /// `None` for snapshots that weren't scanned directly and for older scans.
+    #[serde(default)]
+    pub threads: Option<usize>,
+}
+
+impl Snapshot {
+    /// Whether some entries couldn't be read, so the snapshot may be missing
+    /// files that are on the drive.
+    pub fn is_partial(&self) -> bool {
+        self.scan_errors_count > 0
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SnapshotSummary {
+    pub id: String,
+    pub drive_path: String,
+    pub timestamp: i64,
+    pub total_files: usize,
+    pub total_size: u64,
+    pub scan_duration: u64,
+    /// Whether the snapshot file needs a password to load.
+    #[serde(default)]
+    pub encrypted: bool,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// Whether the snapshot file is compressed.
+    #[serde(default)]
+    pub compressed: bool,
+    /// Layout version the snapshot file was written with, `None` when the
+    /// sidecar predates this field.
+    #[serde(default)]
+    pub format_version: Option<u32>,
+    /// Whether `drive_path` currently exists. Not stored; only filled in by
+    /// `check_drive_paths`, since stat-ing an unreachable network path is slow.
+    #[serde(default)]
+    pub drive_present: Option<bool>,
+    /// Locked scans are kept by `delete_snapshot` and `delete_snapshots_where`
+    /// unless forced, see `set_snapshot_locked`.
+    #[serde(default)]
+    pub locked: bool,
+    /// Copied from the snapshot, see `Snapshot::files_per_sec`.
+    #[serde(default)]
+    pub files_per_sec: Option<f64>,
+    /// Copied from the snapshot, see `Snapshot::peak_memory_bytes`.
+    #[serde(default)]
+    pub peak_memory_bytes: Option<u64>,
+    /// Copied from the snapshot, see `Snapshot::threads`.
+    #[serde(default)]
+    pub threads: Option<usize>,
+}
+
+/// Current snapshot layout version. Bump it whenever a field is added to
+/// `Snapshot` or `FileEntry`, and read the field from that version on in
+/// `LegacySnapshot` or `LegacyFileEntry`: JSON files fill new fields from
+/// their serde defaults, but bincode is positional, so older payloads have to
+/// be read knowing which fields they lack.
+///
+/// - 0: original layout, stored headerless when encrypted and without the
+///   scan error, hard link, inode, hash and child count fields.
+/// - 1: adds `format_version` and the fields above.
+/// - 2: adds `Snapshot::parent_id`.
+/// - 3: adds `Snapshot::is_consistent`.
+/// - 4: adds `FileEntry::file_kind`.
+/// - 5: adds `Snapshot::excluded_by_extension_count` and `excluded_by_extension_bytes`.
+/// - 6: adds `Snapshot::content_filter`.
+/// - 7: adds `FileEntry::raw_path`.
+/// - 8: adds `Snapshot::extracted_from`.
+/// - 9: adds `Snapshot::files_per_sec` and `peak_memory_bytes`.
+/// - 10: adds `FileEntry::mode`.
+/// - 11: adds `FileEntry::created`.
+/// - 12: adds `FileEntry::xattrs`.
+/// - 13: adds `Snapshot::threads`.
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 13;
+
+/// Decodes a bincode payload written with format `version`, reading only the
+/// fields that version had; the others keep their defaults. Version 0 payloads
+/// don't start with a `format_version`.
+pub(crate) fn decode_versioned_payload<R: Read>(version: u32, payload: R) -> Result<Snapshot, String> {
+    // The options `bincode::deserialize_from` uses
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .deserialize_from_seed(LegacySnapshot(version), payload)
+        .map_err(deserialize_error)
+}
+
+/// Reads the fields of one struct in order, see `decode_versioned_payload`.
+struct LegacyFields<'a, A> {
+    seq: &'a mut A,
+    version: u32,
+}
+
+impl<'de, A: SeqAccess<'de>> LegacyFields<'_, A> {
+    fn next<T: Deserialize<'de>>(&mut self) -> Result<T, A::Error> {
+        self.next_seed(PhantomData)
+    }
+
+    fn next_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value, A::Error> {
+        self.seq.next_element_seed(seed)?.ok_or_else(|| de::Error::custom("snapshot ended early"))
+    }
+
+    /// A field added in format `version`, left at its default before that.
+    fn since<T: Deserialize<'de> + Default>(&mut self, version: u32) -> Result<T, A::Error> {
+        if self.version >= version {
+            self.next()
+        } else {
+            Ok(T::default())
+        }
+    }
+}
+
+/// `Snapshot` as laid out by format version `.0`.
+struct LegacySnapshot(u32);
+
+impl<'de> DeserializeSeed<'de> for LegacySnapshot {
+    type Value = Snapshot;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Snapshot, D::Error> {
+        // bincode reads a struct as a tuple of as many fields as are asked for
+        deserializer.deserialize_tuple(usize::MAX, self)
+    }
+}
+
+impl<'de> Visitor<'de> for LegacySnapshot {
+    type Value = Snapshot;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a format version {} snapshot", self.0)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Snapshot, A::Error> {
+        let mut fields = LegacyFields { seq: &mut seq, version: self.0 };
+        Ok(Snapshot {
+            format_version: fields.since(1)?,
+            id: fields.next()?,
+            drive_path: fields.next()?,
+            timestamp: fields.next()?,
+            total_files: fields.next()?,
+            total_size: fields.next()?,
+            scan_duration: fields.next()?,
+            files: fields.next_seed(LegacyFiles(self.0))?,
+            scan_errors_count: fields.since(1)?,
+            scan_errors: fields.since(1)?,
+            hard_link_count: fields.since(1)?,
+            hard_link_bytes_saved: fields.since(1)?,
+            parent_id: fields.since(2)?,
+            is_consistent: fields.since(3)?,
+            excluded_by_extension_count: fields.since(5)?,
+            excluded_by_extension_bytes: fields.since(5)?,
+            content_filter: fields.since(6)?,
+            extracted_from: fields.since(8)?,
+            files_per_sec: fields.since(9)?,
+            peak_memory_bytes: fields.since(9)?,
+            threads: fields.since(13)?,
+        })
+    }
+}
+
+/// `Snapshot::files` as laid out by format version `.0`.
+struct LegacyFiles(u32);
+
+impl<'de> DeserializeSeed<'de> for LegacyFiles {
+    type Value = Vec<FileEntry>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Vec<FileEntry>, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for LegacyFiles {
+    type Value = Vec<FileEntry>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "format version {} file entries", self.0)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<FileEntry>, A::Error> {
+        // The length comes from the file, so it isn't trusted for more than a start
+        let mut files = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(file) = seq.next_element_seed(LegacyFileEntry(self.0))? {
+            files.push(file);
+        }
+        Ok(files)
+    }
+}
+
+/// `FileEntry` as laid out by format version `.0`.
+struct LegacyFileEntry(u32);
+
+impl<'de> DeserializeSeed<'de> for LegacyFileEntry {
+    type Value = FileEntry;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<FileEntry, D::Error> {
+        deserializer.deserialize_tuple(usize::MAX, self)
+    }
+}
+
+impl<'de> Visitor<'de> for LegacyFileEntry {
+    type Value = FileEntry;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a format version {} file entry", self.0)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<FileEntry, A::Error> {
+        let mut fields = LegacyFields { seq: &mut seq, version: self.0 };
+        Ok(FileEntry {
+            path: fields.next()?,
+            size: fields.next()?,
+            modified: fields.next()?,
+            is_dir: fields.next()?,
+            inode: fields.since(1)?,
+            hash: fields.since(1)?,
+            child_count: fields.since(1)?,
+            file_kind: fields.since(4)?,
+            raw_path: fields.since(7)?,
+            mode: fields.since(10)?,
+            created: fields.since(11)?,
+            xattrs: fields.since(12)?,
+        })
+    }
+}
+
+/// Brings a freshly decoded snapshot up to `SNAPSHOT_FORMAT_VERSION`. Fields
+/// introduced since its version already hold their defaults, so only the
+/// version itself needs updating until a migration has to derive data.
+pub(crate) fn upgrade_snapshot(mut snapshot: Snapshot) -> Result<Snapshot, String> {
+    if snapshot.format_version > SNAPSHOT_FORMAT_VERSION {
+        return Err(format!(
+            "Snapshot format version {} is newer than the supported version {}",
+            snapshot.format_version, SNAPSHOT_FORMAT_VERSION
+        ));
+    }
+    snapshot.format_version = SNAPSHOT_FORMAT_VERSION;
+    Ok(snapshot)
+}
+
+/// Decodes a bincode payload, checking the leading format version first since
+/// bincode can't tell one layout from another. The payload is read as it is
+/// decoded, so a streamed one is never held in memory whole.
+pub(crate) fn decode_binary_payload<R: Read>(mut payload: R) -> Result<Snapshot, String> {
+    let mut version_bytes = [0u8; 4];
+    payload.read_exact(&mut version_bytes).map_err(|e| match e.kind() {
+        std::io::ErrorKind::InvalidData => e.to_string(),
+        _ => "Invalid snapshot file".to_string(),
+    })?;
+    let version = u32::from_le_bytes(version_bytes);
+    let payload = (&version_bytes[..]).chain(payload);
+    match version {
+        SNAPSHOT_FORMAT_VERSION => bincode::deserialize_from(payload).map_err(deserialize_error),
+        // Version 0 payloads have no version to check, see `decrypt_snapshot_data`
+        1..SNAPSHOT_FORMAT_VERSION => decode_versioned_payload(version, payload),
+        _ => Err(format!("Unsupported binary snapshot format version {}", version)),
+    }
+}
+
+/// Reports a failure of the payload reader itself (e.g. an encrypted chunk that
+/// fails authentication) as it is, rather than as a deserialization error.
+fn deserialize_error(e: bincode::Error) -> String {
+    match *e {
+        bincode::ErrorKind::Io(ref io) if io.kind() == std::io::ErrorKind::InvalidData => io.to_string(),
+        _ => format!("Failed to deserialize: {}", e),
+    }
+}
+
+/// Snapshot ids are the first 16 hex digits of a hash of the drive path and
+/// the time the snapshot was taken.
+pub(crate) fn new_snapshot_id(drive_path: &str, taken_at: time::Duration) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(drive_path.as_bytes());
+    hasher.update(taken_at.as_nanos().to_string().as_bytes());
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+/// Builds a consistent `Snapshot` from entries gathered elsewhere (imports,
+/// merges, fixtures), working out the totals the way a scan does: every entry
+/// counts towards `total_files`, and a hard-linked inode's size only once.
+///
+/// ```ignore
+/// let snapshot = SnapshotBuilder::new("/data").files(entries).parent_id(previous.id).build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotBuilder {
+    drive_path: String,
+    files: Vec<FileEntry>,
+    id: Option<String>,
+    timestamp: Option<i64>,
+    scan_duration: u64,
+    scan_errors: Vec<String>,
+    parent_id: Option<String>,
+}
+
+impl SnapshotBuilder {
+    pub fn new(drive_path: impl Into<String>) -> Self {
+        SnapshotBuilder { drive_path: drive_path.into(), ..Default::default() }
+    }
+
+    pub fn file(mut self, entry: FileEntry) -> Self {
+        self.files.push(entry);
+        self
+    }
+
+    pub fn files(mut self, entries: impl IntoIterator<Item = FileEntry>) -> Self {
+        self.files.extend(entries);
+        self
+    }
+
+    /// Keeps a known id instead of generating a fresh one.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Unix time of the snapshot; defaults to now.
+    pub fn timestamp(mut self, timestamp: i64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn scan_duration(mut self, seconds: u64) -> Self {
+        self.scan_duration = seconds;
+        self
+    }
+
+    pub fn scan_error(mut self, error: impl Into<String>) -> Self {
+        self.scan_errors.push(error.into());
+        self
+    }
+
+    pub fn parent_id(mut self, parent_id: impl Into<String>) -> Self {
+        self.parent_id = Some(parent_id.into());
+        self
+    }
+
+    /// Number of directory entries added so far.
+    pub fn dir_count(&self) -> usize {
+        self.files.iter().filter(|f| f.is_dir).count()
+    }
+
+    pub fn build(self) -> Snapshot {
+        let now = time::SystemTime::now().duration_since(time::SystemTime::UNIX_EPOCH).unwrap_or_default();
+        let taken_at = match self.timestamp {
+            Some(timestamp) => time::Duration::from_secs(timestamp.max(0) as u64),
+            None => now,
+        };
+        let id = self.id.unwrap_or_else(|| new_snapshot_id(&self.drive_path, taken_at));
+        let mut files = self.files;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut total_size = 0;
+        let mut hard_link_count = 0;
+        let mut hard_link_bytes_saved = 0;
+        let mut seen_inodes = HashSet::new();
+        for file in &files {
+            // Scans only record inodes when deduplicating hard links
+            let already_counted = !file.is_dir && file.inode.is_some_and(|inode| !seen_inodes.insert(inode));
+            if already_counted {
+                hard_link_count += 1;
+                hard_link_bytes_saved += file.size;
+            } else {
+                total_size += file.size;
+            }
+        }
+        Snapshot {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            id,
+            drive_path: self.drive_path,
+            timestamp: taken_at.as_secs() as i64,
+            total_files: files.len(),
+            total_size,
+            scan_duration: self.scan_duration,
+            files,
+            scan_errors_count: self.scan_errors.len(),
+            scan_errors: self.scan_errors,
+            hard_link_count,
+            hard_link_bytes_saved,
+            parent_id: self.parent_id,
+            is_consistent: None,
+            excluded_by_extension_count: 0,
+            excluded_by_extension_bytes: 0,
+            content_filter: None,
+            extracted_from: None,
+            files_per_sec: None,
+            peak_memory_bytes: None,
+            threads: None,
+        }
+    }
+}
+
+/// The part of `snapshot` at or below `prefix` as a snapshot of its own, rooted
+/// at `prefix`, so one folder's inventory can be shared without rescanning.
+/// `prefix` may be absolute or relative to `snapshot.drive_path`. Totals and
+/// hard links are recounted as `SnapshotBuilder` does. The subset keeps the
+/// scan time and consistency result, gets an id derived from its root and that
+/// time, and records the source in `extracted_from`. Files left out by
+/// extension can't be attributed to a folder, so those counts are dropped.
+pub fn subset_snapshot(snapshot: &Snapshot, prefix: &str) -> Snapshot {
+    let prefix = Path::new(prefix);
+    let root = if prefix.starts_with(&snapshot.drive_path) { prefix.to_path_buf() } else { Path::new(&snapshot.drive_path).join(prefix) };
+    let root_str = root.to_string_lossy().to_string();
+    let mut builder = SnapshotBuilder::new(root_str.as_str())
+        .files(snapshot.files.iter().filter(|f| Path::new(&f.path).starts_with(&root)).cloned())
+        .timestamp(snapshot.timestamp);
+    // Errors read "<path>: <message>"; a sibling like `docs-old` shares the prefix
+    let under_root = |error: &&String| {
+        error.strip_prefix(root_str.as_str()).is_some_and(|rest| rest.starts_with([':', '/', std::path::MAIN_SEPARATOR]))
+    };
+    for error in snapshot.scan_errors.iter().filter(under_root) {
+        builder = builder.scan_error(error.as_str());
+    }
+    let mut subset = builder.build();
+    subset.is_consistent = snapshot.is_consistent;
+    if snapshot.content_filter.is_some() {
+        subset.content_filter = Some(ContentFilter::from_files(&subset.files, CONTENT_FILTER_FALSE_POSITIVE_RATE));
+    }
+    subset.extracted_from = Some(snapshot.id.clone());
+    subset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{scan_dir, write_tree, TestDataDir};
+    use crate::scan::ScanOptions;
+    use crate::storage::{load_snapshot, migrate_snapshot};
+
+    /// A snapshot with every field set to something other than its default, as
+    /// stored by the legacy fixtures in `testdata/` for the fields their version had.
+    fn sample_snapshot() -> Snapshot {
+        let docs = FileEntry {
+            path: "/data/docs".to_string(),
+            size: 4096,
+            modified: 1_700_000_000,
+            is_dir: true,
+            inode: Some(41),
+            hash: None,
+            child_count: Some(2),
+            file_kind: Some(FileKind::Dir),
+            raw_path: None,
+            mode: Some(0o755),
+            created: Some(1_600_000_000),
+            xattrs: None,
+        };
+        let report = FileEntry {
+            path: "/data/docs/report.txt".to_string(),
+            size: 1234,
+            modified: 1_700_000_100,
+            is_dir: false,
+            inode: Some(42),
+            hash: Some("ab".repeat(32)),
+            child_count: None,
+            file_kind: Some(FileKind::File),
+            // The path's bytes in hex, as `encode_raw_path` writes them
+            raw_path: Some("2f646174612f646f63732f7265706f72742e747874".to_string()),
+            mode: Some(0o644),
+            created: Some(1_600_000_100),
+            xattrs: Some(BTreeMap::from([("user.tag".to_string(), "blue".to_string())])),
+        };
+        let link = FileEntry {
+            path: "/data/docs/latest".to_string(),
+            size: 10,
+            modified: 1_700_000_200,
+            is_dir: false,
+            inode: Some(43),
+            hash: None,
+            child_count: None,
+            file_kind: Some(FileKind::Symlink),
+            raw_path: None,
+            mode: Some(0o777),
+            created: None,
+            xattrs: None,
+        };
+        let files = vec![docs, link, report];
+        Snapshot {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            id: "0123456789abcdef".to_string(),
+            drive_path: "/data".to_string(),
+            timestamp: 1_704_067_200,
+            total_files: 3,
+            total_size: 5340,
+            scan_duration: 7,
+            content_filter: Some(ContentFilter::from_files(&files, CONTENT_FILTER_FALSE_POSITIVE_RATE)),
+            files,
+            scan_errors_count: 1,
+            scan_errors: vec!["/data/locked: Permission denied".to_string()],
+            hard_link_count: 1,
+            hard_link_bytes_saved: 10,
+            parent_id: Some("fedcba9876543210".to_string()),
+            is_consistent: Some(true),
+            excluded_by_extension_count: 2,
+            excluded_by_extension_bytes: 300,
+            extracted_from: Some("1111222233334444".to_string()),
+            files_per_sec: Some(1234.5),
+            peak_memory_bytes: Some(64 * 1024 * 1024),
+            threads: Some(4),
+        }
+    }
+
+    /// `sample_snapshot` as a format `version` file holds it: the fields added
+    /// since are at their defaults.
+    fn as_of_version(version: u32) -> Snapshot {
+        let mut snapshot = sample_snapshot();
+        snapshot.format_version = version;
+        for file in &mut snapshot.files {
+            if version < 1 {
+                file.inode = None;
+                file.hash = None;
+                file.child_count = None;
+            }
+            if version < 4 {
+                file.file_kind = None;
+            }
+            if version < 7 {
+                file.raw_path = None;
+            }
+            if version < 10 {
+                file.mode = None;
+            }
+            if version < 11 {
+                file.created = None;
+            }
+            if version < 12 {
+                file.xattrs = None;
+            }
+        }
+        if version < 1 {
+            snapshot.scan_errors_count = 0;
+            snapshot.scan_errors.clear();
+            snapshot.hard_link_count = 0;
+            snapshot.hard_link_bytes_saved = 0;
+        }
+        if version < 2 {
+            snapshot.parent_id = None;
+        }
+        if version < 3 {
+            snapshot.is_consistent = None;
+        }
+        if version < 5 {
+            snapshot.excluded_by_extension_count = 0;
+            snapshot.excluded_by_extension_bytes = 0;
+        }
+        if version < 6 {
+            snapshot.content_filter = None;
+        }
+        if version < 8 {
+            snapshot.extracted_from = None;
+        }
+        if version < 9 {
+            snapshot.files_per_sec = None;
+            snapshot.peak_memory_bytes = None;
+        }
+        if version < 13 {
+            snapshot.threads = None;
+        }
+        snapshot
+    }
+
+    fn assert_same(actual: &Snapshot, expected: &Snapshot, context: &str) {
+        assert_eq!(serde_json::to_value(actual).unwrap(), serde_json::to_value(expected).unwrap(), "{}", context);
+    }
+
+    /// Binary fixtures of every format version, written by the layouts of the
+    /// time from `sample_snapshot`. Add one whenever the version is bumped.
+    const BINARY_FIXTURES: [&[u8]; 13] = [
+        include_bytes!("testdata/snapshot_v1.bin"),
+        include_bytes!("testdata/snapshot_v2.bin"),
+        include_bytes!("testdata/snapshot_v3.bin"),
+        include_bytes!("testdata/snapshot_v4.bin"),
+        include_bytes!("testdata/snapshot_v5.bin"),
+        include_bytes!("testdata/snapshot_v6.bin"),
+        include_bytes!("testdata/snapshot_v7.bin"),
+        include_bytes!("testdata/snapshot_v8.bin"),
+        include_bytes!("testdata/snapshot_v9.bin"),
+        include_bytes!("testdata/snapshot_v10.bin"),
+        include_bytes!("testdata/snapshot_v11.bin"),
+        include_bytes!("testdata/snapshot_v12.bin"),
+        include_bytes!("testdata/snapshot_v13.bin"),
+    ];
+
+    #[test]
+    fn every_binary_format_version_decodes() {
+        assert_eq!(BINARY_FIXTURES.len() as u32, SNAPSHOT_FORMAT_VERSION, "add a fixture for the new version");
+        for (version, data) in (1..).zip(BINARY_FIXTURES) {
+            let payload = data.strip_prefix(b"DPB1").unwrap();
+            let decoded = decode_binary_payload(payload).unwrap();
+            assert_same(&decoded, &as_of_version(version), &format!("version {}", version));
+        }
+    }
+
+    #[test]
+    fn sample_raw_path_is_in_the_encoded_form() {
+        let report = sample_snapshot().files.into_iter().find(|f| f.raw_path.is_some()).unwrap();
+        assert_eq!(decode_raw_path(report.raw_path.as_deref().unwrap()), Some(PathBuf::from(&report.path)));
+        // The same form the encoder gives a name that isn't valid Unicode
+        #[cfg(unix)]
+        {
+            let invalid = <std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(b"/data/docs/report\xff.txt");
+            assert_eq!(encode_raw_path(Path::new(invalid)).unwrap(), "2f646174612f646f63732f7265706f7274ff2e747874");
+        }
+    }
+
+    #[test]
+    fn versioned_decoder_reads_the_current_layout_like_serde() {
+        let payload = bincode::serialize(&sample_snapshot()).unwrap();
+        let decoded = decode_versioned_payload(SNAPSHOT_FORMAT_VERSION, &payload[..]).unwrap();
+        assert_same(&decoded, &sample_snapshot(), "current version");
+    }
+
+    #[test]
+    fn stored_legacy_snapshots_load_and_migrate() {
+        let data_dir = TestDataDir::new();
+        let snapshots_dir = data_dir.path().join("snapshots");
+        fs::create_dir_all(&snapshots_dir).unwrap();
+        let mut stored: Vec<(u32, &str, &[u8], Option<&str>)> = vec![
+            (0, "json", include_bytes!("testdata/snapshot_v0.json"), None),
+            (0, "bin", include_bytes!("testdata/snapshot_v0_encrypted.bin"), Some("legacy-password")),
+        ];
+        stored.extend((1..).zip(BINARY_FIXTURES).map(|(version, data)| (version, "bin", data, None)));
+
+        // Migrating saves under the id inside the file, so they take turns
+        let id = sample_snapshot().id;
+        for (version, extension, data, password) in stored {
+            let context = format!("version {} .{}", version, extension);
+            let path = snapshots_dir.join(format!("{}.{}", id, extension));
+            fs::write(&path, data).unwrap();
+            let mut expected = as_of_version(version);
+            expected.format_version = SNAPSHOT_FORMAT_VERSION;
+            assert_same(&load_snapshot(data_dir.path(), &id, password).unwrap(), &expected, &context);
+
+            let migrated = migrate_snapshot(data_dir.path(), &id, password).unwrap();
+            assert_eq!(migrated, (version < SNAPSHOT_FORMAT_VERSION).then_some(version), "{}", context);
+            assert_same(&load_snapshot(data_dir.path(), &id, password).unwrap(), &expected, &context);
+            assert_eq!(migrate_snapshot(data_dir.path(), &id, password).unwrap(), None, "{}", context);
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn builder_totals_match_a_scan_of_the_same_entries() {
+        let _data_dir = TestDataDir::new();
+        let root = write_tree(&["a.txt", "d0/a.txt", "d0/b.txt", "d1/a.txt", "d1/e/b.txt"]);
+        #[cfg(unix)]
+        fs::hard_link(root.path().join("a.txt"), root.path().join("linked.txt")).unwrap();
+        let scanned = scan_dir(root.path(), &ScanOptions { dedupe_hard_links: true, ..ScanOptions::default() });
+
+        let mut entries = scanned.files.clone();
+        entries.reverse();
+        let builder = SnapshotBuilder::new(scanned.drive_path.clone()).files(entries);
+        assert_eq!(builder.dir_count(), scanned.files.iter().filter(|f| f.is_dir).count());
+        let built = builder.build();
+        assert_eq!(built.total_files, scanned.total_files);
+        assert_eq!(built.total_size, scanned.total_size);
+        assert_eq!(built.hard_link_count, scanned.hard_link_count);
+        assert_eq!(built.hard_link_bytes_saved, scanned.hard_link_bytes_saved);
+        let paths = |snapshot: &Snapshot| snapshot.files.iter().map(|f| f.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths(&built), paths(&scanned));
+        assert_eq!(built.id.len(), 16);
+        assert_ne!(built.id, scanned.id);
+    }
+
+    #[test]
+    fn content_filters_find_every_hash_and_few_others() {
+        use crate::fixture::file_entry;
+        let hash = |i: usize| format!("{:x}", Sha256::digest(i.to_le_bytes()));
+        let mut filter = ContentFilter::new(10_000, CONTENT_FILTER_FALSE_POSITIVE_RATE);
+        for i in 0..10_000 {
+            filter.insert(&hash(i));
+        }
+        assert_eq!(filter.len(), 10_000);
+        assert!((0..10_000).all(|i| filter.contains(&hash(i))), "no false negatives");
+        assert!(filter.contains(&hash(0).to_uppercase()));
+
+        let false_positives = (10_000..30_000).filter(|&i| filter.contains(&hash(i))).count();
+        let expected = filter.false_positive_rate();
+        assert!(expected <= CONTENT_FILTER_FALSE_POSITIVE_RATE * 1.1, "{}", expected);
+        // Twice the target leaves ample room for sampling noise over 20k lookups
+        assert!(false_positives as f64 / 20_000.0 <= 2.0 * CONTENT_FILTER_FALSE_POSITIVE_RATE, "{}", false_positives);
+
+        let files = vec![
+            FileEntry { hash: Some(hash(1)), ..file_entry("/data/a.txt", 1) },
+            FileEntry { hash: Some(hash(1)), ..file_entry("/data/copy-of-a.txt", 1) },
+            FileEntry { hash: Some(hash(2)), is_dir: true, ..file_entry("/data/dir", 1) },
+            file_entry("/data/unhashed.txt", 1),
+        ];
+        let from_files = ContentFilter::from_files(&files, CONTENT_FILTER_FALSE_POSITIVE_RATE);
+        assert_eq!(from_files.len(), 1, "distinct file hashes only");
+        assert!(from_files.contains(&hash(1)));
+        assert!(ContentFilter::new(0, CONTENT_FILTER_FALSE_POSITIVE_RATE).is_empty());
+    }
+
+    #[test]
+    fn subsets_keep_only_their_folder_and_recount_totals() {
+        use crate::fixture::file_entry;
+        let dir = |path: &str| FileEntry { is_dir: true, ..file_entry(path, 0) };
+        let source = SnapshotBuilder::new("/data")
+            .id("full")
+            .timestamp(1_704_067_200)
+            .files(vec![
+                dir("/data/docs"),
+                file_entry("/data/docs/a.txt", 10),
+                dir("/data/docs/sub"),
+                file_entry("/data/docs/sub/b.txt", 20),
+                file_entry("/data/docs-old/c.txt", 30),
+                file_entry("/data/other.txt", 40),
+            ])
+            .scan_error("/data/docs/locked: Permission denied")
+            .scan_error("/data/docs-old/locked: Permission denied")
+            .build();
+
+        let subset = subset_snapshot(&source, "docs");
+        let paths: Vec<&str> = subset.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["/data/docs", "/data/docs/a.txt", "/data/docs/sub", "/data/docs/sub/b.txt"]);
+        assert_eq!((subset.total_files, subset.total_size), (4, 30));
+        assert_eq!(subset.drive_path, "/data/docs");
+        assert_eq!(subset.scan_errors, vec!["/data/docs/locked: Permission denied"]);
+        assert_eq!(subset.timestamp, source.timestamp);
+        assert_eq!(subset.extracted_from.as_deref(), Some("full"));
+        assert_ne!(subset.id, source.id);
+
+        // An absolute prefix picks the same entries
+        assert_eq!(serde_json::to_value(subset_snapshot(&source, "/data/docs")).unwrap(), serde_json::to_value(&subset).unwrap());
+        assert!(subset_snapshot(&source, "missing").files.is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+//! Scan entries kept on disk in sorted runs, for scans that go over
+//! `ScanOptions::max_entries_in_memory`.
+
+use crate::scan::mark_ancestors;
+use crate::snapshot::FileEntry;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Read buffer per run while merging. Kept small since a scan far over its
+/// limit has many runs open at once.
+const RUN_BUFFER_SIZE: usize = 16 * 1024;
+
+/// A temporary file of bincode entries, written a batch at a time. Each batch
+/// is sorted by path before it is written, so the whole scan can be read back
+/// in path order by merging the runs. The file is removed on drop.
+pub(crate) struct EntrySpill {
+    path: PathBuf,
+    writer: BufWriter<fs::File>,
+    /// Byte offset and entry count of each run.
+    runs: Vec<(u64, usize)>,
+    len: usize,
+    hashed_len: usize,
+    /// Children seen under each directory so far, when child counts are
+    /// recorded. Only directories are keys, so this stays far smaller than
+    /// the entries themselves.
+    child_counts: Option<HashMap<PathBuf, usize>>,
+    /// Directories with a file somewhere below them, when empty directories
+    /// are left out. `merged` skips every other directory.
+    non_empty_dirs: Option<HashSet<PathBuf>>,
+}
+
+impl EntrySpill {
+    /// Creates the spill file in `dir`, which is created if needed. With
+    /// `exclude_empty_dirs`, call `prune_empty_dirs` once all runs are written.
+    pub(crate) fn create(dir: &Path, record_child_counts: bool, exclude_empty_dirs: bool) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let path = dir.join(format!("scan-{:016x}.spill", rand::random::<u64>()));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to create spill file: {}", e))?;
+        Ok(EntrySpill {
+            path,
+            writer: BufWriter::new(file),
+            runs: Vec::new(),
+            len: 0,
+            hashed_len: 0,
+            child_counts: record_child_counts.then(HashMap::new),
+            non_empty_dirs: exclude_empty_dirs.then(HashSet::new),
+        })
+    }
+
+    /// Entries written so far.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Files with a content hash among them.
+    pub(crate) fn hashed_len(&self) -> usize {
+        self.hashed_len
+    }
+
+    /// Entries in the largest run, i.e. the most held in memory at once.
+    #[cfg(test)]
+    pub(crate) fn largest_run(&self) -> usize {
+        self.runs.iter().map(|(_, count)| *count).max().unwrap_or(0)
+    }
+
+    /// Sorts `files` by path, appends them as a new run and empties `files`.
+    pub(crate) fn write_run(&mut self, files: &mut Vec<FileEntry>) -> Result<(), String> {
+        if files.is_empty() {
+            return Ok(());
+        }
+        if let Some(counts) = self.child_counts.as_mut() {
+            for file in files.iter() {
+                if let Some(parent) = Path::new(&file.path).parent() {
+                    *counts.entry(parent.to_path_buf()).or_insert(0) += 1;
+                }
+            }
+        }
+        if let Some(non_empty) = self.non_empty_dirs.as_mut() {
+            for file in files.iter().filter(|f| !f.is_dir) {
+                mark_ancestors(&file.path, non_empty);
+            }
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let write_error = |e: io::Error| format!("Failed to write spill file: {}", e);
+        let offset = self.writer.stream_position().map_err(write_error)?;
+        for file in files.iter() {
+            bincode::serialize_into(&mut self.writer, file).map_err(|e| format!("Failed to write spill file: {}", e))?;
+        }
+        self.writer.flush().map_err(write_error)?;
+        self.runs.push((offset, files.len()));
+        self.len += files.len();
+        self.hashed_len += files.iter().filter(|f| !f.is_dir && f.hash.is_some()).count();
+        files.clear();
+        Ok(())
+    }
+
+    /// Takes the empty directories out of `len` and the child counts, ahead of
+    /// `merged` leaving them out. Returns the bytes they added to the scan's
+    /// total size.
+    pub(crate) fn prune_empty_dirs(&mut self) -> Result<u64, String> {
+        let non_empty = match self.non_empty_dirs.as_ref() {
+            Some(non_empty) => non_empty,
+            None => return Ok(0),
+        };
+        let mut pruned = 0;
+        let mut pruned_bytes = 0;
+        let mut pruned_parents = Vec::new();
+        for file in self.entries() {
+            let file = file?;
+            if file.is_dir && !non_empty.contains(Path::new(&file.path)) {
+                pruned += 1;
+                pruned_bytes += file.size;
+                pruned_parents.extend(Path::new(&file.path).parent().map(Path::to_path_buf));
+            }
+        }
+        if let Some(counts) = self.child_counts.as_mut() {
+            for parent in pruned_parents {
+                if let Some(count) = counts.get_mut(&parent) {
+                    *count -= 1;
+                }
+            }
+        }
+        self.len -= pruned;
+        Ok(pruned_bytes)
+    }
+
+    /// Every entry, run by run rather than in path order. Like `merged`, only
+    /// for once all runs are written.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = Result<FileEntry, String>> + '_ {
+        let mut reader = BufReader::new(RunReader { file: self.writer.get_ref(), position: 0 });
+        (0..self.len).map(move |_| read_entry(&mut reader))
+    }
+
+    /// Every entry in path order, with directories' child counts filled in
+    /// when they were recorded. Reading moves the file's position, so this is
+    /// only for once all runs are written.
+    pub(crate) fn merged(&self) -> Result<MergedRuns<'_>, String> {
+        let file = self.writer.get_ref();
+        let mut merged = MergedRuns {
+            readers: Vec::with_capacity(self.runs.len()),
+            remaining: Vec::with_capacity(self.runs.len()),
+            heads: Vec::with_capacity(self.runs.len()),
+            order: BinaryHeap::with_capacity(self.runs.len()),
+            child_counts: self.child_counts.as_ref(),
+            non_empty_dirs: self.non_empty_dirs.as_ref(),
+        };
+        for (run, &(offset, count)) in self.runs.iter().enumerate() {
+            let reader = RunReader { file, position: offset };
+            merged.readers.push(BufReader::with_capacity(RUN_BUFFER_SIZE, reader));
+            merged.remaining.push(count);
+            merged.heads.push(None);
+            merged.advance(run)?;
+        }
+        Ok(merged)
+    }
+}
+
+impl Drop for EntrySpill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn read_entry<R: Read>(reader: &mut R) -> Result<FileEntry, String> {
+    bincode::deserialize_from(reader).map_err(|e| format!("Failed to read spill file: {}", e))
+}
+
+/// Reads the spill file from its own position, so every run can share the
+/// one open file.
+struct RunReader<'a> {
+    file: &'a fs::File,
+    position: u64,
+}
+
+impl Read for RunReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = self.file;
+        file.seek(SeekFrom::Start(self.position))?;
+        let count = file.read(buf)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+/// The runs of an `EntrySpill` merged into one path-ordered stream.
+pub(crate) struct MergedRuns<'a> {
+    readers: Vec<BufReader<RunReader<'a>>>,
+    remaining: Vec<usize>,
+    heads: Vec<Option<FileEntry>>,
+    order: BinaryHeap<Reverse<(String, usize)>>,
+    child_counts: Option<&'a HashMap<PathBuf, usize>>,
+    non_empty_dirs: Option<&'a HashSet<PathBuf>>,
+}
+
+impl MergedRuns<'_> {
+    /// Reads the next entry of `run` into its head.
+    fn advance(&mut self, run: usize) -> Result<(), String> {
+        if self.remaining[run] == 0 {
+            return Ok(());
+        }
+        let entry = read_entry(&mut self.readers[run])?;
+        self.remaining[run] -= 1;
+        self.order.push(Reverse((entry.path.clone(), run)));
+        self.heads[run] = Some(entry);
+        Ok(())
+    }
+}
+
+impl Iterator for MergedRuns<'_> {
+    type Item = Result<FileEntry, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut entry = loop {
+            let Reverse((_, run)) = self.order.pop()?;
+            let entry = self.heads[run].take()?;
+            if let Err(e) = self.advance(run) {
+                return Some(Err(e));
+            }
+            let empty = entry.is_dir && self.non_empty_dirs.is_some_and(|dirs| !dirs.contains(Path::new(&entry.path)));
+            if !empty {
+                break entry;
+            }
+        };
+        if let (true, Some(counts)) = (entry.is_dir, self.child_counts) {
+            entry.child_count = Some(counts.get(Path::new(&entry.path)).copied().unwrap_or(0));
+        }
+        Some(Ok(entry))
+    }
+}
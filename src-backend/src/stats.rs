@@ -0,0 +1,287 @@
+//! Aggregate views of a single snapshot.
+
+use crate::snapshot::{FileEntry, Snapshot};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// One bar of `size_histogram`: regular files of at least `min_size` bytes and,
+/// unless it's the last bucket, less than `max_size`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SizeBucket {
+    pub label: String,
+    pub min_size: u64,
+    pub max_size: Option<u64>,
+    pub count: usize,
+    pub total_size: u64,
+}
+
+const SIZE_BUCKET_BOUNDS: [(&str, u64); 5] = [
+    ("< 1 KB", 0),
+    ("1 KB - 1 MB", 1024),
+    ("1 MB - 100 MB", 1024 * 1024),
+    ("100 MB - 1 GB", 100 * 1024 * 1024),
+    (">= 1 GB", 1024 * 1024 * 1024),
+];
+
+/// Groups the regular files of a scan by size in a single pass, for charts that
+/// shouldn't need every entry. Directories are left out, so the counts add up
+/// to the number of files rather than `total_files`.
+pub fn size_histogram(files: &[FileEntry]) -> Vec<SizeBucket> {
+    let mut buckets: Vec<SizeBucket> = SIZE_BUCKET_BOUNDS
+        .iter()
+        .enumerate()
+        .map(|(i, (label, min_size))| SizeBucket {
+            label: label.to_string(),
+            min_size: *min_size,
+            max_size: SIZE_BUCKET_BOUNDS.get(i + 1).map(|(_, next)| *next),
+            count: 0,
+            total_size: 0,
+        })
+        .collect();
+    for file in files.iter().filter(|f| !f.is_dir) {
+        let index = SIZE_BUCKET_BOUNDS.iter().rposition(|(_, min_size)| file.size >= *min_size).unwrap_or(0);
+        buckets[index].count += 1;
+        buckets[index].total_size += file.size;
+    }
+    buckets
+}
+
+/// One `size_histogram` bucket in two scans, see `compare_size_histograms`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SizeBucketDelta {
+    pub label: String,
+    pub min_size: u64,
+    pub max_size: Option<u64>,
+    pub old_count: usize,
+    pub new_count: usize,
+    pub old_total_size: u64,
+    pub new_total_size: u64,
+}
+
+impl SizeBucketDelta {
+    /// Files gained (positive) or lost (negative) in this bucket.
+    pub fn count_delta(&self) -> i64 {
+        self.new_count as i64 - self.old_count as i64
+    }
+
+    pub fn size_delta(&self) -> i64 {
+        self.new_total_size as i64 - self.old_total_size as i64
+    }
+}
+
+/// How the size distribution shifted between two scans: the `size_histogram`
+/// of each, bucket by bucket.
+pub fn compare_size_histograms(old_files: &[FileEntry], new_files: &[FileEntry]) -> Vec<SizeBucketDelta> {
+    size_histogram(old_files)
+        .into_iter()
+        .zip(size_histogram(new_files))
+        .map(|(old, new)| SizeBucketDelta {
+            label: old.label,
+            min_size: old.min_size,
+            max_size: old.max_size,
+            old_count: old.count,
+            new_count: new.count,
+            old_total_size: old.total_size,
+            new_total_size: new.total_size,
+        })
+        .collect()
+}
+
+/// Bucket name used by `top_level_usage` for files directly under the root.
+pub const ROOT_FILES_BUCKET: &str = ".";
+
+/// Disk usage per immediate child directory of `drive_path`: `(name, total
+/// size, file count)` over everything below it, largest first. Files directly
+/// under the root are grouped under `ROOT_FILES_BUCKET`.
+pub fn top_level_usage(snapshot: &Snapshot) -> Vec<(String, u64, usize)> {
+    let root = Path::new(&snapshot.drive_path);
+    let mut usage: HashMap<String, (u64, usize)> = HashMap::new();
+    for file in &snapshot.files {
+        let relative = match Path::new(&file.path).strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => continue,
+        };
+        let mut components = relative.components();
+        let first = match components.next() {
+            Some(first) => first.as_os_str().to_string_lossy().to_string(),
+            None => continue,
+        };
+        let is_top_level = components.next().is_none();
+        if file.is_dir {
+            // Empty directories still get a (zero) bar
+            if is_top_level {
+                usage.entry(first).or_default();
+            }
+            continue;
+        }
+        let bucket = if is_top_level { ROOT_FILES_BUCKET.to_string() } else { first };
+        let entry = usage.entry(bucket).or_default();
+        entry.0 += file.size;
+        entry.1 += 1;
+    }
+    let mut usage: Vec<(String, u64, usize)> = usage.into_iter().map(|(name, (size, count))| (name, size, count)).collect();
+    usage.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    usage
+}
+
+/// The `n` most recently modified entries, newest first. Directories are left
+/// out unless `include_dirs` is set.
+pub fn recent_files(snapshot: &Snapshot, n: usize, include_dirs: bool) -> Vec<&FileEntry> {
+    let mut entries: Vec<&FileEntry> = snapshot
+        .files
+        .iter()
+        .filter(|f| include_dirs || !f.is_dir)
+        .collect();
+    entries.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| a.path.cmp(&b.path)));
+    entries.truncate(n);
+    entries
+}
+
+/// Files of `snapshot` created after `baseline` was taken, i.e. genuinely new
+/// rather than moved in or modified in place, oldest first. Only files with a
+/// recorded `created` time can be told apart, see `FileEntry::created`.
+pub fn new_since<'a>(baseline: &Snapshot, snapshot: &'a Snapshot) -> Vec<&'a FileEntry> {
+    let mut entries: Vec<&FileEntry> = snapshot
+        .files
+        .iter()
+        .filter(|f| !f.is_dir && f.created.is_some_and(|created| created > baseline.timestamp))
+        .collect();
+    entries.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.path.cmp(&b.path)));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{file_entry, snapshot};
+    use crate::snapshot::SnapshotBuilder;
+
+    fn modified_at(path: &str, modified: i64, is_dir: bool) -> FileEntry {
+        FileEntry { modified, is_dir, ..file_entry(path, 1) }
+    }
+
+    #[test]
+    fn recent_files_are_newest_first_and_limited() {
+        let snapshot = snapshot(
+            "recent",
+            1_704_067_200,
+            vec![
+                modified_at("/data/old.txt", 100, false),
+                modified_at("/data/newest.txt", 400, false),
+                modified_at("/data/dir", 500, true),
+                modified_at("/data/b.txt", 300, false),
+                modified_at("/data/a.txt", 300, false),
+            ],
+        );
+        let paths = |entries: Vec<&FileEntry>| entries.into_iter().map(|f| f.path.clone()).collect::<Vec<_>>();
+
+        assert_eq!(paths(recent_files(&snapshot, 3, false)), vec!["/data/newest.txt", "/data/a.txt", "/data/b.txt"]);
+        assert_eq!(paths(recent_files(&snapshot, 2, true)), vec!["/data/dir", "/data/newest.txt"]);
+        assert_eq!(recent_files(&snapshot, 10, false).len(), 4);
+        assert!(recent_files(&snapshot, 0, true).is_empty());
+    }
+
+    #[test]
+    fn only_files_created_after_the_baseline_are_new_since_it() {
+        let created_at = |path: &str, created: Option<i64>, modified: i64| FileEntry { created, ..modified_at(path, modified, false) };
+        let baseline = SnapshotBuilder::new("/data")
+            .timestamp(1_000)
+            .files(vec![created_at("/data/edited.txt", Some(500), 600), created_at("/data/kept.txt", Some(500), 500)])
+            .build();
+        let snapshot = SnapshotBuilder::new("/data")
+            .timestamp(2_000)
+            .files(vec![
+                created_at("/data/edited.txt", Some(500), 1_500),
+                created_at("/data/kept.txt", Some(500), 500),
+                created_at("/data/later.txt", Some(1_800), 1_800),
+                created_at("/data/new.txt", Some(1_200), 1_200),
+                created_at("/data/moved-in.txt", Some(800), 800),
+                created_at("/data/at-baseline.txt", Some(1_000), 1_000),
+                created_at("/data/unknown.txt", None, 1_900),
+                FileEntry { created: Some(1_500), ..modified_at("/data/new-dir", 1_500, true) },
+            ])
+            .build();
+
+        let paths: Vec<&str> = new_since(&baseline, &snapshot).into_iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["/data/new.txt", "/data/later.txt"]);
+        // Modified in place, and still reported as such by a comparison
+        let comparison = crate::compare::compare_snapshots(&baseline, &snapshot);
+        assert!(comparison.with_status(crate::compare::DiffStatus::Modified).any(|diff| diff.path == "/data/edited.txt"));
+    }
+
+    #[test]
+    fn histogram_buckets_sum_to_the_files_of_a_scan() {
+        let snapshot = snapshot("sizes", 1_704_067_200, vec![
+            modified_at("/data/dir", 100, true),
+            file_entry("/data/empty.txt", 0),
+            file_entry("/data/just-under.txt", 1023),
+            file_entry("/data/one-kb.txt", 1024),
+            file_entry("/data/one-mb.bin", 1024 * 1024),
+            file_entry("/data/huge.iso", 5 * 1024 * 1024 * 1024),
+        ]);
+        let histogram = size_histogram(&snapshot.files);
+        let dir_count = snapshot.files.iter().filter(|f| f.is_dir).count();
+
+        assert_eq!(histogram.iter().map(|bucket| bucket.count).sum::<usize>() + dir_count, snapshot.total_files);
+        assert_eq!(histogram.iter().map(|bucket| bucket.count).collect::<Vec<_>>(), vec![2, 1, 1, 0, 1]);
+        assert_eq!(histogram.iter().map(|bucket| bucket.total_size).sum::<u64>(), snapshot.total_size - 1);
+        assert_eq!(histogram.last().unwrap().max_size, None);
+        assert!(histogram.windows(2).all(|pair| pair[0].max_size == Some(pair[1].min_size)));
+    }
+
+    #[test]
+    fn histogram_deltas_follow_files_between_buckets() {
+        const MB: u64 = 1024 * 1024;
+        let old = vec![
+            file_entry("/data/a.txt", 3),
+            file_entry("/data/b.txt", 500),
+            file_entry("/data/grows.bin", 2000),
+            modified_at("/data/dir", 100, true),
+        ];
+        let new = vec![
+            file_entry("/data/b.txt", 500),
+            file_entry("/data/grows.bin", 2 * MB),
+            file_entry("/data/video.mp4", 300 * MB),
+            file_entry("/data/c.txt", 10),
+        ];
+        let deltas = compare_size_histograms(&old, &new);
+
+        let labels: Vec<&str> = deltas.iter().map(|delta| delta.label.as_str()).collect();
+        assert_eq!(labels, SIZE_BUCKET_BOUNDS.iter().map(|(label, _)| *label).collect::<Vec<_>>());
+        let counts: Vec<(usize, usize, i64)> = deltas.iter().map(|d| (d.old_count, d.new_count, d.count_delta())).collect();
+        assert_eq!(counts, vec![(2, 2, 0), (1, 0, -1), (0, 1, 1), (0, 1, 1), (0, 0, 0)]);
+        let sizes: Vec<i64> = deltas.iter().map(|delta| delta.size_delta()).collect();
+        assert_eq!(sizes, vec![7, -2000, 2 * MB as i64, 300 * MB as i64, 0]);
+
+        // Comparing a scan with itself shifts nothing
+        assert!(compare_size_histograms(&new, &new).iter().all(|delta| delta.count_delta() == 0 && delta.size_delta() == 0));
+    }
+
+    #[test]
+    fn top_level_usage_totals_each_child_and_the_root_files() {
+        let dir = |path: &str| FileEntry { is_dir: true, ..file_entry(path, 0) };
+        let snapshot = snapshot("usage", 1_704_067_200, vec![
+            dir("/data"),
+            dir("/data/photos"),
+            dir("/data/photos/2023"),
+            file_entry("/data/photos/2023/a.jpg", 3000),
+            file_entry("/data/photos/b.jpg", 2000),
+            dir("/data/docs"),
+            file_entry("/data/docs/notes.txt", 100),
+            dir("/data/empty"),
+            file_entry("/data/readme.md", 700),
+            file_entry("/data/todo.txt", 300),
+        ]);
+
+        assert_eq!(
+            top_level_usage(&snapshot),
+            vec![
+                ("photos".to_string(), 5000, 2),
+                (ROOT_FILES_BUCKET.to_string(), 1000, 2),
+                ("docs".to_string(), 100, 1),
+                ("empty".to_string(), 0, 0),
+            ]
+        );
+    }
+}
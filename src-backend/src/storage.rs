@@ -0,0 +1,1796 @@
+//! Where snapshots live on disk and how they are saved, loaded and listed.
+
+use crate::compare::clear_comparison_cache;
+use crate::crypto::{chunked_len, decrypt_payload, key_material, ChunkedDecryptReader, ChunkedEncryptWriter, CHUNKED_MAGIC, ENCRYPTED_MAGIC};
+use crate::snapshot::{
+    content_fingerprint, decode_binary_payload, decode_versioned_payload, upgrade_snapshot, ContentFilter, FileEntry, Snapshot,
+    SnapshotSummary, SNAPSHOT_FORMAT_VERSION,
+};
+use serde::{Deserialize, Serialize, Serializer};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::{BufReader, BufWriter, Read, Write};
+
+/// Storage used by Drive Pulse itself, see `data_dir_stats`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DataDirStats {
+    pub data_dir: String,
+    pub snapshot_count: usize,
+    pub encrypted_count: usize,
+    pub plaintext_count: usize,
+    /// Bytes used by snapshot files.
+    pub snapshot_bytes: u64,
+    /// Bytes used by metadata sidecars.
+    pub metadata_bytes: u64,
+    /// Timestamps of the oldest and newest scans in the history.
+    pub oldest_scan: Option<i64>,
+    pub newest_scan: Option<i64>,
+}
+
+/// File sizes before and after `compact_snapshot` rewrote a snapshot.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CompactReport {
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+impl CompactReport {
+    /// Bytes reclaimed; negative if the rewritten file came out larger.
+    pub fn bytes_saved(&self) -> i64 {
+        self.bytes_before as i64 - self.bytes_after as i64
+    }
+}
+
+/// What deleting the scans matching a predicate would remove, see
+/// `preview_delete_snapshots_where`.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DeletePreview {
+    /// Scans that would be deleted, newest first.
+    pub snapshots: Vec<SnapshotSummary>,
+    /// Matching scans that would be kept because they are locked.
+    pub locked: Vec<SnapshotSummary>,
+    /// Bytes of snapshot and metadata files that deleting `snapshots` frees.
+    pub bytes: u64,
+}
+
+/// Where `find_hash` found a content hash among the stored scans.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HashMatches {
+    /// Id of every scan that recorded the content, newest first, with the
+    /// paths it was recorded at.
+    pub found: Vec<(String, Vec<String>)>,
+    /// Scans taken without hashing, which can't be searched.
+    pub unhashed: Vec<String>,
+    /// Encrypted scans that weren't searched because no password was given.
+    pub encrypted: Vec<String>,
+    /// Scans that couldn't be loaded (e.g. encrypted with another password),
+    /// with the error each one failed with.
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Environment variable that overrides where snapshots and metadata are stored.
+pub const DATA_DIR_ENV: &str = "DRIVE_PULSE_DATA_DIR";
+
+/// Where a named profile keeps its data, below the default data directory.
+/// Profiles are separate sets of scans (e.g. "work" and "personal"): passing
+/// this directory to the storage functions instead of the default one keeps
+/// them from ever seeing each other.
+pub fn profile_data_dir(base: &Path, profile: &str) -> Result<PathBuf, String> {
+    if profile.is_empty() || !profile.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Invalid profile name '{}': use letters, digits, '-' and '_'", profile));
+    }
+    Ok(base.join("profiles").join(profile))
+}
+
+/// The default data directory, which also holds every profile's, see
+/// `resolve_data_dir`. The storage functions take the directory to work in
+/// as their first argument.
+pub fn get_data_dir() -> Result<PathBuf, String> {
+    resolve_data_dir(std::env::var_os(DATA_DIR_ENV), dirs::data_local_dir())
+}
+
+/// `DRIVE_PULSE_DATA_DIR` wins when set, otherwise the platform's local data
+/// directory is used. Minimal containers and CI runners often have neither a
+/// desktop profile nor `$HOME`, so the error says how to fix it.
+pub fn resolve_data_dir(env_override: Option<std::ffi::OsString>, local_data_dir: Option<PathBuf>) -> Result<PathBuf, String> {
+    if let Some(dir) = env_override.filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    local_data_dir
+        .map(|dir| dir.join("com.pifrost.drivepulse"))
+        .ok_or_else(|| format!(
+            "Could not find local app data directory; set {} to choose where snapshots are stored",
+            DATA_DIR_ENV
+        ))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotFormat {
+    /// Pretty-printed JSON (`.json`), readable by any tool.
+    Json,
+    /// Unencrypted bincode (`.bin`), much smaller and faster than JSON.
+    Binary,
+    /// AES-256-GCM encrypted bincode (`.bin`).
+    Encrypted,
+}
+
+impl SnapshotFormat {
+    /// Extension a snapshot in this format is saved with.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SnapshotFormat::Json => "json",
+            SnapshotFormat::Binary | SnapshotFormat::Encrypted => "bin",
+        }
+    }
+}
+
+/// Header of plaintext bincode snapshots. Both binary formats share the `.bin`
+/// extension, so the header is what tells them apart on load.
+const BINARY_MAGIC: &[u8; 4] = b"DPB1";
+
+/// Error returned when an encrypted snapshot is loaded without a password, so
+/// the caller knows to ask for one and try again.
+pub const PASSWORD_REQUIRED: &str = "Password required for decryption";
+
+/// Whether a stored snapshot needs a password to load, judged from its header.
+pub fn is_snapshot_encrypted(data_dir: &Path, snapshot_id: &str) -> Result<bool, String> {
+    is_encrypted_file(&snapshot_file_path(data_dir, snapshot_id)?)
+}
+
+fn is_encrypted_file(path: &Path) -> Result<bool, String> {
+    let (header, _) = read_snapshot_header(path)?;
+    Ok(detect_snapshot_format(&header) == SnapshotFormat::Encrypted)
+}
+
+/// Leading bytes of a gzip stream.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+/// Leading bytes of a zstd frame.
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(GZIP_MAGIC) || data.starts_with(ZSTD_MAGIC)
+}
+
+/// Wraps `reader` in the decoder matching a compressed stream's `header`.
+fn decompressing_reader<'a, R: Read + 'a>(header: &[u8], reader: R) -> Result<Box<dyn Read + 'a>, String> {
+    if header.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(flate2::read::MultiGzDecoder::new(reader)))
+    } else {
+        let decoder = zstd::stream::read::Decoder::new(reader).map_err(|e| format!("Failed to decompress: {}", e))?;
+        Ok(Box::new(decoder))
+    }
+}
+
+/// Undoes gzip or zstd compression applied to a snapshot file by external
+/// tools. The compression is recognized by its magic, whatever the extension.
+fn decompress_snapshot_data(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    if !is_compressed(&data) {
+        return Ok(data);
+    }
+    let mut decompressed = Vec::new();
+    decompressing_reader(&data, &data[..])?
+        .read_to_end(&mut decompressed)
+        .map_err(|e| format!("Failed to decompress: {}", e))?;
+    Ok(decompressed)
+}
+
+/// The first `SNIFF_LEN` bytes of a snapshot's content, decompressed if need
+/// be, and whether the file is compressed.
+fn read_snapshot_header(path: &Path) -> Result<(Vec<u8>, bool), String> {
+    let read_error = |e: std::io::Error| format!("Failed to read snapshot file: {}", e);
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    fs::File::open(path)
+        .and_then(|file| file.take(SNIFF_LEN as u64).read_to_end(&mut header))
+        .map_err(read_error)?;
+    if !is_compressed(&header) {
+        return Ok((header, false));
+    }
+    let file = fs::File::open(path).map_err(read_error)?;
+    let mut decompressed = Vec::with_capacity(SNIFF_LEN);
+    decompressing_reader(&header, file)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| format!("Failed to decompress: {}", e))?;
+    Ok((decompressed, true))
+}
+
+/// Checks for `{` followed by `"` or `}`, ignoring whitespace. Two bytes rather
+/// than one keeps a legacy file whose random nonce starts with `{` from being
+/// mistaken for JSON.
+fn looks_like_json_object(data: &[u8]) -> bool {
+    let mut bytes = data.iter().filter(|b| !b.is_ascii_whitespace());
+    bytes.next() == Some(&b'{') && matches!(bytes.next(), Some(b'"') | Some(b'}'))
+}
+
+/// How much of a file `detect_snapshot_format` needs to see.
+const SNIFF_LEN: usize = 64;
+
+/// Tells a stored snapshot's format from its first bytes rather than its
+/// extension. Anything without a header that doesn't look like JSON is taken
+/// to be a legacy headerless encrypted file.
+pub fn detect_snapshot_format(data: &[u8]) -> SnapshotFormat {
+    if data.starts_with(BINARY_MAGIC) {
+        SnapshotFormat::Binary
+    } else if data.starts_with(ENCRYPTED_MAGIC) || data.starts_with(CHUNKED_MAGIC) {
+        SnapshotFormat::Encrypted
+    } else if looks_like_json_object(data) {
+        SnapshotFormat::Json
+    } else {
+        SnapshotFormat::Encrypted
+    }
+}
+
+pub fn save_snapshot(data_dir: &Path, snapshot: &Snapshot, encrypt: bool, password: Option<&str>) -> Result<(), String> {
+    save_snapshot_with_progress(data_dir, snapshot, encrypt, password, |_, _| {})
+}
+
+/// Like `save_snapshot`, reporting `(bytes_written, total_bytes)` as the
+/// serialized (and possibly encrypted) data is written out.
+pub fn save_snapshot_with_progress<F>(data_dir: &Path, snapshot: &Snapshot, encrypt: bool, password: Option<&str>, progress: F) -> Result<(), String>
+where
+    F: FnMut(u64, u64),
+{
+    let format = if encrypt { SnapshotFormat::Encrypted } else { SnapshotFormat::Json };
+    save_snapshot_as(data_dir, snapshot, format, password, progress)
+}
+
+/// Saves a snapshot in the given storage format. `password` is only used (and
+/// required) for `SnapshotFormat::Encrypted`.
+pub fn save_snapshot_as<F>(data_dir: &Path, snapshot: &Snapshot, format: SnapshotFormat, password: Option<&str>, mut progress: F) -> Result<(), String>
+where
+    F: FnMut(u64, u64),
+{
+    let snapshots_dir = data_dir.join("snapshots");
+    fs::create_dir_all(&snapshots_dir).map_err(|e| e.to_string())?;
+    let snapshot_path = snapshots_dir.join(format!("{}.{}", snapshot.id, format.extension()));
+    write_snapshot_file(snapshot, &snapshot_path, &snapshot_path, format, password, &mut progress)
+}
+
+/// Writes `snapshot` to `path` in `format`. `replaces` is the file it is
+/// going to stand in for, whose key material an encrypted save must not reuse.
+fn write_snapshot_file<F>(snapshot: &Snapshot, path: &Path, replaces: &Path, format: SnapshotFormat, password: Option<&str>, progress: &mut F) -> Result<(), String>
+where
+    F: FnMut(u64, u64),
+{
+    if format == SnapshotFormat::Encrypted {
+        let password = password.ok_or("Password required for encryption")?;
+        return save_encrypted(snapshot, path, replaces, password, progress);
+    }
+    let data_to_write = if format == SnapshotFormat::Binary {
+        let mut data = BINARY_MAGIC.to_vec();
+        bincode::serialize_into(&mut data, snapshot).map_err(|e| format!("Failed to serialize: {}", e))?;
+        data
+    } else {
+        let serialized = serde_json::to_string_pretty(snapshot).map_err(|e| format!("Failed to serialize: {}", e))?;
+        serialized.into_bytes()
+    };
+    write_with_progress(path, &data_to_write, progress)
+}
+
+/// Rewrites a stored snapshot as `format`, replacing its file at `old_path`.
+/// The new file is written and synced under a temporary name, then renamed
+/// into place, so a failure at any point leaves the old file as it was. A
+/// file of the other extension is removed only once the new one is in place.
+/// Returns the new file's path.
+fn replace_snapshot_file(data_dir: &Path, snapshot: &Snapshot, format: SnapshotFormat, password: Option<&str>, old_path: &Path) -> Result<PathBuf, String> {
+    let snapshots_dir = data_dir.join("snapshots");
+    let new_path = snapshots_dir.join(format!("{}.{}", snapshot.id, format.extension()));
+    let temp_path = snapshots_dir.join(format!("{}.{}.tmp", snapshot.id, format.extension()));
+    let written = write_snapshot_file(snapshot, &temp_path, &new_path, format, password, &mut |_, _| {})
+        .and_then(|()| {
+            fs::OpenOptions::new().write(true).open(&temp_path).and_then(|file| file.sync_all()).map_err(|e| format!("Failed to write file: {}", e))
+        })
+        .and_then(|()| fs::rename(&temp_path, &new_path).map_err(|e| format!("Failed to replace snapshot file: {}", e)));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    if new_path != old_path {
+        fs::remove_file(old_path).map_err(|e| format!("Failed to remove old snapshot file: {}", e))?;
+    }
+    Ok(new_path)
+}
+
+/// Encrypts `snapshot` into `path` as it is serialized, so neither the
+/// plaintext nor the ciphertext is held in memory whole. The total passed to
+/// `progress` is known up front from the serialized size.
+fn save_encrypted<F>(snapshot: &Snapshot, path: &Path, replaces: &Path, password: &str, progress: &mut F) -> Result<(), String>
+where
+    F: FnMut(u64, u64),
+{
+    let plaintext_len = bincode::serialized_size(snapshot).map_err(|e| format!("Failed to serialize: {}", e))?;
+    let total = chunked_len(plaintext_len);
+    let replaced = replaced_key_material(replaces);
+    let file = fs::File::create(path).map_err(|e| format!("Failed to create file: {}", e))?;
+    progress(0, total);
+    let writer = ProgressWriter { inner: BufWriter::with_capacity(IO_CHUNK_SIZE, file), written: 0, total, progress };
+    let mut encrypter = ChunkedEncryptWriter::new(writer, password, replaced.as_deref())?;
+    bincode::serialize_into(&mut encrypter, snapshot).map_err(|e| format!("Failed to serialize: {}", e))?;
+    encrypter.finish().map_err(|e| format!("Failed to write file: {}", e))?;
+    Ok(())
+}
+
+/// The salt and nonce of the encrypted snapshot at `path`, if there is one,
+/// so that saving over it can make sure not to use them again.
+fn replaced_key_material(path: &Path) -> Option<Vec<u8>> {
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    fs::File::open(path).ok()?.take(SNIFF_LEN as u64).read_to_end(&mut header).ok()?;
+    key_material(&header).map(<[u8]>::to_vec)
+}
+
+/// Saves `snapshot` like `save_snapshot_as`, except that its entries come from
+/// `files` as they are written rather than from `snapshot.files`, which is
+/// ignored. `files` must yield exactly `snapshot.total_files` entries in path
+/// order. Used for scans too large to hold in memory.
+pub(crate) fn save_snapshot_streamed<I>(data_dir: &Path, snapshot: &Snapshot, files: I, format: SnapshotFormat, password: Option<&str>) -> Result<(), String>
+where
+    I: Iterator<Item = Result<FileEntry, String>>,
+{
+    let snapshots_dir = data_dir.join("snapshots");
+    fs::create_dir_all(&snapshots_dir).map_err(|e| e.to_string())?;
+    let snapshot_path = snapshots_dir.join(format!("{}.{}", snapshot.id, format.extension()));
+    let replaced = replaced_key_material(&snapshot_path);
+    let file = fs::File::create(&snapshot_path).map_err(|e| format!("Failed to create file: {}", e))?;
+    let mut writer = BufWriter::with_capacity(IO_CHUNK_SIZE, file);
+    let streamed = StreamedSnapshot::new(snapshot, files);
+    let serialize_error = |e: String| format!("Failed to serialize: {}", e);
+    match format {
+        SnapshotFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &streamed).map_err(|e| serialize_error(e.to_string()))?;
+        }
+        SnapshotFormat::Binary => {
+            writer.write_all(BINARY_MAGIC).map_err(|e| format!("Failed to write file: {}", e))?;
+            bincode::serialize_into(&mut writer, &streamed).map_err(|e| serialize_error(e.to_string()))?;
+        }
+        SnapshotFormat::Encrypted => {
+            let password = password.ok_or("Password required for encryption")?;
+            let mut encrypter = ChunkedEncryptWriter::new(&mut writer, password, replaced.as_deref())?;
+            bincode::serialize_into(&mut encrypter, &streamed).map_err(|e| serialize_error(e.to_string()))?;
+            encrypter.finish().map_err(|e| format!("Failed to write file: {}", e))?;
+        }
+    }
+    writer.flush().map_err(|e| format!("Failed to write file: {}", e))
+}
+
+/// Serializes exactly like `Snapshot`, field for field and in the same order,
+/// with `files` drawn from an iterator. Keep it in step with `Snapshot`.
+#[derive(Serialize)]
+#[serde(bound = "StreamedFiles<I>: Serialize")]
+struct StreamedSnapshot<'a, I> {
+    format_version: u32,
+    id: &'a str,
+    drive_path: &'a str,
+    timestamp: i64,
+    total_files: usize,
+    total_size: u64,
+    scan_duration: u64,
+    files: StreamedFiles<I>,
+    scan_errors_count: usize,
+    scan_errors: &'a [String],
+    hard_link_count: usize,
+    hard_link_bytes_saved: u64,
+    parent_id: &'a Option<String>,
+    is_consistent: Option<bool>,
+    excluded_by_extension_count: usize,
+    excluded_by_extension_bytes: u64,
+    content_filter: &'a Option<ContentFilter>,
+    extracted_from: &'a Option<String>,
+    files_per_sec: Option<f64>,
+    peak_memory_bytes: Option<u64>,
+    threads: Option<usize>,
+}
+
+impl<'a, I> StreamedSnapshot<'a, I> {
+    fn new(snapshot: &'a Snapshot, files: I) -> Self {
+        StreamedSnapshot {
+            format_version: snapshot.format_version,
+            id: &snapshot.id,
+            drive_path: &snapshot.drive_path,
+            timestamp: snapshot.timestamp,
+            total_files: snapshot.total_files,
+            total_size: snapshot.total_size,
+            scan_duration: snapshot.scan_duration,
+            files: StreamedFiles { files: RefCell::new(files), len: snapshot.total_files },
+            scan_errors_count: snapshot.scan_errors_count,
+            scan_errors: &snapshot.scan_errors,
+            hard_link_count: snapshot.hard_link_count,
+            hard_link_bytes_saved: snapshot.hard_link_bytes_saved,
+            parent_id: &snapshot.parent_id,
+            is_consistent: snapshot.is_consistent,
+            excluded_by_extension_count: snapshot.excluded_by_extension_count,
+            excluded_by_extension_bytes: snapshot.excluded_by_extension_bytes,
+            content_filter: &snapshot.content_filter,
+            extracted_from: &snapshot.extracted_from,
+            files_per_sec: snapshot.files_per_sec,
+            peak_memory_bytes: snapshot.peak_memory_bytes,
+            threads: snapshot.threads,
+        }
+    }
+}
+
+/// A sequence of `len` entries read from `files` as it is serialized. Binary
+/// payloads carry the length up front, so it has to be known in advance.
+struct StreamedFiles<I> {
+    files: RefCell<I>,
+    len: usize,
+}
+
+impl<I: Iterator<Item = Result<FileEntry, String>>> Serialize for StreamedFiles<I> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeSeq};
+        let mut files = self.files.borrow_mut();
+        let mut seq = serializer.serialize_seq(Some(self.len))?;
+        for _ in 0..self.len {
+            let file = files
+                .next()
+                .ok_or_else(|| S::Error::custom("fewer entries than total_files"))?
+                .map_err(S::Error::custom)?;
+            seq.serialize_element(&file)?;
+        }
+        seq.end()
+    }
+}
+
+const IO_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+fn write_with_progress<F>(path: &Path, data: &[u8], progress: &mut F) -> Result<(), String>
+where
+    F: FnMut(u64, u64),
+{
+    let mut file = fs::File::create(path).map_err(|e| format!("Failed to create file: {}", e))?;
+    let total = data.len() as u64;
+    let mut written: u64 = 0;
+    progress(written, total);
+    for chunk in data.chunks(IO_CHUNK_SIZE) {
+        file.write_all(chunk).map_err(|e| format!("Failed to write file: {}", e))?;
+        written += chunk.len() as u64;
+        progress(written, total);
+    }
+    Ok(())
+}
+
+fn read_with_progress<F>(path: &Path, progress: &mut F) -> Result<Vec<u8>, String>
+where
+    F: FnMut(u64, u64),
+{
+    let mut file = fs::File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let total = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut data = Vec::with_capacity(total as usize);
+    let mut buffer = vec![0u8; IO_CHUNK_SIZE];
+    progress(0, total);
+    loop {
+        let read = file.read(&mut buffer).map_err(|e| format!("Failed to read file: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        data.extend_from_slice(&buffer[..read]);
+        progress(data.len() as u64, total);
+    }
+    Ok(data)
+}
+
+/// Resolves the on-disk file of a snapshot, whichever format it was saved in.
+/// The `.bin` file wins when both exist: it is the newer one after an
+/// interrupted compaction, and a wrong or missing password is then reported
+/// as such rather than masked by a stale JSON file.
+pub fn snapshot_file_path(data_dir: &Path, snapshot_id: &str) -> Result<PathBuf, String> {
+    let snapshots_dir = data_dir.join("snapshots");
+    ["bin", "json"]
+        .iter()
+        .map(|ext| snapshots_dir.join(format!("{}.{}", snapshot_id, ext)))
+        .find(|path| path.exists())
+        .ok_or_else(|| format!("Snapshot file not found for id {}", snapshot_id))
+}
+
+/// Saves a newly scanned snapshot with `save_snapshot_as`, followed by its
+/// metadata sidecar unless `write_metadata` is false (e.g. for throwaway scans).
+/// `get_scan_history` still lists an unencrypted snapshot without a sidecar.
+pub fn save_scan<F>(data_dir: &Path, snapshot: &Snapshot, format: SnapshotFormat, password: Option<&str>, write_metadata: bool, progress: F) -> Result<(), String>
+where
+    F: FnMut(u64, u64),
+{
+    save_snapshot_as(data_dir, snapshot, format, password, progress)?;
+    if write_metadata {
+        save_snapshot_metadata(data_dir, snapshot)?;
+    }
+    Ok(())
+}
+
+/// Writes the summary sidecar used for fast history loading. Call it after the
+/// snapshot itself is saved, since its `encrypted` flag is read from the file.
+/// A lock set on an existing sidecar is kept.
+pub fn save_snapshot_metadata(data_dir: &Path, snapshot: &Snapshot) -> Result<(), String> {
+    let (header, compressed) = read_snapshot_header(&snapshot_file_path(data_dir, &snapshot.id)?)?;
+    let encrypted = detect_snapshot_format(&header) == SnapshotFormat::Encrypted;
+    let metadata_dir = data_dir.join("metadata");
+    fs::create_dir_all(&metadata_dir).map_err(|e| e.to_string())?;
+    let metadata_path = metadata_dir.join(format!("{}.json", snapshot.id));
+    let locked = read_metadata(&metadata_path).is_some_and(|summary| summary.locked);
+    let summary = SnapshotSummary {
+        id: snapshot.id.clone(),
+        drive_path: snapshot.drive_path.clone(),
+        timestamp: snapshot.timestamp,
+        total_files: snapshot.total_files,
+        total_size: snapshot.total_size,
+        scan_duration: snapshot.scan_duration,
+        encrypted,
+        parent_id: snapshot.parent_id.clone(),
+        compressed,
+        format_version: Some(snapshot.format_version),
+        drive_present: None,
+        locked,
+        files_per_sec: snapshot.files_per_sec,
+        peak_memory_bytes: snapshot.peak_memory_bytes,
+        threads: snapshot.threads,
+    };
+    write_metadata(&metadata_path, &summary)
+}
+
+fn read_metadata(metadata_path: &Path) -> Option<SnapshotSummary> {
+    let content = fs::read_to_string(metadata_path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn write_metadata(metadata_path: &Path, summary: &SnapshotSummary) -> Result<(), String> {
+    let json = serde_json::to_string(summary).map_err(|e| format!("Failed to serialize metadata: {}", e))?;
+    fs::write(metadata_path, json).map_err(|e| format!("Failed to write metadata: {}", e))?;
+    if let Some(data_dir) = metadata_path.parent().and_then(Path::parent) {
+        let key = history_index_key("metadata", metadata_path);
+        let fingerprint = file_fingerprint(metadata_path);
+        update_history_index(data_dir, |index| {
+            index.entries.insert(key, IndexedSummary { fingerprint, summary: Some(summary.clone()) });
+        });
+    }
+    Ok(())
+}
+
+/// Bump when `SnapshotSummary` changes shape, so an older index is rebuilt
+/// instead of read with defaults for the new fields.
+const HISTORY_INDEX_VERSION: u32 = 1;
+
+/// Every scan summary in one file, `index.json` in the data directory, so
+/// listing the history parses a single file instead of one per scan. Entries
+/// are keyed by the sidecar or snapshot file they summarize and remember its
+/// size and modification time; a file that changed, or has no entry, is read
+/// again. The sidecars stay the source of truth, and a missing or damaged
+/// index is simply rebuilt from them.
+#[derive(Serialize, Deserialize)]
+struct HistoryIndex {
+    version: u32,
+    entries: HashMap<String, IndexedSummary>,
+}
+
+impl Default for HistoryIndex {
+    fn default() -> Self {
+        HistoryIndex { version: HISTORY_INDEX_VERSION, entries: HashMap::new() }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexedSummary {
+    /// Size and modification time of the file when it was read.
+    fingerprint: String,
+    /// `None` for a file that can't be summarized: an unparseable sidecar, or
+    /// an encrypted snapshot without one.
+    summary: Option<SnapshotSummary>,
+}
+
+impl HistoryIndex {
+    /// Moves the entry for `path` into `fresh`, reading the file again with
+    /// `summarize` unless it is unchanged since it was indexed. Returns
+    /// whether it had to be read.
+    fn refresh<F>(&mut self, fresh: &mut HashMap<String, IndexedSummary>, key: String, path: &Path, summarize: F) -> Result<bool, String>
+    where
+        F: FnOnce(&Path) -> Result<Option<SnapshotSummary>, String>,
+    {
+        // Taken before reading, so a write in between shows up as a change next time
+        let fingerprint = file_fingerprint(path);
+        let (entry, read) = match self.entries.remove(&key) {
+            Some(entry) if entry.fingerprint == fingerprint => (entry, false),
+            _ => (IndexedSummary { fingerprint, summary: summarize(path)? }, true),
+        };
+        fresh.insert(key, entry);
+        Ok(read)
+    }
+}
+
+fn history_index_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("index.json")
+}
+
+/// `metadata/<file>` or `snapshots/<file>`, the same on every platform.
+fn history_index_key(dir: &str, path: &Path) -> String {
+    format!("{}/{}", dir, path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default())
+}
+
+fn file_fingerprint(path: &Path) -> String {
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_nanos());
+            format!("{}:{}", metadata.len(), modified)
+        }
+        Err(_) => String::new(),
+    }
+}
+
+/// The stored index, or an empty one if it is missing, unreadable or from
+/// another version.
+fn read_history_index(data_dir: &Path) -> HistoryIndex {
+    fs::read_to_string(history_index_path(data_dir))
+        .ok()
+        .and_then(|content| serde_json::from_str::<HistoryIndex>(&content).ok())
+        .filter(|index| index.version == HISTORY_INDEX_VERSION)
+        .unwrap_or_default()
+}
+
+/// Best effort: an index that fails to save is stale, and stale entries are
+/// noticed and re-read by `get_scan_history`.
+fn write_history_index(data_dir: &Path, index: &HistoryIndex) {
+    let index_path = history_index_path(data_dir);
+    let temp_path = index_path.with_extension("json.tmp");
+    if let Ok(json) = serde_json::to_string(index) {
+        if fs::write(&temp_path, json).is_ok() && fs::rename(&temp_path, &index_path).is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+    }
+}
+
+/// Applies `update` to the stored index, for writers that know what they
+/// changed. Without an index there is nothing to keep in step; the next
+/// `get_scan_history` builds it.
+fn update_history_index<F>(data_dir: &Path, update: F)
+where
+    F: FnOnce(&mut HistoryIndex),
+{
+    if !history_index_path(data_dir).exists() {
+        return;
+    }
+    let mut index = read_history_index(data_dir);
+    update(&mut index);
+    write_history_index(data_dir, &index);
+}
+
+/// Locks or unlocks a scan. The flag lives in the metadata sidecar, which is
+/// written for a scan saved without one.
+pub fn set_snapshot_locked(data_dir: &Path, snapshot_id: &str, locked: bool) -> Result<(), String> {
+    let mut summary = get_scan_history(data_dir)?
+        .into_iter()
+        .find(|summary| summary.id == snapshot_id)
+        .ok_or_else(|| format!("Scan {} not found", snapshot_id))?;
+    summary.locked = locked;
+    let metadata_dir = data_dir.join("metadata");
+    fs::create_dir_all(&metadata_dir).map_err(|e| e.to_string())?;
+    write_metadata(&metadata_dir.join(format!("{}.json", snapshot_id)), &summary)
+}
+
+/// Whether the scan's sidecar marks it as locked. Scans without one can't be.
+pub fn is_snapshot_locked(data_dir: &Path, snapshot_id: &str) -> Result<bool, String> {
+    let metadata_path = data_dir.join("metadata").join(format!("{}.json", snapshot_id));
+    Ok(read_metadata(&metadata_path).is_some_and(|summary| summary.locked))
+}
+
+pub fn load_snapshot(data_dir: &Path, snapshot_id: &str, password: Option<&str>) -> Result<Snapshot, String> {
+    load_snapshot_with_progress(data_dir, snapshot_id, password, |_, _| {})
+}
+
+/// Like `load_snapshot`, reporting `(bytes_read, total_bytes)` while the file is read.
+pub fn load_snapshot_with_progress<F>(data_dir: &Path, snapshot_id: &str, password: Option<&str>, mut progress: F) -> Result<Snapshot, String>
+where
+    F: FnMut(u64, u64),
+{
+    let (snapshot, _) = load_snapshot_stored(data_dir, snapshot_id, password, &mut progress)?;
+    upgrade_snapshot(snapshot)
+}
+
+/// Loads a snapshot as stored, without upgrading it, along with its format.
+/// The format comes from the file's content, so a snapshot saved under the
+/// wrong extension still loads; see `snapshot_extension_mismatch`.
+fn load_snapshot_stored<F>(data_dir: &Path, snapshot_id: &str, password: Option<&str>, progress: &mut F) -> Result<(Snapshot, SnapshotFormat), String>
+where
+    F: FnMut(u64, u64),
+{
+    let snapshot_path = snapshot_file_path(data_dir, snapshot_id)?;
+    // Judged from the header, before a large file is read for nothing
+    if password.is_none() && is_encrypted_file(&snapshot_path)? {
+        return Err(PASSWORD_REQUIRED.to_string());
+    }
+    if is_chunk_framed(&snapshot_path)? {
+        let password = password.ok_or(PASSWORD_REQUIRED)?;
+        return load_chunk_framed(&snapshot_path, password, progress).map(|snapshot| (snapshot, SnapshotFormat::Encrypted));
+    }
+    let data = decompress_snapshot_data(read_with_progress(&snapshot_path, progress)?)?;
+    let format = detect_snapshot_format(&data);
+    decode_snapshot_data(&data, format, password).map(|snapshot| (snapshot, format))
+}
+
+/// Describes how a stored snapshot's extension disagrees with its content,
+/// for the caller to warn about, or `None` when they agree. Loading goes by
+/// the content either way.
+pub fn snapshot_extension_mismatch(data_dir: &Path, snapshot_id: &str) -> Result<Option<String>, String> {
+    let snapshot_path = snapshot_file_path(data_dir, snapshot_id)?;
+    let (header, _) = read_snapshot_header(&snapshot_path)?;
+    Ok(extension_mismatch(snapshot_id, &snapshot_path, detect_snapshot_format(&header)))
+}
+
+fn is_chunk_framed(path: &Path) -> Result<bool, String> {
+    let mut magic = Vec::with_capacity(CHUNKED_MAGIC.len());
+    fs::File::open(path)
+        .and_then(|file| file.take(CHUNKED_MAGIC.len() as u64).read_to_end(&mut magic))
+        .map_err(|e| format!("Failed to read snapshot file: {}", e))?;
+    Ok(magic == CHUNKED_MAGIC)
+}
+
+/// Decrypts and decodes a chunk-framed file as it is read, so neither the
+/// ciphertext nor the plaintext is ever held in memory whole.
+fn load_chunk_framed<F>(path: &Path, password: &str, progress: &mut F) -> Result<Snapshot, String>
+where
+    F: FnMut(u64, u64),
+{
+    let file = fs::File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let total = file.metadata().map(|m| m.len()).unwrap_or(0);
+    progress(0, total);
+    let reader = ProgressReader { inner: file, read: 0, total, progress };
+    decode_binary_payload(ChunkedDecryptReader::new(BufReader::with_capacity(IO_CHUNK_SIZE, reader), password)?)
+}
+
+/// Reports `(bytes_read, total_bytes)` as `inner` is read.
+struct ProgressReader<'a, R, F> {
+    inner: R,
+    read: u64,
+    total: u64,
+    progress: &'a mut F,
+}
+
+impl<R: Read, F: FnMut(u64, u64)> Read for ProgressReader<'_, R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.read += count as u64;
+        (self.progress)(self.read, self.total);
+        Ok(count)
+    }
+}
+
+/// Reports `(bytes_written, total_bytes)` as `inner` is written.
+struct ProgressWriter<'a, W, F> {
+    inner: W,
+    written: u64,
+    total: u64,
+    progress: &'a mut F,
+}
+
+impl<W: Write, F: FnMut(u64, u64)> Write for ProgressWriter<'_, W, F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.written += count as u64;
+        (self.progress)(self.written, self.total);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Describes a snapshot file whose extension doesn't match its detected content.
+fn extension_mismatch(snapshot_id: &str, snapshot_path: &Path, format: SnapshotFormat) -> Option<String> {
+    let extension = snapshot_path.extension().and_then(|s| s.to_str()).unwrap_or("");
+    (extension != format.extension())
+        .then(|| format!("snapshot {} is stored as .{} but its content is {:?}", snapshot_id, extension, format))
+}
+
+fn decode_snapshot_data(data: &[u8], format: SnapshotFormat, password: Option<&str>) -> Result<Snapshot, String> {
+    match format {
+        SnapshotFormat::Json => serde_json::from_slice(data).map_err(|e| format!("Failed to parse: {}", e)),
+        SnapshotFormat::Binary => decode_binary_payload(&data[BINARY_MAGIC.len()..]),
+        SnapshotFormat::Encrypted => decrypt_snapshot_data(data, password),
+    }
+}
+
+fn decrypt_snapshot_data(data: &[u8], password: Option<&str>) -> Result<Snapshot, String> {
+    let password = password.ok_or(PASSWORD_REQUIRED)?;
+    if data.starts_with(CHUNKED_MAGIC) {
+        return decode_binary_payload(ChunkedDecryptReader::new(data, password)?);
+    }
+    let legacy = !data.starts_with(ENCRYPTED_MAGIC);
+    let decrypted = decrypt_payload(data, password)?;
+    if legacy {
+        // Headerless files predate format versioning and use the version 0 layout
+        decode_versioned_payload(0, &decrypted[..])
+    } else {
+        decode_binary_payload(&decrypted[..])
+    }
+}
+
+/// Rewrites a snapshot stored with an older format version in the current
+/// layout, keeping its storage format (but not any external compression).
+/// Returns the version it was upgraded from, or `None` when it was already
+/// current.
+pub fn migrate_snapshot(data_dir: &Path, snapshot_id: &str, password: Option<&str>) -> Result<Option<u32>, String> {
+    let old_path = snapshot_file_path(data_dir, snapshot_id)?;
+    let (snapshot, format) = load_snapshot_stored(data_dir, snapshot_id, password, &mut |_, _| {})?;
+    let stored_version = snapshot.format_version;
+    let snapshot = upgrade_snapshot(snapshot)?;
+    if stored_version == SNAPSHOT_FORMAT_VERSION {
+        return Ok(None);
+    }
+    replace_snapshot_file(data_dir, &snapshot, format, password, &old_path)?;
+    // Keep the sidecar's format version in step with the file
+    if data_dir.join("metadata").join(format!("{}.json", snapshot_id)).exists() {
+        save_snapshot_metadata(data_dir, &snapshot)?;
+    }
+    Ok(Some(stored_version))
+}
+
+/// Rewrites a snapshot in the most compact storage it can have: JSON becomes
+/// plaintext bincode, encrypted snapshots stay encrypted in the chunk-framed
+/// layout, and older format versions are upgraded on the way. Returns `None`
+/// when the file is already compact. Files compressed by external tools are
+/// left alone too, since rewriting them would drop the compression.
+pub fn compact_snapshot(data_dir: &Path, snapshot_id: &str, password: Option<&str>) -> Result<Option<CompactReport>, String> {
+    let old_path = snapshot_file_path(data_dir, snapshot_id)?;
+    let (header, compressed) = read_snapshot_header(&old_path)?;
+    let (snapshot, format) = load_snapshot_stored(data_dir, snapshot_id, password, &mut |_, _| {})?;
+    let compact_layout = format == SnapshotFormat::Binary || header.starts_with(CHUNKED_MAGIC);
+    if compressed || (compact_layout && snapshot.format_version == SNAPSHOT_FORMAT_VERSION) {
+        return Ok(None);
+    }
+    let bytes_before = fs::metadata(&old_path).map_err(|e| format!("Failed to read snapshot file: {}", e))?.len();
+    let target = if format == SnapshotFormat::Json { SnapshotFormat::Binary } else { format };
+    let snapshot = upgrade_snapshot(snapshot)?;
+    let new_path = replace_snapshot_file(data_dir, &snapshot, target, password, &old_path)?;
+    if data_dir.join("metadata").join(format!("{}.json", snapshot_id)).exists() {
+        save_snapshot_metadata(data_dir, &snapshot)?;
+    }
+    let bytes_after = fs::metadata(&new_path).map_err(|e| format!("Failed to read snapshot file: {}", e))?.len();
+    Ok(Some(CompactReport { bytes_before, bytes_after }))
+}
+
+/// Removes a snapshot's files (a `.json` and a `.bin` one both go) and its
+/// metadata sidecar. A locked scan is refused unless `force` is set.
+pub fn delete_snapshot(data_dir: &Path, snapshot_id: &str, force: bool) -> Result<(), String> {
+    if !force && is_snapshot_locked(data_dir, snapshot_id)? {
+        return Err(format!("Scan {} is locked; unlock it or force the deletion", snapshot_id));
+    }
+    for path in snapshot_storage_paths(data_dir, snapshot_id) {
+        if path.exists() {
+            fs::remove_file(path).map_err(|e| e.to_string())?;
+        }
+    }
+    update_history_index(data_dir, |index| {
+        for key in ["metadata", "snapshots"].iter().flat_map(|dir| {
+            ["json", "bin"].iter().map(move |extension| format!("{}/{}.{}", dir, snapshot_id, extension))
+        }) {
+            index.entries.remove(&key);
+        }
+    });
+    clear_comparison_cache(data_dir, Some(snapshot_id))?;
+    Ok(())
+}
+
+/// Every file that can belong to `snapshot_id`: both snapshot extensions and
+/// the metadata sidecar, whether they exist or not.
+fn snapshot_storage_paths(data_dir: &Path, snapshot_id: &str) -> [PathBuf; 3] {
+    let snapshots_dir = data_dir.join("snapshots");
+    [
+        snapshots_dir.join(format!("{}.bin", snapshot_id)),
+        snapshots_dir.join(format!("{}.json", snapshot_id)),
+        data_dir.join("metadata").join(format!("{}.json", snapshot_id)),
+    ]
+}
+
+/// Size of the files `delete_snapshot` removes for `snapshot_id`, counted as
+/// `data_dir_stats` counts them.
+fn snapshot_storage_bytes(data_dir: &Path, snapshot_id: &str) -> Result<u64, String> {
+    Ok(snapshot_storage_paths(data_dir, snapshot_id).iter().map(|path| fs::metadata(path).map(|m| m.len()).unwrap_or(0)).sum())
+}
+
+/// The scans `delete_snapshots_where` would delete with the same arguments,
+/// and the space that would free, without deleting anything.
+pub fn preview_delete_snapshots_where<P>(data_dir: &Path, predicate: P, force: bool) -> Result<DeletePreview, String>
+where
+    P: Fn(&SnapshotSummary) -> bool,
+{
+    let mut preview = DeletePreview::default();
+    for summary in get_scan_history(data_dir)?.into_iter().filter(|summary| predicate(summary)) {
+        if force || !summary.locked {
+            preview.bytes += snapshot_storage_bytes(data_dir, &summary.id)?;
+            preview.snapshots.push(summary);
+        } else {
+            preview.locked.push(summary);
+        }
+    }
+    Ok(preview)
+}
+
+/// Deletes every snapshot whose summary matches `predicate` and returns their
+/// ids. Locked scans are skipped unless `force` is set.
+pub fn delete_snapshots_where<P>(data_dir: &Path, predicate: P, force: bool) -> Result<Vec<String>, String>
+where
+    P: Fn(&SnapshotSummary) -> bool,
+{
+    let mut deleted = Vec::new();
+    for summary in preview_delete_snapshots_where(data_dir, predicate, force)?.snapshots {
+        delete_snapshot(data_dir, &summary.id, force)?;
+        deleted.push(summary.id);
+    }
+    Ok(deleted)
+}
+
+/// Groups of scans with the same `content_fingerprint`, i.e. scans of one drive
+/// that found exactly the same entries, each group sorted oldest first. Only
+/// scans with matching totals are loaded to be fingerprinted. Encrypted scans
+/// can't be loaded without a password and are never grouped.
+pub fn find_duplicate_snapshots(data_dir: &Path) -> Result<Vec<Vec<SnapshotSummary>>, String> {
+    let mut candidates: HashMap<(String, usize, u64), Vec<SnapshotSummary>> = HashMap::new();
+    for summary in get_scan_history(data_dir)?.into_iter().filter(|summary| !summary.encrypted) {
+        candidates
+            .entry((summary.drive_path.clone(), summary.total_files, summary.total_size))
+            .or_default()
+            .push(summary);
+    }
+    let mut groups = Vec::new();
+    for summaries in candidates.into_values().filter(|summaries| summaries.len() > 1) {
+        let mut by_fingerprint: HashMap<String, Vec<SnapshotSummary>> = HashMap::new();
+        for summary in summaries {
+            let snapshot = load_snapshot(data_dir, &summary.id, None)?;
+            by_fingerprint.entry(content_fingerprint(&snapshot)).or_default().push(summary);
+        }
+        groups.extend(by_fingerprint.into_values().filter(|group| group.len() > 1));
+    }
+    for group in &mut groups {
+        group.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.id.cmp(&b.id)));
+    }
+    groups.sort_by(|a, b| a[0].timestamp.cmp(&b[0].timestamp));
+    Ok(groups)
+}
+
+/// Every stored scan that recorded a file with content `hash` (a SHA-256 hex
+/// digest, in either case), and the paths it was found at. Each scan has to
+/// be loaded, so this reads the whole history. Encrypted scans are only
+/// searched with a `password`; a scan that fails to load is listed in
+/// `skipped` and the search goes on.
+pub fn find_hash(data_dir: &Path, hash: &str, password: Option<&str>) -> Result<HashMatches, String> {
+    let mut matches = HashMatches::default();
+    for summary in get_scan_history(data_dir)? {
+        if summary.encrypted && password.is_none() {
+            matches.encrypted.push(summary.id);
+            continue;
+        }
+        let snapshot = match load_snapshot(data_dir, &summary.id, if summary.encrypted { password } else { None }) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                matches.skipped.push((summary.id, e));
+                continue;
+            }
+        };
+        let hashed = snapshot.files.iter().filter(|f| !f.is_dir).filter_map(|f| f.hash.as_deref().map(|h| (f, h)));
+        let mut any_hashed = false;
+        let mut paths = Vec::new();
+        for (file, file_hash) in hashed {
+            any_hashed = true;
+            if file_hash.eq_ignore_ascii_case(hash) {
+                paths.push(file.path.clone());
+            }
+        }
+        if !any_hashed {
+            matches.unhashed.push(summary.id);
+        } else if !paths.is_empty() {
+            matches.found.push((summary.id, paths));
+        }
+    }
+    Ok(matches)
+}
+
+/// Fills in `drive_present` for each scan, checking every distinct drive path
+/// once.
+pub fn check_drive_paths(history: &mut [SnapshotSummary]) {
+    let mut present: HashMap<String, bool> = HashMap::new();
+    for scan in history.iter_mut() {
+        let exists = *present
+            .entry(scan.drive_path.clone())
+            .or_insert_with(|| Path::new(&scan.drive_path).exists());
+        scan.drive_present = Some(exists);
+    }
+}
+
+/// Every stored scan, newest first. Scans are summarized from their sidecars,
+/// and any snapshot without one (saved with `write_metadata` off, or by an older
+/// version) from the snapshot file itself, which is slower. Encrypted snapshots
+/// without a sidecar can't be read without a password and are left out. Files
+/// unchanged since the last call come from the history index instead of
+/// being parsed again.
+pub fn get_scan_history(data_dir: &Path) -> Result<Vec<SnapshotSummary>, String> {
+    let mut index = read_history_index(data_dir);
+    let mut fresh = HashMap::new();
+    let mut changed = false;
+    let metadata_dir = data_dir.join("metadata");
+    if metadata_dir.exists() {
+        for entry in fs::read_dir(&metadata_dir).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                let key = history_index_key("metadata", &path);
+                changed |= index.refresh(&mut fresh, key, &path, |path| Ok(read_metadata(path)))?;
+            }
+        }
+    }
+    let summarized: HashSet<String> = fresh
+        .values()
+        .filter_map(|entry| entry.summary.as_ref().map(|summary| summary.id.clone()))
+        .collect();
+    let snapshots_dir = data_dir.join("snapshots");
+    if snapshots_dir.exists() {
+        for entry in fs::read_dir(&snapshots_dir).map_err(|e| format!("Failed to read data directory: {}", e))? {
+            let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+            let path = entry.path();
+            let extension = path.extension().and_then(|s| s.to_str());
+            let has_sidecar = path.file_stem().and_then(|s| s.to_str()).is_some_and(|id| summarized.contains(id));
+            if (extension == Some("json") || extension == Some("bin")) && !has_sidecar {
+                let key = history_index_key("snapshots", &path);
+                changed |= index.refresh(&mut fresh, key, &path, summarize_snapshot_file)?;
+            }
+        }
+    }
+    // Anything left was deleted behind the index's back
+    if changed || !index.entries.is_empty() || !history_index_path(data_dir).exists() {
+        index.entries = fresh;
+        write_history_index(data_dir, &index);
+        fresh = index.entries;
+    }
+    let mut summaries: Vec<SnapshotSummary> = fresh.into_values().filter_map(|entry| entry.summary).collect();
+    // By id too, as the index keeps no order of its own
+    summaries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| a.id.cmp(&b.id)));
+    Ok(summaries)
+}
+
+/// Summary of a snapshot read from its file, or `None` if it is encrypted.
+fn summarize_snapshot_file(path: &Path) -> Result<Option<SnapshotSummary>, String> {
+    let raw = fs::read(path).map_err(|e| format!("Failed to read snapshot file: {}", e))?;
+    let compressed = is_compressed(&raw);
+    let content = decompress_snapshot_data(raw)?;
+    let snapshot: Snapshot = match detect_snapshot_format(&content) {
+        // Encrypted snapshots can't be summarized without a password
+        SnapshotFormat::Encrypted => return Ok(None),
+        format => decode_snapshot_data(&content, format, None).map_err(|e| format!("Failed to parse snapshot: {}", e))?,
+    };
+    Ok(Some(SnapshotSummary {
+        id: snapshot.id,
+        drive_path: snapshot.drive_path,
+        timestamp: snapshot.timestamp,
+        total_files: snapshot.total_files,
+        total_size: snapshot.total_size,
+        scan_duration: snapshot.scan_duration,
+        encrypted: false,
+        parent_id: snapshot.parent_id,
+        compressed,
+        format_version: Some(snapshot.format_version),
+        drive_present: None,
+        locked: false,
+        files_per_sec: snapshot.files_per_sec,
+        peak_memory_bytes: snapshot.peak_memory_bytes,
+        threads: snapshot.threads,
+    }))
+}
+
+/// Ids of every snapshot file in the data directory, including encrypted ones
+/// that `get_scan_history` can't summarize without a sidecar.
+pub fn stored_snapshot_ids(data_dir: &Path) -> Result<Vec<String>, String> {
+    let snapshots_dir = data_dir.join("snapshots");
+    let mut ids = Vec::new();
+    if !snapshots_dir.exists() {
+        return Ok(ids);
+    }
+    for entry in fs::read_dir(&snapshots_dir).map_err(|e| format!("Failed to read data directory: {}", e))? {
+        let path = entry.map_err(|e| format!("Failed to read entry: {}", e))?.path();
+        let extension = path.extension().and_then(|s| s.to_str());
+        if extension == Some("json") || extension == Some("bin") {
+            if let Some(id) = path.file_stem().and_then(|s| s.to_str()) {
+                ids.push(id.to_string());
+            }
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// The most recent snapshot of `drive_path` in the history, used as the parent
+/// of a new scan of the same drive.
+pub fn latest_snapshot_for_drive(data_dir: &Path, drive_path: &str) -> Result<Option<SnapshotSummary>, String> {
+    Ok(get_scan_history(data_dir)?
+        .into_iter()
+        .filter(|scan| scan.drive_path == drive_path)
+        .max_by_key(|scan| scan.timestamp))
+}
+
+/// Follows `parent_id` links from `snapshot_id` back to the first scan of the
+/// drive, newest first. Parents missing from the history end the chain.
+pub fn snapshot_lineage(data_dir: &Path, snapshot_id: &str) -> Result<Vec<SnapshotSummary>, String> {
+    let mut by_id: HashMap<String, SnapshotSummary> =
+        get_scan_history(data_dir)?.into_iter().map(|scan| (scan.id.clone(), scan)).collect();
+    let mut lineage = Vec::new();
+    let mut next = Some(snapshot_id.to_string());
+    // Removing visited entries also guards against a corrupted, cyclic chain
+    while let Some(summary) = next.and_then(|id| by_id.remove(&id)) {
+        next = summary.parent_id.clone();
+        lineage.push(summary);
+    }
+    Ok(lineage)
+}
+
+/// Summarizes the data directory: how many snapshots it holds, how many are
+/// encrypted, the bytes used and the span of scan dates. Encrypted snapshots
+/// without a metadata sidecar are counted but can't contribute dates.
+pub fn data_dir_stats(data_dir: &Path) -> Result<DataDirStats, String> {
+    let mut stats = DataDirStats {
+        data_dir: data_dir.to_string_lossy().to_string(),
+        ..DataDirStats::default()
+    };
+
+    let snapshots_dir = data_dir.join("snapshots");
+    if snapshots_dir.exists() {
+        for entry in fs::read_dir(&snapshots_dir).map_err(|e| format!("Failed to read data directory: {}", e))? {
+            let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+            let path = entry.path();
+            let extension = path.extension().and_then(|s| s.to_str());
+            if extension != Some("json") && extension != Some("bin") {
+                continue;
+            }
+            stats.snapshot_count += 1;
+            if is_encrypted_file(&path)? {
+                stats.encrypted_count += 1;
+            } else {
+                stats.plaintext_count += 1;
+            }
+            stats.snapshot_bytes += entry.metadata().map_err(|e| format!("Failed to read entry: {}", e))?.len();
+        }
+    }
+
+    let metadata_dir = data_dir.join("metadata");
+    if metadata_dir.exists() {
+        for entry in fs::read_dir(&metadata_dir).map_err(|e| format!("Failed to read metadata directory: {}", e))? {
+            let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+            stats.metadata_bytes += entry.metadata().map_err(|e| format!("Failed to read entry: {}", e))?.len();
+        }
+    }
+
+    let history = get_scan_history(data_dir)?;
+    stats.oldest_scan = history.iter().map(|scan| scan.timestamp).min();
+    stats.newest_scan = history.iter().map(|scan| scan.timestamp).max();
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{file_entry, snapshot, TestDataDir};
+    use crate::snapshot::{FileEntry, SnapshotBuilder};
+    use tempfile::TempDir;
+
+    /// Asserts that `calls` count up to a fixed total, ending at it.
+    fn assert_monotonic(calls: &[(u64, u64)], expected_total: u64) {
+        assert!(calls.len() > 2, "{:?}", calls);
+        assert!(calls.windows(2).all(|pair| pair[0].0 <= pair[1].0), "{:?}", calls);
+        assert!(calls.iter().all(|&(_, total)| total == expected_total), "{:?}", calls);
+        assert_eq!(calls.last().unwrap().0, expected_total);
+    }
+
+    #[test]
+    fn save_and_load_report_increasing_byte_counts() {
+        let data_dir = TestDataDir::new();
+        // Several IO chunks' worth once serialized
+        let files = (0..100_000).map(|i| file_entry(&format!("/data/some/longer/directory/name/file{:06}.dat", i), i)).collect();
+        let snapshot = snapshot("big", 1_704_067_200, files);
+
+        for (encrypt, extension) in [(false, "json"), (true, "bin")] {
+            let password = encrypt.then_some("secret");
+            let path = data_dir.path().join("snapshots").join(format!("big.{}", extension));
+            let mut saved = Vec::new();
+            save_snapshot_with_progress(data_dir.path(), &snapshot, encrypt, password, |written, total| saved.push((written, total))).unwrap();
+            let stored_len = fs::metadata(&path).unwrap().len();
+            assert_monotonic(&saved, stored_len);
+
+            let mut loaded = Vec::new();
+            let reloaded = load_snapshot_with_progress(data_dir.path(), "big", password, |read, total| loaded.push((read, total))).unwrap();
+            assert_monotonic(&loaded, stored_len);
+            assert_eq!(reloaded.total_files, 100_000);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    fn small_snapshot(id: &str) -> Snapshot {
+        let mut snapshot = snapshot(id, 1_704_067_200, vec![file_entry("/data/a.txt", 10), file_entry("/data/b/c.bin", 2048)]);
+        snapshot.scan_errors = vec!["/data/locked: Permission denied".to_string()];
+        snapshot.scan_errors_count = 1;
+        snapshot
+    }
+
+    #[test]
+    fn plaintext_binary_round_trips_without_a_password() {
+        let data_dir = TestDataDir::new();
+        let snapshot = small_snapshot("plain");
+        save_snapshot_as(data_dir.path(), &snapshot, SnapshotFormat::Binary, None, |_, _| {}).unwrap();
+
+        let path = data_dir.path().join("snapshots").join("plain.bin");
+        assert!(fs::read(&path).unwrap().starts_with(BINARY_MAGIC));
+        let loaded = load_snapshot(data_dir.path(), "plain", None).unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&snapshot).unwrap());
+    }
+
+    #[test]
+    fn encrypted_snapshots_without_a_password_need_one_rather_than_go_missing() {
+        let data_dir = TestDataDir::new();
+        save_snapshot(data_dir.path(), &small_snapshot("secret"), true, Some("pw")).unwrap();
+        assert_eq!(load_snapshot(data_dir.path(), "secret", None).unwrap_err(), PASSWORD_REQUIRED);
+
+        // Whatever extension it ended up with
+        let snapshots_dir = data_dir.path().join("snapshots");
+        fs::rename(snapshots_dir.join("secret.bin"), snapshots_dir.join("secret.json")).unwrap();
+        assert_eq!(load_snapshot(data_dir.path(), "secret", None).unwrap_err(), PASSWORD_REQUIRED);
+        assert_eq!(load_snapshot(data_dir.path(), "secret", Some("pw")).unwrap().files.len(), 2);
+
+        // Whole-blob files as written by older versions too
+        let mut whole_blob = ENCRYPTED_MAGIC.to_vec();
+        whole_blob.extend_from_slice(&[7u8; 64]);
+        fs::write(snapshots_dir.join("old-secret.bin"), whole_blob).unwrap();
+        assert_eq!(load_snapshot(data_dir.path(), "old-secret", None).unwrap_err(), PASSWORD_REQUIRED);
+
+        let missing = load_snapshot(data_dir.path(), "never-saved", None).unwrap_err();
+        assert_ne!(missing, PASSWORD_REQUIRED);
+        save_scan(data_dir.path(), "plain", "/data", 1_000);
+        let compared = crate::compare::compare_snapshots_cached(data_dir.path(), "plain", "secret", &Default::default(), None, None);
+        assert_eq!(compared.unwrap_err(), PASSWORD_REQUIRED);
+    }
+
+    #[test]
+    fn data_dir_falls_back_to_the_environment_override() {
+        let local = PathBuf::from("/home/me/.local/share");
+        let chosen = std::ffi::OsString::from("/srv/drive-pulse");
+
+        // No desktop profile: the override is used, or the error names it
+        assert_eq!(resolve_data_dir(Some(chosen.clone()), None), Ok(PathBuf::from("/srv/drive-pulse")));
+        let error = resolve_data_dir(None, None).unwrap_err();
+        assert!(error.contains(DATA_DIR_ENV), "{}", error);
+        assert!(resolve_data_dir(Some(std::ffi::OsString::new()), None).is_err());
+
+        assert_eq!(resolve_data_dir(Some(chosen), Some(local.clone())), Ok(PathBuf::from("/srv/drive-pulse")));
+        assert_eq!(resolve_data_dir(None, Some(local.clone())), Ok(local.join("com.pifrost.drivepulse")));
+    }
+
+    #[test]
+    fn snapshot_file_path_finds_either_extension() {
+        let data_dir = TestDataDir::new();
+        save_snapshot_as(data_dir.path(), &small_snapshot("as-json"), SnapshotFormat::Json, None, |_, _| {}).unwrap();
+        save_snapshot_as(data_dir.path(), &small_snapshot("as-binary"), SnapshotFormat::Binary, None, |_, _| {}).unwrap();
+
+        let snapshots_dir = data_dir.path().join("snapshots");
+        assert_eq!(snapshot_file_path(data_dir.path(), "as-json").unwrap(), snapshots_dir.join("as-json.json"));
+        assert_eq!(snapshot_file_path(data_dir.path(), "as-binary").unwrap(), snapshots_dir.join("as-binary.bin"));
+        let error = snapshot_file_path(data_dir.path(), "missing").unwrap_err();
+        assert!(error.contains("not found") && error.contains("missing"), "{}", error);
+    }
+
+    #[test]
+    fn a_stale_json_file_is_shadowed_by_the_bin_one_and_deleted_with_it() {
+        let data_dir = TestDataDir::new();
+        let snapshots_dir = data_dir.path().join("snapshots");
+        save_snapshot_as(data_dir.path(), &small_snapshot("both"), SnapshotFormat::Encrypted, Some("secret"), |_, _| {}).unwrap();
+        // As left behind by an interrupted compaction
+        let mut stale = small_snapshot("both");
+        stale.files.clear();
+        fs::write(snapshots_dir.join("both.json"), serde_json::to_vec(&stale).unwrap()).unwrap();
+
+        assert_eq!(snapshot_file_path(data_dir.path(), "both").unwrap(), snapshots_dir.join("both.bin"));
+        assert!(is_snapshot_encrypted(data_dir.path(), "both").unwrap());
+        assert_eq!(load_snapshot(data_dir.path(), "both", None).unwrap_err(), PASSWORD_REQUIRED);
+        assert_eq!(load_snapshot(data_dir.path(), "both", Some("secret")).unwrap().files.len(), 2);
+
+        delete_snapshot(data_dir.path(), "both", false).unwrap();
+        assert!(!snapshots_dir.join("both.bin").exists());
+        assert!(!snapshots_dir.join("both.json").exists());
+        assert!(snapshot_file_path(data_dir.path(), "both").is_err());
+    }
+
+    /// Saves a small unencrypted scan of `drive_path` with its metadata, as a scan would.
+    fn save_scan(data_dir: &Path, id: &str, drive_path: &str, timestamp: i64) {
+        let mut scan = snapshot(id, timestamp, vec![file_entry(&format!("{}/a.txt", drive_path), 10)]);
+        scan.drive_path = drive_path.to_string();
+        save_snapshot(data_dir, &scan, false, None).unwrap();
+        save_snapshot_metadata(data_dir, &scan).unwrap();
+    }
+
+    fn history_ids(data_dir: &Path) -> Vec<String> {
+        let mut ids: Vec<String> = get_scan_history(data_dir).unwrap().into_iter().map(|summary| summary.id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn scans_saved_without_metadata_still_show_in_history() {
+        let data_dir = TestDataDir::new();
+        // The library's `save_scan`, not the helper above
+        super::save_scan(data_dir.path(), &small_snapshot("with-sidecar"), SnapshotFormat::Json, None, true, |_, _| {}).unwrap();
+        super::save_scan(data_dir.path(), &small_snapshot("bare-json"), SnapshotFormat::Json, None, false, |_, _| {}).unwrap();
+        super::save_scan(data_dir.path(), &small_snapshot("bare-binary"), SnapshotFormat::Binary, None, false, |_, _| {}).unwrap();
+        super::save_scan(data_dir.path(), &small_snapshot("bare-secret"), SnapshotFormat::Encrypted, Some("secret"), false, |_, _| {}).unwrap();
+
+        let metadata_dir = data_dir.path().join("metadata");
+        assert!(metadata_dir.join("with-sidecar.json").exists());
+        for id in ["bare-json", "bare-binary", "bare-secret"] {
+            assert!(!metadata_dir.join(format!("{}.json", id)).exists(), "{}", id);
+        }
+
+        // Encrypted snapshots can only be summarized from a sidecar
+        assert_eq!(history_ids(data_dir.path()), vec!["bare-binary", "bare-json", "with-sidecar"]);
+        let expected = small_snapshot("bare-binary");
+        let bare = get_scan_history(data_dir.path()).unwrap().into_iter().find(|summary| summary.id == "bare-binary").unwrap();
+        assert_eq!((bare.drive_path.as_str(), bare.timestamp), ("/data", expected.timestamp));
+        assert_eq!((bare.total_files, bare.total_size), (expected.total_files, expected.total_size));
+        assert!(!bare.encrypted);
+
+        delete_snapshot(data_dir.path(), "bare-json", false).unwrap();
+        assert_eq!(history_ids(data_dir.path()), vec!["bare-binary", "with-sidecar"]);
+    }
+
+    #[test]
+    fn content_identical_scans_of_a_drive_are_grouped_oldest_first() {
+        let data_dir = TestDataDir::new();
+        save_scan(data_dir.path(), "a-second", "/a", 2_000);
+        save_scan(data_dir.path(), "a-first", "/a", 1_000);
+        save_scan(data_dir.path(), "a-third", "/a", 3_000);
+        save_scan(data_dir.path(), "b-only", "/b", 1_000);
+        // Same drive and totals as the "/a" scans, but the file was touched since
+        let mut touched = file_entry("/a/a.txt", 10);
+        touched.modified += 60;
+        let changed = SnapshotBuilder::new("/a").id("a-touched").timestamp(4_000).file(touched).build();
+        save_snapshot(data_dir.path(), &changed, false, None).unwrap();
+        save_snapshot_metadata(data_dir.path(), &changed).unwrap();
+        let secret = SnapshotBuilder::new("/a").id("a-secret").timestamp(5_000).file(file_entry("/a/a.txt", 10)).build();
+        save_snapshot(data_dir.path(), &secret, true, Some("secret")).unwrap();
+        save_snapshot_metadata(data_dir.path(), &secret).unwrap();
+
+        let groups: Vec<Vec<String>> = find_duplicate_snapshots(data_dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|group| group.into_iter().map(|summary| summary.id).collect())
+            .collect();
+        assert_eq!(groups, vec![vec!["a-first", "a-second", "a-third"]]);
+    }
+
+    #[test]
+    fn compacted_snapshots_load_identically_from_fewer_bytes() {
+        let data_dir = TestDataDir::new();
+        let files: Vec<FileEntry> = (0..50).map(|i| file_entry(&format!("/data/dir/file-{}.txt", i), i * 100)).collect();
+        let verbose = SnapshotBuilder::new("/data").id("verbose").timestamp(1_704_067_200).files(files).build();
+        save_snapshot_as(data_dir.path(), &verbose, SnapshotFormat::Json, None, |_, _| {}).unwrap();
+        save_snapshot_metadata(data_dir.path(), &verbose).unwrap();
+        let as_json = |snapshot: &Snapshot| serde_json::to_value(snapshot).unwrap();
+
+        let report = compact_snapshot(data_dir.path(), "verbose", None).unwrap().expect("JSON is rewritten");
+        assert!(report.bytes_after < report.bytes_before, "{:?}", report);
+        assert_eq!(report.bytes_saved(), report.bytes_before as i64 - report.bytes_after as i64);
+        assert!(!data_dir.path().join("snapshots/verbose.json").exists());
+        assert_eq!(
+            fs::metadata(data_dir.path().join("snapshots/verbose.bin")).unwrap().len(),
+            report.bytes_after
+        );
+        assert_eq!(as_json(&load_snapshot(data_dir.path(), "verbose", None).unwrap()), as_json(&verbose));
+        assert!(compact_snapshot(data_dir.path(), "verbose", None).unwrap().is_none(), "already compact");
+
+        // Encrypted snapshots stay encrypted
+        save_snapshot(data_dir.path(), &small_snapshot("secret"), true, Some("secret")).unwrap();
+        assert!(compact_snapshot(data_dir.path(), "secret", Some("secret")).unwrap().is_none());
+        assert!(is_snapshot_encrypted(data_dir.path(), "secret").unwrap());
+        assert_eq!(as_json(&load_snapshot(data_dir.path(), "secret", Some("secret")).unwrap()), as_json(&small_snapshot("secret")));
+    }
+
+    #[test]
+    fn a_failed_compaction_leaves_the_original_loadable() {
+        let data_dir = TestDataDir::new();
+        let snapshot = small_snapshot("verbose");
+        save_snapshot_as(data_dir.path(), &snapshot, SnapshotFormat::Json, None, |_, _| {}).unwrap();
+        // A directory where the new file would be written makes the write fail
+        let blocked = data_dir.path().join("snapshots/verbose.bin.tmp");
+        fs::create_dir(&blocked).unwrap();
+
+        assert!(compact_snapshot(data_dir.path(), "verbose", None).is_err());
+        assert!(!data_dir.path().join("snapshots/verbose.bin").exists(), "no partial file shadows the original");
+        assert_eq!(load_snapshot(data_dir.path(), "verbose", None).unwrap().total_files, snapshot.total_files);
+
+        fs::remove_dir(&blocked).unwrap();
+        assert!(compact_snapshot(data_dir.path(), "verbose", None).unwrap().is_some());
+        assert!(!blocked.exists());
+        assert_eq!(load_snapshot(data_dir.path(), "verbose", None).unwrap().total_files, snapshot.total_files);
+    }
+
+    #[test]
+    fn a_failed_migration_leaves_the_original_loadable() {
+        let data_dir = TestDataDir::new();
+        let snapshots_dir = data_dir.path().join("snapshots");
+        fs::create_dir_all(&snapshots_dir).unwrap();
+        // Migrated in place: the old and new files have the same name
+        let id = "0123456789abcdef";
+        fs::write(snapshots_dir.join(format!("{}.bin", id)), include_bytes!("testdata/snapshot_v12.bin")).unwrap();
+        let blocked = snapshots_dir.join(format!("{}.bin.tmp", id));
+        fs::create_dir(&blocked).unwrap();
+
+        assert!(migrate_snapshot(data_dir.path(), id, None).is_err());
+        let (stored, _) = load_snapshot_stored(data_dir.path(), id, None, &mut |_, _| {}).unwrap();
+        assert_eq!(stored.format_version, 12, "the original is untouched");
+
+        fs::remove_dir(&blocked).unwrap();
+        assert_eq!(migrate_snapshot(data_dir.path(), id, None).unwrap(), Some(12));
+        let (stored, _) = load_snapshot_stored(data_dir.path(), id, None, &mut |_, _| {}).unwrap();
+        assert_eq!(stored.format_version, SNAPSHOT_FORMAT_VERSION);
+    }
+
+    #[test]
+    fn a_hash_is_found_in_every_scan_that_recorded_it() {
+        let data_dir = TestDataDir::new();
+        let wanted = "ab".repeat(32);
+        let hashed = |path: &str, hash: &str| FileEntry { hash: Some(hash.to_string()), ..file_entry(path, 1) };
+        let store = |id: &str, timestamp: i64, files: Vec<FileEntry>, password: Option<&str>| {
+            let snapshot = SnapshotBuilder::new("/a").id(id).timestamp(timestamp).files(files).build();
+            save_snapshot(data_dir.path(), &snapshot, password.is_some(), password).unwrap();
+            save_snapshot_metadata(data_dir.path(), &snapshot).unwrap();
+        };
+        store("first", 1_000, vec![hashed("/a/x.txt", &wanted), hashed("/a/y.txt", &"cd".repeat(32))], None);
+        store("second", 2_000, vec![hashed("/a/moved.txt", &wanted), hashed("/a/copy.txt", &wanted)], None);
+        store("other", 3_000, vec![hashed("/a/x.txt", &"cd".repeat(32))], None);
+        store("unhashed", 4_000, vec![file_entry("/a/x.txt", 1)], None);
+        store("secret", 5_000, vec![hashed("/a/hidden.txt", &wanted)], Some("pw"));
+        store("other-secret", 500, vec![hashed("/a/x.txt", &wanted)], Some("another pw"));
+
+        let matches = find_hash(data_dir.path(), &wanted.to_uppercase(), None).unwrap();
+        assert_eq!(
+            matches.found,
+            vec![
+                ("second".to_string(), vec!["/a/copy.txt".to_string(), "/a/moved.txt".to_string()]),
+                ("first".to_string(), vec!["/a/x.txt".to_string()]),
+            ]
+        );
+        assert_eq!(matches.unhashed, vec!["unhashed"]);
+        assert_eq!(matches.encrypted, vec!["secret", "other-secret"]);
+        assert!(matches.skipped.is_empty());
+
+        let with_password = find_hash(data_dir.path(), &wanted, Some("pw")).unwrap();
+        assert_eq!(with_password.found[0], ("secret".to_string(), vec!["/a/hidden.txt".to_string()]));
+        assert!(with_password.encrypted.is_empty());
+        assert_eq!(with_password.found.len(), 3);
+        assert_eq!(with_password.skipped.len(), 1);
+        assert_eq!(with_password.skipped[0].0, "other-secret");
+        assert!(find_hash(data_dir.path(), &"ef".repeat(32), None).unwrap().found.is_empty());
+    }
+
+    #[test]
+    fn delete_where_removes_only_the_selected_scans() {
+        let data_dir = TestDataDir::new();
+        save_scan(data_dir.path(), "a-old", "/a", 1_000);
+        save_scan(data_dir.path(), "a-new", "/a", 2_000);
+        save_scan(data_dir.path(), "b-old", "/b", 1_000);
+
+        let mut deleted = delete_snapshots_where(data_dir.path(), |summary| summary.drive_path == "/a" && summary.timestamp < 1_500, false).unwrap();
+        deleted.sort();
+        assert_eq!(deleted, vec!["a-old"]);
+        assert_eq!(history_ids(data_dir.path()), vec!["a-new", "b-old"]);
+        assert!(!data_dir.path().join("snapshots/a-old.json").exists());
+        assert!(!data_dir.path().join("metadata/a-old.json").exists());
+        assert!(data_dir.path().join("snapshots/b-old.json").exists());
+
+        assert!(delete_snapshots_where(data_dir.path(), |summary| summary.drive_path == "/c", false).unwrap().is_empty());
+        assert_eq!(history_ids(data_dir.path()), vec!["a-new", "b-old"]);
+    }
+
+    #[test]
+    fn locked_scans_survive_pruning_and_need_force_to_delete() {
+        let data_dir = TestDataDir::new();
+        save_scan(data_dir.path(), "a-old", "/a", 1_000);
+        save_scan(data_dir.path(), "b-old", "/b", 1_000);
+        save_scan(data_dir.path(), "c-old", "/c", 1_000);
+        set_snapshot_locked(data_dir.path(), "a-old", true).unwrap();
+        set_snapshot_locked(data_dir.path(), "c-old", true).unwrap();
+        // Rewriting the sidecar keeps the lock
+        save_snapshot_metadata(data_dir.path(), &SnapshotBuilder::new("/a").id("a-old").timestamp(1_000).build()).unwrap();
+        assert!(is_snapshot_locked(data_dir.path(), "a-old").unwrap());
+        assert!(!is_snapshot_locked(data_dir.path(), "b-old").unwrap());
+
+        let preview = preview_delete_snapshots_where(data_dir.path(), |summary| summary.timestamp < 1_500, false).unwrap();
+        let mut locked: Vec<&str> = preview.locked.iter().map(|summary| summary.id.as_str()).collect();
+        locked.sort();
+        assert_eq!(locked, vec!["a-old", "c-old"]);
+        assert_eq!(delete_snapshots_where(data_dir.path(), |summary| summary.timestamp < 1_500, false).unwrap(), vec!["b-old"]);
+        assert_eq!(history_ids(data_dir.path()), vec!["a-old", "c-old"]);
+
+        let refused = delete_snapshot(data_dir.path(), "a-old", false).unwrap_err();
+        assert!(refused.contains("locked"), "{}", refused);
+        assert!(data_dir.path().join("snapshots/a-old.json").exists());
+        delete_snapshot(data_dir.path(), "a-old", true).unwrap();
+        set_snapshot_locked(data_dir.path(), "c-old", false).unwrap();
+        delete_snapshot(data_dir.path(), "c-old", false).unwrap();
+        assert!(history_ids(data_dir.path()).is_empty());
+    }
+
+    #[test]
+    fn delete_previews_list_the_matching_scans_and_their_bytes_without_deleting() {
+        let data_dir = TestDataDir::new();
+        save_scan(data_dir.path(), "old-1", "/a", 1_000);
+        save_scan(data_dir.path(), "old-2", "/b", 1_200);
+        save_scan(data_dir.path(), "new", "/a", 3_000);
+        let file_bytes = |id: &str| {
+            ["snapshots", "metadata"]
+                .iter()
+                .map(|dir| fs::metadata(data_dir.path().join(dir).join(format!("{}.json", id))).unwrap().len())
+                .sum::<u64>()
+        };
+
+        let preview = preview_delete_snapshots_where(data_dir.path(), |summary| summary.timestamp < 2_000, false).unwrap();
+        let ids: Vec<&str> = preview.snapshots.iter().map(|summary| summary.id.as_str()).collect();
+        assert_eq!(ids, vec!["old-2", "old-1"]);
+        assert!(preview.locked.is_empty());
+        assert_eq!(preview.bytes, file_bytes("old-1") + file_bytes("old-2"));
+        assert_eq!(history_ids(data_dir.path()), vec!["new", "old-1", "old-2"]);
+
+        assert_eq!(preview_delete_snapshots_where(data_dir.path(), |_| false, false).unwrap().bytes, 0);
+        let mut deleted = delete_snapshots_where(data_dir.path(), |summary| summary.timestamp < 2_000, false).unwrap();
+        deleted.sort();
+        assert_eq!(deleted, vec!["old-1", "old-2"]);
+        assert_eq!(history_ids(data_dir.path()), vec!["new"]);
+    }
+
+    #[test]
+    fn the_history_index_follows_saves_and_deletes_and_is_rebuilt_when_missing() {
+        let data_dir = TestDataDir::new();
+        let indexed_ids = || {
+            let mut ids: Vec<String> = read_history_index(data_dir.path())
+                .entries
+                .into_values()
+                .filter_map(|entry| entry.summary.map(|summary| summary.id))
+                .collect();
+            ids.sort();
+            ids
+        };
+        save_scan(data_dir.path(), "a", "/a", 1_000);
+        save_scan(data_dir.path(), "b", "/b", 2_000);
+        save_scan(data_dir.path(), "c", "/c", 3_000);
+        assert_eq!(history_ids(data_dir.path()), vec!["a", "b", "c"]);
+        assert!(history_index_path(data_dir.path()).exists());
+        assert_eq!(indexed_ids(), vec!["a", "b", "c"]);
+
+        set_snapshot_locked(data_dir.path(), "b", true).unwrap();
+        let locked = |id: &str| get_scan_history(data_dir.path()).unwrap().into_iter().find(|summary| summary.id == id).unwrap().locked;
+        assert!(locked("b"));
+        delete_snapshot(data_dir.path(), "c", false).unwrap();
+        assert_eq!(indexed_ids(), vec!["a", "b"]);
+        assert_eq!(history_ids(data_dir.path()), vec!["a", "b"]);
+
+        // Missing, unreadable, or left behind by files changed outside the app
+        fs::remove_file(history_index_path(data_dir.path())).unwrap();
+        assert_eq!(history_ids(data_dir.path()), vec!["a", "b"]);
+        assert_eq!(indexed_ids(), vec!["a", "b"]);
+        assert!(locked("b"));
+        fs::write(history_index_path(data_dir.path()), "not json").unwrap();
+        assert_eq!(history_ids(data_dir.path()), vec!["a", "b"]);
+        fs::remove_file(data_dir.path().join("metadata/a.json")).unwrap();
+        fs::remove_file(data_dir.path().join("snapshots/a.json")).unwrap();
+        assert_eq!(history_ids(data_dir.path()), vec!["b"]);
+        assert_eq!(indexed_ids(), vec!["b"]);
+    }
+
+    #[test]
+    fn data_dir_stats_counts_snapshots_and_their_bytes() {
+        let data_dir = TestDataDir::new();
+        let empty = data_dir_stats(data_dir.path()).unwrap();
+        assert_eq!((empty.snapshot_count, empty.snapshot_bytes, empty.metadata_bytes), (0, 0, 0));
+        assert_eq!((empty.oldest_scan, empty.newest_scan), (None, None));
+
+        save_scan(data_dir.path(), "plain-old", "/a", 1_000);
+        save_scan(data_dir.path(), "plain-new", "/b", 3_000);
+        save_snapshot(data_dir.path(), &small_snapshot("secret"), true, Some("secret")).unwrap();
+        save_snapshot_metadata(data_dir.path(), &snapshot("secret", 2_000, Vec::new())).unwrap();
+
+        let stats = data_dir_stats(data_dir.path()).unwrap();
+        assert_eq!(stats.data_dir, data_dir.path().to_string_lossy());
+        assert_eq!((stats.snapshot_count, stats.encrypted_count, stats.plaintext_count), (3, 1, 2));
+        let bytes_in = |dir: &str| -> u64 {
+            fs::read_dir(data_dir.path().join(dir)).unwrap().map(|entry| entry.unwrap().metadata().unwrap().len()).sum()
+        };
+        assert_eq!(stats.snapshot_bytes, bytes_in("snapshots"));
+        assert_eq!(stats.metadata_bytes, bytes_in("metadata"));
+        assert!(stats.snapshot_bytes > 0 && stats.metadata_bytes > 0);
+        assert_eq!((stats.oldest_scan, stats.newest_scan), (Some(1_000), Some(3_000)));
+    }
+
+    #[test]
+    fn metadata_records_whether_the_snapshot_is_encrypted() {
+        let data_dir = TestDataDir::new();
+        for (id, format, password) in [
+            ("as-json", SnapshotFormat::Json, None),
+            ("as-binary", SnapshotFormat::Binary, None),
+            ("as-encrypted", SnapshotFormat::Encrypted, Some("secret")),
+        ] {
+            let snapshot = small_snapshot(id);
+            save_snapshot_as(data_dir.path(), &snapshot, format, password, |_, _| {}).unwrap();
+            save_snapshot_metadata(data_dir.path(), &snapshot).unwrap();
+        }
+
+        let history = get_scan_history(data_dir.path()).unwrap();
+        assert_eq!(history.len(), 3);
+        for summary in &history {
+            let encrypted = summary.id == "as-encrypted";
+            assert_eq!(summary.encrypted, encrypted, "{}", summary.id);
+            assert_eq!(is_snapshot_encrypted(data_dir.path(), &summary.id).unwrap(), encrypted, "{}", summary.id);
+            // A password is asked for exactly when loading without one fails for lack of it
+            match load_snapshot(data_dir.path(), &summary.id, None) {
+                Ok(_) => assert!(!encrypted, "{}", summary.id),
+                Err(error) => assert!(encrypted && error == PASSWORD_REQUIRED, "{}: {}", summary.id, error),
+            }
+        }
+    }
+
+    #[test]
+    fn snapshots_load_whatever_their_extension_says() {
+        let data_dir = TestDataDir::new();
+        let snapshots_dir = data_dir.path().join("snapshots");
+        for (id, format, password, wrong_extension) in [
+            ("json-as-bin", SnapshotFormat::Json, None, "bin"),
+            ("binary-as-json", SnapshotFormat::Binary, None, "json"),
+            ("encrypted-as-json", SnapshotFormat::Encrypted, Some("secret"), "json"),
+        ] {
+            save_snapshot_as(data_dir.path(), &small_snapshot(id), format, password, |_, _| {}).unwrap();
+            let swapped = snapshots_dir.join(format!("{}.{}", id, wrong_extension));
+            fs::rename(snapshot_file_path(data_dir.path(), id).unwrap(), &swapped).unwrap();
+
+            let loaded = load_snapshot(data_dir.path(), id, password).unwrap();
+            assert_eq!(loaded.files.len(), 2, "{}", id);
+            assert_eq!(snapshot_extension_mismatch(data_dir.path(), id).unwrap(), extension_mismatch(id, &swapped, format));
+            let warning = extension_mismatch(id, &swapped, format).expect("mismatch reported");
+            assert!(warning.contains(id) && warning.contains(wrong_extension), "{}", warning);
+            assert_eq!(extension_mismatch(id, &swapped.with_extension(format.extension()), format), None);
+            fs::rename(&swapped, swapped.with_extension(format.extension())).unwrap();
+            assert_eq!(snapshot_extension_mismatch(data_dir.path(), id).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn metadata_describes_how_each_snapshot_is_stored() {
+        let data_dir = TestDataDir::new();
+        for (id, format, password) in [
+            ("as-json", SnapshotFormat::Json, None),
+            ("as-binary", SnapshotFormat::Binary, None),
+            ("as-encrypted", SnapshotFormat::Encrypted, Some("secret")),
+        ] {
+            save_snapshot_as(data_dir.path(), &small_snapshot(id), format, password, |_, _| {}).unwrap();
+        }
+        // Compressed by an external tool, keeping its extension
+        save_snapshot_as(data_dir.path(), &small_snapshot("gzipped"), SnapshotFormat::Json, None, |_, _| {}).unwrap();
+        let path = data_dir.path().join("snapshots/gzipped.json");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&fs::read(&path).unwrap()).unwrap();
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        for id in ["as-json", "as-binary", "as-encrypted", "gzipped"] {
+            save_snapshot_metadata(data_dir.path(), &small_snapshot(id)).unwrap();
+        }
+        let history: HashMap<String, SnapshotSummary> =
+            get_scan_history(data_dir.path()).unwrap().into_iter().map(|summary| (summary.id.clone(), summary)).collect();
+        for (id, encrypted, compressed) in [
+            ("as-json", false, false),
+            ("as-binary", false, false),
+            ("as-encrypted", true, false),
+            ("gzipped", false, true),
+        ] {
+            let summary = &history[id];
+            assert_eq!((summary.encrypted, summary.compressed), (encrypted, compressed), "{}", id);
+            assert_eq!(summary.format_version, Some(SNAPSHOT_FORMAT_VERSION), "{}", id);
+        }
+    }
+
+    #[test]
+    fn profiles_only_see_their_own_scans() {
+        let data_dir = TestDataDir::new();
+        let work = profile_data_dir(data_dir.path(), "work").unwrap();
+        let personal = profile_data_dir(data_dir.path(), "personal").unwrap();
+        assert_eq!(work, data_dir.path().join("profiles/work"));
+        save_scan(data_dir.path(), "default-scan", "/data", 1_000);
+        assert!(history_ids(&work).is_empty());
+        save_scan(&work, "work-scan", "/work", 2_000);
+        save_scan(&personal, "personal-scan", "/home", 3_000);
+
+        assert_eq!(history_ids(&personal), vec!["personal-scan"]);
+        assert!(load_snapshot(&personal, "work-scan", None).is_err());
+        assert_eq!(history_ids(&work), vec!["work-scan"]);
+        assert_eq!(history_ids(data_dir.path()), vec!["default-scan"]);
+
+        for invalid in ["", "../escape", "a/b", "with space"] {
+            assert!(profile_data_dir(data_dir.path(), invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(get_data_dir().unwrap(), data_dir.path());
+    }
+
+    #[test]
+    fn drive_paths_are_flagged_present_or_missing() {
+        let data_dir = TestDataDir::new();
+        let present = TempDir::new().unwrap();
+        let removed = TempDir::new().unwrap();
+        let present_path = present.path().to_string_lossy().to_string();
+        let removed_path = removed.path().to_string_lossy().to_string();
+        save_scan(data_dir.path(), "on-present", &present_path, 1_000);
+        save_scan(data_dir.path(), "on-removed", &removed_path, 2_000);
+        save_scan(data_dir.path(), "on-removed-again", &removed_path, 3_000);
+        removed.close().unwrap();
+
+        let mut history = get_scan_history(data_dir.path()).unwrap();
+        assert!(history.iter().all(|scan| scan.drive_present.is_none()));
+        check_drive_paths(&mut history);
+        for scan in &history {
+            assert_eq!(scan.drive_present, Some(scan.id == "on-present"), "{}", scan.id);
+        }
+    }
+
+    #[test]
+    fn saving_twice_with_the_same_password_uses_fresh_salts_and_nonces() {
+        let data_dir = TestDataDir::new();
+        let snapshot = snapshot("twice", 1_704_067_200, vec![file_entry("/data/a.txt", 10)]);
+        let path = data_dir.path().join("snapshots").join("twice.bin");
+        let stored_key_material = || key_material(&fs::read(&path).unwrap()).expect("encrypted header").to_vec();
+
+        save_snapshot(data_dir.path(), &snapshot, true, Some("secret")).unwrap();
+        assert!(fs::read(&path).unwrap().starts_with(CHUNKED_MAGIC));
+        let first = stored_key_material();
+
+        save_snapshot(data_dir.path(), &snapshot, true, Some("secret")).unwrap();
+        let second = stored_key_material();
+        assert_ne!(second, first);
+
+        let files = snapshot.files.iter().cloned().map(Ok);
+        save_snapshot_streamed(data_dir.path(), &snapshot, files, SnapshotFormat::Encrypted, Some("secret")).unwrap();
+        assert_ne!(stored_key_material(), second);
+        assert_eq!(load_snapshot(data_dir.path(), "twice", Some("secret")).unwrap().files.len(), 1);
+        assert!(load_snapshot(data_dir.path(), "twice", Some("wrong")).is_err());
+    }
+
+    #[test]
+    fn every_format_loads_back() {
+        let data_dir = TestDataDir::new();
+        for (id, format, password, file) in [
+            ("as-json", SnapshotFormat::Json, None, "as-json.json"),
+            ("as-binary", SnapshotFormat::Binary, None, "as-binary.bin"),
+            ("as-encrypted", SnapshotFormat::Encrypted, Some("secret"), "as-encrypted.bin"),
+        ] {
+            let snapshot = small_snapshot(id);
+            save_snapshot_as(data_dir.path(), &snapshot, format, password, |_, _| {}).unwrap();
+            assert!(data_dir.path().join("snapshots").join(file).exists(), "{}", id);
+            let loaded = load_snapshot(data_dir.path(), id, password).unwrap();
+            assert_eq!(loaded.files.len(), 2, "{}", id);
+            assert_eq!(loaded.scan_errors, snapshot.scan_errors, "{}", id);
+        }
+        assert_eq!(load_snapshot(data_dir.path(), "as-encrypted", None).unwrap_err(), PASSWORD_REQUIRED);
+    }
+
+    #[test]
+    fn externally_compressed_snapshots_load() {
+        let data_dir = TestDataDir::new();
+        save_snapshot_as(data_dir.path(), &small_snapshot("gzipped"), SnapshotFormat::Json, None, |_, _| {}).unwrap();
+        save_snapshot_as(data_dir.path(), &small_snapshot("zstd"), SnapshotFormat::Binary, None, |_, _| {}).unwrap();
+
+        let gzip_path = snapshot_file_path(data_dir.path(), "gzipped").unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&fs::read(&gzip_path).unwrap()).unwrap();
+        fs::write(&gzip_path, encoder.finish().unwrap()).unwrap();
+        let zstd_path = snapshot_file_path(data_dir.path(), "zstd").unwrap();
+        let compressed = zstd::encode_all(fs::read(&zstd_path).unwrap().as_slice(), 3).unwrap();
+        fs::write(&zstd_path, compressed).unwrap();
+
+        for id in ["gzipped", "zstd"] {
+            assert!(is_compressed(&fs::read(snapshot_file_path(data_dir.path(), id).unwrap()).unwrap()), "{}", id);
+            let loaded = load_snapshot(data_dir.path(), id, None).unwrap();
+            assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(small_snapshot(id)).unwrap(), "{}", id);
+        }
+    }
+}
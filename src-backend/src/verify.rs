@@ -0,0 +1,292 @@
+//! Checking a snapshot's hashes against the files on disk or a manifest.
+
+use crate::scan::hash_file;
+use crate::snapshot::Snapshot;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ManifestReport {
+    /// Files whose snapshot hash equals the manifest hash.
+    pub matched: Vec<String>,
+    /// Files present in both whose hashes differ.
+    pub mismatched: Vec<String>,
+    /// Manifest entries with no corresponding file in the snapshot.
+    pub missing_from_snapshot: Vec<String>,
+    /// Hashed snapshot files the manifest doesn't mention.
+    pub extra_in_snapshot: Vec<String>,
+}
+
+/// Parses `sha256sum` output (`<hex>  <path>` or `<hex> *<path>` per line) into
+/// `(path, hash)` pairs. Blank lines and `#` comments are skipped.
+pub fn parse_sha256_manifest(manifest: &str) -> Result<Vec<(String, String)>, String> {
+    let mut entries = Vec::new();
+    for (line_number, line) in manifest.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (hash, rest) = line
+            .split_once(' ')
+            .ok_or_else(|| format!("Invalid manifest line {}: {}", line_number + 1, line))?;
+        if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid hash on manifest line {}: {}", line_number + 1, hash));
+        }
+        // sha256sum separates with " " + mode char: ' ' for text, '*' for binary
+        let path = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*')).unwrap_or(rest);
+        entries.push((normalize_manifest_path(path), hash.to_lowercase()));
+    }
+    Ok(entries)
+}
+
+fn normalize_manifest_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    path.strip_prefix("./").map(str::to_string).unwrap_or(path)
+}
+
+/// Reconciles a hashed snapshot with an external `sha256sum` manifest. Manifest
+/// paths may be absolute or relative to the snapshot's `drive_path`.
+pub fn verify_manifest(snapshot: &Snapshot, manifest: &str) -> Result<ManifestReport, String> {
+    if !snapshot.files.iter().any(|f| f.hash.is_some()) {
+        return Err("Snapshot has no content hashes; rescan with hashing enabled".to_string());
+    }
+    let root = Path::new(&snapshot.drive_path);
+    let mut snapshot_hashes: HashMap<String, &str> = HashMap::new();
+    for file in &snapshot.files {
+        if let Some(hash) = &file.hash {
+            let relative = Path::new(&file.path)
+                .strip_prefix(root)
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_else(|_| file.path.clone());
+            snapshot_hashes.insert(normalize_manifest_path(&relative), hash.as_str());
+        }
+    }
+    let mut report = ManifestReport::default();
+    let mut seen = HashSet::new();
+    for (path, manifest_hash) in parse_sha256_manifest(manifest)? {
+        let key = Path::new(&path)
+            .strip_prefix(root)
+            .map(|p| normalize_manifest_path(&p.to_string_lossy()))
+            .unwrap_or(path.clone());
+        match snapshot_hashes.get(&key) {
+            Some(hash) if hash.eq_ignore_ascii_case(&manifest_hash) => report.matched.push(path),
+            Some(_) => report.mismatched.push(path),
+            None => report.missing_from_snapshot.push(path),
+        }
+        seen.insert(key);
+    }
+    report.extra_in_snapshot = snapshot_hashes
+        .into_keys()
+        .filter(|path| !seen.contains(path))
+        .collect();
+    report.extra_in_snapshot.sort();
+    Ok(report)
+}
+
+/// Outcome of re-hashing one file during `verify_snapshot_hashes`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyStatus {
+    Matched,
+    Mismatched,
+    Missing,
+    Unreadable,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct VerifyReport {
+    /// Files whose current contents still hash to the recorded value.
+    pub matched: usize,
+    /// Files whose contents changed since the scan.
+    pub mismatched: Vec<String>,
+    /// Files that no longer exist.
+    pub missing: Vec<String>,
+    /// Files that exist but could not be read.
+    pub unreadable: Vec<String>,
+    /// Files already checked by an earlier, interrupted run.
+    pub resumed: usize,
+    /// False when the run was cancelled before every file was checked.
+    pub completed: bool,
+}
+
+impl VerifyReport {
+    fn record(&mut self, path: &str, status: VerifyStatus) {
+        match status {
+            VerifyStatus::Matched => self.matched += 1,
+            VerifyStatus::Mismatched => self.mismatched.push(path.to_string()),
+            VerifyStatus::Missing => self.missing.push(path.to_string()),
+            VerifyStatus::Unreadable => self.unreadable.push(path.to_string()),
+        }
+    }
+}
+
+/// One line of a verification state file.
+#[derive(Serialize, Deserialize)]
+struct VerifyStateEntry {
+    path: String,
+    status: VerifyStatus,
+}
+
+/// Where an unfinished verification of `snapshot_id` records its progress.
+pub fn verify_state_path(data_dir: &Path, snapshot_id: &str) -> Result<PathBuf, String> {
+    Ok(data_dir.join("verify").join(format!("{}.jsonl", snapshot_id)))
+}
+
+/// Re-hashes every file that has a recorded hash and compares it with the
+/// snapshot, reporting `(checked, total)` as it goes. Each result is appended
+/// to a state file as soon as it is known, so with `resume` a run that was
+/// cancelled through `cancel` (or killed) skips the files it already checked.
+/// The state file is removed once every file has been checked.
+pub fn verify_snapshot_hashes<F>(data_dir: &Path, snapshot: &Snapshot, resume: bool, cancel: &AtomicBool, mut progress: F) -> Result<VerifyReport, String>
+where
+    F: FnMut(usize, usize),
+{
+    let hashed: Vec<(&str, &str, PathBuf)> = snapshot
+        .files
+        .iter()
+        .filter_map(|f| f.hash.as_deref().map(|hash| (f.path.as_str(), hash, f.os_path())))
+        .collect();
+    if hashed.is_empty() {
+        return Err("Snapshot has no content hashes; rescan with hashing enabled".to_string());
+    }
+
+    let state_path = verify_state_path(data_dir, &snapshot.id)?;
+    let mut report = VerifyReport::default();
+    let mut done = HashSet::new();
+    let mut torn_tail = false;
+    if resume && state_path.exists() {
+        let content = fs::read_to_string(&state_path).map_err(|e| format!("Failed to read verification state: {}", e))?;
+        torn_tail = !content.is_empty() && !content.ends_with('\n');
+        for line in content.lines() {
+            // A line torn by an interruption is simply checked again
+            if let Ok(entry) = serde_json::from_str::<VerifyStateEntry>(line) {
+                if done.insert(entry.path.clone()) {
+                    report.record(&entry.path, entry.status);
+                    report.resumed += 1;
+                }
+            }
+        }
+    }
+    if let Some(parent) = state_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut state = if resume {
+        fs::OpenOptions::new().create(true).append(true).open(&state_path)
+    } else {
+        fs::File::create(&state_path)
+    }
+    .map_err(|e| format!("Failed to open verification state: {}", e))?;
+    if torn_tail {
+        writeln!(state).map_err(|e| format!("Failed to write verification state: {}", e))?;
+    }
+
+    let total = hashed.len();
+    let mut checked = report.resumed;
+    progress(checked, total);
+    for (path, expected, os_path) in hashed {
+        if done.contains(path) {
+            continue;
+        }
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(report);
+        }
+        let status = match hash_file(&os_path) {
+            Ok(hash) if hash.eq_ignore_ascii_case(expected) => VerifyStatus::Matched,
+            Ok(_) => VerifyStatus::Mismatched,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VerifyStatus::Missing,
+            Err(_) => VerifyStatus::Unreadable,
+        };
+        let line = serde_json::to_string(&VerifyStateEntry { path: path.to_string(), status })
+            .map_err(|e| format!("Failed to serialize verification state: {}", e))?;
+        writeln!(state, "{}", line).map_err(|e| format!("Failed to write verification state: {}", e))?;
+        report.record(path, status);
+        checked += 1;
+        progress(checked, total);
+    }
+
+    drop(state);
+    fs::remove_file(&state_path).map_err(|e| format!("Failed to remove verification state: {}", e))?;
+    report.completed = true;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{file_entry, scan_dir, snapshot, write_tree, TestDataDir};
+    use crate::scan::ScanOptions;
+    use crate::snapshot::FileEntry;
+
+    fn hashed(path: &str, hash_byte: char) -> FileEntry {
+        FileEntry { hash: Some(hash_byte.to_string().repeat(64)), ..file_entry(path, 1) }
+    }
+
+    #[test]
+    fn manifest_entries_are_categorized() {
+        let snapshot = snapshot(
+            "hashed",
+            1_704_067_200,
+            vec![hashed("/data/same.txt", 'a'), hashed("/data/sub/changed.txt", 'b'), hashed("/data/only-in-snapshot.txt", 'c')],
+        );
+        let manifest = format!(
+            "# made with sha256sum\n{}  ./same.txt\n{} *sub\\changed.txt\n{}  /data/gone.txt\n\n",
+            "A".repeat(64),
+            "f".repeat(64),
+            "d".repeat(64),
+        );
+        let report = verify_manifest(&snapshot, &manifest).unwrap();
+        assert_eq!(report.matched, vec!["same.txt"]);
+        assert_eq!(report.mismatched, vec!["sub/changed.txt"]);
+        assert_eq!(report.missing_from_snapshot, vec!["/data/gone.txt"]);
+        assert_eq!(report.extra_in_snapshot, vec!["only-in-snapshot.txt"]);
+    }
+
+    #[test]
+    fn manifests_need_hashes_and_valid_lines() {
+        let unhashed = snapshot("unhashed", 1_704_067_200, vec![file_entry("/data/a.txt", 1)]);
+        assert!(verify_manifest(&unhashed, "").is_err());
+        assert!(parse_sha256_manifest("not-a-hash  a.txt").is_err());
+        assert!(parse_sha256_manifest(&"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn an_interrupted_verify_resumes_where_it_stopped() {
+        let data_dir = TestDataDir::new();
+        let files = ["a.txt", "b.txt", "c.txt", "d0/a.txt", "d0/b.txt", "d0/c.txt", "d1/a.txt", "d1/b.txt", "d1/c.txt"];
+        let dir = write_tree(&files);
+        let snapshot = scan_dir(dir.path(), &ScanOptions { hash_files: true, ..ScanOptions::default() });
+        fs::write(dir.path().join(files[0]), b"changed since the scan").unwrap();
+        let total = files.len();
+
+        // Stop after a few files, as a cancelled run would
+        let cancel = AtomicBool::new(false);
+        let mut first_checked = 0;
+        let first = verify_snapshot_hashes(data_dir.path(), &snapshot, false, &cancel, |checked, _| {
+            first_checked = checked;
+            if checked == 4 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        })
+        .unwrap();
+        assert!(!first.completed);
+        assert_eq!(first_checked, 4);
+        let state_path = verify_state_path(data_dir.path(), &snapshot.id).unwrap();
+        assert_eq!(fs::read_to_string(&state_path).unwrap().lines().count(), 4);
+
+        let cancel = AtomicBool::new(false);
+        let mut calls = Vec::new();
+        let second = verify_snapshot_hashes(data_dir.path(), &snapshot, true, &cancel, |checked, total| calls.push((checked, total))).unwrap();
+        assert!(second.completed);
+        assert_eq!(second.resumed, 4);
+        // Each remaining file is checked once, the first four not again
+        let expected: Vec<(usize, usize)> = (4..=total).map(|checked| (checked, total)).collect();
+        assert_eq!(calls, expected);
+        assert_eq!(second.matched + second.mismatched.len(), total);
+        assert_eq!(second.mismatched.len(), 1);
+        assert!(!state_path.exists());
+    }
+}
@@ -1,7 +1,9 @@
+// Wrappers over the library; not every one is used by the commands below
+#[allow(dead_code)]
 mod backend;
 
 use clap::{App, Arg, SubCommand};
-use dialoguer::{Input, Select, Confirm};
+use dialoguer::{Input, Select, Confirm, Password};
 use rustyline::completion::{Completer, FilenameCompleter, Pair};
 use rustyline::error::ReadlineError;
 use rustyline::{Editor, Context};
@@ -9,12 +11,18 @@ use rustyline::highlight::Highlighter;
 use rustyline::hint::Hinter;
 use rustyline::validate::Validator;
 use rustyline::Helper;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use console::style;
-use prettytable::{Table, Row, Cell};
+use prettytable::{format, format::TableFormat, Table, Row, Cell};
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use drive_pulse_lib::DiffStatus;
-use drive_pulse_lib::{scan_drive, compare_snapshots, save_snapshot, get_scan_history, load_snapshot};
+use std::path::Path;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+use drive_pulse_lib::{ComparisonResult, DiffOptions, DiffStatus, DirectoryChurn, ProgressThrottle, ReparsePointPolicy, ScanOptions, ScanProgress, Snapshot, SnapshotFormat, SnapshotSummary};
+use drive_pulse_lib::PROGRESS_INTERVAL;
 
 struct PathHelper {
     completer: FilenameCompleter,
@@ -48,16 +56,154 @@ fn main() {
         .version("1.0")
         .author("Drive Pulse Team")
         .about("Manage and compare drive scans")
+        .arg(Arg::with_name("table-style")
+            .long("table-style")
+            .help("Table rendering: full (default), compact (no borders) or plain (tab-separated)")
+            .takes_value(true)
+            .possible_values(&["full", "compact", "plain"])
+            .global(true))
+        .arg(Arg::with_name("profile")
+            .long("profile")
+            .value_name("NAME")
+            .help("Use a separate set of scans stored under this profile name")
+            .takes_value(true)
+            .global(true))
+        .arg(Arg::with_name("password-stdin")
+            .long("password-stdin")
+            .help("Read the snapshot password from the first line of stdin")
+            .global(true))
+        .arg(Arg::with_name("timezone")
+            .long("timezone")
+            .value_name("ZONE")
+            .help("Timezone for dates: local (default), utc or an offset like +05:30 (see DRIVE_PULSE_TIMEZONE)")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .global(true))
         .subcommand(
             SubCommand::with_name("scan")
                 .about("Run a new scan")
                 .arg(Arg::with_name("path")
                     .help("Path to scan (optional, will prompt if not provided)")
                     .index(1))
+                .arg(Arg::with_name("paths-from")
+                    .long("paths-from")
+                    .value_name("FILE")
+                    .help("Scan every path listed in FILE (one per line, # comments), each into its own snapshot")
+                    .takes_value(true)
+                    .conflicts_with("path"))
+                .arg(Arg::with_name("include-data-dir")
+                    .long("include-data-dir")
+                    .help("Also scan Drive Pulse's own data directory (skipped by default)"))
+                .arg(Arg::with_name("canonicalize")
+                    .long("canonicalize")
+                    .help("Store absolute, canonical paths (resolves symlinks in the scan root)"))
+                .arg(Arg::with_name("lossless-paths")
+                    .long("lossless-paths")
+                    .help("Also store the exact bytes of file names that aren't valid UTF-8"))
+                .arg(Arg::with_name("one-filesystem")
+                    .short("x")
+                    .long("one-filesystem")
+                    .help("Don't descend into directories on other filesystems (mounted disks, network shares)"))
+                .arg(Arg::with_name("dedupe-hard-links")
+                    .long("dedupe-hard-links")
+                    .help("Count hard-linked files only once in the total size (Unix)"))
+                .arg(Arg::with_name("hash")
+                    .long("hash")
+                    .help("Record a SHA-256 of every file (reads all contents, much slower)"))
+                .arg(Arg::with_name("content-filter")
+                    .long("content-filter")
+                    .help("With --hash, also store a compact Bloom filter of the content hashes")
+                    .requires("hash"))
+                .arg(Arg::with_name("child-counts")
+                    .long("child-counts")
+                    .help("Record how many entries each directory holds, for compare --structure"))
+                .arg(Arg::with_name("xattrs")
+                    .long("xattrs")
+                    .help("Record extended attributes, including Linux ACLs, for compare --xattrs (Unix)"))
+                .arg(Arg::with_name("xattr-name")
+                    .long("xattr-name")
+                    .value_name("NAMES")
+                    .help("With --xattrs, only record these attributes, comma-separated, e.g. user.owner,system.posix_acl_access")
+                    .takes_value(true)
+                    .use_delimiter(true)
+                    .requires("xattrs"))
+                .arg(Arg::with_name("check-consistency")
+                    .long("check-consistency")
+                    .help("Afterwards, re-check a sample of files and warn if the drive changed during the scan"))
+                .arg(Arg::with_name("threads")
+                    .long("threads")
+                    .value_name("N")
+                    .help("Threads used to hash files with --hash: auto (default, one per CPU) or a number; 1 hashes serially")
+                    .takes_value(true)
+                    .default_value("auto"))
+                .arg(Arg::with_name("hash-buffer")
+                    .long("hash-buffer")
+                    .value_name("SIZE")
+                    .help("Read size used by --hash, e.g. 1M for fast NVMe drives (default 64K)")
+                    .takes_value(true))
+                .arg(Arg::with_name("max-entries-in-memory")
+                    .long("max-entries-in-memory")
+                    .value_name("N")
+                    .help("Hold at most about N entries in memory, spilling the rest to disk (for very large drives)")
+                    .takes_value(true))
+                .arg(Arg::with_name("max-size")
+                    .long("max-size")
+                    .help("Leave out files larger than this size, e.g. 4G or 500M")
+                    .takes_value(true))
+                .arg(Arg::with_name("min-depth")
+                    .long("min-depth")
+                    .value_name("LEVELS")
+                    .help("Only record entries at least this many levels below the scan root (the root is level 0)")
+                    .takes_value(true))
+                .arg(Arg::with_name("exclude-empty-dirs")
+                    .long("exclude-empty-dirs")
+                    .help("Leave out directories with no recorded file below them, after all other filters"))
+                .arg(Arg::with_name("exclude-ext")
+                    .long("exclude-ext")
+                    .value_name("EXTS")
+                    .help("Leave out files with these extensions, comma-separated, e.g. tmp,log,cache")
+                    .takes_value(true)
+                    .use_delimiter(true))
+                .arg(Arg::with_name("reparse-points")
+                    .long("reparse-points")
+                    .help("Windows junctions and other reparse points: skip, record (default, don't descend) or follow")
+                    .takes_value(true)
+                    .possible_values(&["skip", "record", "follow"]))
+                .arg(Arg::with_name("binary")
+                    .long("binary")
+                    .help("Store the snapshot in compact binary form instead of JSON"))
+                .arg(Arg::with_name("encrypt")
+                    .long("encrypt")
+                    .help("Encrypt the snapshot with a password (see DRIVE_PULSE_PASSWORD / --password-stdin)"))
+                .arg(Arg::with_name("no-metadata")
+                    .long("no-metadata")
+                    .help("Don't write the metadata file that speeds up list, e.g. for throwaway scans"))
+                .arg(Arg::with_name("json")
+                    .long("json")
+                    .help("Emit progress as JSON lines on stderr and the summary as JSON on stdout"))
         )
         .subcommand(
             SubCommand::with_name("list")
                 .about("List scan history")
+                .arg(Arg::with_name("since")
+                    .long("since")
+                    .help("Only show scans taken at or after this date (YYYY-MM-DD, RFC 3339, or relative like 7d, 12h, 2w)")
+                    .takes_value(true))
+                .arg(Arg::with_name("until")
+                    .long("until")
+                    .help("Only show scans taken before this date (a plain date includes that whole day)")
+                    .takes_value(true))
+                .arg(Arg::with_name("check-paths")
+                    .long("check-paths")
+                    .help("Mark scans whose drive path no longer exists (can be slow for network drives)"))
+                .arg(Arg::with_name("drive")
+                    .long("drive")
+                    .value_name("PATH")
+                    .help("Only show scans of this path or of folders below it")
+                    .takes_value(true))
+                .arg(Arg::with_name("group-by-drive")
+                    .long("group-by-drive")
+                    .help("Show a separate table for each scanned path"))
         )
         .subcommand(
             SubCommand::with_name("view")
@@ -65,6 +211,14 @@ fn main() {
                 .arg(Arg::with_name("scan_id")
                     .help("ID of the scan to view (optional, will prompt if not provided)")
                     .index(1))
+                .arg(Arg::with_name("check-paths")
+                    .long("check-paths")
+                    .help("Show whether the scanned drive path still exists"))
+                .arg(Arg::with_name("max-results")
+                    .long("max-results")
+                    .value_name("N")
+                    .help("Show at most N files in the file list (default 100)")
+                    .takes_value(true))
         )
         .subcommand(
             SubCommand::with_name("compare")
@@ -73,8 +227,57 @@ fn main() {
                     .help("ID of the first scan (optional, will prompt if not provided)")
                     .index(1))
                 .arg(Arg::with_name("scan2")
-                    .help("ID of the second scan (optional, will prompt if not provided)")
+                    .help("ID of the second scan (defaults to comparing the first scan against its parent)")
                     .index(2))
+                .arg(Arg::with_name("presence-only")
+                    .long("presence-only")
+                    .help("Only report added and deleted paths, ignoring size and date changes"))
+                .arg(Arg::with_name("propagate-dirs")
+                    .long("propagate-dirs")
+                    .help("Also report every directory above a changed path as modified"))
+                .arg(Arg::with_name("unicode-normalize")
+                    .long("unicode-normalize")
+                    .help("Treat names that only differ in Unicode normalization (NFC/NFD) as the same path"))
+                .arg(Arg::with_name("match-by-content")
+                    .long("match-by-content")
+                    .help("Match files by content hash instead of path, so moved files are unchanged (needs scans taken with --hash)"))
+                .arg(Arg::with_name("xattrs")
+                    .long("xattrs")
+                    .help("Also report extended attribute and ACL changes (needs scans taken with --xattrs)"))
+                .arg(Arg::with_name("ext")
+                    .long("ext")
+                    .help("Only compare files with these extensions, comma-separated (e.g. jpg,raw,cr2)")
+                    .takes_value(true))
+                .arg(Arg::with_name("structure")
+                    .long("structure")
+                    .help("Only report directories whose number of entries changed (needs scans taken with --child-counts)"))
+                .args(&only_status_args())
+                .arg(Arg::with_name("oneline")
+                    .long("oneline")
+                    .help("Print a single line like `added=12 deleted=3 modified=40 bytes_delta=+2.10GB`, without tables or prompts")
+                    .conflicts_with("structure")
+                    .conflicts_with_all(&ONLY_STATUS_FLAGS))
+                .arg(Arg::with_name("diff")
+                    .long("diff")
+                    .help("Print every change as a diff-style line (+ added, - deleted, ~ modified), sorted by path, without tables or prompts")
+                    .conflicts_with_all(&["structure", "oneline"]))
+                .arg(Arg::with_name("fail-on")
+                    .long("fail-on")
+                    .value_name("STATUSES")
+                    .help("Exit with status 2 if there are changes of these kinds, comma-separated: added, deleted, modified, type_changed")
+                    .takes_value(true)
+                    .conflicts_with("structure")
+                    .conflicts_with_all(&ONLY_STATUS_FLAGS))
+                .arg(Arg::with_name("summary-table")
+                    .long("summary-table")
+                    .help("Print a table of the changes below each top-level directory, without prompts")
+                    .conflicts_with_all(&["structure", "oneline", "diff"]))
+                .arg(Arg::with_name("depth")
+                    .long("depth")
+                    .value_name("N")
+                    .help("For --summary-table: how many levels below the drive to group by (default 1)")
+                    .takes_value(true)
+                    .requires("summary-table"))
         )
         .subcommand(
             SubCommand::with_name("export")
@@ -85,30 +288,317 @@ fn main() {
                 .arg(Arg::with_name("scan2")
                     .help("ID of the second scan (optional, will prompt if not provided)")
                     .index(2))
+                .arg(Arg::with_name("presence-only")
+                    .long("presence-only")
+                    .help("Only report added and deleted paths, ignoring size and date changes"))
+                .arg(Arg::with_name("propagate-dirs")
+                    .long("propagate-dirs")
+                    .help("Also report every directory above a changed path as modified"))
+                .arg(Arg::with_name("unicode-normalize")
+                    .long("unicode-normalize")
+                    .help("Treat names that only differ in Unicode normalization (NFC/NFD) as the same path"))
+                .arg(Arg::with_name("match-by-content")
+                    .long("match-by-content")
+                    .help("Match files by content hash instead of path, so moved files are unchanged (needs scans taken with --hash)"))
+                .arg(Arg::with_name("xattrs")
+                    .long("xattrs")
+                    .help("Also report extended attribute and ACL changes (needs scans taken with --xattrs)"))
+                .arg(Arg::with_name("ext")
+                    .long("ext")
+                    .help("Only compare files with these extensions, comma-separated (e.g. jpg,raw,cr2)")
+                    .takes_value(true))
                 .arg(Arg::with_name("format")
-                    .help("Export format: json or csv (optional, will prompt if not provided)")
+                    .help("Export format: json, csv, script or sqlite (optional, will prompt if not provided)")
                     .index(3))
+                .arg(Arg::with_name("source-root")
+                    .long("source-root")
+                    .value_name("DIR")
+                    .help("For the script format: where added and modified files are copied from")
+                    .takes_value(true))
+                .arg(Arg::with_name("echo")
+                    .long("echo")
+                    .help("For the script format: prefix every command with echo, so running it only prints them"))
+                .args(&only_status_args())
+                .arg(Arg::with_name("include-unchanged")
+                    .long("include-unchanged")
+                    .help("Also write every unchanged file (output can be very large)"))
+                .arg(Arg::with_name("group-by-status")
+                    .long("group-by-status")
+                    .help("Sort rows by status (added, deleted, modified, unchanged), then by path"))
+                .arg(Arg::with_name("summary-table")
+                    .long("summary-table")
+                    .help("For json and csv: write the changes totalled per directory instead of every changed path"))
+                .arg(Arg::with_name("depth")
+                    .long("depth")
+                    .value_name("N")
+                    .help("For --summary-table: how many levels below the drive to group by (default 1)")
+                    .takes_value(true)
+                    .requires("summary-table"))
                 .arg(Arg::with_name("output")
                     .short("o")
                     .long("output")
-                    .help("Output file path (optional, will prompt if not provided)")
+                    .help("Output file path, or - for stdout (optional, will prompt if not provided)")
+                    .takes_value(true))
+        )
+        .subcommand(
+            SubCommand::with_name("verify-manifest")
+                .about("Check a scan's file hashes against a sha256sum manifest")
+                .arg(Arg::with_name("scan_id")
+                    .help("ID of a scan taken with --hash")
+                    .required(true)
+                    .index(1))
+                .arg(Arg::with_name("manifest")
+                    .help("File in sha256sum format (<hash>  <path> per line)")
+                    .required(true)
+                    .index(2))
+        )
+        .subcommand(
+            SubCommand::with_name("recent")
+                .about("Show the most recently modified files in a scan")
+                .arg(Arg::with_name("scan_id")
+                    .help("ID of the scan (optional, will prompt if not provided)")
+                    .index(1))
+                .arg(Arg::with_name("count")
+                    .short("n")
+                    .long("count")
+                    .help("Number of files to show (default 20)")
+                    .takes_value(true))
+                .arg(Arg::with_name("include-dirs")
+                    .long("include-dirs")
+                    .help("Include directories in the list"))
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Show how a scan's files are distributed by size")
+                .arg(Arg::with_name("scan_id")
+                    .help("ID of the scan (optional, will prompt if not provided)")
+                    .index(1))
+                .arg(Arg::with_name("compare")
+                    .long("compare")
+                    .value_name("SCAN_ID")
+                    .help("Show the distribution of this later scan side by side, with the change per bucket")
+                    .takes_value(true))
+        )
+        .subcommand(
+            SubCommand::with_name("delete")
+                .alias("prune")
+                .about("Delete one scan, or every scan matching the given filters")
+                .arg(Arg::with_name("scan_id")
+                    .help("ID of a single scan to delete")
+                    .index(1))
+                .arg(Arg::with_name("drive")
+                    .long("drive")
+                    .help("Delete scans of this drive path")
+                    .takes_value(true))
+                .arg(Arg::with_name("older-than")
+                    .long("older-than")
+                    .help("Delete scans taken before this date (YYYY-MM-DD, RFC 3339, or relative like 30d)")
+                    .takes_value(true))
+                .arg(Arg::with_name("duplicates")
+                    .long("duplicates")
+                    .help("Delete scans that found exactly the same entries as another scan of the same drive, keeping one of each")
+                    .conflicts_with("scan_id"))
+                .arg(Arg::with_name("keep")
+                    .long("keep")
+                    .help("With --duplicates, which scan of each group to keep (default: oldest)")
+                    .takes_value(true)
+                    .possible_values(&["oldest", "newest"])
+                    .requires("duplicates"))
+                .arg(Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Only show which scans would be deleted"))
+                .arg(Arg::with_name("force")
+                    .long("force")
+                    .help("Also delete locked scans"))
+                .arg(Arg::with_name("yes")
+                    .short("y")
+                    .long("yes")
+                    .help("Don't ask for confirmation"))
+        )
+        .subcommand(
+            SubCommand::with_name("lock")
+                .about("Protect a scan from delete and prune")
+                .arg(Arg::with_name("scan_id")
+                    .help("ID of the scan to lock")
+                    .required(true)
+                    .index(1))
+        )
+        .subcommand(
+            SubCommand::with_name("unlock")
+                .about("Allow a locked scan to be deleted again")
+                .arg(Arg::with_name("scan_id")
+                    .help("ID of the scan to unlock")
+                    .required(true)
+                    .index(1))
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Rewrite scans saved by older versions in the current snapshot format")
+                .arg(Arg::with_name("scan_id")
+                    .help("ID of a single scan to migrate (default: all scans)")
+                    .index(1))
+        )
+        .subcommand(
+            SubCommand::with_name("compact")
+                .about("Rewrite scans in the smallest storage format and report the space saved")
+                .arg(Arg::with_name("scan_id")
+                    .help("ID of the scan to compact")
+                    .required_unless("all")
+                    .index(1))
+                .arg(Arg::with_name("all")
+                    .long("all")
+                    .help("Compact every stored scan")
+                    .conflicts_with("scan_id"))
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Save the part of a scan under a folder as a scan of its own")
+                .arg(Arg::with_name("scan_id")
+                    .help("ID of the scan to extract from")
+                    .required(true)
+                    .index(1))
+                .arg(Arg::with_name("path")
+                    .help("Folder to keep, absolute or relative to the scanned drive")
+                    .required(true)
+                    .index(2))
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Show where scans are stored and how much space they use")
+        )
+        .subcommand(
+            SubCommand::with_name("log")
+                .about("Show the chain of scans of a drive, newest first")
+                .arg(Arg::with_name("drive_path")
+                    .help("Drive path as recorded in the scans")
+                    .required(true)
+                    .index(1))
+        )
+        .subcommand(
+            SubCommand::with_name("find-hash")
+                .about("List the scans that recorded a file with the given content hash")
+                .arg(Arg::with_name("hash")
+                    .help("SHA-256 hex digest, as recorded by scan --hash")
+                    .required(true)
+                    .index(1))
+                .arg(Arg::with_name("max-results")
+                    .long("max-results")
+                    .value_name("N")
+                    .help("Show at most N matching paths (default 100)")
                     .takes_value(true))
         )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Re-hash the files of a scan taken with --hash and report what changed")
+                .arg(Arg::with_name("scan_id")
+                    .help("ID of a scan taken with --hash")
+                    .required(true)
+                    .index(1))
+                .arg(Arg::with_name("resume")
+                    .long("resume")
+                    .help("Continue an interrupted verification, skipping files it already checked"))
+        )
+        .subcommand(
+            SubCommand::with_name("changes")
+                .about("Show what changed since the previous scan of the same drive")
+                .arg(Arg::with_name("scan_id")
+                    .help("ID of the scan (optional, will prompt if not provided)")
+                    .index(1))
+                .arg(Arg::with_name("new-since")
+                    .long("new-since")
+                    .help("Also list the files created after the baseline scan, apart from moved-in or modified ones"))
+        )
         .get_matches();
 
+    let profile = matches
+        .value_of("profile")
+        .or_else(|| matches.subcommand().1.and_then(|m| m.value_of("profile")));
+    let data_dir = match drive_pulse_lib::get_data_dir()
+        .and_then(|base| profile.map_or(Ok(base.clone()), |name| drive_pulse_lib::profile_data_dir(&base, name)))
+    {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let _ = PASSWORD_FROM_STDIN.set(
+        matches.is_present("password-stdin")
+            || matches.subcommand().1.is_some_and(|m| m.is_present("password-stdin")),
+    );
+
+    let table_style = matches.value_of("table-style")
+        .or_else(|| matches.subcommand().1.and_then(|m| m.value_of("table-style")))
+        .map(TableStyle::parse)
+        .unwrap_or(Ok(TableStyle::Full));
+    match table_style {
+        Ok(style) => {
+            let _ = TABLE_STYLE.set(style);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let timezone = matches.value_of("timezone")
+        .or_else(|| matches.subcommand().1.and_then(|m| m.value_of("timezone")))
+        .map(str::to_string)
+        .or_else(|| std::env::var(TIMEZONE_ENV).ok())
+        .map(|value| DisplayTimezone::parse(&value))
+        .unwrap_or(Ok(DisplayTimezone::Local));
+    match timezone {
+        Ok(timezone) => {
+            let _ = DISPLAY_TIMEZONE.set(timezone);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
     let result = if let Some(matches) = matches.subcommand_matches("scan") {
-        handle_scan(matches)
-    } else if let Some(_) = matches.subcommand_matches("list") {
-        handle_list()
+        handle_scan(&data_dir, matches)
+    } else if let Some(matches) = matches.subcommand_matches("list") {
+        handle_list(&data_dir, matches)
     } else if let Some(matches) = matches.subcommand_matches("view") {
-        handle_view(matches)
+        handle_view(&data_dir, matches)
     } else if let Some(matches) = matches.subcommand_matches("compare") {
-        handle_compare(matches)
+        handle_compare(&data_dir, matches)
     } else if let Some(matches) = matches.subcommand_matches("export") {
-        handle_export(matches)
+        handle_export(&data_dir, matches)
+    } else if let Some(matches) = matches.subcommand_matches("verify-manifest") {
+        handle_verify_manifest(&data_dir, matches)
+    } else if let Some(matches) = matches.subcommand_matches("recent") {
+        handle_recent(&data_dir, matches)
+    } else if let Some(matches) = matches.subcommand_matches("stats") {
+        handle_stats(&data_dir, matches)
+    } else if let Some(matches) = matches.subcommand_matches("delete") {
+        handle_delete(&data_dir, matches)
+    } else if let Some(matches) = matches.subcommand_matches("lock") {
+        handle_lock(&data_dir, matches, true)
+    } else if let Some(matches) = matches.subcommand_matches("unlock") {
+        handle_lock(&data_dir, matches, false)
+    } else if let Some(matches) = matches.subcommand_matches("migrate") {
+        handle_migrate(&data_dir, matches)
+    } else if let Some(matches) = matches.subcommand_matches("compact") {
+        handle_compact(&data_dir, matches)
+    } else if let Some(matches) = matches.subcommand_matches("extract") {
+        handle_extract(&data_dir, matches)
+    } else if matches.subcommand_matches("info").is_some() {
+        handle_info(&data_dir)
+    } else if let Some(matches) = matches.subcommand_matches("log") {
+        handle_log(&data_dir, matches)
+    } else if let Some(matches) = matches.subcommand_matches("find-hash") {
+        handle_find_hash(&data_dir, matches)
+    } else if let Some(matches) = matches.subcommand_matches("verify") {
+        handle_verify(&data_dir, matches)
+    } else if let Some(matches) = matches.subcommand_matches("changes") {
+        handle_changes(&data_dir, matches)
     } else {
         // Interactive mode
-        handle_interactive()
+        handle_interactive(&data_dir)
     };
 
     if let Err(e) = result {
@@ -117,37 +607,135 @@ fn main() {
     }
 }
 
-fn handle_scan(matches: &clap::ArgMatches) -> Result<(), String> {
-    let path = match matches.value_of("path") {
-        Some(p) => p.to_string(),
-        None => {
-            let mut rl = Editor::new().map_err(|e| format!("Failed to create editor: {}", e))?;
-            rl.set_helper(Some(PathHelper {
-                completer: FilenameCompleter::new(),
-            }));
-            
-            println!("\n{}", style("Enter path to scan (use Tab for autocomplete):").cyan());
-            match rl.readline("Path: ") {
-                Ok(line) => line.trim().to_string(),
-                Err(ReadlineError::Interrupted) => {
-                    return Err("Cancelled by user".to_string());
-                }
-                Err(ReadlineError::Eof) => {
-                    return Err("EOF".to_string());
-                }
-                Err(err) => {
-                    return Err(format!("Failed to read input: {}", err));
-                }
+fn handle_scan(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let json = matches.is_present("json");
+    let paths = match matches.value_of("paths-from") {
+        Some(file) => {
+            let paths = read_paths_file(file)?;
+            // Check every root up front rather than failing halfway through the list
+            let missing: Vec<&str> = paths.iter().filter(|p| !Path::new(p).exists()).map(String::as_str).collect();
+            if !missing.is_empty() {
+                return Err(format!("Paths listed in {} don't exist: {}", file, missing.join(", ")));
             }
+            paths
         }
+        None => vec![match matches.value_of("path") {
+            Some(p) => p.to_string(),
+            None if json => return Err("A path is required with --json".to_string()),
+            None => prompt_scan_path()?,
+        }],
+    };
+    
+    let options = ScanOptions {
+        include_data_dir: matches.is_present("include-data-dir"),
+        canonicalize_paths: matches.is_present("canonicalize"),
+        lossless_paths: matches.is_present("lossless-paths"),
+        one_filesystem: matches.is_present("one-filesystem"),
+        dedupe_hard_links: matches.is_present("dedupe-hard-links"),
+        hash_files: matches.is_present("hash"),
+        content_filter: matches.is_present("content-filter"),
+        record_child_counts: matches.is_present("child-counts"),
+        record_xattrs: matches.is_present("xattrs"),
+        xattr_names: matches.values_of("xattr-name").map(|names| names.map(String::from).collect()).unwrap_or_default(),
+        reparse_points: match matches.value_of("reparse-points") {
+            Some("skip") => ReparsePointPolicy::Skip,
+            Some("follow") => ReparsePointPolicy::Follow,
+            _ => ReparsePointPolicy::Record,
+        },
+        max_size: matches.value_of("max-size").map(parse_size).transpose()?,
+        min_depth: matches.value_of("min-depth")
+            .map(|v| v.parse::<usize>().map_err(|_| format!("Invalid --min-depth: {}", v)))
+            .transpose()?,
+        exclude_empty_dirs: matches.is_present("exclude-empty-dirs"),
+        exclude_extensions: matches.values_of("exclude-ext").map(|exts| exts.map(String::from).collect()).unwrap_or_default(),
+        threads: parse_threads(matches.value_of("threads").unwrap_or("auto"))?,
+        hash_buffer_size: matches.value_of("hash-buffer").map(parse_size).transpose()?.unwrap_or(0) as usize,
+        consistency_sample: if matches.is_present("check-consistency") { CONSISTENCY_SAMPLE_SIZE } else { 0 },
+        max_entries_in_memory: matches.value_of("max-entries-in-memory")
+            .map(|v| v.parse::<usize>().map_err(|_| format!("Invalid --max-entries-in-memory: {}", v)))
+            .transpose()?,
+        ..ScanOptions::default()
+    };
+
+    let format = if matches.is_present("encrypt") {
+        SnapshotFormat::Encrypted
+    } else if matches.is_present("binary") {
+        SnapshotFormat::Binary
+    } else {
+        SnapshotFormat::Json
     };
+    let password = if format == SnapshotFormat::Encrypted { Some(snapshot_password()?) } else { None };
+    let write_metadata = !matches.is_present("no-metadata");
+    if !write_metadata && format == SnapshotFormat::Encrypted {
+        eprintln!("{} Encrypted scans without metadata are not shown by list; open them by ID.", style("⚠").yellow());
+    }
+
+    for path in paths {
+        scan_path(data_dir, path, &options, format, password.as_deref(), write_metadata, json)?;
+    }
+    Ok(())
+}
+
+fn prompt_scan_path() -> Result<String, String> {
+    let mut rl = Editor::new().map_err(|e| format!("Failed to create editor: {}", e))?;
+    rl.set_helper(Some(PathHelper {
+        completer: FilenameCompleter::new(),
+    }));
+    
+    println!("\n{}", style("Enter path to scan (use Tab for autocomplete):").cyan());
+    match rl.readline("Path: ") {
+        Ok(line) => Ok(line.trim().to_string()),
+        Err(ReadlineError::Interrupted) => Err("Cancelled by user".to_string()),
+        Err(ReadlineError::Eof) => Err("EOF".to_string()),
+        Err(err) => Err(format!("Failed to read input: {}", err)),
+    }
+}
+
+/// Scan roots listed in a `--paths-from` file, one per line. Blank lines and
+/// lines starting with `#` are skipped. Relative paths are taken from the
+/// file's own directory, so a list kept under version control works from
+/// anywhere.
+fn read_paths_file(file: &str) -> Result<Vec<String>, String> {
+    let content = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+    let base = Path::new(file).parent().unwrap_or_else(|| Path::new(""));
+    let paths: Vec<String> = content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line).to_string_lossy().to_string())
+        .collect();
+    if paths.is_empty() {
+        return Err(format!("No paths listed in {}", file));
+    }
+    Ok(paths)
+}
 
-    println!("\n{} Starting scan of: {}\n", style("🔍").cyan(), style(&path).yellow().bold());
+/// Scans one root with the options of `scan` and reports the result.
+fn scan_path(data_dir: &Path, path: String, options: &ScanOptions, format: SnapshotFormat, password: Option<&str>, write_metadata: bool, json: bool) -> Result<(), String> {
+    if !json {
+        println!("\n{} Starting scan of: {}\n", style("🔍").cyan(), style(&path).yellow().bold());
+    }
     
     let mut last_count = 0;
-    let snapshot = drive_pulse_lib::scan_drive(path, |count: usize, current_path: String| {
-        if count % 100 == 0 || count != last_count {
+    let mut last_progress: Option<Instant> = None;
+    // The previous scan of this drive tells roughly how many entries to expect
+    let expected_files = drive_pulse_lib::latest_snapshot_for_drive(data_dir, &path)
+        .ok()
+        .flatten()
+        .map(|previous| previous.total_files);
+    let mut eta = drive_pulse_lib::EtaEstimator::default();
+    let scan_start = Instant::now();
+    let snapshot = drive_pulse_lib::scan_drive_to_storage(data_dir, path, options, format, password, write_metadata, |progress: &ScanProgress| {
+        let count = progress.files_scanned;
+        let elapsed = scan_start.elapsed();
+        let remaining = eta.update(count, expected_files, elapsed);
+        if json {
+            if last_progress.is_none_or(|t| t.elapsed() >= PROGRESS_INTERVAL) {
+                eprintln!("{}", progress_json(progress, remaining));
+                last_progress = Some(Instant::now());
+            }
+        } else if count.is_multiple_of(100) || count != last_count {
             // Truncate path if too long using character-aware slicing
+            let current_path = &progress.current_path;
             let truncated_path = if current_path.chars().count() > 60 {
                 let chars: Vec<char> = current_path.chars().collect();
                 let start = chars.len().saturating_sub(57);
@@ -155,9 +743,15 @@ fn handle_scan(matches: &clap::ArgMatches) -> Result<(), String> {
             } else {
                 current_path.clone()
             };
-            print!("\r{} Scanning... {} files found | {:<60}", 
-                style("🔍").cyan(), 
+            let rate = count as f64 / elapsed.as_secs_f64().max(0.001);
+            let eta_label = remaining.map(|d| format!(" | ~{} left", format_eta(d))).unwrap_or_default();
+            print!("\r{} Scanning... {} files found | {} dirs | {} | {:.0}/s{} | {:<60}",
+                style("🔍").cyan(),
                 style(format!("{:6}", count)).yellow().bold(),
+                progress.total_dirs,
+                format_size(progress.total_size),
+                rate,
+                eta_label,
                 style(&truncated_path).dim()
             );
             use std::io::Write;
@@ -166,105 +760,251 @@ fn handle_scan(matches: &clap::ArgMatches) -> Result<(), String> {
         }
     })?;
     
+    if json {
+        println!("{}", scan_summary_json(&snapshot, options));
+        return Ok(());
+    }
+    
     print!("\r{}\r", " ".repeat(150)); // Clear the line
-    println!("{} Scan completed successfully!", style("✓").green().bold());
+    if snapshot.is_partial() {
+        println!("{} Scan completed with {} unreadable entries; the snapshot is partial.",
+            style("⚠").yellow().bold(), snapshot.scan_errors_count);
+    } else {
+        println!("{} Scan completed successfully!", style("✓").green().bold());
+    }
     println!();
     
-    let rows = vec![
+    let mut rows = vec![
         vec![style("Snapshot ID").cyan().bold().to_string(), snapshot.id.clone()],
         vec![style("Total Files").cyan().bold().to_string(), format!("{}", snapshot.total_files)],
         vec![style("Total Size").cyan().bold().to_string(), format_size(snapshot.total_size)],
         vec![style("Duration").cyan().bold().to_string(), format!("{} seconds", snapshot.scan_duration)],
     ];
+    rows.extend(performance_rows(&snapshot));
+    if snapshot.is_partial() {
+        rows.push(vec![
+            style("Errors").cyan().bold().to_string(),
+            style(format!("{} entries could not be read (partial)", snapshot.scan_errors_count)).yellow().to_string(),
+        ]);
+    }
+    if let Some(max_size) = options.max_size {
+        rows.push(vec![
+            style("Size Cap").cyan().bold().to_string(),
+            format!("files over {} were left out", format_size(max_size)),
+        ]);
+    }
+    if let Some(min_depth) = options.min_depth {
+        rows.push(vec![
+            style("Min Depth").cyan().bold().to_string(),
+            format!("entries above level {} were left out", min_depth),
+        ]);
+    }
+    if options.exclude_empty_dirs {
+        rows.push(vec![
+            style("Empty Dirs").cyan().bold().to_string(),
+            "directories with no recorded files were left out".to_string(),
+        ]);
+    }
+    if !options.exclude_extensions.is_empty() {
+        rows.push(vec![
+            style("Excluded").cyan().bold().to_string(),
+            format!(
+                "{} files ({}) by extension: {}",
+                snapshot.excluded_by_extension_count,
+                format_size(snapshot.excluded_by_extension_bytes),
+                options.exclude_extensions.join(", ")
+            ),
+        ]);
+    }
+    if options.record_xattrs {
+        rows.push(vec![
+            style("Xattrs").cyan().bold().to_string(),
+            if options.xattr_names.is_empty() {
+                "all extended attributes recorded".to_string()
+            } else {
+                format!("recorded: {}", options.xattr_names.join(", "))
+            },
+        ]);
+    }
+    if options.dedupe_hard_links {
+        rows.push(vec![
+            style("Hard Links").cyan().bold().to_string(),
+            format!("{} ({} not double-counted)", snapshot.hard_link_count, format_size(snapshot.hard_link_bytes_saved)),
+        ]);
+    }
     let table = create_table_with_rows(rows);
     
     println!("{}", table);
-    
-    drive_pulse_lib::save_snapshot(&snapshot, false, None)?;
+    if snapshot.is_consistent == Some(false) {
+        println!("\n{} Some files changed while the scan was running, so this snapshot may not be a\n  consistent point-in-time view. Consider rescanning once writes have finished.",
+            style("⚠").yellow());
+    }
+    if snapshot.is_partial() && std::io::stdin().is_terminal() {
+        println!();
+        let show_errors = Confirm::new()
+            .with_prompt("Show the entries that could not be read?")
+            .interact()
+            .map_err(|e| format!("Failed to get confirmation: {}", e))?;
+        if show_errors {
+            for error in &snapshot.scan_errors {
+                println!("  {}", error);
+            }
+            if snapshot.scan_errors.len() < snapshot.scan_errors_count {
+                println!("{} {} more not recorded", style("...").dim(), snapshot.scan_errors_count - snapshot.scan_errors.len());
+            }
+        }
+    }
     
     Ok(())
 }
 
-fn handle_list() -> Result<(), String> {
-    let history = drive_pulse_lib::get_scan_history()?;
+fn progress_json(progress: &ScanProgress, remaining: Option<Duration>) -> String {
+    serde_json::json!({
+        "scanned": progress.files_scanned,
+        "path": progress.current_path,
+        "total_size": progress.total_size,
+        "total_dirs": progress.total_dirs,
+        "eta_secs": remaining.map(|d| d.as_secs()),
+    })
+    .to_string()
+}
+
+/// `45s`, `3m 20s` or `1h 05m`.
+fn format_eta(remaining: Duration) -> String {
+    let secs = remaining.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/// Files re-checked by `scan --check-consistency`.
+const CONSISTENCY_SAMPLE_SIZE: usize = 100;
+
+fn scan_summary_json(snapshot: &Snapshot, options: &ScanOptions) -> String {
+    serde_json::json!({
+        "id": snapshot.id,
+        "drive_path": snapshot.drive_path,
+        "timestamp": snapshot.timestamp,
+        "total_files": snapshot.total_files,
+        "total_size": snapshot.total_size,
+        "scan_duration": snapshot.scan_duration,
+        "files_per_sec": snapshot.files_per_sec,
+        "peak_memory_bytes": snapshot.peak_memory_bytes,
+        "scan_errors_count": snapshot.scan_errors_count,
+        "partial": snapshot.is_partial(),
+        "hard_link_count": snapshot.hard_link_count,
+        "hard_link_bytes_saved": snapshot.hard_link_bytes_saved,
+        "max_size": options.max_size,
+        "min_depth": options.min_depth,
+        "exclude_empty_dirs": options.exclude_empty_dirs,
+        "exclude_extensions": options.exclude_extensions,
+        "record_xattrs": options.record_xattrs,
+        "xattr_names": options.xattr_names,
+        "excluded_by_extension_count": snapshot.excluded_by_extension_count,
+        "excluded_by_extension_bytes": snapshot.excluded_by_extension_bytes,
+        "threads": snapshot.threads,
+        "hash_buffer_size": options.effective_hash_buffer_size(),
+        "content_filter_fp_rate": snapshot.content_filter.as_ref().map(|filter| filter.false_positive_rate()),
+        "is_consistent": snapshot.is_consistent,
+        "max_entries_in_memory": options.max_entries_in_memory,
+        // Entries spilled to disk aren't in memory to be counted
+        "kinds": (snapshot.files.len() == snapshot.total_files).then(|| drive_pulse_lib::count_file_kinds(&snapshot.files)
+            .into_iter()
+            .map(|(kind, count)| (kind.as_str().to_string(), serde_json::json!(count)))
+            .collect::<serde_json::Map<String, serde_json::Value>>()),
+    }).to_string()
+}
+
+/// `120 file, 8 dir, 3 symlink`, listing only kinds that occur.
+fn kind_counts_label(snapshot: &Snapshot) -> String {
+    drive_pulse_lib::count_file_kinds(&snapshot.files)
+        .iter()
+        .map(|(kind, count)| format!("{} {}", count, kind.as_str()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `auto` leaves the choice to the library, anything else must be a positive count.
+fn parse_threads(value: &str) -> Result<Option<usize>, String> {
+    if value.eq_ignore_ascii_case("auto") {
+        return Ok(None);
+    }
+    match value.parse::<usize>() {
+        Ok(threads) if threads > 0 => Ok(Some(threads)),
+        _ => Err(format!("Invalid thread count: {} (use auto or a positive number)", value)),
+    }
+}
+
+fn handle_list(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let since = matches.value_of("since").map(|v| parse_date_bound(v, false)).transpose()?;
+    let until = matches.value_of("until").map(|v| parse_date_bound(v, true)).transpose()?;
+    let mut history = filter_by_date(drive_pulse_lib::get_scan_history(data_dir)?, since, until);
+    if let Some(drive) = matches.value_of("drive") {
+        history = filter_by_drive(history, drive);
+    }
     
     if history.is_empty() {
         println!("\n{} No scans found.", style("ℹ").blue());
         return Ok(());
     }
+    if matches.is_present("check-paths") {
+        drive_pulse_lib::check_drive_paths(&mut history);
+    }
     
     println!("\n{} Scan History\n", style("📊").cyan().bold());
     
-    let mut table = Table::new();
-    table.add_row(Row::new(vec![
-        Cell::new("ID"),
-        Cell::new("Drive Path"),
-        Cell::new("Date"),
-        Cell::new("Files"),
-        Cell::new("Size"),
-    ]));
-    
-    for scan in history {
-        let datetime = DateTime::from_timestamp(scan.timestamp, 0)
-            .map(|dt| dt.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
-            .unwrap_or_else(|| "Unknown".to_string());
-        
-        table.add_row(Row::new(vec![
-            Cell::new(&scan.id),
-            Cell::new(&scan.drive_path),
-            Cell::new(&datetime),
-            Cell::new(&format!("{}", scan.total_files)),
-            Cell::new(&format_size(scan.total_size)),
-        ]));
+    if matches.is_present("group-by-drive") {
+        for (drive_path, scans) in group_by_drive(history) {
+            println!("{} ({} scan(s))", style(drive_path).yellow().bold(), scans.len());
+            println!("{}\n", history_table(&scans));
+        }
+        return Ok(());
     }
     
+    let table = history_table(&history);
+    
     println!("{}\n", table);
     
     Ok(())
 }
 
-fn handle_view(matches: &clap::ArgMatches) -> Result<(), String> {
+fn handle_view(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
     let scan_id = match matches.value_of("scan_id") {
         Some(id) => id.to_string(),
-        None => {
-            // Show list and let user select
-            let history = drive_pulse_lib::get_scan_history()?;
-            if history.is_empty() {
-                return Err("No scans found.".to_string());
-            }
-            
-            let items: Vec<String> = history.iter()
-                .map(|s| format!("{} - {} ({})", s.id, s.drive_path, 
-                    DateTime::from_timestamp(s.timestamp, 0)
-                        .map(|dt| dt.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
-                        .unwrap_or_else(|| "Unknown".to_string())))
-                .collect();
-            
-            let selection = Select::new()
-                .with_prompt("Select a scan to view")
-                .items(&items)
-                .interact()
-                .map_err(|e| format!("Failed to get selection: {}", e))?;
-            
-            history[selection].id.clone()
-        }
+        None => select_scan(data_dir, "Select a scan to view")?,
     };
 
-    let snapshot = drive_pulse_lib::load_snapshot(&scan_id, None)?;
+    let max_results = parse_max_results(matches)?;
+    let snapshot = load_scan(data_dir, &scan_id)?;
     
     println!("\n{} Snapshot Details\n", style("📄").cyan().bold());
     
-    let rows = vec![
+    let mut rows = vec![
         vec![style("ID").cyan().bold().to_string(), snapshot.id.clone()],
         vec![style("Drive Path").cyan().bold().to_string(), snapshot.drive_path.clone()],
         vec![style("Timestamp").cyan().bold().to_string(),
-            DateTime::from_timestamp(snapshot.timestamp, 0)
-                .map(|dt| dt.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
-                .unwrap_or_else(|| "Unknown".to_string())],
+            format_timestamp(snapshot.timestamp)],
         vec![style("Total Files").cyan().bold().to_string(), format!("{}", snapshot.total_files)],
         vec![style("Total Size").cyan().bold().to_string(), format_size(snapshot.total_size)],
         vec![style("Scan Duration").cyan().bold().to_string(), format!("{} seconds", snapshot.scan_duration)],
+        vec![style("Entries").cyan().bold().to_string(), kind_counts_label(&snapshot)],
     ];
+    rows.extend(performance_rows(&snapshot));
+    if let Some(source) = &snapshot.extracted_from {
+        rows.push(vec![style("Extracted From").cyan().bold().to_string(), source.clone()]);
+    }
+    if matches.is_present("check-paths") {
+        let status = if Path::new(&snapshot.drive_path).exists() {
+            style("present").green().to_string()
+        } else {
+            style("missing").red().to_string()
+        };
+        rows.push(vec![style("Drive").cyan().bold().to_string(), status]);
+    }
     let table = create_table_with_rows(rows);
     
     println!("{}\n", table);
@@ -275,154 +1015,251 @@ fn handle_view(matches: &clap::ArgMatches) -> Result<(), String> {
         .map_err(|e| format!("Failed to get confirmation: {}", e))?;
     
     if show_files {
-        println!("\n{} File List (showing first 100)\n", style("📁").cyan().bold());
-        
-        let mut table = Table::new();
-        table.add_row(Row::new(vec![
-            Cell::new("#"),
-            Cell::new("Path"),
-            Cell::new("Size"),
-        ]));
+        println!("\n{} File List\n", style("📁").cyan().bold());
         
-        for (i, file) in snapshot.files.iter().take(100).enumerate() {
-            table.add_row(Row::new(vec![
-                Cell::new(&format!("{}", i + 1)),
-                Cell::new(&file.path),
-                Cell::new(&format_size(file.size)),
-            ]));
-        }
+        let (files, total) = take_results(snapshot.files.iter(), max_results);
+        let shown = files.len();
+        let rows = files.iter().enumerate()
+            .map(|(i, file)| vec![format!("{}", i + 1), file.path.clone(), format_size(file.size)])
+            .collect();
+        let table = create_table_with_header(vec!["#", "Path", "Size"], rows);
         
         println!("{}", table);
-        
-        if snapshot.files.len() > 100 {
-            println!("\n{} {} more files not shown", style("...").dim(), snapshot.files.len() - 100);
-        }
+        print_truncation_note(shown, total);
     }
     
     Ok(())
 }
 
-fn handle_compare(matches: &clap::ArgMatches) -> Result<(), String> {
-    let history = drive_pulse_lib::get_scan_history()?;
+fn handle_compare(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let fail_on = fail_on_statuses(matches)?;
+    let oneline = matches.is_present("oneline");
+    if oneline && matches.value_of("scan1").is_none() {
+        return Err("--oneline needs the scan IDs on the command line".to_string());
+    }
+    let unified = matches.is_present("diff");
+    if unified && matches.value_of("scan1").is_none() {
+        return Err("--diff needs the scan IDs on the command line".to_string());
+    }
+    let history = drive_pulse_lib::get_scan_history(data_dir)?;
     if history.len() < 2 {
         return Err("Need at least 2 scans to compare.".to_string());
     }
     
-    let scan1_id = match matches.value_of("scan1") {
-        Some(id) => id.to_string(),
-        None => {
-            let items: Vec<String> = history.iter()
-                .map(|s| format!("{} - {} ({})", s.id, s.drive_path, 
-                    DateTime::from_timestamp(s.timestamp, 0)
-                        .map(|dt| dt.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
-                        .unwrap_or_else(|| "Unknown".to_string())))
-                .collect();
+    let (scan1_id, scan2_id) = if let (Some(id), None) = (matches.value_of("scan1"), matches.value_of("scan2")) {
+        // A single scan is compared against the previous scan of the same drive
+        let parent_id = history.iter()
+            .find(|s| s.id == id)
+            .and_then(|s| s.parent_id.clone())
+            .ok_or_else(|| format!("Scan {} has no parent scan to compare against", id))?;
+        (parent_id, id.to_string())
+    } else {
+        let scan1_id = match matches.value_of("scan1") {
+            Some(id) => id.to_string(),
+            None => {
+                let items: Vec<String> = history.iter()
+                    .map(|s| format!("{} - {} ({})", s.id, s.drive_path, format_timestamp(s.timestamp)))
+                    .collect();
             
-            let selection = Select::new()
-                .with_prompt("Select first scan")
-                .items(&items)
-                .interact()
-                .map_err(|e| format!("Failed to get selection: {}", e))?;
+                let selection = Select::new()
+                    .with_prompt("Select first scan")
+                    .items(&items)
+                    .interact()
+                    .map_err(|e| format!("Failed to get selection: {}", e))?;
             
-            history[selection].id.clone()
-        }
-    };
+                history[selection].id.clone()
+            }
+        };
     
-    let scan2_id = match matches.value_of("scan2") {
-        Some(id) => id.to_string(),
-        None => {
-            let items: Vec<String> = history.iter()
-                .filter(|s| s.id != scan1_id)
-                .map(|s| format!("{} - {} ({})", s.id, s.drive_path, 
-                    DateTime::from_timestamp(s.timestamp, 0)
-                        .map(|dt| dt.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
-                        .unwrap_or_else(|| "Unknown".to_string())))
-                .collect();
+        let scan2_id = match matches.value_of("scan2") {
+            Some(id) => id.to_string(),
+            None => {
+                let items: Vec<String> = history.iter()
+                    .filter(|s| s.id != scan1_id)
+                    .map(|s| format!("{} - {} ({})", s.id, s.drive_path, format_timestamp(s.timestamp)))
+                    .collect();
             
-            let selection = Select::new()
-                .with_prompt("Select second scan")
-                .items(&items)
-                .interact()
-                .map_err(|e| format!("Failed to get selection: {}", e))?;
+                let selection = Select::new()
+                    .with_prompt("Select second scan")
+                    .items(&items)
+                    .interact()
+                    .map_err(|e| format!("Failed to get selection: {}", e))?;
             
-            history.iter()
-                .filter(|s| s.id != scan1_id)
-                .nth(selection)
-                .unwrap()
-                .id.clone()
-        }
+                history.iter()
+                    .filter(|s| s.id != scan1_id)
+                    .nth(selection)
+                    .unwrap()
+                    .id.clone()
+            }
+        };
+        (scan1_id, scan2_id)
     };
 
-    println!("\n{} Comparing scans...\n", style("🔄").cyan());
-    let snapshot1 = drive_pulse_lib::load_snapshot(&scan1_id, None)?;
-    let snapshot2 = drive_pulse_lib::load_snapshot(&scan2_id, None)?;
-    let comparison = drive_pulse_lib::compare_snapshots(&snapshot1, &snapshot2);
+    if !oneline && !unified {
+        println!("\n{} Comparing scans...\n", style("🔄").cyan());
+    }
+    let snapshot1 = load_scan(data_dir, &scan1_id)?;
+    let snapshot2 = load_scan(data_dir, &scan2_id)?;
+    
+    if matches.is_present("structure") {
+        return print_structure_changes(&snapshot1, &snapshot2);
+    }
     
+    let options = diff_options(matches);
+    if options.match_by_content {
+        require_hashes(&snapshot1)?;
+        require_hashes(&snapshot2)?;
+    }
+    if options.compare_xattrs {
+        require_xattrs(&snapshot1)?;
+        require_xattrs(&snapshot2)?;
+    }
+    let mut comparison = drive_pulse_lib::compare_snapshots_with_options(&snapshot1, &snapshot2, &options);
+    keep_only_status(matches, &mut comparison);
+    if oneline {
+        println!("{}", oneline_summary(&comparison));
+    } else if unified {
+        print!("{}", drive_pulse_lib::to_unified_text(&comparison));
+    } else if matches.is_present("summary-table") {
+        let churn = drive_pulse_lib::aggregate_diffs_by_dir(&comparison, summary_table_depth(matches)?);
+        println!("{} Changes by Directory\n", style("📊").cyan().bold());
+        println!("{}", summary_table(&churn));
+    } else {
+        print_comparison(&comparison)?;
+    }
+
+    let failed = fail_on_findings(&fail_on, &comparison);
+    if !failed.is_empty() {
+        eprintln!("{} --fail-on: {}", style("✗").red().bold(), failed.join(", "));
+        std::process::exit(FAIL_ON_EXIT_CODE);
+    }
+    Ok(())
+}
+
+/// How many results `--max-results` shows when not given, enough for a screen
+/// or two without flooding the terminal on huge scans.
+const DEFAULT_MAX_RESULTS: usize = 100;
+
+fn parse_max_results(matches: &clap::ArgMatches) -> Result<usize, String> {
+    match matches.value_of("max-results") {
+        Some(value) => match value.parse() {
+            Ok(max) if max > 0 => Ok(max),
+            _ => Err(format!("Invalid --max-results: {} (expected a positive number)", value)),
+        },
+        None => Ok(DEFAULT_MAX_RESULTS),
+    }
+}
+
+/// The first `max` of `results`, and how many there were in all.
+fn take_results<I: ExactSizeIterator>(results: I, max: usize) -> (Vec<I::Item>, usize) {
+    let total = results.len();
+    (results.take(max).collect(), total)
+}
+
+/// Says how much of a truncated listing is shown.
+fn print_truncation_note(shown: usize, total: usize) {
+    if let Some(note) = truncation_note(shown, total) {
+        println!("\n{} {}", style("...").dim(), note);
+    }
+}
+
+/// "Showing N of M" for a listing cut short, `None` when nothing was left out.
+fn truncation_note(shown: usize, total: usize) -> Option<String> {
+    (shown < total).then(|| format!("Showing {} of {} (use --max-results to see more)", shown, total))
+}
+
+/// Exit status of `compare` when `--fail-on` finds changes, kept apart from
+/// the 1 of errors so CI can tell the two apart.
+const FAIL_ON_EXIT_CODE: i32 = 2;
+
+/// The `--fail-on` kinds present in `comparison`, each with its count (e.g.
+/// "2 deleted"). Empty when the gate passes.
+fn fail_on_findings(fail_on: &[DiffStatus], comparison: &ComparisonResult) -> Vec<String> {
+    fail_on.iter()
+        .filter(|status| comparison.count(**status) > 0)
+        .map(|status| format!("{} {}", comparison.count(*status), status.as_str()))
+        .collect()
+}
+
+/// The change kinds named by `--fail-on`.
+fn fail_on_statuses(matches: &clap::ArgMatches) -> Result<Vec<DiffStatus>, String> {
+    let list = match matches.value_of("fail-on") {
+        Some(list) => list,
+        None => return Ok(Vec::new()),
+    };
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| match DiffStatus::parse(name) {
+            Some(DiffStatus::Unchanged) | None => {
+                Err(format!("Unknown --fail-on status: {} (use added, deleted, modified or type_changed)", name))
+            }
+            Some(status) => Ok(status),
+        })
+        .collect()
+}
+
+/// `--depth` of `--summary-table`.
+fn summary_table_depth(matches: &clap::ArgMatches) -> Result<usize, String> {
+    match matches.value_of("depth") {
+        Some(depth) => depth.parse().map_err(|_| format!("Invalid depth: {}", depth)),
+        None => Ok(1),
+    }
+}
+
+/// One row per directory of `compare --summary-table`, each change column
+/// giving the number of paths and the bytes they added or removed, followed
+/// by a row of totals.
+fn summary_table(churn: &[DirectoryChurn]) -> Table {
+    let cell = |count: usize, delta: i64| {
+        if count == 0 {
+            "-".to_string()
+        } else {
+            format!("{} ({})", count, format_size_delta(delta))
+        }
+    };
+    let mut total = DirectoryChurn { directory: style("Total").bold().to_string(), ..Default::default() };
+    for dir in churn {
+        total.added_count += dir.added_count;
+        total.deleted_count += dir.deleted_count;
+        total.modified_count += dir.modified_count;
+        total.type_changed_count += dir.type_changed_count;
+        total.added_bytes += dir.added_bytes;
+        total.deleted_bytes += dir.deleted_bytes;
+        total.modified_bytes_delta += dir.modified_bytes_delta;
+    }
+    let mut rows = Vec::new();
+    for dir in churn.iter().chain(std::iter::once(&total)) {
+        rows.push(vec![
+            dir.directory.clone(),
+            cell(dir.added_count, dir.added_bytes as i64),
+            cell(dir.deleted_count, -(dir.deleted_bytes as i64)),
+            cell(dir.modified_count + dir.type_changed_count, dir.modified_bytes_delta),
+            format_size_delta(dir.size_delta()),
+        ]);
+    }
+    create_table_with_header(vec!["Directory", "Added", "Deleted", "Modified", "Net Change"], rows)
+}
+
+/// Prints the scan details and change counts, then offers the detailed list.
+fn print_comparison(comparison: &ComparisonResult) -> Result<(), String> {
     println!("{} Comparison Results\n", style("📊").cyan().bold());
     
     // Snapshot info
-    let mut table = Table::new();
-    table.add_row(Row::new(vec![
-        Cell::new("ID"),
-        Cell::new("Drive Path"),
-        Cell::new("Date"),
-        Cell::new("Files"),
-        Cell::new("Size"),
-    ]));
-    table.add_row(Row::new(vec![
-        Cell::new("ID"),
-        Cell::new(&comparison.snapshot1.id),
-        Cell::new(&comparison.snapshot2.id),
-    ]));
-    table.add_row(Row::new(vec![
-        Cell::new("Path"),
-        Cell::new(&comparison.snapshot1.drive_path),
-        Cell::new(&comparison.snapshot2.drive_path),
-    ]));
-    table.add_row(Row::new(vec![
-        Cell::new("Date"),
-        Cell::new(&DateTime::from_timestamp(comparison.snapshot1.timestamp, 0)
-            .map(|dt| dt.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
-            .unwrap_or_else(|| "Unknown".to_string())),
-        Cell::new(&DateTime::from_timestamp(comparison.snapshot2.timestamp, 0)
-            .map(|dt| dt.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
-            .unwrap_or_else(|| "Unknown".to_string())),
-    ]));
-    table.add_row(Row::new(vec![
-        Cell::new("Files"),
-        Cell::new(&format!("{}", comparison.snapshot1.total_files)),
-        Cell::new(&format!("{}", comparison.snapshot2.total_files)),
-    ]));
-    table.add_row(Row::new(vec![
-        Cell::new("Size"),
-        Cell::new(&format_size(comparison.snapshot1.total_size)),
-        Cell::new(&format_size(comparison.snapshot2.total_size)),
-    ]));
+    let rows = vec![
+        vec!["ID".to_string(), comparison.snapshot1.id.clone(), comparison.snapshot2.id.clone()],
+        vec!["Path".to_string(), comparison.snapshot1.drive_path.clone(), comparison.snapshot2.drive_path.clone()],
+        vec!["Date".to_string(), format_timestamp(comparison.snapshot1.timestamp), format_timestamp(comparison.snapshot2.timestamp)],
+        vec!["Files".to_string(), format!("{}", comparison.snapshot1.total_files), format!("{}", comparison.snapshot2.total_files)],
+        vec!["Size".to_string(), format_size(comparison.snapshot1.total_size), format_size(comparison.snapshot2.total_size)],
+    ];
+    let table = create_table_with_header(vec!["", "Scan 1", "Scan 2"], rows);
     
     println!("{}\n", table);
     
     // Changes summary
-    let mut table = Table::new();
-    table.add_row(Row::new(vec![
-        Cell::new("ID"),
-        Cell::new("Drive Path"),
-        Cell::new("Date"),
-        Cell::new("Files"),
-        Cell::new("Size"),
-    ]));
-    for scan in &history {
-        let datetime = DateTime::from_timestamp(scan.timestamp, 0)
-            .map(|dt| dt.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
-            .unwrap_or_else(|| "Unknown".to_string());
-        table.add_row(Row::new(vec![
-            Cell::new(&scan.id),
-            Cell::new(&scan.drive_path),
-            Cell::new(&datetime),
-            Cell::new(&format!("{}", scan.total_files)),
-            Cell::new(&format_size(scan.total_size)),
-        ]));
-    }
-    table.printstd();
+    println!("{}\n", changes_summary_table(comparison));
+    
     let show_details = Confirm::new()
         .with_prompt("Show detailed changes?")
         .interact()
@@ -431,66 +1268,7 @@ fn handle_compare(matches: &clap::ArgMatches) -> Result<(), String> {
     if show_details {
         println!("\n{} Detailed Changes (showing first 50)\n", style("📝").cyan().bold());
         
-        let mut table = Table::new();
-        table.add_row(Row::new(vec![
-            Cell::new("ID"),
-            Cell::new("Drive Path"),
-            Cell::new("Date"),
-            Cell::new("Files"),
-            Cell::new("Size"),
-        ]));
-        for scan in &history {
-            let datetime = DateTime::from_timestamp(scan.timestamp, 0)
-                .map(|dt| dt.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
-                .unwrap_or_else(|| "Unknown".to_string());
-            table.add_row(Row::new(vec![
-                Cell::new(&scan.id),
-                Cell::new(&scan.drive_path),
-                Cell::new(&datetime),
-                Cell::new(&format!("{}", scan.total_files)),
-                Cell::new(&format_size(scan.total_size)),
-            ]));
-        }
-        table.printstd();
-
-        // Details table for diffs (example, refactor as needed)
-        let mut details_table = Table::new();
-        details_table.add_row(Row::new(vec![
-            Cell::new("Change"),
-            Cell::new("Path"),
-            Cell::new("Old Size"),
-            Cell::new("New Size"),
-        ]));
-        for diff in comparison.diffs.iter().take(50) {
-            match diff.status {
-                DiffStatus::Added => {
-                    details_table.add_row(Row::new(vec![
-                        Cell::new("Added"),
-                        Cell::new(&diff.path),
-                        Cell::new("-"),
-                        Cell::new(&format_size(diff.new_size.unwrap_or(0))),
-                    ]));
-                },
-                DiffStatus::Deleted => {
-                    details_table.add_row(Row::new(vec![
-                        Cell::new("Deleted"),
-                        Cell::new(&diff.path),
-                        Cell::new(&format_size(diff.old_size.unwrap_or(0))),
-                        Cell::new("-"),
-                    ]));
-                },
-                DiffStatus::Modified => {
-                    details_table.add_row(Row::new(vec![
-                        Cell::new("Modified"),
-                        Cell::new(&diff.path),
-                        Cell::new(&format_size(diff.old_size.unwrap_or(0))),
-                        Cell::new(&format_size(diff.new_size.unwrap_or(0))),
-                    ]));
-                },
-                DiffStatus::Unchanged => {},
-            }
-        }
-        details_table.printstd();
+        println!("{}", diff_details_table(comparison, 50));
         if comparison.diffs.len() > 50 {
             println!("\n{} {} more changes not shown", style("...").dim(), comparison.diffs.len() - 50);
         }
@@ -499,8 +1277,207 @@ fn handle_compare(matches: &clap::ArgMatches) -> Result<(), String> {
     Ok(())
 }
 
-fn handle_export(matches: &clap::ArgMatches) -> Result<(), String> {
-    let history = drive_pulse_lib::get_scan_history()?;
+fn handle_changes(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let scan_id = match matches.value_of("scan_id") {
+        Some(id) => id.to_string(),
+        None => select_scan(data_dir, "Select a scan")?,
+    };
+    let snapshot = load_scan(data_dir, &scan_id)?;
+    let baseline = match load_parent(data_dir, &snapshot)? {
+        Some(parent) => parent,
+        None => {
+            println!("\n{} Scan {} has no recorded predecessor.", style("ℹ").blue(), scan_id);
+            load_scan(data_dir, &select_scan(data_dir, "Select a baseline scan")?)?
+        }
+    };
+
+    println!("\n{} Changes since {}...\n", style("🔄").cyan(), style(&baseline.id).yellow());
+    let comparison = drive_pulse_lib::compare_snapshots(&baseline, &snapshot);
+    print_comparison(&comparison)?;
+    if matches.is_present("new-since") {
+        print_new_since(&baseline, &snapshot);
+    }
+    Ok(())
+}
+
+fn print_new_since(baseline: &Snapshot, snapshot: &Snapshot) {
+    if !snapshot.files.iter().any(|f| f.created.is_some()) {
+        println!("\n{} Scan {} has no creation times (an older scan, or a filesystem that doesn't record them).",
+            style("⚠").yellow(), snapshot.id);
+        return;
+    }
+    let new_files = drive_pulse_lib::new_since(baseline, snapshot);
+    println!("\n{} New Since Last Scan ({})\n", style("✨").cyan().bold(), new_files.len());
+    let rows = new_files.iter()
+        .map(|file| vec![format_timestamp(file.created.unwrap_or_default()), file.path.clone(), format_size(file.size)])
+        .collect();
+    println!("{}", create_table_with_header(vec!["Created", "Path", "Size"], rows));
+}
+
+/// The scan recorded as `snapshot`'s predecessor, if it has one.
+fn load_parent(data_dir: &Path, snapshot: &Snapshot) -> Result<Option<Snapshot>, String> {
+    snapshot.parent_id.as_deref().map(|id| load_scan(data_dir, id)).transpose()
+}
+
+fn print_structure_changes(snapshot1: &Snapshot, snapshot2: &Snapshot) -> Result<(), String> {
+    let changes = drive_pulse_lib::compare_directory_counts(snapshot1, snapshot2);
+    println!("{} Directories With Changed Entry Counts ({})\n", style("📂").cyan().bold(), changes.len());
+    let rows = changes.iter()
+        .map(|change| {
+            let delta = change.new_count as i64 - change.old_count as i64;
+            vec![change.path.clone(), format!("{}", change.old_count), format!("{}", change.new_count), format!("{:+}", delta)]
+        })
+        .collect();
+    println!("{}", create_table_with_header(vec!["Directory", "Before", "After", "Change"], rows));
+    Ok(())
+}
+
+fn diff_options(matches: &clap::ArgMatches) -> DiffOptions {
+    DiffOptions {
+        presence_only: matches.is_present("presence-only"),
+        include_unchanged: matches.is_present("include-unchanged"),
+        propagate_dir_changes: matches.is_present("propagate-dirs"),
+        unicode_normalize: matches.is_present("unicode-normalize"),
+        match_by_content: matches.is_present("match-by-content"),
+        compare_xattrs: matches.is_present("xattrs"),
+        extensions: matches.value_of("ext").map(|list| {
+            list.split(',')
+                .map(|ext| ext.trim().to_string())
+                .filter(|ext| !ext.is_empty())
+                .collect()
+        }),
+    }
+}
+
+/// Names of the arguments from `only_status_args`.
+const ONLY_STATUS_FLAGS: [&str; 3] = ["only-added", "only-deleted", "only-modified"];
+
+/// `--only-added`, `--only-deleted` and `--only-modified`, shared by `compare`
+/// and `export`. At most one of them can be given.
+fn only_status_args() -> [Arg<'static, 'static>; 3] {
+    [
+        Arg::with_name("only-added")
+            .long("only-added")
+            .help("Only list added paths")
+            .conflicts_with_all(&["only-deleted", "only-modified"]),
+        Arg::with_name("only-deleted")
+            .long("only-deleted")
+            .help("Only list deleted paths")
+            .conflicts_with_all(&["only-added", "only-modified"]),
+        Arg::with_name("only-modified")
+            .long("only-modified")
+            .help("Only list modified paths")
+            .conflicts_with_all(&["only-added", "only-deleted"]),
+    ]
+}
+
+/// `--match-by-content` compares file hashes, which only scans taken with
+/// `--hash` have.
+fn require_hashes(snapshot: &Snapshot) -> Result<(), String> {
+    if snapshot.files.iter().any(|f| f.hash.is_some()) {
+        Ok(())
+    } else {
+        Err(format!("Scan {} has no file hashes; --match-by-content needs scans taken with --hash", snapshot.id))
+    }
+}
+
+/// `--xattrs` compares extended attributes, which only scans taken with
+/// `--xattrs` have.
+fn require_xattrs(snapshot: &Snapshot) -> Result<(), String> {
+    if snapshot.files.iter().any(|f| f.xattrs.is_some()) {
+        Ok(())
+    } else {
+        Err(format!("Scan {} has no extended attributes; --xattrs needs scans taken with --xattrs", snapshot.id))
+    }
+}
+
+/// Applies `--only-added`/`--only-deleted`/`--only-modified` to the listed
+/// diffs. The counts still describe the whole comparison, which is why
+/// `--oneline` and `--fail-on` can't be combined with these flags.
+fn keep_only_status(matches: &clap::ArgMatches, comparison: &mut ComparisonResult) {
+    let status = if matches.is_present("only-added") {
+        DiffStatus::Added
+    } else if matches.is_present("only-deleted") {
+        DiffStatus::Deleted
+    } else if matches.is_present("only-modified") {
+        DiffStatus::Modified
+    } else {
+        return;
+    };
+    comparison.diffs.retain(|diff| diff.status == status);
+}
+
+fn changes_summary_table(comparison: &ComparisonResult) -> Table {
+    let mut rows = vec![
+        vec![style("Added").green().to_string(), format!("{}", comparison.added_count)],
+        vec![style("Deleted").red().to_string(), format!("{}", comparison.deleted_count)],
+        vec![style("Modified").yellow().to_string(), format!("{}", comparison.modified_count)],
+        vec![style("Type changed").magenta().to_string(), format!("{}", comparison.type_changed_count)],
+        vec![style("Unchanged").dim().to_string(), format!("{}", comparison.unchanged_count)],
+    ];
+    if comparison.propagated_dir_count > 0 {
+        rows.push(vec![style("Folders with changes").yellow().to_string(), format!("{}", comparison.propagated_dir_count)]);
+    }
+    create_table_with_header(vec!["Change", "Count"], rows)
+}
+
+/// `added=12 deleted=3 modified=40 type_changed=0 bytes_delta=+2.10GB`, for scraping.
+/// The bytes delta is summed over the diffs, leaving out the folders
+/// `--propagate-dirs` adds, whose changes are already counted below them.
+fn oneline_summary(comparison: &ComparisonResult) -> String {
+    let delta: i64 = comparison.diffs.iter()
+        .filter(|diff| !is_propagated_dir(diff))
+        .map(|diff| diff.new_size.unwrap_or(0) as i64 - diff.old_size.unwrap_or(0) as i64)
+        .sum();
+    format!(
+        "added={} deleted={} modified={} type_changed={} bytes_delta={}{}",
+        comparison.added_count,
+        comparison.deleted_count,
+        comparison.modified_count,
+        comparison.type_changed_count,
+        if delta < 0 { "-" } else { "+" },
+        format_size(delta.unsigned_abs()).replace(' ', "")
+    )
+}
+
+/// A folder `--propagate-dirs` reports only because something below it
+/// changed: `Modified` without any change of its own.
+fn is_propagated_dir(diff: &drive_pulse_lib::FileDiff) -> bool {
+    diff.status == DiffStatus::Modified && diff.change_reasons().is_empty()
+}
+
+fn diff_details_table(comparison: &ComparisonResult, limit: usize) -> Table {
+    let rows = comparison.diffs.iter()
+        .filter(|diff| diff.status != DiffStatus::Unchanged)
+        .take(limit)
+        .map(|diff| {
+            let (change, old_size, new_size) = match diff.status {
+                DiffStatus::Added => ("Added", "-".to_string(), format_size(diff.new_size.unwrap_or(0))),
+                DiffStatus::Deleted => ("Deleted", format_size(diff.old_size.unwrap_or(0)), "-".to_string()),
+                DiffStatus::TypeChanged => ("Type changed", format_size(diff.old_size.unwrap_or(0)), format_size(diff.new_size.unwrap_or(0))),
+                _ => ("Modified", format_size(diff.old_size.unwrap_or(0)), format_size(diff.new_size.unwrap_or(0))),
+            };
+            let reasons = diff.change_reasons();
+            let change = if reasons.is_empty() {
+                change.to_string()
+            } else {
+                // Highlighted so permission and ACL changes stand out among ordinary edits
+                let labels: Vec<String> = reasons.iter()
+                    .map(|reason| match *reason {
+                        "perm" | "xattrs" => style(reason).yellow().bold().to_string(),
+                        _ => reason.to_string(),
+                    })
+                    .collect();
+                format!("{} ({})", change, labels.join(", "))
+            };
+            vec![change, diff.path.clone(), old_size, new_size]
+        })
+        .collect();
+    create_table_with_header(vec!["Change", "Path", "Old Size", "New Size"], rows)
+}
+
+fn handle_export(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let history = drive_pulse_lib::get_scan_history(data_dir)?;
     if history.len() < 2 {
         return Err("Need at least 2 scans to compare.".to_string());
     }
@@ -547,70 +1524,870 @@ fn handle_export(matches: &clap::ArgMatches) -> Result<(), String> {
     let format = match matches.value_of("format") {
         Some(f) => f.to_lowercase(),
         None => {
-            let formats = vec!["json", "csv"];
+            let formats = EXPORT_FORMATS;
             let selection = Select::new()
                 .with_prompt("Select export format")
-                .items(&formats)
+                .items(formats)
                 .interact()
                 .map_err(|e| format!("Failed to get selection: {}", e))?;
             
             formats[selection].to_string()
         }
     };
+    if format == "sqlite" && !EXPORT_FORMATS.contains(&"sqlite") {
+        return Err("SQLite export needs drive-pulse-cli built with --features sqlite".to_string());
+    }
+    if !EXPORT_FORMATS.contains(&format.as_str()) {
+        return Err(format!("Unsupported format: {}", format));
+    }
+    let summary_depth = if matches.is_present("summary-table") {
+        if format != "json" && format != "csv" {
+            return Err("--summary-table can only be exported as json or csv".to_string());
+        }
+        Some(summary_table_depth(matches)?)
+    } else {
+        None
+    };
+    let script_options = if format == "script" {
+        let source_root = matches.value_of("source-root")
+            .ok_or("Script export needs --source-root, the directory holding the new versions of added files")?;
+        Some(ScriptOptions { source_root: source_root.to_string(), echo: matches.is_present("echo") })
+    } else {
+        None
+    };
 
     let output = match matches.value_of("output") {
         Some(o) => o.to_string(),
         None => {
             Input::new()
                 .with_prompt("Enter output file path")
-                .default(format!("comparison.{}", format))
+                .default(format!("comparison.{}", if format == "script" { "sh" } else { format.as_str() }))
                 .interact()
                 .map_err(|e| format!("Failed to get input: {}", e))?
         }
     };
 
-    println!("\n{} Comparing scans...\n", style("🔄").cyan());
-    let snapshot1 = drive_pulse_lib::load_snapshot(&scan1_id, None)?;
-    let snapshot2 = drive_pulse_lib::load_snapshot(&scan2_id, None)?;
-    let comparison = drive_pulse_lib::compare_snapshots(&snapshot1, &snapshot2);
+    // `-` writes the export to stdout, so keep everything else off it
+    let to_stdout = output == "-";
+    if to_stdout && format == "sqlite" {
+        return Err("SQLite exports need an output file".to_string());
+    }
+
+    if matches.is_present("include-unchanged") {
+        eprintln!("\n{} Including unchanged files: the export lists every file in both scans and can be very large.",
+            style("⚠").yellow());
+    }
+
+    if !to_stdout {
+        println!("\n{} Comparing scans...\n", style("🔄").cyan());
+    }
+    let snapshot1 = load_scan(data_dir, &scan1_id)?;
+    let snapshot2 = load_scan(data_dir, &scan2_id)?;
+    let options = diff_options(matches);
+    if options.match_by_content {
+        require_hashes(&snapshot1)?;
+        require_hashes(&snapshot2)?;
+    }
+    if options.compare_xattrs {
+        require_xattrs(&snapshot1)?;
+        require_xattrs(&snapshot2)?;
+    }
+    let mut comparison = drive_pulse_lib::compare_snapshots_with_options(&snapshot1, &snapshot2, &options);
+    keep_only_status(matches, &mut comparison);
+    if matches.is_present("group-by-status") {
+        comparison.sort_by_status();
+    }
     
-    println!("{} Exporting to {}...", style("💾").cyan(), style(&output).yellow());
+    if let Some(depth) = summary_depth {
+        let churn = drive_pulse_lib::aggregate_diffs_by_dir(&comparison, depth);
+        write_summary_table_export(&churn, &format, export_output(&output, std::io::stdout().lock())?)?;
+        if !to_stdout {
+            println!("{} Exported {} directories to {}", style("✓").green().bold(), churn.len(), style(&output).yellow());
+        }
+        return Ok(());
+    }
+
+    if !to_stdout {
+        println!("{} Exporting to {}...", style("💾").cyan(), style(&output).yellow());
+    }
+    let mut throttle = ProgressThrottle::default();
+    let progress = |written: usize, total: usize| {
+        if !to_stdout && throttle.ready(written, total) {
+            print!("\r{} Exporting... {}/{} entries", style("📝").cyan(), style(written).yellow().bold(), total);
+            use std::io::Write;
+            std::io::stdout().flush().unwrap();
+        }
+    };
+    if format == "sqlite" {
+        #[cfg(feature = "sqlite")]
+        write_sqlite_export(&comparison, Path::new(&output), progress)?;
+    } else if let Some(options) = &script_options {
+        write_script_export(&comparison, &snapshot1, &snapshot2, options, export_output(&output, std::io::stdout().lock())?, progress)?;
+    } else {
+        write_export(&comparison, &format, export_output(&output, std::io::stdout().lock())?, progress)?;
+    }
+    if !to_stdout {
+        println!();
+        println!("\n{} Exported successfully to {}", style("✓").green().bold(), style(&output).yellow());
+    }
     
-    match format.as_str() {
+    Ok(())
+}
+
+/// Where an export is written: `stdout` for an output path of `-`, otherwise
+/// a newly created file at `output`.
+fn export_output<'a, W: std::io::Write + 'a>(output: &str, stdout: W) -> Result<Box<dyn std::io::Write + 'a>, String> {
+    if output == "-" {
+        return Ok(Box::new(stdout));
+    }
+    let file = fs::File::create(output).map_err(|e| format!("Failed to create file: {}", e))?;
+    Ok(Box::new(std::io::BufWriter::new(file)))
+}
+
+#[cfg(feature = "sqlite")]
+const EXPORT_FORMATS: &[&str] = &["json", "csv", "script", "sqlite"];
+#[cfg(not(feature = "sqlite"))]
+const EXPORT_FORMATS: &[&str] = &["json", "csv", "script"];
+
+struct ScriptOptions {
+    /// Directory laid out like the scanned drive that holds the new files.
+    source_root: String,
+    /// Prefix each command with `echo` for a dry run.
+    echo: bool,
+}
+
+/// Quotes `value` for a POSIX shell. Anything beyond a conservative set of
+/// safe characters goes inside single quotes, with embedded quotes escaped.
+fn shell_quote(value: &str) -> String {
+    let safe = !value.is_empty()
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || "/._-+,:=@%".contains(c));
+    if safe {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+/// Writes a `sh` script of `rm`, `rmdir`, `mkdir` and `cp` commands that would
+/// turn the first scan's tree into the second's, copying new content from
+/// `options.source_root`. Removals come first, deepest paths first, then
+/// creations in path order so parents exist before their children. Directories
+/// only reported as modified need no command. The script is advisory: it
+/// reflects the scans, not the drive as it is now. `progress` gets
+/// `(commands_written, total_commands)`.
+fn write_script_export<W: std::io::Write, F: FnMut(usize, usize)>(
+    comparison: &ComparisonResult,
+    snapshot1: &Snapshot,
+    snapshot2: &Snapshot,
+    options: &ScriptOptions,
+    mut writer: W,
+    mut progress: F,
+) -> Result<(), String> {
+    let old_dirs: HashSet<&str> = snapshot1.files.iter().filter(|f| f.is_dir).map(|f| f.path.as_str()).collect();
+    let new_dirs: HashSet<&str> = snapshot2.files.iter().filter(|f| f.is_dir).map(|f| f.path.as_str()).collect();
+    let source_of = |path: &str| {
+        let relative = Path::new(path).strip_prefix(&snapshot2.drive_path).unwrap_or(Path::new(path));
+        Path::new(&options.source_root).join(relative).to_string_lossy().to_string()
+    };
+
+    let mut removals: Vec<(&str, String)> = Vec::new();
+    let mut creations: Vec<(&str, String)> = Vec::new();
+    for diff in &comparison.diffs {
+        let path = diff.path.as_str();
+        let removes = matches!(diff.status, DiffStatus::Deleted | DiffStatus::TypeChanged);
+        let creates = match diff.status {
+            DiffStatus::Added | DiffStatus::TypeChanged => true,
+            DiffStatus::Modified => !new_dirs.contains(path),
+            _ => false,
+        };
+        if removes {
+            let command = if diff.status == DiffStatus::TypeChanged && old_dirs.contains(path) {
+                format!("rm -rf -- {}", shell_quote(path))
+            } else if old_dirs.contains(path) {
+                format!("rmdir -- {}", shell_quote(path))
+            } else {
+                format!("rm -f -- {}", shell_quote(path))
+            };
+            removals.push((path, command));
+        }
+        if creates {
+            let command = if new_dirs.contains(path) {
+                format!("mkdir -p -- {}", shell_quote(path))
+            } else {
+                format!("cp -pP -- {} {}", shell_quote(&source_of(path)), shell_quote(path))
+            };
+            creations.push((path, command));
+        }
+    }
+    removals.sort_by(|a, b| b.0.cmp(a.0));
+    creations.sort_by(|a, b| a.0.cmp(b.0));
+
+    let write_error = |e: std::io::Error| format!("Failed to write script: {}", e);
+    writeln!(writer, "#!/bin/sh").map_err(write_error)?;
+    writeln!(writer, "# Advisory script generated by drive-pulse. Review it before running.").map_err(write_error)?;
+    writeln!(writer, "# Turns {} as scanned in {} into its state in {},",
+        comment_safe(&snapshot1.drive_path), comparison.snapshot1.id, comparison.snapshot2.id).map_err(write_error)?;
+    writeln!(writer, "# copying new content from {}.", comment_safe(&options.source_root)).map_err(write_error)?;
+    writeln!(writer, "set -e").map_err(write_error)?;
+    let prefix = if options.echo { "echo " } else { "" };
+    let total = removals.len() + creations.len();
+    progress(0, total);
+    for (written, (_, command)) in removals.iter().chain(creations.iter()).enumerate() {
+        writeln!(writer, "{}{}", prefix, command).map_err(write_error)?;
+        progress(written + 1, total);
+    }
+    writer.flush().map_err(write_error)
+}
+
+/// Keeps a path from ending a `#` comment line early.
+fn comment_safe(value: &str) -> String {
+    value.replace(['\n', '\r'], "?")
+}
+
+/// Writes a comparison to a new SQLite database at `path`: the two scans in
+/// `snapshots` and one row per diff in `diffs`, with the CSV export's columns.
+/// `progress` gets `(diffs_written, total_diffs)`.
+#[cfg(feature = "sqlite")]
+fn write_sqlite_export<F: FnMut(usize, usize)>(comparison: &ComparisonResult, path: &Path, mut progress: F) -> Result<(), String> {
+    // Start from an empty database rather than adding to an older export
+    if path.exists() {
+        fs::remove_file(path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))?;
+    }
+    let mut conn = rusqlite::Connection::open(path).map_err(|e| format!("Failed to create database: {}", e))?;
+    conn.execute_batch(
+        "CREATE TABLE snapshots (
+            role TEXT NOT NULL,
+            id TEXT NOT NULL,
+            drive_path TEXT NOT NULL,
+            timestamp INTEGER NOT NULL,
+            total_files INTEGER NOT NULL,
+            total_size INTEGER NOT NULL,
+            scan_duration INTEGER NOT NULL
+        );
+        CREATE TABLE diffs (
+            path TEXT NOT NULL,
+            status TEXT NOT NULL,
+            old_size INTEGER,
+            new_size INTEGER,
+            old_modified INTEGER,
+            new_modified INTEGER
+        );
+        CREATE INDEX diffs_status ON diffs (status);",
+    ).map_err(|e| format!("Failed to create tables: {}", e))?;
+
+    // One transaction, or SQLite syncs after every row
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    {
+        let mut insert = tx
+            .prepare("INSERT INTO snapshots VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")
+            .map_err(|e| format!("Failed to prepare insert: {}", e))?;
+        for (role, scan) in [("old", &comparison.snapshot1), ("new", &comparison.snapshot2)] {
+            insert.execute(rusqlite::params![
+                role,
+                scan.id,
+                scan.drive_path,
+                scan.timestamp,
+                scan.total_files as i64,
+                scan.total_size as i64,
+                scan.scan_duration as i64,
+            ]).map_err(|e| format!("Failed to write snapshot row: {}", e))?;
+        }
+        let mut insert = tx
+            .prepare("INSERT INTO diffs VALUES (?1, ?2, ?3, ?4, ?5, ?6)")
+            .map_err(|e| format!("Failed to prepare insert: {}", e))?;
+        let total = comparison.diffs.len();
+        progress(0, total);
+        for (written, diff) in comparison.diffs.iter().enumerate() {
+            insert.execute(rusqlite::params![
+                diff.path,
+                diff.status.as_str(),
+                diff.old_size.map(|s| s as i64),
+                diff.new_size.map(|s| s as i64),
+                diff.old_modified,
+                diff.new_modified,
+            ]).map_err(|e| format!("Failed to write diff row: {}", e))?;
+            progress(written + 1, total);
+        }
+    }
+    tx.commit().map_err(|e| format!("Failed to commit export: {}", e))
+}
+
+/// Renders a comparison as `json` or `csv` into `writer`. `progress` gets
+/// `(diffs_written, total_diffs)`; JSON is serialized in one go, so it only
+/// reports the start and the end.
+fn write_export<W: std::io::Write, F: FnMut(usize, usize)>(comparison: &ComparisonResult, format: &str, mut writer: W, mut progress: F) -> Result<(), String> {
+    let total = comparison.diffs.len();
+    progress(0, total);
+    match format {
         "json" => {
-            let json = serde_json::to_string_pretty(&comparison)
+            serde_json::to_writer_pretty(&mut writer, comparison)
                 .map_err(|e| format!("Failed to serialize: {}", e))?;
-            fs::write(&output, json)
-                .map_err(|e| format!("Failed to write file: {}", e))?;
+            writer.flush().map_err(|e| format!("Failed to write file: {}", e))?;
+            progress(total, total);
         },
         "csv" => {
-            let mut wtr = csv::Writer::from_path(&output)
-                .map_err(|e| format!("Failed to create CSV writer: {}", e))?;
+            let mut wtr = csv::Writer::from_writer(writer);
             
-            wtr.write_record(&["Path", "Status", "Old Size", "New Size", "Old Modified", "New Modified"])
+            wtr.write_record(["Path", "Status", "Old Size", "New Size", "Old Modified", "New Modified"])
                 .map_err(|e| format!("Failed to write CSV header: {}", e))?;
             
-            for diff in &comparison.diffs {
-                wtr.write_record(&[
+            for (written, diff) in comparison.diffs.iter().enumerate() {
+                wtr.write_record([
                     &diff.path,
-                    &format!("{:?}", diff.status),
+                    diff.status.as_str(),
                     &diff.old_size.map(|s: u64| s.to_string()).unwrap_or_default(),
                     &diff.new_size.map(|s: u64| s.to_string()).unwrap_or_default(),
                     &diff.old_modified.map(|m: i64| m.to_string()).unwrap_or_default(),
                     &diff.new_modified.map(|m: i64| m.to_string()).unwrap_or_default(),
                 ]).map_err(|e| format!("Failed to write CSV record: {}", e))?;
+                progress(written + 1, total);
             }
             
             wtr.flush().map_err(|e| format!("Failed to flush CSV: {}", e))?;
         },
         _ => return Err(format!("Unsupported format: {}", format)),
     }
-    
-    println!("\n{} Exported successfully to {}", style("✓").green().bold(), style(&output).yellow());
-    
     Ok(())
 }
 
+/// Writes the rows of `export --summary-table`, one per directory.
+fn write_summary_table_export<W: std::io::Write>(churn: &[DirectoryChurn], format: &str, mut writer: W) -> Result<(), String> {
+    match format {
+        "json" => {
+            serde_json::to_writer_pretty(&mut writer, churn)
+                .map_err(|e| format!("Failed to serialize: {}", e))?;
+            writer.flush().map_err(|e| format!("Failed to write file: {}", e))?;
+        },
+        "csv" => {
+            let mut wtr = csv::Writer::from_writer(writer);
+            wtr.write_record([
+                "Directory", "Added", "Added Bytes", "Deleted", "Deleted Bytes", "Modified", "Type Changed",
+                "Modified Bytes Delta", "Size Delta",
+            ]).map_err(|e| format!("Failed to write CSV header: {}", e))?;
+            for dir in churn {
+                wtr.write_record([
+                    dir.directory.clone(),
+                    dir.added_count.to_string(),
+                    dir.added_bytes.to_string(),
+                    dir.deleted_count.to_string(),
+                    dir.deleted_bytes.to_string(),
+                    dir.modified_count.to_string(),
+                    dir.type_changed_count.to_string(),
+                    dir.modified_bytes_delta.to_string(),
+                    dir.size_delta().to_string(),
+                ]).map_err(|e| format!("Failed to write CSV record: {}", e))?;
+            }
+            wtr.flush().map_err(|e| format!("Failed to flush CSV: {}", e))?;
+        },
+        _ => return Err(format!("Unsupported format: {}", format)),
+    }
+    Ok(())
+}
+
+fn handle_verify_manifest(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let scan_id = matches.value_of("scan_id").ok_or("Scan ID is required")?;
+    let manifest_path = matches.value_of("manifest").ok_or("Manifest file is required")?;
+    let manifest = fs::read_to_string(manifest_path)
+        .map_err(|e| format!("Failed to read manifest: {}", e))?;
+    let snapshot = load_scan(data_dir, scan_id)?;
+    let report = drive_pulse_lib::verify_manifest(&snapshot, &manifest)?;
+
+    println!("\n{} Manifest Verification\n", style("🔐").cyan().bold());
+    let rows = vec![
+        vec![style("Matched").green().to_string(), format!("{}", report.matched.len())],
+        vec![style("Mismatched").red().to_string(), format!("{}", report.mismatched.len())],
+        vec![style("Missing from snapshot").yellow().to_string(), format!("{}", report.missing_from_snapshot.len())],
+        vec![style("Extra in snapshot").yellow().to_string(), format!("{}", report.extra_in_snapshot.len())],
+    ];
+    println!("{}\n", create_table_with_header(vec!["Result", "Files"], rows));
+
+    for (title, paths) in [
+        ("Mismatched", &report.mismatched),
+        ("Missing from snapshot", &report.missing_from_snapshot),
+        ("Extra in snapshot", &report.extra_in_snapshot),
+    ] {
+        if paths.is_empty() {
+            continue;
+        }
+        println!("{} (showing first 50)", style(title).bold());
+        for path in paths.iter().take(50) {
+            println!("  {}", path);
+        }
+        if paths.len() > 50 {
+            println!("  {} {} more not shown", style("...").dim(), paths.len() - 50);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn handle_verify(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let scan_id = matches.value_of("scan_id").ok_or("Scan ID is required")?;
+    let snapshot = load_scan(data_dir, scan_id)?;
+    // Ctrl-C simply ends the process; the state written so far lets --resume continue
+    let cancel = AtomicBool::new(false);
+
+    println!("\n{} Verifying file contents of {}\n", style("🔐").cyan().bold(), style(scan_id).yellow());
+    let mut throttle = ProgressThrottle::default();
+    let report = drive_pulse_lib::verify_snapshot_hashes(data_dir, &snapshot, matches.is_present("resume"), &cancel, |checked, total| {
+        if throttle.ready(checked, total) {
+            print!("\r{} Verifying... {}/{} files", style("🔍").cyan(), style(checked).yellow().bold(), total);
+            use std::io::Write;
+            std::io::stdout().flush().unwrap();
+        }
+    })?;
+    println!();
+    println!();
+
+    let rows = vec![
+        vec![style("Matched").green().to_string(), format!("{}", report.matched)],
+        vec![style("Mismatched").red().to_string(), format!("{}", report.mismatched.len())],
+        vec![style("Missing").yellow().to_string(), format!("{}", report.missing.len())],
+        vec![style("Unreadable").yellow().to_string(), format!("{}", report.unreadable.len())],
+    ];
+    println!("{}\n", create_table_with_header(vec!["Result", "Files"], rows));
+    if report.resumed > 0 {
+        println!("{} {} file(s) were checked by the previous run\n", style("ℹ").blue(), report.resumed);
+    }
+
+    for (title, paths) in [
+        ("Mismatched", &report.mismatched),
+        ("Missing", &report.missing),
+        ("Unreadable", &report.unreadable),
+    ] {
+        if paths.is_empty() {
+            continue;
+        }
+        println!("{} (showing first 50)", style(title).bold());
+        for path in paths.iter().take(50) {
+            println!("  {}", path);
+        }
+        if paths.len() > 50 {
+            println!("  {} {} more not shown", style("...").dim(), paths.len() - 50);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn handle_recent(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let count: usize = match matches.value_of("count") {
+        Some(c) => c.parse().map_err(|_| format!("Invalid count: {}", c))?,
+        None => 20,
+    };
+    let scan_id = match matches.value_of("scan_id") {
+        Some(id) => id.to_string(),
+        None => select_scan(data_dir, "Select a scan")?,
+    };
+    let snapshot = load_scan(data_dir, &scan_id)?;
+    let recent = drive_pulse_lib::recent_files(&snapshot, count, matches.is_present("include-dirs"));
+
+    println!("\n{} Most Recently Modified ({})\n", style("🕒").cyan().bold(), recent.len());
+    let rows = recent.iter()
+        .map(|file| vec![format_timestamp(file.modified), file.path.clone(), format_size(file.size)])
+        .collect();
+    println!("{}", create_table_with_header(vec!["Modified", "Path", "Size"], rows));
+
+    Ok(())
+}
+
+fn handle_stats(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let scan_id = match matches.value_of("scan_id") {
+        Some(id) => id.to_string(),
+        None => select_scan(data_dir, "Select a scan")?,
+    };
+    let snapshot = load_scan(data_dir, &scan_id)?;
+
+    let other_id = match matches.value_of("compare") {
+        Some(id) => id,
+        None => {
+            println!("\n{} File sizes in {}\n", style("📊").cyan().bold(), style(&scan_id).yellow());
+            let rows = drive_pulse_lib::size_histogram(&snapshot.files)
+                .into_iter()
+                .map(|bucket| vec![bucket.label, format!("{}", bucket.count), format_size(bucket.total_size)])
+                .collect();
+            println!("{}", create_table_with_header(vec!["Size", "Files", "Total"], rows));
+            if let Some(files_per_sec) = snapshot.files_per_sec {
+                println!("\nScanned at {}.", format_throughput(files_per_sec));
+            }
+            return Ok(());
+        }
+    };
+
+    let other = load_scan(data_dir, other_id)?;
+    println!(
+        "\n{} File sizes in {} and {}\n",
+        style("📊").cyan().bold(),
+        style(&scan_id).yellow(),
+        style(other_id).yellow()
+    );
+    let rows = drive_pulse_lib::compare_size_histograms(&snapshot.files, &other.files)
+        .into_iter()
+        .map(|bucket| {
+            vec![
+                bucket.label.clone(),
+                format!("{}", bucket.old_count),
+                format!("{}", bucket.new_count),
+                format!("{:+}", bucket.count_delta()),
+                format_size(bucket.old_total_size),
+                format_size(bucket.new_total_size),
+                format_size_delta(bucket.size_delta()),
+            ]
+        })
+        .collect();
+    println!(
+        "{}",
+        create_table_with_header(vec!["Size", "Files (1)", "Files (2)", "Change", "Total (1)", "Total (2)", "Change"], rows)
+    );
+    if let (Some(before), Some(after)) = (snapshot.files_per_sec, other.files_per_sec) {
+        println!(
+            "\nScan speed: {} -> {} ({:+.0}%)",
+            format_throughput(before),
+            format_throughput(after),
+            if before > 0.0 { (after / before - 1.0) * 100.0 } else { 0.0 }
+        );
+    }
+
+    Ok(())
+}
+
+fn select_scan(data_dir: &Path, prompt: &str) -> Result<String, String> {
+    let history = drive_pulse_lib::get_scan_history(data_dir)?;
+    if history.is_empty() {
+        return Err("No scans found.".to_string());
+    }
+    let items: Vec<String> = history.iter()
+        .map(|s| format!("{} - {} ({})", s.id, s.drive_path, format_timestamp(s.timestamp)))
+        .collect();
+    let selection = Select::new()
+        .with_prompt(prompt)
+        .items(&items)
+        .interact()
+        .map_err(|e| format!("Failed to get selection: {}", e))?;
+    Ok(history[selection].id.clone())
+}
+
+fn handle_delete(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let scan_id = matches.value_of("scan_id");
+    let drive = matches.value_of("drive");
+    let older_than = matches.value_of("older-than").map(|v| parse_date_bound(v, false)).transpose()?;
+    let duplicates = matches.is_present("duplicates");
+    if scan_id.is_none() && drive.is_none() && older_than.is_none() && !duplicates {
+        return Err("Specify a scan ID or at least one of --drive / --older-than / --duplicates".to_string());
+    }
+    let redundant: Option<HashSet<String>> = if duplicates {
+        let keep_newest = matches.value_of("keep") == Some("newest");
+        Some(redundant_duplicates(drive_pulse_lib::find_duplicate_snapshots(data_dir)?, keep_newest))
+    } else {
+        None
+    };
+    let predicate = |scan: &SnapshotSummary| {
+        scan_id.is_none_or(|id| scan.id == id)
+            && drive.is_none_or(|drive| scan.drive_path == drive)
+            && older_than.is_none_or(|before| scan.timestamp < before)
+            && redundant.as_ref().is_none_or(|ids| ids.contains(&scan.id))
+    };
+
+    let force = matches.is_present("force");
+    let preview = drive_pulse_lib::preview_delete_snapshots_where(data_dir, predicate, force)?;
+    let (targets, locked) = (&preview.snapshots, &preview.locked);
+    if !locked.is_empty() {
+        println!(
+            "\n{} Skipping {} locked scan(s): {} (use --force to delete them)",
+            style("🔒").yellow(),
+            locked.len(),
+            locked.iter().map(|scan| scan.id.as_str()).collect::<Vec<_>>().join(", ")
+        );
+    }
+    if targets.is_empty() {
+        println!("\n{} No scans match.", style("ℹ").blue());
+        return Ok(());
+    }
+
+    println!("\n{} {} scan(s) selected for deletion\n", style("🗑").red(), targets.len());
+    println!("{}\n", history_table(targets));
+    println!("{} Deleting them frees {}\n", style("💾").cyan(), format_size(preview.bytes));
+
+    if matches.is_present("dry-run") {
+        println!("{} Dry run, nothing was deleted.", style("ℹ").blue());
+        return Ok(());
+    }
+
+    if !matches.is_present("yes") {
+        let confirmed = Confirm::new()
+            .with_prompt(format!("Delete {} scan(s)?", targets.len()))
+            .interact()
+            .map_err(|e| format!("Failed to get confirmation: {}", e))?;
+        if !confirmed {
+            return Err("Cancelled by user".to_string());
+        }
+    }
+
+    let deleted = drive_pulse_lib::delete_snapshots_where(data_dir, predicate, force)?;
+    println!("{} Deleted {} scan(s)", style("✓").green().bold(), deleted.len());
+
+    Ok(())
+}
+
+/// Every scan of each duplicate group (sorted oldest first) except the one
+/// being kept.
+fn redundant_duplicates(groups: Vec<Vec<SnapshotSummary>>, keep_newest: bool) -> HashSet<String> {
+    groups
+        .into_iter()
+        .flat_map(|mut group| {
+            if keep_newest { group.pop(); } else { group.remove(0); }
+            group.into_iter().map(|scan| scan.id)
+        })
+        .collect()
+}
+
+fn handle_lock(data_dir: &Path, matches: &clap::ArgMatches, locked: bool) -> Result<(), String> {
+    let scan_id = matches.value_of("scan_id").unwrap();
+    drive_pulse_lib::set_snapshot_locked(data_dir, scan_id, locked)?;
+    if locked {
+        println!("{} {} locked; delete and prune will skip it", style("🔒").green(), scan_id);
+    } else {
+        println!("{} {} unlocked", style("✓").green(), scan_id);
+    }
+    Ok(())
+}
+
+fn handle_migrate(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let scan_ids: Vec<String> = match matches.value_of("scan_id") {
+        Some(id) => vec![id.to_string()],
+        None => drive_pulse_lib::stored_snapshot_ids(data_dir)?,
+    };
+
+    let mut migrated = 0;
+    for id in &scan_ids {
+        let password = if drive_pulse_lib::is_snapshot_encrypted(data_dir, id)? { Some(snapshot_password()?) } else { None };
+        match drive_pulse_lib::migrate_snapshot(data_dir, id, password.as_deref()) {
+            Ok(Some(version)) => {
+                println!("{} {} upgraded from format version {}", style("✓").green(), id, version);
+                migrated += 1;
+            }
+            Ok(None) => {}
+            Err(e) => println!("{} {} skipped: {}", style("⚠").yellow(), id, e),
+        }
+    }
+    println!(
+        "\n{} {} of {} scan(s) migrated to format version {}",
+        style("✓").green().bold(),
+        migrated,
+        scan_ids.len(),
+        drive_pulse_lib::SNAPSHOT_FORMAT_VERSION
+    );
+
+    Ok(())
+}
+
+fn handle_compact(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let scan_ids: Vec<String> = match matches.value_of("scan_id") {
+        Some(id) => vec![id.to_string()],
+        None => drive_pulse_lib::stored_snapshot_ids(data_dir)?,
+    };
+
+    let mut compacted = 0;
+    let mut saved: i64 = 0;
+    for id in &scan_ids {
+        let password = if drive_pulse_lib::is_snapshot_encrypted(data_dir, id)? { Some(snapshot_password()?) } else { None };
+        match drive_pulse_lib::compact_snapshot(data_dir, id, password.as_deref()) {
+            Ok(Some(report)) => {
+                println!(
+                    "{} {} {} -> {} ({})",
+                    style("✓").green(),
+                    id,
+                    format_size(report.bytes_before),
+                    format_size(report.bytes_after),
+                    format_size_delta(-report.bytes_saved())
+                );
+                compacted += 1;
+                saved += report.bytes_saved();
+            }
+            Ok(None) => {}
+            Err(e) => println!("{} {} skipped: {}", style("⚠").yellow(), id, e),
+        }
+    }
+    println!(
+        "\n{} {} of {} scan(s) compacted, {} saved",
+        style("✓").green().bold(),
+        compacted,
+        scan_ids.len(),
+        format_size(saved.max(0) as u64)
+    );
+
+    Ok(())
+}
+
+fn handle_extract(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let scan_id = matches.value_of("scan_id").ok_or("Scan ID is required")?;
+    let path = matches.value_of("path").ok_or("Path is required")?;
+    let snapshot = load_scan(data_dir, scan_id)?;
+    let subset = drive_pulse_lib::subset_snapshot(&snapshot, path);
+    if subset.files.is_empty() {
+        return Err(format!("No entries of scan {} are under {}", scan_id, subset.drive_path));
+    }
+
+    // Stored the way the source scan is, so an encrypted scan stays encrypted
+    let password = if drive_pulse_lib::is_snapshot_encrypted(data_dir, scan_id)? { Some(snapshot_password()?) } else { None };
+    let format = if password.is_some() {
+        SnapshotFormat::Encrypted
+    } else if drive_pulse_lib::snapshot_file_path(data_dir, scan_id)?.extension().is_some_and(|ext| ext == "json") {
+        SnapshotFormat::Json
+    } else {
+        SnapshotFormat::Binary
+    };
+    drive_pulse_lib::save_scan(data_dir, &subset, format, password.as_deref(), true, |_, _| {})?;
+
+    println!(
+        "{} Extracted {} ({} files, {}) from {} as {}",
+        style("✓").green().bold(),
+        style(&subset.drive_path).yellow(),
+        subset.total_files,
+        format_size(subset.total_size),
+        scan_id,
+        style(&subset.id).cyan()
+    );
+
+    Ok(())
+}
+
+fn handle_find_hash(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let hash = matches.value_of("hash").ok_or("Hash is required")?.trim();
+    if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{} is not a hex digest", hash));
+    }
+    let max_results = parse_max_results(matches)?;
+    let history = drive_pulse_lib::get_scan_history(data_dir)?;
+    let password = if history.iter().any(|scan| scan.encrypted) { Some(snapshot_password()?) } else { None };
+    let result = drive_pulse_lib::find_hash(data_dir, hash, password.as_deref())?;
+
+    if result.found.is_empty() {
+        println!("\n{} No scan recorded a file with hash {}", style("ℹ").blue(), hash);
+    } else {
+        println!("\n{} Scans with hash {}\n", style("🔎").cyan().bold(), style(hash).yellow());
+        let mut rows = Vec::new();
+        for (id, paths) in &result.found {
+            let scan = history.iter().find(|scan| &scan.id == id);
+            let date = scan.map(|scan| format_timestamp(scan.timestamp)).unwrap_or_default();
+            let drive = scan.map(|scan| scan.drive_path.clone()).unwrap_or_default();
+            for path in paths {
+                rows.push(vec![id.clone(), drive.clone(), date.clone(), path.clone()]);
+            }
+        }
+        let (rows, total) = take_results(rows.into_iter(), max_results);
+        let shown = rows.len();
+        println!("{}", create_table_with_header(vec!["Scan ID", "Drive Path", "Date", "Path"], rows));
+        print_truncation_note(shown, total);
+    }
+    if !result.unhashed.is_empty() {
+        eprintln!("\n{} {} scan(s) were taken without --hash and could not be searched: {}",
+            style("⚠").yellow(), result.unhashed.len(), result.unhashed.join(", "));
+    }
+    for (id, e) in &result.skipped {
+        eprintln!("{} {} skipped: {}", style("⚠").yellow(), id, e);
+    }
+
+    Ok(())
+}
+
+fn handle_log(data_dir: &Path, matches: &clap::ArgMatches) -> Result<(), String> {
+    let drive_path = matches.value_of("drive_path").ok_or("Drive path is required")?;
+    let latest = drive_pulse_lib::latest_snapshot_for_drive(data_dir, drive_path)?
+        .ok_or_else(|| format!("No scans found for {}", drive_path))?;
+    let lineage = drive_pulse_lib::snapshot_lineage(data_dir, &latest.id)?;
+
+    println!("\n{} Scan history of {}\n", style("📜").cyan().bold(), style(drive_path).yellow());
+    println!("{}", history_table(&lineage));
+
+    Ok(())
+}
+
+fn handle_info(data_dir: &Path) -> Result<(), String> {
+    let stats = drive_pulse_lib::data_dir_stats(data_dir)?;
+    let date = |timestamp: Option<i64>| timestamp.map(format_timestamp).unwrap_or_else(|| "-".to_string());
+
+    println!("\n{} Drive Pulse Data\n", style("🗄").cyan().bold());
+    let rows = vec![
+        vec![style("Data Directory").cyan().bold().to_string(), stats.data_dir.clone()],
+        vec![style("Snapshots").cyan().bold().to_string(), format!("{}", stats.snapshot_count)],
+        vec![style("Encrypted").cyan().bold().to_string(), format!("{}", stats.encrypted_count)],
+        vec![style("Plaintext").cyan().bold().to_string(), format!("{}", stats.plaintext_count)],
+        vec![style("Snapshot Files").cyan().bold().to_string(), format_size(stats.snapshot_bytes)],
+        vec![style("Metadata Files").cyan().bold().to_string(), format_size(stats.metadata_bytes)],
+        vec![style("Total Used").cyan().bold().to_string(), format_size(stats.snapshot_bytes + stats.metadata_bytes)],
+        vec![style("Oldest Scan").cyan().bold().to_string(), date(stats.oldest_scan)],
+        vec![style("Newest Scan").cyan().bold().to_string(), date(stats.newest_scan)],
+    ];
+    println!("{}", create_table_with_rows(rows));
+
+    Ok(())
+}
+
+const PASSWORD_ENV: &str = "DRIVE_PULSE_PASSWORD";
+
+static PASSWORD_FROM_STDIN: OnceLock<bool> = OnceLock::new();
+static PASSWORD: OnceLock<String> = OnceLock::new();
+
+/// Password for encrypted snapshots, taken from `DRIVE_PULSE_PASSWORD`, then
+/// `--password-stdin`, then an interactive prompt. It is never accepted as an
+/// argument so it stays out of process listings. Resolved once per run since
+/// stdin can only be read once.
+fn snapshot_password() -> Result<String, String> {
+    if let Some(password) = PASSWORD.get() {
+        return Ok(password.clone());
+    }
+    let env = std::env::var(PASSWORD_ENV).ok();
+    let supplied = if PASSWORD_FROM_STDIN.get().copied().unwrap_or(false) {
+        password_from_sources(env, Some(&mut std::io::stdin().lock()))?
+    } else {
+        password_from_sources(env, None)?
+    };
+    let password = if let Some(password) = supplied {
+        password
+    } else if !std::io::stdin().is_terminal() {
+        return Err(format!("A password is required: set {} or use --password-stdin", PASSWORD_ENV));
+    } else {
+        Password::new()
+            .with_prompt("Snapshot password")
+            .interact()
+            .map_err(|e| format!("Failed to read password: {}", e))?
+    };
+    if password.is_empty() {
+        return Err("Password must not be empty".to_string());
+    }
+    Ok(PASSWORD.get_or_init(|| password).clone())
+}
+
+/// The password from the environment variable (`env`), or else the first line
+/// of `stdin` when `--password-stdin` was given. `None` leaves it to a prompt.
+fn password_from_sources(env: Option<String>, stdin: Option<&mut dyn std::io::BufRead>) -> Result<Option<String>, String> {
+    if let Some(password) = env {
+        return Ok(Some(password));
+    }
+    let Some(stdin) = stdin else {
+        return Ok(None);
+    };
+    let mut line = String::new();
+    stdin
+        .read_line(&mut line)
+        .map_err(|e| format!("Failed to read password from stdin: {}", e))?;
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// Loads a scan, asking for the password only when it is encrypted.
+fn load_scan(data_dir: &Path, scan_id: &str) -> Result<Snapshot, String> {
+    if let Some(warning) = drive_pulse_lib::snapshot_extension_mismatch(data_dir, scan_id)? {
+        eprintln!("{} {}", style("⚠").yellow(), warning);
+    }
+    match drive_pulse_lib::load_snapshot(data_dir, scan_id, None) {
+        Err(e) if e == drive_pulse_lib::PASSWORD_REQUIRED => drive_pulse_lib::load_snapshot(data_dir, scan_id, Some(&snapshot_password()?)),
+        result => result,
+    }
+}
+
 fn format_size(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
@@ -630,7 +2407,184 @@ fn format_size(bytes: u64) -> String {
     }
 }
 
-fn handle_interactive() -> Result<(), String> {
+/// Parses a `--since`/`--until` value into a unix timestamp. Relative values
+/// (`30m`, `12h`, `7d`, `2w`) count back from now. A plain `YYYY-MM-DD` date is
+/// midnight in the `--timezone` zone, or the following midnight when
+/// `end_of_day` is set so that `--until` includes the named day.
+fn parse_date_bound(value: &str, end_of_day: bool) -> Result<i64, String> {
+    let value = value.trim();
+    if let Some(seconds) = parse_relative_duration(value) {
+        return Ok(Local::now().timestamp() - seconds);
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Ok(dt.timestamp());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let date = if end_of_day { date.succ_opt().unwrap_or(date) } else { date };
+        return date.and_hms_opt(0, 0, 0)
+            .and_then(|naive| display_timezone().timestamp_of(naive))
+            .ok_or_else(|| format!("Invalid date: {}", value));
+    }
+    Err(format!("Invalid date '{}': expected YYYY-MM-DD, RFC 3339 or a relative value like 7d", value))
+}
+
+fn parse_relative_duration(value: &str) -> Option<i64> {
+    let unit = value.chars().last()?;
+    let amount: i64 = value[..value.len() - unit.len_utf8()].parse().ok()?;
+    let seconds = match unit {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(amount * seconds)
+}
+
+/// Parses a size like `500`, `64K`, `500M`, `1.5G` or `2TB` into bytes, using
+/// the same 1024-based units as `format_size`.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let upper = value.to_uppercase();
+    let number = upper.trim_end_matches('B');
+    let (number, multiplier) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1024u64),
+        Some('M') => (&number[..number.len() - 1], 1024 * 1024),
+        Some('G') => (&number[..number.len() - 1], 1024 * 1024 * 1024),
+        Some('T') => (&number[..number.len() - 1], 1024 * 1024 * 1024 * 1024),
+        _ => (number, 1),
+    };
+    number.trim().parse::<f64>()
+        .ok()
+        .filter(|n| *n >= 0.0)
+        .map(|n| (n * multiplier as f64) as u64)
+        .ok_or_else(|| format!("Invalid size '{}': expected bytes or a value like 500M or 4G", value))
+}
+
+/// Keeps scans with `since <= timestamp < until`.
+fn filter_by_date(history: Vec<SnapshotSummary>, since: Option<i64>, until: Option<i64>) -> Vec<SnapshotSummary> {
+    history.into_iter()
+        .filter(|scan| since.is_none_or(|since| scan.timestamp >= since))
+        .filter(|scan| until.is_none_or(|until| scan.timestamp < until))
+        .collect()
+}
+
+/// Scans of `drive` itself or of a folder below it. Whole path components
+/// are compared, so `/mnt/data` doesn't match `/mnt/database`.
+fn filter_by_drive(history: Vec<SnapshotSummary>, drive: &str) -> Vec<SnapshotSummary> {
+    history.into_iter()
+        .filter(|scan| Path::new(&scan.drive_path).starts_with(drive))
+        .collect()
+}
+
+/// `history` split by drive path, sorted by path. Each group keeps the
+/// history's order.
+fn group_by_drive(history: Vec<SnapshotSummary>) -> BTreeMap<String, Vec<SnapshotSummary>> {
+    let mut groups: BTreeMap<String, Vec<SnapshotSummary>> = BTreeMap::new();
+    for scan in history {
+        groups.entry(scan.drive_path.clone()).or_default().push(scan);
+    }
+    groups
+}
+
+/// `format_size` with a `+` or `-` sign.
+fn format_size_delta(delta: i64) -> String {
+    format!("{}{}", if delta < 0 { "-" } else { "+" }, format_size(delta.unsigned_abs()))
+}
+
+fn format_throughput(files_per_sec: f64) -> String {
+    format!("{:.0} files/s", files_per_sec)
+}
+
+/// Throughput, peak memory and thread rows for a scan's details, as far as recorded.
+fn performance_rows(snapshot: &Snapshot) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    if let Some(files_per_sec) = snapshot.files_per_sec {
+        rows.push(vec![style("Throughput").cyan().bold().to_string(), format_throughput(files_per_sec)]);
+    }
+    if let Some(bytes) = snapshot.peak_memory_bytes {
+        rows.push(vec![style("Peak Memory").cyan().bold().to_string(), format_size(bytes)]);
+    }
+    if let Some(threads) = snapshot.threads {
+        rows.push(vec![style("Threads").cyan().bold().to_string(), threads.to_string()]);
+    }
+    rows
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    display_timezone().format(timestamp)
+}
+
+const TIMEZONE_ENV: &str = "DRIVE_PULSE_TIMEZONE";
+
+/// Zone that dates are shown in and plain `--since`/`--until` dates are read in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DisplayTimezone {
+    Local,
+    Utc,
+    Fixed(FixedOffset),
+}
+
+impl DisplayTimezone {
+    /// Accepts `local`, `utc` (or `z`) and offsets like `+05:30`, `-0800` or `+2`.
+    fn parse(value: &str) -> Result<DisplayTimezone, String> {
+        let invalid = || format!("Invalid timezone '{}': expected local, utc or an offset like +05:30", value);
+        match value.trim().to_lowercase().as_str() {
+            "local" => return Ok(DisplayTimezone::Local),
+            "utc" | "z" => return Ok(DisplayTimezone::Utc),
+            _ => {}
+        }
+        let value = value.trim();
+        let sign = match value.chars().next() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => return Err(invalid()),
+        };
+        let digits = value[1..].replace(':', "");
+        let (hours, minutes) = match digits.len() {
+            1 | 2 => (digits.as_str(), "0"),
+            4 => digits.split_at(2),
+            _ => return Err(invalid()),
+        };
+        let hours: i32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+        if minutes >= 60 {
+            return Err(invalid());
+        }
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .map(DisplayTimezone::Fixed)
+            .ok_or_else(invalid)
+    }
+
+    fn format(self, timestamp: i64) -> String {
+        const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+        DateTime::from_timestamp(timestamp, 0)
+            .map(|dt| match self {
+                DisplayTimezone::Local => dt.with_timezone(&Local).format(FORMAT).to_string(),
+                DisplayTimezone::Utc => dt.format(FORMAT).to_string(),
+                DisplayTimezone::Fixed(offset) => dt.with_timezone(&offset).format(FORMAT).to_string(),
+            })
+            .unwrap_or_else(|| "Unknown".to_string())
+    }
+
+    /// Unix timestamp of a wall-clock time in this zone, taking the earlier
+    /// instant when a local clock change makes it ambiguous.
+    fn timestamp_of(self, naive: NaiveDateTime) -> Option<i64> {
+        match self {
+            DisplayTimezone::Local => Local.from_local_datetime(&naive).earliest().map(|dt| dt.timestamp()),
+            DisplayTimezone::Utc => Some(Utc.from_utc_datetime(&naive).timestamp()),
+            DisplayTimezone::Fixed(offset) => offset.from_local_datetime(&naive).earliest().map(|dt| dt.timestamp()),
+        }
+    }
+}
+
+static DISPLAY_TIMEZONE: OnceLock<DisplayTimezone> = OnceLock::new();
+
+fn display_timezone() -> DisplayTimezone {
+    DISPLAY_TIMEZONE.get().copied().unwrap_or(DisplayTimezone::Local)
+}
+
+fn handle_interactive(data_dir: &Path) -> Result<(), String> {
     println!("\n{}\n", style("Drive Pulse CLI").cyan().bold().underlined());
     
     loop {
@@ -650,11 +2604,11 @@ fn handle_interactive() -> Result<(), String> {
             .map_err(|e| format!("Failed to get selection: {}", e))?;
         
         let result = match selection {
-            0 => handle_scan(&clap::ArgMatches::default()),
-            1 => handle_list(),
-            2 => handle_view(&clap::ArgMatches::default()),
-            3 => handle_compare(&clap::ArgMatches::default()),
-            4 => handle_export(&clap::ArgMatches::default()),
+            0 => handle_scan(data_dir, &clap::ArgMatches::default()),
+            1 => handle_list(data_dir, &clap::ArgMatches::default()),
+            2 => handle_view(data_dir, &clap::ArgMatches::default()),
+            3 => handle_compare(data_dir, &clap::ArgMatches::default()),
+            4 => handle_export(data_dir, &clap::ArgMatches::default()),
             5 => {
                 println!("\n{} Goodbye!\n", style("👋").cyan());
                 return Ok(());
@@ -671,7 +2625,7 @@ fn handle_interactive() -> Result<(), String> {
 }
 
 fn create_table_with_rows(rows: Vec<Vec<String>>) -> Table {
-    let mut table = Table::new();
+    let mut table = new_table();
     for row in rows {
         table.add_row(Row::new(row.iter().map(|s| Cell::new(s)).collect()));
     }
@@ -679,11 +2633,680 @@ fn create_table_with_rows(rows: Vec<Vec<String>>) -> Table {
 }
 
 // Ensure all header strings are wrapped in `Cell::new`
-fn create_table_with_header(header: Vec<&str>, rows: Vec<Vec<&str>>) -> Table {
-    let mut table = Table::new();
+fn create_table_with_header(header: Vec<&str>, rows: Vec<Vec<String>>) -> Table {
+    let mut table = new_table();
     table.add_row(Row::new(header.into_iter().map(Cell::new).collect()));
     for row in rows {
-        table.add_row(Row::new(row.into_iter().map(Cell::new).collect()));
+        table.add_row(Row::new(row.iter().map(|s| Cell::new(s)).collect()));
+    }
+    table
+}
+
+fn history_table(history: &[SnapshotSummary]) -> Table {
+    let rows = history.iter()
+        .map(|scan| {
+            vec![
+                scan.id.clone(),
+                match scan.drive_present {
+                    Some(false) => format!("{} {}", scan.drive_path, style("(missing)").red()),
+                    _ => scan.drive_path.clone(),
+                },
+                format_timestamp(scan.timestamp),
+                format!("{}", scan.total_files),
+                format_size(scan.total_size),
+                scan.files_per_sec.map(format_throughput).unwrap_or_default(),
+                if scan.encrypted { "🔒 yes".to_string() } else { String::new() },
+                storage_label(scan),
+                if scan.locked { "yes".to_string() } else { String::new() },
+            ]
+        })
+        .collect();
+    create_table_with_header(vec!["ID", "Drive Path", "Date", "Files", "Size", "Speed", "Encrypted", "Format", "Locked"], rows)
+}
+
+/// Format version and compression of a scan, as far as its metadata knows.
+fn storage_label(scan: &SnapshotSummary) -> String {
+    let version = match scan.format_version {
+        Some(version) => format!("v{}", version),
+        None => "?".to_string(),
+    };
+    if scan.compressed { format!("{} compressed", version) } else { version }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TableStyle {
+    Full,
+    Compact,
+    Plain,
+}
+
+impl TableStyle {
+    fn parse(value: &str) -> Result<TableStyle, String> {
+        match value.to_lowercase().as_str() {
+            "full" => Ok(TableStyle::Full),
+            "compact" => Ok(TableStyle::Compact),
+            "plain" => Ok(TableStyle::Plain),
+            other => Err(format!("Unsupported table style: {}", other)),
+        }
     }
+
+    fn format(self) -> TableFormat {
+        match self {
+            TableStyle::Full => *format::consts::FORMAT_DEFAULT,
+            TableStyle::Compact => *format::consts::FORMAT_CLEAN,
+            // Tab-separated and unpadded so output can be cut/grepped
+            TableStyle::Plain => format::FormatBuilder::new()
+                .column_separator('\t')
+                .padding(0, 0)
+                .build(),
+        }
+    }
+}
+
+static TABLE_STYLE: OnceLock<TableStyle> = OnceLock::new();
+
+/// Every CLI table is created here so the `--table-style` setting applies everywhere.
+fn new_table() -> Table {
+    styled_table(TABLE_STYLE.get().copied().unwrap_or(TableStyle::Full))
+}
+
+fn styled_table(style: TableStyle) -> Table {
+    let mut table = Table::new();
+    table.set_format(style.format());
     table
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(style: TableStyle) -> String {
+        let mut table = styled_table(style);
+        table.add_row(Row::new(vec![Cell::new("ID"), Cell::new("Path")]));
+        table.add_row(Row::new(vec![Cell::new("abc"), Cell::new("/data/a b")]));
+        table.to_string()
+    }
+
+    #[test]
+    fn table_builder_honors_the_style() {
+        let full = render(TableStyle::Full);
+        assert!(full.contains("+-----+"), "{}", full);
+        assert!(full.contains("| abc |"), "{}", full);
+
+        let compact = render(TableStyle::Compact);
+        assert!(!compact.contains('|') && !compact.contains('+'), "{}", compact);
+        assert!(compact.contains("abc"));
+
+        // Columns stay aligned, so fields are padded up to the tab
+        let plain = render(TableStyle::Plain);
+        let rows: Vec<Vec<&str>> = plain
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.split('\t').map(str::trim).collect())
+            .collect();
+        assert_eq!(rows, vec![vec!["ID", "Path"], vec!["abc", "/data/a b"]]);
+        assert!(!plain.contains('|') && !plain.contains('+'), "{}", plain);
+    }
+
+    fn entry(path: &str, size: u64) -> drive_pulse_lib::FileEntry {
+        serde_json::from_value(serde_json::json!({
+            "path": path,
+            "size": size,
+            "modified": 1_704_067_200,
+            "is_dir": false,
+        }))
+        .unwrap()
+    }
+
+    fn snapshot(id: &str, timestamp: i64, files: Vec<drive_pulse_lib::FileEntry>) -> drive_pulse_lib::Snapshot {
+        serde_json::from_value(serde_json::json!({
+            "format_version": drive_pulse_lib::SNAPSHOT_FORMAT_VERSION,
+            "id": id,
+            "drive_path": "/data",
+            "timestamp": timestamp,
+            "total_files": files.len(),
+            "total_size": files.iter().map(|f| f.size).sum::<u64>(),
+            "scan_duration": 0,
+            "files": files,
+        }))
+        .unwrap()
+    }
+
+    fn sample_snapshots() -> (Snapshot, Snapshot) {
+        let old = snapshot("old", 1_704_067_200, vec![entry("/data/same.txt", 10), entry("/data/gone.txt", 2048), entry("/data/grown.txt", 100)]);
+        let new = snapshot("new", 1_704_153_600, vec![entry("/data/same.txt", 10), entry("/data/fresh.txt", 4096), entry("/data/grown.txt", 5000)]);
+        (old, new)
+    }
+
+    fn sample_comparison() -> ComparisonResult {
+        let (old, new) = sample_snapshots();
+        drive_pulse_lib::compare_snapshots(&old, &new)
+    }
+
+    #[test]
+    fn diff_table_shows_the_comparison_not_history() {
+        let comparison = sample_comparison();
+        let table = diff_details_table(&comparison, 50).to_string();
+
+        for path in ["/data/fresh.txt", "/data/gone.txt", "/data/grown.txt"] {
+            assert!(table.contains(path), "{} missing: {}", path, table);
+        }
+        assert!(!table.contains("same.txt"), "unchanged files are not listed: {}", table);
+        assert!(table.contains("Added") && table.contains("Deleted") && table.contains("Modified"), "{}", table);
+        assert!(table.contains(&format_size(2048)) && table.contains(&format_size(5000)), "{}", table);
+        for history_column in ["Scan ID", "Drive", "Files", "Date"] {
+            assert!(!table.contains(history_column), "{}", table);
+        }
+
+        let limited = diff_details_table(&comparison, 1);
+        assert_eq!(limited.len(), 2, "header plus one row");
+    }
+
+    #[test]
+    fn oneline_summary_has_a_fixed_format() {
+        let comparison = sample_comparison();
+        // +4096 added, -2048 deleted and +4900 modified
+        assert_eq!(oneline_summary(&comparison), "added=1 deleted=1 modified=1 type_changed=0 bytes_delta=+6.79KB");
+        let (old, new) = sample_snapshots();
+        let reversed = drive_pulse_lib::compare_snapshots(&new, &old);
+        assert!(oneline_summary(&reversed).ends_with(" bytes_delta=-6.79KB"));
+
+        // A folder reported by `--propagate-dirs` adds nothing of its own, even
+        // when its entry's size differs without counting as a change
+        let folder = |size: u64| drive_pulse_lib::FileEntry { is_dir: true, ..entry("/data/docs", size) };
+        let old = snapshot("old", 1_704_067_200, vec![folder(4096)]);
+        let new = snapshot("new", 1_704_153_600, vec![folder(8192), entry("/data/docs/fresh.txt", 1024)]);
+        let options = DiffOptions { presence_only: true, propagate_dir_changes: true, ..DiffOptions::default() };
+        let propagated = drive_pulse_lib::compare_snapshots_with_options(&old, &new, &options);
+        assert_eq!(propagated.propagated_dir_count, 1);
+        assert_eq!(oneline_summary(&propagated), "added=1 deleted=0 modified=0 type_changed=0 bytes_delta=+1.00KB");
+    }
+
+    fn summary(id: &str, drive_path: &str, timestamp: i64) -> SnapshotSummary {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "drive_path": drive_path,
+            "timestamp": timestamp,
+            "total_files": 0,
+            "total_size": 0,
+            "scan_duration": 0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn relative_durations_parse_to_seconds() {
+        assert_eq!(parse_relative_duration("90m"), Some(90 * 60));
+        assert_eq!(parse_relative_duration("3h"), Some(3 * 60 * 60));
+        assert_eq!(parse_relative_duration("7d"), Some(7 * 24 * 60 * 60));
+        assert_eq!(parse_relative_duration("2w"), Some(14 * 24 * 60 * 60));
+        assert_eq!(parse_relative_duration("7x"), None);
+        assert_eq!(parse_relative_duration("d"), None);
+        assert_eq!(parse_relative_duration(""), None);
+        assert_eq!(parse_relative_duration("2024-01-01"), None);
+    }
+
+    #[test]
+    fn date_bounds_accept_relative_iso_and_rfc3339() {
+        let week_ago = Local::now().timestamp() - 7 * 24 * 60 * 60;
+        assert!((parse_date_bound("7d", false).unwrap() - week_ago).abs() <= 5);
+
+        assert_eq!(parse_date_bound("2024-01-01T12:00:00Z", false), Ok(1_704_110_400));
+
+        // A date starts the day for --since and ends it for --until, so the
+        // until bound of one day is the since bound of the next
+        let start = parse_date_bound("2024-01-01", false).unwrap();
+        let end = parse_date_bound("2024-01-01", true).unwrap();
+        assert_eq!(end - start, 24 * 60 * 60);
+        assert_eq!(end, parse_date_bound("2024-01-02", false).unwrap());
+
+        assert!(parse_date_bound("yesterday", false).is_err());
+        assert!(parse_date_bound("2024-13-01", false).is_err());
+    }
+
+    #[test]
+    fn timestamps_render_in_utc_and_fixed_offsets() {
+        // 2024-01-01T12:30:00Z
+        let timestamp = 1_704_112_200;
+        assert_eq!(DisplayTimezone::parse("UTC").unwrap().format(timestamp), "2024-01-01 12:30:00");
+        for offset in ["+05:30", "+0530"] {
+            assert_eq!(DisplayTimezone::parse(offset).unwrap().format(timestamp), "2024-01-01 18:00:00");
+        }
+        assert_eq!(DisplayTimezone::parse("-8").unwrap().format(timestamp), "2024-01-01 04:30:00");
+        assert_eq!(DisplayTimezone::parse("-13:00").unwrap().format(timestamp), "2023-12-31 23:30:00");
+        assert_eq!(DisplayTimezone::parse("local"), Ok(DisplayTimezone::Local));
+
+        // Wall-clock times are read back in the same zone
+        let naive = NaiveDateTime::parse_from_str("2024-01-01 18:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(DisplayTimezone::parse("+05:30").unwrap().timestamp_of(naive), Some(timestamp));
+
+        for invalid in ["5:30", "+5:3", "+05:60", "+25:00", "mars"] {
+            assert!(DisplayTimezone::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn date_filter_includes_since_and_excludes_until() {
+        let history = || vec![summary("a", "/data", 100), summary("b", "/data", 200), summary("c", "/data", 300)];
+        let ids = |scans: Vec<SnapshotSummary>| scans.into_iter().map(|scan| scan.id).collect::<Vec<_>>();
+
+        assert_eq!(ids(filter_by_date(history(), Some(200), Some(300))), vec!["b"]);
+        assert_eq!(ids(filter_by_date(history(), Some(200), None)), vec!["b", "c"]);
+        assert_eq!(ids(filter_by_date(history(), None, Some(200))), vec!["a"]);
+        assert_eq!(ids(filter_by_date(history(), None, None)), vec!["a", "b", "c"]);
+        assert!(filter_by_date(history(), Some(300), Some(300)).is_empty());
+    }
+
+    #[test]
+    fn drive_filter_matches_whole_components_and_groups_partition_the_history() {
+        let history = || {
+            vec![
+                summary("new-data", "/mnt/data", 400),
+                summary("photos", "/mnt/data/photos", 300),
+                summary("database", "/mnt/database", 200),
+                summary("old-data", "/mnt/data", 100),
+            ]
+        };
+        let ids = |scans: &[SnapshotSummary]| scans.iter().map(|scan| scan.id.clone()).collect::<Vec<_>>();
+
+        assert_eq!(ids(&filter_by_drive(history(), "/mnt/data")), vec!["new-data", "photos", "old-data"]);
+        assert_eq!(ids(&filter_by_drive(history(), "/mnt/data/")), vec!["new-data", "photos", "old-data"]);
+        assert_eq!(ids(&filter_by_drive(history(), "/mnt/data/photos")), vec!["photos"]);
+        assert!(filter_by_drive(history(), "/mnt/dat").is_empty());
+
+        let groups = group_by_drive(history());
+        assert_eq!(groups.keys().collect::<Vec<_>>(), vec!["/mnt/data", "/mnt/data/photos", "/mnt/database"]);
+        assert_eq!(ids(&groups["/mnt/data"]), vec!["new-data", "old-data"]);
+        assert_eq!(groups.values().map(Vec::len).sum::<usize>(), history().len());
+    }
+
+    #[test]
+    fn max_results_caps_the_listing_and_counts_everything() {
+        let max_results = |args: Vec<&str>| {
+            let app = App::new("search").arg(Arg::with_name("max-results").long("max-results").takes_value(true));
+            parse_max_results(&app.get_matches_from(args))
+        };
+        assert_eq!(max_results(vec!["search"]), Ok(DEFAULT_MAX_RESULTS));
+        assert_eq!(max_results(vec!["search", "--max-results", "3"]), Ok(3));
+        assert!(max_results(vec!["search", "--max-results", "0"]).is_err());
+        assert!(max_results(vec!["search", "--max-results", "many"]).is_err());
+
+        let matches: Vec<u32> = (1..=250).collect();
+        let (shown, total) = take_results(matches.iter(), 3);
+        assert_eq!(shown, vec![&1, &2, &3]);
+        assert_eq!(total, 250);
+        assert_eq!(truncation_note(shown.len(), total).unwrap(), "Showing 3 of 250 (use --max-results to see more)");
+
+        let (shown, total) = take_results(matches[..2].iter(), 3);
+        assert_eq!((shown.len(), total), (2, 2));
+        assert_eq!(truncation_note(shown.len(), total), None);
+    }
+
+    #[test]
+    fn scans_with_unreadable_entries_are_reported_as_partial() {
+        let summary = |snapshot: &Snapshot| -> serde_json::Value {
+            serde_json::from_str(&scan_summary_json(snapshot, &ScanOptions::default())).unwrap()
+        };
+        let partial = drive_pulse_lib::SnapshotBuilder::new("/data")
+            .file(entry("/data/readable.txt", 10))
+            .scan_error("/data/locked: Permission denied (os error 13)")
+            .scan_error("/data/other/locked: Permission denied (os error 13)")
+            .build();
+        assert!(partial.is_partial());
+        let value = summary(&partial);
+        assert_eq!(value["scan_errors_count"], 2);
+        assert_eq!(value["partial"], true);
+
+        let complete = drive_pulse_lib::SnapshotBuilder::new("/data").file(entry("/data/readable.txt", 10)).build();
+        assert!(!complete.is_partial());
+        let value = summary(&complete);
+        assert_eq!(value["scan_errors_count"], 0);
+        assert_eq!(value["partial"], false);
+    }
+
+    #[test]
+    fn progress_lines_are_json_objects() {
+        let progress = ScanProgress {
+            files_scanned: 1200,
+            current_path: "/data/odd \"name\"\nwith newline".to_string(),
+            total_size: 4096,
+            total_dirs: 7,
+        };
+        for remaining in [Some(Duration::from_secs(42)), None] {
+            let line = progress_json(&progress, remaining);
+            assert!(!line.contains('\n'), "one line per event: {}", line);
+            let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+            let object = value.as_object().unwrap();
+            assert_eq!(object["scanned"], 1200);
+            assert_eq!(object["path"], progress.current_path.as_str());
+            assert_eq!(object["total_dirs"], 7);
+            assert_eq!(object["eta_secs"], serde_json::json!(remaining.map(|d| d.as_secs())));
+        }
+    }
+
+    #[test]
+    fn export_with_unchanged_covers_the_union_of_both_snapshots() {
+        let old = snapshot("old", 1, vec![entry("/data/gone.txt", 1), entry("/data/same.txt", 2), entry("/data/grown.txt", 3)]);
+        let new = snapshot("new", 2, vec![entry("/data/same.txt", 2), entry("/data/grown.txt", 30), entry("/data/fresh.txt", 4)]);
+        let union = 4;
+        let options = DiffOptions { include_unchanged: true, ..DiffOptions::default() };
+        let comparison = drive_pulse_lib::compare_snapshots_with_options(&old, &new, &options);
+
+        let mut csv_out = Vec::new();
+        write_export(&comparison, "csv", &mut csv_out, |_, _| {}).unwrap();
+        let csv_text = String::from_utf8(csv_out).unwrap();
+        assert_eq!(csv_text.lines().count(), union + 1, "{}", csv_text);
+        assert!(csv_text.contains("/data/same.txt,unchanged"), "{}", csv_text);
+
+        let mut json_out = Vec::new();
+        write_export(&comparison, "json", &mut json_out, |_, _| {}).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json_out).unwrap();
+        assert_eq!(json["diffs"].as_array().unwrap().len(), union);
+
+        // Without the flag only the changes are written
+        let changes_only = drive_pulse_lib::compare_snapshots(&old, &new);
+        let mut csv_out = Vec::new();
+        write_export(&changes_only, "csv", &mut csv_out, |_, _| {}).unwrap();
+        assert_eq!(String::from_utf8(csv_out).unwrap().lines().count(), 3 + 1);
+    }
+
+    #[test]
+    fn grouped_csv_rows_are_ordered_with_serde_status_names() {
+        let mut comparison = sample_comparison();
+        comparison.diffs.reverse();
+        comparison.sort_by_status();
+
+        let mut csv_out = Vec::new();
+        write_export(&comparison, "csv", &mut csv_out, |_, _| {}).unwrap();
+        let csv_text = String::from_utf8(csv_out).unwrap();
+        let rows: Vec<&str> = csv_text.lines().collect();
+        assert_eq!(rows[0], "Path,Status,Old Size,New Size,Old Modified,New Modified");
+        let keys: Vec<(&str, &str)> = rows[1..].iter().map(|row| {
+            let mut fields = row.split(',');
+            (fields.next().unwrap(), fields.next().unwrap())
+        }).collect();
+        assert_eq!(keys, vec![("/data/fresh.txt", "added"), ("/data/gone.txt", "deleted"), ("/data/grown.txt", "modified")]);
+
+        // The same names as the JSON export
+        for diff in &comparison.diffs {
+            assert_eq!(serde_json::to_value(diff.status).unwrap(), diff.status.as_str());
+        }
+        assert_eq!(serde_json::to_value(DiffStatus::Unchanged).unwrap(), DiffStatus::Unchanged.as_str());
+    }
+
+    #[test]
+    fn each_only_flag_keeps_the_diffs_of_its_status() {
+        let app = || App::new("compare").args(&only_status_args());
+        for (flag, status) in [("--only-added", DiffStatus::Added), ("--only-deleted", DiffStatus::Deleted), ("--only-modified", DiffStatus::Modified)] {
+            let full = sample_comparison();
+            let mut filtered = sample_comparison();
+            keep_only_status(&app().get_matches_from(vec!["compare", flag]), &mut filtered);
+            let expected: Vec<&str> = full.with_status(status).map(|diff| diff.path.as_str()).collect();
+            let kept: Vec<&str> = filtered.diffs.iter().map(|diff| diff.path.as_str()).collect();
+            assert_eq!(kept, expected, "{}", flag);
+            assert_eq!(kept.len(), 1, "{}", flag);
+            // The counts still describe the whole comparison
+            assert_eq!(filtered.added_count + filtered.deleted_count + filtered.modified_count, 3);
+        }
+
+        let mut untouched = sample_comparison();
+        keep_only_status(&app().get_matches_from(vec!["compare"]), &mut untouched);
+        assert_eq!(untouched.diffs.len(), 3);
+        assert!(app().get_matches_from_safe(vec!["compare", "--only-added", "--only-deleted"]).is_err());
+    }
+
+    #[test]
+    fn fail_on_deleted_trips_only_when_something_was_deleted() {
+        let fail_on = |list: &str| {
+            let app = App::new("compare").arg(Arg::with_name("fail-on").long("fail-on").takes_value(true));
+            fail_on_statuses(&app.get_matches_from(vec!["compare", "--fail-on", list]))
+        };
+        let deleted = fail_on("deleted").unwrap();
+        let with_deletion = sample_comparison();
+        assert_eq!(fail_on_findings(&deleted, &with_deletion), vec!["1 deleted"]);
+
+        let only_added = drive_pulse_lib::compare_snapshots(
+            &drive_pulse_lib::SnapshotBuilder::new("/data").file(entry("/data/kept.txt", 1)).build(),
+            &drive_pulse_lib::SnapshotBuilder::new("/data").files(vec![entry("/data/kept.txt", 1), entry("/data/new.txt", 2)]).build(),
+        );
+        assert!(fail_on_findings(&deleted, &only_added).is_empty());
+        assert_eq!(fail_on_findings(&fail_on("Added, deleted").unwrap(), &only_added), vec!["1 added"]);
+
+        // Filtering the listed diffs doesn't hide what the gate looks for
+        let mut filtered = sample_comparison();
+        filtered.diffs.retain(|diff| diff.status == DiffStatus::Added);
+        assert_eq!(fail_on_findings(&deleted, &filtered), vec!["1 deleted"]);
+
+        assert!(fail_on("unchanged").is_err());
+        assert!(fail_on("renamed").is_err());
+    }
+
+    #[test]
+    fn dash_exports_to_the_given_writer_instead_of_a_file() {
+        let comparison = sample_comparison();
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut stdout = Vec::new();
+        write_export(&comparison, "csv", export_output("-", &mut stdout).unwrap(), |_, _| {}).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap().lines().count(), 3 + 1);
+        assert!(!std::path::Path::new("-").exists());
+
+        let path = dir.path().join("out.csv");
+        let mut untouched = Vec::new();
+        write_export(&comparison, "csv", export_output(path.to_str().unwrap(), &mut untouched).unwrap(), |_, _| {}).unwrap();
+        assert!(untouched.is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3 + 1);
+    }
+
+    #[test]
+    fn script_commands_follow_the_diff_and_quote_every_path() {
+        let dir = |path: &str| drive_pulse_lib::FileEntry { is_dir: true, ..entry(path, 0) };
+        let old = drive_pulse_lib::SnapshotBuilder::new("/data")
+            .files(vec![dir("/data/gone"), entry("/data/gone/a.txt", 1), entry("/data/it's here.txt", 1), entry("/data/swap", 1)])
+            .build();
+        let new = drive_pulse_lib::SnapshotBuilder::new("/data")
+            .files(vec![
+                entry("/data/it's here.txt", 2),
+                dir("/data/new dir"),
+                entry("/data/new dir/$(rm).txt", 1),
+                dir("/data/swap"),
+                entry("/data/swap/x.txt", 1),
+            ])
+            .build();
+        let comparison = drive_pulse_lib::compare_snapshots(&old, &new);
+        let script = |echo: bool| {
+            let mut out = Vec::new();
+            let options = ScriptOptions { source_root: "/src".to_string(), echo };
+            write_script_export(&comparison, &old, &new, &options, &mut out, |_, _| {}).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let text = script(false);
+        assert!(text.starts_with("#!/bin/sh\n# Advisory"), "{}", text);
+        let commands: Vec<&str> = text.lines().filter(|line| !line.starts_with('#') && *line != "set -e").collect();
+        assert_eq!(
+            commands,
+            vec![
+                "rm -f -- /data/swap",
+                "rm -f -- /data/gone/a.txt",
+                "rmdir -- /data/gone",
+                r"cp -pP -- '/src/it'\''s here.txt' '/data/it'\''s here.txt'",
+                "mkdir -p -- '/data/new dir'",
+                "cp -pP -- '/src/new dir/$(rm).txt' '/data/new dir/$(rm).txt'",
+                "mkdir -p -- /data/swap",
+                "cp -pP -- /src/swap/x.txt /data/swap/x.txt",
+            ]
+        );
+        let dry_run = script(true);
+        assert!(dry_run.lines().filter(|line| !line.starts_with('#') && *line != "set -e").all(|line| line.starts_with("echo ")));
+
+        // The shell reads every quoted value back unchanged
+        if cfg!(unix) {
+            for value in ["it's here", "$(rm) `x` \"y\" *", "new\nline", "", "plain/path.txt"] {
+                let output = std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(format!("printf %s {}", shell_quote(value)))
+                    .output()
+                    .unwrap();
+                assert_eq!(String::from_utf8(output.stdout).unwrap(), value);
+            }
+        }
+    }
+
+    #[test]
+    fn export_progress_is_reported_once_per_diff() {
+        let comparison = sample_comparison();
+        let total = comparison.diffs.len();
+        let mut calls = Vec::new();
+        write_export(&comparison, "csv", Vec::new(), |done, total| calls.push((done, total))).unwrap();
+        let expected: Vec<(usize, usize)> = (0..=total).map(|done| (done, total)).collect();
+        assert_eq!(calls, expected);
+
+        // JSON is written in one go
+        calls.clear();
+        write_export(&comparison, "json", Vec::new(), |done, total| calls.push((done, total))).unwrap();
+        assert_eq!(calls, vec![(0, total), (total, total)]);
+
+        // One call per command, of which there is one per change here
+        calls.clear();
+        let no_dirs = drive_pulse_lib::SnapshotBuilder::new("/data").build();
+        let options = ScriptOptions { source_root: "/src".to_string(), echo: false };
+        write_script_export(&comparison, &no_dirs, &no_dirs, &options, Vec::new(), |done, total| calls.push((done, total))).unwrap();
+        assert_eq!(calls, expected);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_export_holds_both_scans_and_every_diff() {
+        let comparison = sample_comparison();
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("comparison.db");
+        // An older export at the same path is replaced, not added to
+        write_sqlite_export(&comparison, &path, |_, _| {}).unwrap();
+        write_sqlite_export(&comparison, &path, |_, _| {}).unwrap();
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let count = |sql: &str| conn.query_row(sql, [], |row| row.get::<_, i64>(0)).unwrap();
+        assert_eq!(count("SELECT COUNT(*) FROM snapshots"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM diffs"), comparison.diffs.len() as i64);
+        assert_eq!(count("SELECT new_size - old_size FROM diffs WHERE status = 'modified'"), 4900);
+        let added: String = conn.query_row("SELECT path FROM diffs WHERE status = 'added'", [], |row| row.get(0)).unwrap();
+        assert_eq!(added, "/data/fresh.txt");
+        let new_id: String = conn.query_row("SELECT id FROM snapshots WHERE role = 'new'", [], |row| row.get(0)).unwrap();
+        assert_eq!(new_id, comparison.snapshot2.id);
+    }
+
+    #[test]
+    fn paths_files_skip_comments_and_each_listed_root_is_scanned() {
+        let data_dir = tempfile::TempDir::new().unwrap();
+        let roots = tempfile::TempDir::new().unwrap();
+        fs::create_dir_all(roots.path().join("photos")).unwrap();
+        fs::write(roots.path().join("photos/cat.jpg"), b"meow").unwrap();
+        let documents = tempfile::TempDir::new().unwrap();
+        fs::write(documents.path().join("letter.txt"), b"hello").unwrap();
+        let list = roots.path().join("scan-roots.txt");
+        let listing = format!("# Folders to inventory\n\nphotos\n   \n  # indented comment\n{}\n", documents.path().display());
+        fs::write(&list, listing).unwrap();
+
+        let paths = read_paths_file(list.to_str().unwrap()).unwrap();
+        let expected = vec![
+            roots.path().join("photos").to_string_lossy().to_string(),
+            documents.path().to_string_lossy().to_string(),
+        ];
+        assert_eq!(paths, expected);
+
+        for path in paths {
+            scan_path(data_dir.path(), path, &ScanOptions::default(), SnapshotFormat::Json, None, true, true).unwrap();
+        }
+        let mut scanned: Vec<String> = drive_pulse_lib::get_scan_history(data_dir.path()).unwrap().into_iter().map(|scan| scan.drive_path).collect();
+        scanned.sort();
+        let mut expected = expected;
+        expected.sort();
+        assert_eq!(scanned, expected);
+
+        let empty = roots.path().join("empty.txt");
+        fs::write(&empty, "# nothing yet\n\n").unwrap();
+        assert!(read_paths_file(empty.to_str().unwrap()).is_err());
+        assert!(read_paths_file(roots.path().join("missing.txt").to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn changes_compares_a_scan_with_its_parent() {
+        let data_dir = tempfile::TempDir::new().unwrap();
+        let parent = snapshot("parent", 1_704_067_200, vec![entry("/data/kept.txt", 1), entry("/data/gone.txt", 2)]);
+        let mut child = snapshot("child", 1_704_153_600, vec![entry("/data/kept.txt", 1), entry("/data/fresh.txt", 3)]);
+        child.parent_id = Some("parent".to_string());
+        for snapshot in [&parent, &child] {
+            drive_pulse_lib::save_snapshot(data_dir.path(), snapshot, false, None).unwrap();
+            drive_pulse_lib::save_snapshot_metadata(data_dir.path(), snapshot).unwrap();
+        }
+
+        let baseline = load_parent(data_dir.path(), &child).unwrap().unwrap();
+        assert_eq!(baseline.id, "parent");
+        let comparison = drive_pulse_lib::compare_snapshots(&baseline, &child);
+        let diffs: Vec<(&str, DiffStatus)> = comparison.diffs.iter().map(|diff| (diff.path.as_str(), diff.status)).collect();
+        assert_eq!(diffs, vec![("/data/fresh.txt", DiffStatus::Added), ("/data/gone.txt", DiffStatus::Deleted)]);
+
+        // Without a recorded parent the user is asked for a baseline instead
+        assert!(load_parent(data_dir.path(), &parent).unwrap().is_none());
+    }
+
+    #[test]
+    fn pruning_duplicates_keeps_exactly_one_scan_of_each_group() {
+        let data_dir = tempfile::TempDir::new().unwrap();
+        for (id, timestamp) in [("old", 1_000), ("middle", 2_000), ("new", 3_000)] {
+            let snapshot = drive_pulse_lib::SnapshotBuilder::new("/data")
+                .id(id)
+                .timestamp(timestamp)
+                .file(entry("/data/a.txt", 1))
+                .build();
+            drive_pulse_lib::save_snapshot(data_dir.path(), &snapshot, false, None).unwrap();
+            drive_pulse_lib::save_snapshot_metadata(data_dir.path(), &snapshot).unwrap();
+        }
+        let groups = drive_pulse_lib::find_duplicate_snapshots(data_dir.path()).unwrap();
+        assert_eq!(groups.len(), 1);
+
+        let sorted = |ids: HashSet<String>| {
+            let mut ids: Vec<String> = ids.into_iter().collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(sorted(redundant_duplicates(drive_pulse_lib::find_duplicate_snapshots(data_dir.path()).unwrap(), true)), vec!["middle", "old"]);
+        let redundant = redundant_duplicates(groups, false);
+        assert_eq!(sorted(redundant.clone()), vec!["middle", "new"]);
+
+        drive_pulse_lib::delete_snapshots_where(data_dir.path(), |scan| redundant.contains(&scan.id), false).unwrap();
+        let left: Vec<String> = drive_pulse_lib::get_scan_history(data_dir.path()).unwrap().into_iter().map(|scan| scan.id).collect();
+        assert_eq!(left, vec!["old"]);
+        assert!(drive_pulse_lib::find_duplicate_snapshots(data_dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn passwords_from_env_or_stdin_unlock_what_they_saved() {
+        let data_dir = tempfile::TempDir::new().unwrap();
+        let snapshot = snapshot("secret-scan", 1_704_067_200, vec![entry("/data/a.txt", 1)]);
+
+        let from_env = password_from_sources(Some("from env".to_string()), None).unwrap().unwrap();
+        let mut stdin: &[u8] = b"from stdin\r\nignored\n";
+        let from_stdin = password_from_sources(None, Some(&mut stdin)).unwrap().unwrap();
+        assert_eq!(from_stdin, "from stdin");
+        // The variable wins, and stdin is left alone
+        let mut unread: &[u8] = b"other\n";
+        assert_eq!(password_from_sources(Some("from env".to_string()), Some(&mut unread)).unwrap().unwrap(), "from env");
+        assert_eq!(unread, b"other\n");
+        assert_eq!(password_from_sources(None, None).unwrap(), None);
+
+        for password in [from_env, from_stdin] {
+            drive_pulse_lib::save_snapshot(data_dir.path(), &snapshot, true, Some(&password)).unwrap();
+            assert_eq!(drive_pulse_lib::load_snapshot(data_dir.path(), "secret-scan", Some(&password)).unwrap().total_files, 1);
+            assert!(drive_pulse_lib::load_snapshot(data_dir.path(), "secret-scan", Some("wrong")).is_err());
+        }
+    }
+
+    #[test]
+    fn table_style_parses_known_names_only() {
+        assert_eq!(TableStyle::parse("Plain"), Ok(TableStyle::Plain));
+        assert_eq!(TableStyle::parse("compact"), Ok(TableStyle::Compact));
+        assert_eq!(TableStyle::parse("full"), Ok(TableStyle::Full));
+        assert!(TableStyle::parse("fancy").is_err());
+    }
+}
@@ -20,6 +20,8 @@ interface Snapshot {
   total_size: number;
   scan_duration: number;
   files: FileEntry[];
+  scan_errors_count: number;
+  scan_errors: string[];
 }
 
 interface SnapshotSummary {
@@ -29,6 +31,11 @@ interface SnapshotSummary {
   total_files: number;
   total_size: number;
   scan_duration: number;
+  encrypted: boolean;
+  compressed: boolean;
+  format_version: number | null;
+  drive_present: boolean | null;
+  locked: boolean;
 }
 
 interface FileDiff {
@@ -53,11 +60,30 @@ interface ScanProgress {
   files_scanned: number;
   current_path: string;
   total_size: number;
+  total_dirs: number;
+  eta_seconds: number | null;
+}
+
+// Mirrors the library's ScanOptions; omitted fields take their defaults
+interface ScanOptions {
+  include_data_dir?: boolean;
+  exclude_paths?: string[];
+  canonicalize_paths?: boolean;
+  one_filesystem?: boolean;
+  hash_files?: boolean;
+  max_size?: number | null;
+  min_depth?: number | null;
+  exclude_empty_dirs?: boolean;
+  exclude_extensions?: string[];
+  threads?: number | null;
+  record_xattrs?: boolean;
+  xattr_names?: string[];
 }
 
 interface DriveInfo {
   path: string;
   label: string;
+  status: string | null;
 }
 
 function App() {
@@ -76,6 +102,7 @@ function App() {
   const [loadingDrives, setLoadingDrives] = useState(true);
   const [encrypt, setEncrypt] = useState(false);
   const [password, setPassword] = useState("");
+  const [scanOptions] = useState<ScanOptions>({});
   const [successMessage, setSuccessMessage] = useState("");
   const scanningRef = useRef(false);
 
@@ -124,7 +151,7 @@ function App() {
   const loadHistory = async () => {
     setLoadingHistory(true);
     try {
-      const history = await invoke<SnapshotSummary[]>("get_scan_history");
+      const history = await invoke<SnapshotSummary[]>("get_scan_history", { checkPaths: true });
       setSnapshots(history);
     } catch (err) {
       setError(`Failed to load history: ${err}`);
@@ -168,6 +195,7 @@ function App() {
         drivePath,
         encrypt,
         password: encrypt ? password : null,
+        options: scanOptions,
       });
 
       // Explicitly clear scanning state and progress before saving
@@ -180,7 +208,8 @@ function App() {
       // Update scan history after scan completes, so tab label is always up to date
       loadHistory();
 
-      const message = `Scan complete! ${snapshot.total_files.toLocaleString()} files scanned (${formatBytes(snapshot.total_size)})${encrypt ? " - Encrypted" : ""}`;
+      const unreadable = snapshot.scan_errors_count > 0 ? ` - completed with ${snapshot.scan_errors_count.toLocaleString()} unreadable items` : "";
+      const message = `Scan complete! ${snapshot.total_files.toLocaleString()} files scanned (${formatBytes(snapshot.total_size)})${encrypt ? " - Encrypted" : ""}${unreadable}`;
       setSuccessMessage(message);
 
       setTimeout(() => setSuccessMessage(""), 5000);
@@ -230,31 +259,47 @@ function App() {
     setLoadingComparison(true);
     console.log("Loading comparison started");
     try {
-      const needsPassword = snapshots.find((s) => (s.id === selectedSnapshots[0] || s.id === selectedSnapshots[1]) && s.total_files === 0);
-
-      let pwd = null;
-      if (needsPassword) {
-        pwd = prompt("Enter password for encrypted snapshot:");
-        if (!pwd) return;
+      // Only encrypted snapshots need a password, and each may have its own
+      const passwords: (string | null)[] = [];
+      for (const id of selectedSnapshots) {
+        const snapshot = snapshots.find((s) => s.id === id);
+        let pwd = null;
+        if (snapshot?.encrypted) {
+          pwd = prompt(`Enter password for encrypted snapshot of ${snapshot.drive_path}:`);
+          if (!pwd) return;
+        }
+        passwords.push(pwd);
       }
 
       const result = await invoke<ComparisonResult>("compare_snapshots", {
         snapshot1Id: selectedSnapshots[0],
         snapshot2Id: selectedSnapshots[1],
-        password: pwd,
+        password1: passwords[0],
+        password2: passwords[1],
       });
       console.log("Comparison result received:", result);
       setComparison(result);
       console.log("Comparison state updated");
     } catch (err) {
-      console.error("Comparison failed:", err);
-      setError(`Comparison failed: ${err}`);
+      // A cancelled comparison isn't a failure worth reporting
+      if (err !== "Cancelled") {
+        console.error("Comparison failed:", err);
+        setError(`Comparison failed: ${err}`);
+      }
     } finally {
       setLoadingComparison(false);
       console.log("Loading comparison ended");
     }
   };
 
+  const handleCancelCompare = async () => {
+    try {
+      await invoke("cancel_compare");
+    } catch (err) {
+      console.error("Failed to cancel comparison:", err);
+    }
+  };
+
   const handleDelete = async (id: string, e: React.MouseEvent) => {
     e.stopPropagation();
     try {
@@ -353,8 +398,8 @@ function App() {
                       )}
                     </MenuItem>
                     {availableDrives.map((drive) => (
-                      <MenuItem key={drive.path} value={drive.path}>
-                        {drive.label}
+                      <MenuItem key={drive.path} value={drive.path} disabled={!!drive.status}>
+                        {drive.status ? `${drive.label} (${drive.status})` : drive.label}
                       </MenuItem>
                     ))}
                     <MenuItem value="custom">Custom Path...</MenuItem>
@@ -392,9 +437,17 @@ function App() {
                     <Typography variant="body2">
                       <strong>Files scanned:</strong> {scanProgress.files_scanned.toLocaleString()}
                     </Typography>
+                    <Typography variant="body2">
+                      <strong>Folders:</strong> {scanProgress.total_dirs.toLocaleString()}
+                    </Typography>
                     <Typography variant="body2">
                       <strong>Total size:</strong> {formatBytes(scanProgress.total_size)}
                     </Typography>
+                    {scanProgress.eta_seconds !== null && scanProgress.eta_seconds !== undefined && (
+                      <Typography variant="body2">
+                        <strong>Time left:</strong> ~{formatDuration(scanProgress.eta_seconds)}
+                      </Typography>
+                    )}
                     <Typography variant="caption" color="text.secondary" sx={{ wordBreak: "break-all" }}>
                       <strong>Current:</strong> {scanProgress.current_path}
                     </Typography>
@@ -520,6 +573,13 @@ function App() {
                           <Box sx={{ display: "flex", alignItems: "center", gap: 1 }}>
                             <StorageIcon fontSize="small" color="action" />
                             {snapshot.drive_path}
+                            {snapshot.encrypted && <LockIcon fontSize="small" color="action" titleAccess="Encrypted" />}
+                            {snapshot.drive_present === false && <Chip label="drive missing" size="small" color="warning" variant="outlined" />}
+                            {snapshot.compressed && <Chip label="compressed" size="small" variant="outlined" />}
+                            {snapshot.locked && <Chip label="locked" size="small" color="info" variant="outlined" title="Locked scans can't be deleted" />}
+                            {snapshot.format_version !== null && snapshot.format_version !== undefined && (
+                              <Chip label={`v${snapshot.format_version}`} size="small" variant="outlined" title="Snapshot format version" />
+                            )}
                           </Box>
                         </TableCell>
                         <TableCell>{formatDate(snapshot.timestamp)}</TableCell>
@@ -544,6 +604,9 @@ function App() {
             <Paper elevation={2} sx={{ p: 2, textAlign: "center" }}>
               <CircularProgress sx={{ mb: 2 }} />
               <Typography variant="body2">Comparing snapshots, please wait...</Typography>
+              <Button onClick={handleCancelCompare} size="small" sx={{ mt: 1 }}>
+                Cancel
+              </Button>
             </Paper>
           )}
           {!loadingComparison && comparison && (